@@ -46,7 +46,7 @@ pub fn bench_create_flac(c: &mut Criterion) {
             let filehint = "flac".to_string();
             let filepath = format!("{}/a.flac", get_soundfiles_path_1());
             let mut afile = AudioFileFLAC::default();
-            afile.load_file(filepath);
+            afile.load_file(filepath).unwrap();
         })
     });
 }
@@ -56,7 +56,7 @@ pub fn bench_create_mp3(c: &mut Criterion) {
         b.iter(|| {
             let filepath = format!("{}/cbat.mp3", get_soundfiles_path_1());
             let mut afile = AudioFileMP3::default();
-            afile.load_file(filepath);
+            afile.load_file(filepath).unwrap();
         })
     });
 }
@@ -66,7 +66,7 @@ pub fn bench_insert_song_information(c: &mut Criterion) {
         b.iter(|| {
             let filepath = format!("{}/a.flac", get_soundfiles_path_1());
             let mut afile = AudioFileFLAC::default();
-            afile.load_file(filepath);
+            afile.load_file(filepath).unwrap();
 
             create_all_tables();
             clear_all_tables();
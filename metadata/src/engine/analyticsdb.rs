@@ -2,14 +2,35 @@
 
 use crate::engine::audio_metadata::*;
 use crate::engine::config::*;
+use crate::engine::file_health::{deep_check_file, save_file_health};
 use crate::engine::models::*;
+use crate::engine::playlists::renumber_playlist;
+use crate::engine::smart_playlists::{evaluate_smart_playlist, get_smart_playlist_rules};
 use indicatif::ProgressBar;
 use indicatif::ProgressState;
 use indicatif::ProgressStyle;
 use rusqlite::params;
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
 use rusqlite::Connection;
+use rusqlite::TransactionBehavior;
+use serde::{Deserialize, Serialize};
 use std::fmt::Write;
 use walkdir;
+
+/// How every timestamp in the database is written: UTC to the second, so they sort and compare as plain strings
+pub const DT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Writes a date and time the way the database stores them (DT_FORMAT)
+pub fn format_dt(dt: chrono::NaiveDateTime) -> String {
+    dt.format(DT_FORMAT).to_string()
+}
+
+/// The current time, the way the database stores timestamps
+pub fn now_dt() -> String {
+    format_dt(chrono::Utc::now().naive_utc())
+}
+
 // --------------------------------------------------------------------------------------------------------------------------------------------
 // --------------------------------------------------------------------------------------------------------------------------------------------
 //                                                           CREATE TABLES
@@ -128,6 +149,78 @@ pub fn create_albums_table() {
     create_table(albums_sql_query)
 }
 
+/// Creates the 'smart_playlists' table in the SQLite database.
+pub fn create_smart_playlists_table() {
+    let smart_playlists_sql_query = compile_smart_playlists_table();
+    create_table(smart_playlists_sql_query)
+}
+
+/// Creates the 'song_versions' table in the SQLite database.
+pub fn create_song_versions_table() {
+    let song_versions_sql_query = compile_song_versions_table();
+    create_table(song_versions_sql_query)
+}
+
+/// Creates the 'flac_verifications' table in the SQLite database.
+pub fn create_flac_verifications_table() {
+    let flac_verifications_sql_query = compile_flac_verifications_table();
+    create_table(flac_verifications_sql_query)
+}
+
+/// Creates the 'file_health' table in the SQLite database.
+pub fn create_file_health_table() {
+    let file_health_sql_query = compile_file_health_table();
+    create_table(file_health_sql_query)
+}
+
+/// Creates the 'loudness' table in the SQLite database.
+pub fn create_loudness_table() {
+    let loudness_sql_query = compile_loudness_table();
+    create_table(loudness_sql_query)
+}
+
+/// Creates the 'music_analysis' table in the SQLite database.
+pub fn create_music_analysis_table() {
+    let music_analysis_sql_query = compile_music_analysis_table();
+    create_table(music_analysis_sql_query)
+}
+
+/// Creates the 'gapless' table in the SQLite database.
+pub fn create_gapless_table() {
+    let gapless_sql_query = compile_gapless_table();
+    create_table(gapless_sql_query)
+}
+
+/// Creates the 'artwork' table in the SQLite database.
+pub fn create_artwork_table() {
+    let artwork_sql_query = compile_artwork_table();
+    create_table(artwork_sql_query)
+}
+
+/// Creates the 'song_artwork' table in the SQLite database.
+pub fn create_song_artwork_table() {
+    let song_artwork_sql_query = compile_song_artwork_table();
+    create_table(song_artwork_sql_query)
+}
+
+/// Creates the 'album_artwork' table in the SQLite database.
+pub fn create_album_artwork_table() {
+    let album_artwork_sql_query = compile_album_artwork_table();
+    create_table(album_artwork_sql_query)
+}
+
+/// Creates the 'tag_edit_batches' table in the SQLite database.
+pub fn create_tag_edit_batches_table() {
+    let tag_edit_batches_sql_query = compile_tag_edit_batches_table();
+    create_table(tag_edit_batches_sql_query)
+}
+
+/// Creates the 'tag_edit_journal' table in the SQLite database.
+pub fn create_tag_edit_journal_table() {
+    let tag_edit_journal_sql_query = compile_tag_edit_journal_table();
+    create_table(tag_edit_journal_sql_query)
+}
+
 /// Creates all the tables in the SQLite database.
 pub fn create_all_tables() {
     create_all_files();
@@ -142,6 +235,133 @@ pub fn create_all_tables() {
     create_song_paths_table();
     create_artists_table();
     create_albums_table();
+    create_smart_playlists_table();
+    create_song_versions_table();
+    create_flac_verifications_table();
+    create_file_health_table();
+    create_loudness_table();
+    create_music_analysis_table();
+    create_gapless_table();
+    create_artwork_table();
+    create_song_artwork_table();
+    create_album_artwork_table();
+    create_tag_edit_batches_table();
+    create_tag_edit_journal_table();
+    migrate_all_tables();
+}
+
+/// Adds any columns that are in the model but not in the table on disk, and returns their names.
+/// CREATE TABLE IF NOT EXISTS leaves an existing table alone, so databases made by an older version need this.
+pub fn add_missing_columns(table: &Table) -> Vec<String> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut added: Vec<String> = Vec::new();
+
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table.name))
+        .expect("Could not prepare statement");
+    let existing: Vec<String> = stmt
+        .query_map([], |row| row.get(1))
+        .unwrap()
+        .map(|name| name.unwrap())
+        .collect();
+
+    for column in &table.columns {
+        if existing.iter().any(|name| name == column.name) {
+            continue;
+        }
+        // rows that were already there get the same "unknown" values the TABLE_DATA defaults use
+        let default = if column.data_type == "TEXT" { "''" } else { "-1" };
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {} DEFAULT {}",
+                table.name, column.name, column.data_type, default
+            ),
+            [],
+        )
+        .unwrap();
+        added.push(column.name.to_string());
+    }
+
+    added
+}
+
+/// Gives songs without a date added (from before songs had their own) the earliest date added of their artists,
+/// composers or genres, which is the closest thing. Those were written with fractions of a second, DT_FORMAT stops
+/// at the second.
+pub fn fill_songs_dt_added(conn: &Connection) {
+    conn.execute(
+        "UPDATE songs SET dt_added = COALESCE((SELECT SUBSTR(MIN(NULLIF(dt_added, '')), 1, 19) FROM ( \
+         SELECT dt_added FROM song_artists WHERE song_id = songs.song_id \
+         UNION ALL SELECT dt_added FROM album_artists WHERE song_id = songs.song_id \
+         UNION ALL SELECT dt_added FROM composers WHERE song_id = songs.song_id \
+         UNION ALL SELECT dt_added FROM genres WHERE song_id = songs.song_id)), ?1) \
+         WHERE dt_added = ''",
+        [now_dt()],
+    )
+    .unwrap();
+}
+
+/// Brings the tables of an older database up to date with the models.
+pub fn migrate_all_tables() {
+    for table in [
+        &*SONGS,
+        &*PLAYS,
+        &*PLAYLISTS,
+        &*PLAYLIST_SONGS,
+        &*SONG_ARTISTS,
+        &*ALBUM_ARTISTS,
+        &*COMPOSERS,
+        &*GENRES,
+        &*SONGPATHS,
+        &*ARTISTS,
+        &*ALBUMS,
+        &*SMART_PLAYLISTS,
+        &*SONG_VERSIONS,
+        &*FLAC_VERIFICATIONS,
+        &*FILE_HEALTH,
+        &*LOUDNESS,
+        &*MUSIC_ANALYSIS,
+        &*GAPLESS,
+        &*ARTWORK,
+        &*SONG_ARTWORK,
+        &*ALBUM_ARTWORK,
+        &*TAG_EDIT_BATCHES,
+        &*TAG_EDIT_JOURNAL,
+    ] {
+        let added = add_missing_columns(table);
+
+        if table.name == SONGS.name && added.iter().any(|name| name == "dt_added") {
+            let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+            fill_songs_dt_added(&conn);
+        }
+
+        // playlist songs used to be unordered, so keep the order they were inserted in
+        if table.name == PLAYLIST_SONGS.name && added.iter().any(|name| name == "position") {
+            let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+            conn.execute(
+                "UPDATE playlist_songs SET position = (SELECT COUNT(*) FROM playlist_songs p2 \
+                 WHERE p2.playlist_id = playlist_songs.playlist_id AND p2.rowid < playlist_songs.rowid)",
+                [],
+            )
+            .unwrap();
+        }
+    }
+
+    // a song can be in a playlist more than once, but only one song can be at each position
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS playlist_songs_position ON playlist_songs (playlist_id, position)",
+        [],
+    )
+    .unwrap();
+
+    // an empty library has no old song_ids to re-key, so it starts out on the current scheme
+    let songs: i64 = conn
+        .query_row("SELECT COUNT(*) FROM songs", [], |row| row.get(0))
+        .unwrap();
+    if songs == 0 {
+        set_song_id_version(&conn, SONG_ID_VERSION);
+    }
 }
 
 /// Clears all the tables in the SQLite database.
@@ -157,6 +377,18 @@ pub fn clear_all_tables() {
     clear_table("songpaths".to_string());
     clear_table("artists".to_string());
     clear_table("albums".to_string());
+    clear_table("smart_playlists".to_string());
+    clear_table("song_versions".to_string());
+    clear_table("flac_verifications".to_string());
+    clear_table("file_health".to_string());
+    clear_table("loudness".to_string());
+    clear_table("music_analysis".to_string());
+    clear_table("gapless".to_string());
+    clear_table("artwork".to_string());
+    clear_table("song_artwork".to_string());
+    clear_table("album_artwork".to_string());
+    clear_table("tag_edit_batches".to_string());
+    clear_table("tag_edit_journal".to_string());
 }
 
 // --------------------------------------------------------------------------------------------------------------------------------------------
@@ -218,44 +450,9 @@ pub fn insert_song(song_table_data: SONG_TABLE_DATA) {
     // check if song_id already exists
     // if it does, dont dod anything
 
-    let sql_query = generate_insertion_sql(&SONGS);
-    
     match conn {
         Ok(conn) => {
-            conn.execute(
-                &sql_query,
-                params![
-                    song_table_data.song_id,
-                    song_table_data.main_artist,
-                    song_table_data.filesize_bytes,
-                    song_table_data.padding_bytes,
-                    song_table_data.album_artwork_bit_depth,
-                    song_table_data.album_artwork_colors,
-                    song_table_data.album_artwork_height,
-                    song_table_data.album_artwork_width,
-                    song_table_data.bit_depth,
-                    song_table_data.bitrate,
-                    song_table_data.channels,
-                    song_table_data.duration,
-                    song_table_data.sample_rate,
-                    song_table_data.album,
-                    song_table_data.barcode,
-                    song_table_data.date_created,
-                    song_table_data.disc_number,
-                    song_table_data.disc_total,
-                    song_table_data.isrc,
-                    song_table_data.itunesadvisory,
-                    song_table_data.length,
-                    song_table_data.publisher,
-                    song_table_data.rating,
-                    song_table_data.title,
-                    song_table_data.track_number,
-                    song_table_data.track_total,
-                    song_table_data.source,
-                    song_table_data.filetype,
-                ],
-            )
-            .unwrap();
+            insert_song_row(&conn, &song_table_data).unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
@@ -263,7 +460,49 @@ pub fn insert_song(song_table_data: SONG_TABLE_DATA) {
     }
 }
 
-/// Insert a new play into the database
+/// insert_song on a connection that's already open, so it can be part of a transaction
+pub fn insert_song_row(conn: &Connection, song_table_data: &SONG_TABLE_DATA) -> rusqlite::Result<usize> {
+    conn.execute(
+        &generate_insertion_sql(&SONGS),
+        params![
+            song_table_data.song_id,
+            song_table_data.main_artist,
+            song_table_data.filesize_bytes,
+            song_table_data.padding_bytes,
+            song_table_data.album_artwork_bit_depth,
+            song_table_data.album_artwork_colors,
+            song_table_data.album_artwork_height,
+            song_table_data.album_artwork_width,
+            song_table_data.bit_depth,
+            song_table_data.bitrate,
+            song_table_data.channels,
+            song_table_data.duration,
+            song_table_data.sample_rate,
+            song_table_data.album,
+            song_table_data.barcode,
+            song_table_data.date_created,
+            song_table_data.disc_number,
+            song_table_data.disc_total,
+            song_table_data.isrc,
+            song_table_data.itunesadvisory,
+            song_table_data.length,
+            song_table_data.publisher,
+            song_table_data.rating,
+            song_table_data.title,
+            song_table_data.track_number,
+            song_table_data.track_total,
+            song_table_data.source,
+            song_table_data.filetype,
+            song_table_data.audio_hash,
+            song_table_data.bitrate_mode,
+            song_table_data.total_samples,
+            song_table_data.recording_id,
+            song_table_data.dt_added,
+        ],
+    )
+}
+
+/// Insert a new play into the database. To record a play as it happens use play_sessions::start_play and end_play instead.
 /// ```
 /// use rusqlite::{Connection, Result};
 /// use rusqlite::params;
@@ -281,6 +520,10 @@ pub fn insert_song(song_table_data: SONG_TABLE_DATA) {
 ///   filesize_bytes: 1234567890,
 ///  start_dt: "2022-01-01",
 /// end_dt: "2022-01-01",
+/// position_seconds: 200.0,
+/// completion: 1.0,
+/// skipped: 0,
+/// seek_count: 0,
 /// };
 ///
 /// insert_play(play_table_data);
@@ -301,7 +544,11 @@ pub fn insert_play(plays: PLAY_TABLE_DATA) {
                     plays.main_artist,
                     plays.filesize_bytes,
                     plays.start_dt,
-                    plays.end_dt
+                    plays.end_dt,
+                    plays.position_seconds,
+                    plays.completion,
+                    plays.skipped,
+                    plays.seek_count
                 ],
             )
             .unwrap();
@@ -350,11 +597,14 @@ pub fn insert_playlist(playlist: PLAYLIST_TABLE_DATA) {
 }
 
 /// Insert a new playlist_song into the database. Make sure the playlist_id and song_id is real.
+/// A position of -1 (or one past the end) appends the song to the end of the playlist. Any other position pushes the
+/// songs from there onwards down by one, the same as playlists::insert_playlist_song_at.
 /// ```
 /// let playlist_song_table_data = PLAYLIST_SONGS_TABLE_DATA {
 ///  playlist_id: "1234567890",
 /// song_id: "1234567890",
 /// added_dt: "2022-01-01",
+/// position: -1,
 /// };
 ///     
 /// insert_playlist_song(playlist_song_table_data);
@@ -365,16 +615,46 @@ pub fn insert_playlist_song(playlist_song: PLAYLIST_SONGS_TABLE_DATA) {
     let sql_query = generate_insertion_sql(&PLAYLIST_SONGS);
     
     match conn {
-        Ok(conn) => {
-            conn.execute(
+        Ok(mut conn) => {
+            // work out the end of the playlist and insert in one go so two appends can't grab the same position
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .unwrap();
+            let end: i64 = tx
+                .query_row(
+                    "SELECT COALESCE(MAX(position) + 1, 0) FROM playlist_songs WHERE playlist_id = ?1",
+                    [&playlist_song.playlist_id],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            let position = if playlist_song.position < 0 || playlist_song.position > end {
+                end
+            } else {
+                playlist_song.position
+            };
+            // make room, going through negative positions so the unique (playlist_id, position) index doesn't trip
+            // mid update (see playlists::edit_playlist)
+            tx.execute(
+                "UPDATE playlist_songs SET position = -2 - position WHERE playlist_id = ?1 AND position >= ?2",
+                params![playlist_song.playlist_id, position],
+            )
+            .unwrap();
+            tx.execute(
+                "UPDATE playlist_songs SET position = -1 - position WHERE playlist_id = ?1 AND position < 0",
+                [&playlist_song.playlist_id],
+            )
+            .unwrap();
+            tx.execute(
                 &sql_query,
                 params![
                     playlist_song.playlist_id,
                     playlist_song.song_id,
                     playlist_song.added_dt,
+                    position,
                 ],
             )
             .unwrap();
+            tx.commit().unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
@@ -591,480 +871,1282 @@ pub fn insert_album(album: ALBUMS_TABLE_DATA) {
     }
 }
 
-/// Important function: Pass in an object with trait AudioFile and it will insert the important information in the following tables:
-/// songs, song_artists, album_artists, composers, genres
-/// ```
-/// let filepath = "C:\\Users\\John\\Music\\John Doe\\John Doe - John Doe.mp3";
-/// let audioFile = AudioFileMP3::default(); // you have to decide which audioFile class to use by default! Use like an if statement or see populate_database() down below
-/// audioFile.load_file(filepath);
-///
-/// insert_song_information(audioFile);
-/// ```
-pub fn insert_song_information<T: AudioFile>(song: T) {
-    // lets get the important structs
-    let song_table_data = song.get_song_table_data();
-    let song_artists_table_data = song.get_song_artists_table_data();
-    let album_artists_table_data = song.get_album_artists_table_data();
-    let composers_table_data = song.get_composers_table_data();
-    let genres_table_data = song.get_genres_table_data();
-
-    // print composers
-    // insert into the appropriate tables
-    insert_song(song_table_data);
-
-    for song_artist in song_artists_table_data {
-        insert_song_artist(song_artist);
-    }
-
-    for album_artist in album_artists_table_data {
-        insert_album_artist(album_artist);
-    }
+/// Insert the rules of a smart playlist into the database. Make sure the playlist_id is real.
+/// Use smart_playlists::set_smart_playlist_rules if you want to overwrite existing rules.
+pub fn insert_smart_playlist(smart_playlist: SMART_PLAYLISTS_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path());
 
-    for composer in composers_table_data {
-        insert_composer(composer);
-    }
+    let sql_query = generate_insertion_sql(&SMART_PLAYLISTS);
 
-    for genre in genres_table_data {
-        insert_genre(genre);
+    match conn {
+        Ok(conn) => {
+            conn.execute(
+                &sql_query,
+                params![
+                    smart_playlist.playlist_id,
+                    smart_playlist.rules,
+                    smart_playlist.updated_dt,
+                ],
+            )
+            .unwrap();
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
     }
 }
 
-// --------------------------------------------------------------------------------------------------------------------------------------------
-// --------------------------------------------------------------------------------------------------------------------------------------------
-//                                                           RETRIEVE DATA MASS
-// --------------------------------------------------------------------------------------------------------------------------------------------
-// --------------------------------------------------------------------------------------------------------------------------------------------
-
-// mass retrieval
-
-/// Get all the table names in the database.
-pub fn get_all_table_names() -> Vec<String> {
-    let mut table_names: Vec<String> = Vec::new();
-    table_names.push(SONGS.name.to_string());
-    table_names.push(PLAYS.name.to_string());
-    table_names.push(PLAYLISTS.name.to_string());
-    table_names.push(PLAYLIST_SONGS.name.to_string());
-    table_names.push(SONG_ARTISTS.name.to_string());
-    table_names.push(ALBUM_ARTISTS.name.to_string());
-    table_names.push(COMPOSERS.name.to_string());
-    table_names.push(GENRES.name.to_string());
-    table_names.push(SONGPATHS.name.to_string());
-    table_names.push(ARTISTS.name.to_string());
-    table_names.push(ALBUMS.name.to_string());
-    table_names
-}
-
-/// Get all the songs in the database.
-pub fn get_all_songs() -> Vec<SONG_TABLE_DATA> {
+/// Insert a song's place in a duplicate group into the database.
+/// Use duplicates::save_duplicate_groups to write whole groups, it replaces what was there.
+pub fn insert_song_version(song_version: SONG_VERSIONS_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut songs: Vec<SONG_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&SONGS);
+    let sql_query = generate_insertion_sql(&SONG_VERSIONS);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let song_iter = stmt
-                .query_map([], |row| {
-                    Ok(SONG_TABLE_DATA {
-                        song_id: row.get(0).unwrap(),
-                        main_artist: row.get(1).unwrap(),
-                        filesize_bytes: row.get(2).unwrap(),
-                        padding_bytes: row.get(3).unwrap(),
-                        album_artwork_bit_depth: row.get(4).unwrap(),
-                        album_artwork_colors: row.get(5).unwrap(),
-                        album_artwork_height: row.get(6).unwrap(),
-                        album_artwork_width: row.get(7).unwrap(),
-                        bit_depth: row.get(8).unwrap(),
-                        bitrate: row.get(9).unwrap(),
-                        channels: row.get(10).unwrap(),
-                        duration: row.get(11).unwrap(),
-                        sample_rate: row.get(12).unwrap(),
-                        album: row.get(13).unwrap(),
-                        barcode: row.get(14).unwrap(),
-                        date_created: row.get(15).unwrap(),
-                        disc_number: row.get(16).unwrap(),
-                        disc_total: row.get(17).unwrap(),
-                        isrc: row.get(18).unwrap(),
-                        itunesadvisory: row.get(19).unwrap(),
-                        length: row.get(20).unwrap(),
-                        publisher: row.get(21).unwrap(),
-                        rating: row.get(22).unwrap(),
-                        title: row.get(23).unwrap(),
-                        track_number: row.get(24).unwrap(),
-                        track_total: row.get(25).unwrap(),
-                        source: row.get(26).unwrap(),
-                        filetype: row.get(27).unwrap(),
-                    })
-                })
-                .unwrap();
-            for song in song_iter {
-                songs.push(song.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    song_version.song_id,
+                    song_version.group_id,
+                    song_version.quality_rank,
+                    song_version.preferred,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    songs
 }
 
-/// Get all the plays in the database.
-pub fn get_all_plays() -> Vec<PLAY_TABLE_DATA> {
+/// Insert a FLAC verification result into the database.
+/// Use file_health::verify_song to run a verification, it replaces the old result.
+pub fn insert_flac_verification(flac_verification: FLAC_VERIFICATIONS_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut plays: Vec<PLAY_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&PLAYS);
+    let sql_query = generate_insertion_sql(&FLAC_VERIFICATIONS);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let play_iter = stmt
-                .query_map([], |row| {
-                    Ok(PLAY_TABLE_DATA {
-                        play_id: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        song_title: row.get(2).unwrap(),
-                        main_artist: row.get(3).unwrap(),
-                        filesize_bytes: row.get(4).unwrap(),
-                        start_dt: row.get(5).unwrap(),
-                        end_dt: row.get(6).unwrap(),
-                    })
-                })
-                .unwrap();
-            for play in play_iter {
-                plays.push(play.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    flac_verification.song_id,
+                    flac_verification.song_path,
+                    flac_verification.status,
+                    flac_verification.expected_md5,
+                    flac_verification.md5_ok,
+                    flac_verification.decode_errors,
+                    flac_verification.expected_samples,
+                    flac_verification.decoded_samples,
+                    flac_verification.verified_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    plays
 }
 
-/// Get all the playlists in the database.
-pub fn get_all_playlists() -> Vec<PLAYLIST_TABLE_DATA> {
+/// Insert a file health result into the database.
+/// Use file_health::deep_check_song to run a check, it replaces the old result.
+pub fn insert_file_health(file_health: FILE_HEALTH_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut playlists: Vec<PLAYLIST_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&PLAYLISTS);
+    let sql_query = generate_insertion_sql(&FILE_HEALTH);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let playlist_iter = stmt
-                .query_map([], |row| {
-                    Ok(PLAYLIST_TABLE_DATA {
-                        playlist_id: row.get(0).unwrap(),
-                        playlist_name: row.get(1).unwrap(),
-                        playlist_desc: row.get(2).unwrap(),
-                        created_dt: row.get(3).unwrap(),
-                    })
-                })
-                .unwrap();
-            for playlist in playlist_iter {
-                playlists.push(playlist.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    file_health.song_id,
+                    file_health.song_path,
+                    file_health.filetype,
+                    file_health.status,
+                    file_health.decode_errors,
+                    file_health.lost_sync,
+                    file_health.truncated_final_frame,
+                    file_health.declared_duration,
+                    file_health.decoded_duration,
+                    file_health.checked_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    playlists
 }
 
-/// Get all the playlist songs in the database.
-pub fn get_all_playlist_songs() -> Vec<PLAYLIST_SONGS_TABLE_DATA> {
+/// Insert a loudness result into the database.
+/// Use loudness::analyze_library to measure songs, it replaces the old results.
+pub fn insert_loudness(loudness: LOUDNESS_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut playlist_songs: Vec<PLAYLIST_SONGS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&PLAYLIST_SONGS);
+    let sql_query = generate_insertion_sql(&LOUDNESS);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let playlist_song_iter = stmt
-                .query_map([], |row| {
-                    Ok(PLAYLIST_SONGS_TABLE_DATA {
-                        playlist_id: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        added_dt: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for playlist_song in playlist_song_iter {
-                playlist_songs.push(playlist_song.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    loudness.song_id,
+                    loudness.integrated_lufs,
+                    loudness.loudness_range,
+                    loudness.true_peak,
+                    loudness.track_gain,
+                    loudness.track_peak,
+                    loudness.album_gain,
+                    loudness.album_peak,
+                    loudness.source,
+                    loudness.analyzed_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    playlist_songs
 }
 
-/// Get all the song artists in the database.
-pub fn get_all_song_artists() -> Vec<SONG_ARTISTS_TABLE_DATA> {
+/// Insert a tempo and key result into the database.
+/// Use music_analysis::analyze_song to run an analysis, it replaces the old result.
+pub fn insert_music_analysis(music_analysis: MUSIC_ANALYSIS_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut song_artists: Vec<SONG_ARTISTS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&SONG_ARTISTS);
+    let sql_query = generate_insertion_sql(&MUSIC_ANALYSIS);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let song_artist_iter = stmt
-                .query_map([], |row| {
-                    Ok(SONG_ARTISTS_TABLE_DATA {
-                        artist_name: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        dt_added: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for song_artist in song_artist_iter {
-                song_artists.push(song_artist.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    music_analysis.song_id,
+                    music_analysis.bpm,
+                    music_analysis.bpm_confidence,
+                    music_analysis.musical_key,
+                    music_analysis.camelot,
+                    music_analysis.key_confidence,
+                    music_analysis.tag_bpm,
+                    music_analysis.tag_key,
+                    music_analysis.analyzed_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    song_artists
 }
 
-/// Get all the album artists in the database.
-pub fn get_all_album_artists() -> Vec<ALBUM_ARTISTS_TABLE_DATA> {
+/// Insert gapless info into the database.
+/// Use gapless::analyze_song to work it out, it replaces the old row.
+pub fn insert_gapless(gapless: GAPLESS_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut album_artists: Vec<ALBUM_ARTISTS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&ALBUM_ARTISTS);
+    let sql_query = generate_insertion_sql(&GAPLESS);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let album_artist_iter = stmt
-                .query_map([], |row| {
-                    Ok(ALBUM_ARTISTS_TABLE_DATA {
-                        artist_name: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        dt_added: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for album_artist in album_artist_iter {
-                album_artists.push(album_artist.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    gapless.song_id,
+                    gapless.encoder_delay,
+                    gapless.encoder_padding,
+                    gapless.total_samples,
+                    gapless.sample_rate,
+                    gapless.leading_silence,
+                    gapless.trailing_silence,
+                    gapless.source,
+                    gapless.analyzed_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    album_artists
 }
 
-/// Get all the composers in the database.
-pub fn get_all_composers() -> Vec<COMPOSERS_TABLE_DATA> {
+/// Insert an image of the artwork store into the database.
+/// Use artwork_store::store_artwork, it writes the image and its thumbnails too.
+pub fn insert_artwork(artwork: ARTWORK_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut composers: Vec<COMPOSERS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&COMPOSERS);
+    let sql_query = generate_insertion_sql(&ARTWORK);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let composer_iter = stmt
-                .query_map([], |row| {
-                    Ok(COMPOSERS_TABLE_DATA {
-                        composer_name: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        dt_added: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for composer in composer_iter {
-                composers.push(composer.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    artwork.artwork_id,
+                    artwork.mime,
+                    artwork.width,
+                    artwork.height,
+                    artwork.filesize_bytes,
+                    artwork.palette,
+                    artwork.location,
+                    artwork.added_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    composers
 }
 
-/// Get all the genres in the database.
-pub fn get_all_genres() -> Vec<GENRES_TABLE_DATA> {
+/// Insert a link between a song and an image of the artwork store into the database.
+/// Use artwork_store::cache_song_artwork, it replaces all the links of the song.
+pub fn insert_song_artwork(song_artwork: SONG_ARTWORK_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut genres: Vec<GENRES_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&GENRES);
+    let sql_query = generate_insertion_sql(&SONG_ARTWORK);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let genre_iter = stmt
-                .query_map([], |row| {
-                    Ok(GENRES_TABLE_DATA {
-                        genre_name: row.get(0).unwrap(),
-                        song_id: row.get(1).unwrap(),
-                        dt_added: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for genre in genre_iter {
-                genres.push(genre.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    song_artwork.song_id,
+                    song_artwork.artwork_id,
+                    song_artwork.picture_type,
+                    song_artwork.source,
+                    song_artwork.position,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    genres
 }
 
-/// Get all the song paths in the database.
-pub fn get_all_songpaths() -> Vec<SONGPATHS_TABLE_DATA> {
+/// Insert the cover of an album into the database.
+/// Use artwork_store::link_album_artwork, it updates albums.album_art_location too.
+pub fn insert_album_artwork(album_artwork: ALBUM_ARTWORK_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut songpaths: Vec<SONGPATHS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&SONGPATHS);
+    let sql_query = generate_insertion_sql(&ALBUM_ARTWORK);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let songpath_iter = stmt
-                .query_map([], |row| {
-                    Ok(SONGPATHS_TABLE_DATA {
-                        song_id: row.get(0).unwrap(),
-                        song_path: row.get(1).unwrap(),
-                    })
-                })
-                .unwrap();
-            for songpath in songpath_iter {
-                songpaths.push(songpath.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    album_artwork.album_id,
+                    album_artwork.artwork_id,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    songpaths
 }
 
-/// Get all the artists in the database.
-pub fn get_all_artists() -> Vec<ARTISTS_TABLE_DATA> {
+/// Insert a batch tag edit into the database.
+/// Use batch_edit::apply_batch, it journals the changes of every song too.
+pub fn insert_tag_edit_batch(tag_edit_batch: TAG_EDIT_BATCHES_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut artists: Vec<ARTISTS_TABLE_DATA> = Vec::new();
 
-    // make sql query
-
-    let sql_query = generate_select_all_sql(&ARTISTS);
+    let sql_query = generate_insertion_sql(&TAG_EDIT_BATCHES);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let artist_iter = stmt
-                .query_map([], |row| {
-                    Ok(ARTISTS_TABLE_DATA {
-                        artist_name: row.get(0).unwrap(),
-                        artist_bio: row.get(1).unwrap(),
-                        artist_photo_location: row.get(2).unwrap(),
-                    })
-                })
-                .unwrap();
-            for artist in artist_iter {
-                artists.push(artist.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    tag_edit_batch.batch_id,
+                    tag_edit_batch.description,
+                    tag_edit_batch.song_count,
+                    tag_edit_batch.status,
+                    tag_edit_batch.created_dt,
+                    tag_edit_batch.reverted_dt,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    artists
 }
 
-/// Get all the albums in the database.
-pub fn get_all_albums() -> Vec<ALBUMS_TABLE_DATA> {
+/// Insert the change to one field of one song by a batch tag edit into the database.
+/// Use batch_edit::apply_batch.
+pub fn insert_tag_edit_journal(tag_edit_journal: TAG_EDIT_JOURNAL_TABLE_DATA) {
     let conn = Connection::open(get_database_file_path());
-    let mut albums: Vec<ALBUMS_TABLE_DATA> = Vec::new();
-
-    // make sql query
 
-    let sql_query = generate_select_all_sql(&ALBUMS);
+    let sql_query = generate_insertion_sql(&TAG_EDIT_JOURNAL);
 
     match conn {
         Ok(conn) => {
-            let mut stmt = conn.prepare(&sql_query).unwrap();
-            let album_iter = stmt
-                .query_map([], |row| {
-                    Ok(ALBUMS_TABLE_DATA {
-                        album_id: row.get(0).unwrap(),
-                        album_name: row.get(1).unwrap(),
-                        artist_name: row.get(2).unwrap(),
-                        album_description: row.get(3).unwrap(),
-                        album_art_location: row.get(4).unwrap(),
-                        album_release_date: row.get(5).unwrap(),
-                    })
-                })
-                .unwrap();
-            for album in album_iter {
-                albums.push(album.unwrap());
-            }
+            conn.execute(
+                &sql_query,
+                params![
+                    tag_edit_journal.batch_id,
+                    tag_edit_journal.song_id,
+                    tag_edit_journal.song_path,
+                    tag_edit_journal.field,
+                    tag_edit_journal.old_values,
+                    tag_edit_journal.new_values,
+                ],
+            )
+            .unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
-    albums
 }
 
-// --------------------------------------------------------------------------------------------------------------------------------------------
-// --------------------------------------------------------------------------------------------------------------------------------------------
-//                                                           RETRIEVE DATA SINGLE (TYPICALLY MORE USEFUL)
-// --------------------------------------------------------------------------------------------------------------------------------------------
-// --------------------------------------------------------------------------------------------------------------------------------------------
+/// Important function: Pass in an object with trait AudioFile and it will insert the important information in the following tables:
+/// songs, song_artists, album_artists, composers, genres
+/// ```
+/// let filepath = "C:\\Users\\John\\Music\\John Doe\\John Doe - John Doe.mp3";
+/// let audioFile = AudioFileMP3::default(); // you have to decide which audioFile class to use by default! Use like an if statement or see populate_database() down below
+/// audioFile.load_file(filepath).unwrap();
+///
+/// insert_song_information(audioFile);
+/// ```
+pub fn insert_song_information<T: AudioFile>(song: T) {
+    // lets get the important structs
+    let song_table_data = song.get_song_table_data();
+    let song_artists_table_data = song.get_song_artists_table_data();
+    let album_artists_table_data = song.get_album_artists_table_data();
+    let composers_table_data = song.get_composers_table_data();
+    let genres_table_data = song.get_genres_table_data();
 
-/// Get a single song from the database.
-pub fn get_song_by_id(song_id: String) -> SONG_TABLE_DATA {
-    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
-    let mut song: SONG_TABLE_DATA = SONG_TABLE_DATA::default();
+    // print composers
+    // insert into the appropriate tables
+    insert_song(song_table_data);
 
-    // make sql query
+    for song_artist in song_artists_table_data {
+        insert_song_artist(song_artist);
+    }
 
-    // let sql_query =  "SELECT * FROM SONGS WHERE song_id = ?1".to_string();
-    let sql_query = format!("SELECT * FROM SONGS WHERE song_id = '{}'", song_id).to_string();
+    for album_artist in album_artists_table_data {
+        insert_album_artist(album_artist);
+    }
 
+    for composer in composers_table_data {
+        insert_composer(composer);
+    }
 
-    let mut receiver = conn
-        .prepare(&sql_query)
-        .expect("Could not prepare statement");
+    for genre in genres_table_data {
+        insert_genre(genre);
+    }
+}
 
-    let rows = receiver
-        .query_map([], |row| {
-            Ok(SONG_TABLE_DATA {
-                song_id: row.get(0).unwrap(),
-                main_artist: row.get(1).unwrap(),
-                filesize_bytes: row.get(2).unwrap(),
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           RETRIEVE DATA MASS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// mass retrieval
+
+/// Get all the table names in the database.
+pub fn get_all_table_names() -> Vec<String> {
+    let mut table_names: Vec<String> = Vec::new();
+    table_names.push(SONGS.name.to_string());
+    table_names.push(PLAYS.name.to_string());
+    table_names.push(PLAYLISTS.name.to_string());
+    table_names.push(PLAYLIST_SONGS.name.to_string());
+    table_names.push(SONG_ARTISTS.name.to_string());
+    table_names.push(ALBUM_ARTISTS.name.to_string());
+    table_names.push(COMPOSERS.name.to_string());
+    table_names.push(GENRES.name.to_string());
+    table_names.push(SONGPATHS.name.to_string());
+    table_names.push(ARTISTS.name.to_string());
+    table_names.push(ALBUMS.name.to_string());
+    table_names.push(SMART_PLAYLISTS.name.to_string());
+    table_names.push(SONG_VERSIONS.name.to_string());
+    table_names.push(FLAC_VERIFICATIONS.name.to_string());
+    table_names.push(FILE_HEALTH.name.to_string());
+    table_names.push(LOUDNESS.name.to_string());
+    table_names.push(MUSIC_ANALYSIS.name.to_string());
+    table_names.push(GAPLESS.name.to_string());
+    table_names.push(ARTWORK.name.to_string());
+    table_names.push(SONG_ARTWORK.name.to_string());
+    table_names.push(ALBUM_ARTWORK.name.to_string());
+    table_names.push(TAG_EDIT_BATCHES.name.to_string());
+    table_names.push(TAG_EDIT_JOURNAL.name.to_string());
+    table_names
+}
+
+/// Get all the songs in the database.
+pub fn get_all_songs() -> Vec<SONG_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut songs: Vec<SONG_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SONGS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let song_iter = stmt
+                .query_map([], |row| {
+                    Ok(SONG_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        main_artist: row.get(1).unwrap(),
+                        filesize_bytes: row.get(2).unwrap(),
+                        padding_bytes: row.get(3).unwrap(),
+                        album_artwork_bit_depth: row.get(4).unwrap(),
+                        album_artwork_colors: row.get(5).unwrap(),
+                        album_artwork_height: row.get(6).unwrap(),
+                        album_artwork_width: row.get(7).unwrap(),
+                        bit_depth: row.get(8).unwrap(),
+                        bitrate: row.get(9).unwrap(),
+                        channels: row.get(10).unwrap(),
+                        duration: row.get(11).unwrap(),
+                        sample_rate: row.get(12).unwrap(),
+                        album: row.get(13).unwrap(),
+                        barcode: row.get(14).unwrap(),
+                        date_created: row.get(15).unwrap(),
+                        disc_number: row.get(16).unwrap(),
+                        disc_total: row.get(17).unwrap(),
+                        isrc: row.get(18).unwrap(),
+                        itunesadvisory: row.get(19).unwrap(),
+                        length: row.get(20).unwrap(),
+                        publisher: row.get(21).unwrap(),
+                        rating: row.get(22).unwrap(),
+                        title: row.get(23).unwrap(),
+                        track_number: row.get(24).unwrap(),
+                        track_total: row.get(25).unwrap(),
+                        source: row.get(26).unwrap(),
+                        filetype: row.get(27).unwrap(),
+                        audio_hash: row.get(28).unwrap(),
+                        bitrate_mode: row.get(29).unwrap(),
+                        total_samples: row.get(30).unwrap(),
+                        recording_id: row.get(31).unwrap(),
+                        dt_added: row.get(32).unwrap(),
+                    })
+                })
+                .unwrap();
+            for song in song_iter {
+                songs.push(song.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    songs
+}
+
+/// Turns a row of `SELECT * FROM plays` into a PLAY_TABLE_DATA. Plays from before the session columns existed come back with -1s.
+pub fn play_from_row(row: &rusqlite::Row) -> rusqlite::Result<PLAY_TABLE_DATA> {
+    Ok(PLAY_TABLE_DATA {
+        play_id: row.get(0)?,
+        song_id: row.get(1)?,
+        song_title: row.get(2)?,
+        main_artist: row.get(3)?,
+        filesize_bytes: row.get(4)?,
+        start_dt: row.get(5)?,
+        end_dt: row.get(6)?,
+        position_seconds: row.get::<_, Option<f64>>(7)?.unwrap_or(-1.0),
+        completion: row.get::<_, Option<f64>>(8)?.unwrap_or(-1.0),
+        skipped: row.get::<_, Option<i64>>(9)?.unwrap_or(-1),
+        seek_count: row.get::<_, Option<i64>>(10)?.unwrap_or(-1),
+    })
+}
+
+/// Get all the plays in the database.
+pub fn get_all_plays() -> Vec<PLAY_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut plays: Vec<PLAY_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&PLAYS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let play_iter = stmt
+                .query_map([], play_from_row)
+                .unwrap();
+            for play in play_iter {
+                plays.push(play.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    plays
+}
+
+/// Get all the playlists in the database.
+pub fn get_all_playlists() -> Vec<PLAYLIST_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut playlists: Vec<PLAYLIST_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&PLAYLISTS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let playlist_iter = stmt
+                .query_map([], |row| {
+                    Ok(PLAYLIST_TABLE_DATA {
+                        playlist_id: row.get(0).unwrap(),
+                        playlist_name: row.get(1).unwrap(),
+                        playlist_desc: row.get(2).unwrap(),
+                        created_dt: row.get(3).unwrap(),
+                    })
+                })
+                .unwrap();
+            for playlist in playlist_iter {
+                playlists.push(playlist.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    playlists
+}
+
+/// Get all the playlist songs in the database.
+pub fn get_all_playlist_songs() -> Vec<PLAYLIST_SONGS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut playlist_songs: Vec<PLAYLIST_SONGS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&PLAYLIST_SONGS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let playlist_song_iter = stmt
+                .query_map([], |row| {
+                    Ok(PLAYLIST_SONGS_TABLE_DATA {
+                        playlist_id: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        added_dt: row.get(2).unwrap(),
+                        position: row.get(3).unwrap(),
+                    })
+                })
+                .unwrap();
+            for playlist_song in playlist_song_iter {
+                playlist_songs.push(playlist_song.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    playlist_songs
+}
+
+/// Get all the song artists in the database.
+pub fn get_all_song_artists() -> Vec<SONG_ARTISTS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut song_artists: Vec<SONG_ARTISTS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SONG_ARTISTS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let song_artist_iter = stmt
+                .query_map([], |row| {
+                    Ok(SONG_ARTISTS_TABLE_DATA {
+                        artist_name: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        dt_added: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for song_artist in song_artist_iter {
+                song_artists.push(song_artist.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    song_artists
+}
+
+/// Get all the album artists in the database.
+pub fn get_all_album_artists() -> Vec<ALBUM_ARTISTS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut album_artists: Vec<ALBUM_ARTISTS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&ALBUM_ARTISTS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let album_artist_iter = stmt
+                .query_map([], |row| {
+                    Ok(ALBUM_ARTISTS_TABLE_DATA {
+                        artist_name: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        dt_added: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for album_artist in album_artist_iter {
+                album_artists.push(album_artist.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    album_artists
+}
+
+/// Get all the composers in the database.
+pub fn get_all_composers() -> Vec<COMPOSERS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut composers: Vec<COMPOSERS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&COMPOSERS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let composer_iter = stmt
+                .query_map([], |row| {
+                    Ok(COMPOSERS_TABLE_DATA {
+                        composer_name: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        dt_added: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for composer in composer_iter {
+                composers.push(composer.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    composers
+}
+
+/// Get all the genres in the database.
+pub fn get_all_genres() -> Vec<GENRES_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut genres: Vec<GENRES_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&GENRES);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let genre_iter = stmt
+                .query_map([], |row| {
+                    Ok(GENRES_TABLE_DATA {
+                        genre_name: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        dt_added: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for genre in genre_iter {
+                genres.push(genre.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    genres
+}
+
+/// Get all the song paths in the database.
+pub fn get_all_songpaths() -> Vec<SONGPATHS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut songpaths: Vec<SONGPATHS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SONGPATHS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let songpath_iter = stmt
+                .query_map([], |row| {
+                    Ok(SONGPATHS_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        song_path: row.get(1).unwrap(),
+                    })
+                })
+                .unwrap();
+            for songpath in songpath_iter {
+                songpaths.push(songpath.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    songpaths
+}
+
+/// Get all the artists in the database.
+pub fn get_all_artists() -> Vec<ARTISTS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut artists: Vec<ARTISTS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&ARTISTS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let artist_iter = stmt
+                .query_map([], |row| {
+                    Ok(ARTISTS_TABLE_DATA {
+                        artist_name: row.get(0).unwrap(),
+                        artist_bio: row.get(1).unwrap(),
+                        artist_photo_location: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for artist in artist_iter {
+                artists.push(artist.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    artists
+}
+
+/// Get all the albums in the database.
+pub fn get_all_albums() -> Vec<ALBUMS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut albums: Vec<ALBUMS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&ALBUMS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let album_iter = stmt
+                .query_map([], |row| {
+                    Ok(ALBUMS_TABLE_DATA {
+                        album_id: row.get(0).unwrap(),
+                        album_name: row.get(1).unwrap(),
+                        artist_name: row.get(2).unwrap(),
+                        album_description: row.get(3).unwrap(),
+                        album_art_location: row.get(4).unwrap(),
+                        album_release_date: row.get(5).unwrap(),
+                    })
+                })
+                .unwrap();
+            for album in album_iter {
+                albums.push(album.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    albums
+}
+
+/// Get all the smart playlist rules in the database.
+pub fn get_all_smart_playlists() -> Vec<SMART_PLAYLISTS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut smart_playlists: Vec<SMART_PLAYLISTS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SMART_PLAYLISTS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let smart_playlist_iter = stmt
+                .query_map([], |row| {
+                    Ok(SMART_PLAYLISTS_TABLE_DATA {
+                        playlist_id: row.get(0).unwrap(),
+                        rules: row.get(1).unwrap(),
+                        updated_dt: row.get(2).unwrap(),
+                    })
+                })
+                .unwrap();
+            for smart_playlist in smart_playlist_iter {
+                smart_playlists.push(smart_playlist.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    smart_playlists
+}
+
+/// Get all the song versions (duplicate groups) in the database.
+pub fn get_all_song_versions() -> Vec<SONG_VERSIONS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut song_versions: Vec<SONG_VERSIONS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SONG_VERSIONS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let song_version_iter = stmt
+                .query_map([], |row| {
+                    Ok(SONG_VERSIONS_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        group_id: row.get(1).unwrap(),
+                        quality_rank: row.get(2).unwrap(),
+                        preferred: row.get(3).unwrap(),
+                    })
+                })
+                .unwrap();
+            for song_version in song_version_iter {
+                song_versions.push(song_version.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    song_versions
+}
+
+/// Get all the FLAC verification results in the database.
+pub fn get_all_flac_verifications() -> Vec<FLAC_VERIFICATIONS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut flac_verifications: Vec<FLAC_VERIFICATIONS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&FLAC_VERIFICATIONS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let flac_verification_iter = stmt
+                .query_map([], |row| {
+                    Ok(FLAC_VERIFICATIONS_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        song_path: row.get(1).unwrap(),
+                        status: row.get(2).unwrap(),
+                        expected_md5: row.get(3).unwrap(),
+                        md5_ok: row.get(4).unwrap(),
+                        decode_errors: row.get(5).unwrap(),
+                        expected_samples: row.get(6).unwrap(),
+                        decoded_samples: row.get(7).unwrap(),
+                        verified_dt: row.get(8).unwrap(),
+                    })
+                })
+                .unwrap();
+            for flac_verification in flac_verification_iter {
+                flac_verifications.push(flac_verification.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    flac_verifications
+}
+
+/// Get all the file health results in the database.
+pub fn get_all_file_health() -> Vec<FILE_HEALTH_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut file_health: Vec<FILE_HEALTH_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&FILE_HEALTH);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let file_health_iter = stmt
+                .query_map([], |row| {
+                    Ok(FILE_HEALTH_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        song_path: row.get(1).unwrap(),
+                        filetype: row.get(2).unwrap(),
+                        status: row.get(3).unwrap(),
+                        decode_errors: row.get(4).unwrap(),
+                        lost_sync: row.get(5).unwrap(),
+                        truncated_final_frame: row.get(6).unwrap(),
+                        declared_duration: row.get(7).unwrap(),
+                        decoded_duration: row.get(8).unwrap(),
+                        checked_dt: row.get(9).unwrap(),
+                    })
+                })
+                .unwrap();
+            for health in file_health_iter {
+                file_health.push(health.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    file_health
+}
+
+/// Get all the loudness results in the database.
+pub fn get_all_loudness() -> Vec<LOUDNESS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut loudness: Vec<LOUDNESS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&LOUDNESS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let loudness_iter = stmt
+                .query_map([], loudness_from_row)
+                .unwrap();
+            for result in loudness_iter {
+                loudness.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    loudness
+}
+
+/// Turns a row from `SELECT * FROM loudness` into a LOUDNESS_TABLE_DATA.
+pub fn loudness_from_row(row: &rusqlite::Row) -> rusqlite::Result<LOUDNESS_TABLE_DATA> {
+    Ok(LOUDNESS_TABLE_DATA {
+        song_id: row.get(0)?,
+        integrated_lufs: row.get(1)?,
+        loudness_range: row.get(2)?,
+        true_peak: row.get(3)?,
+        track_gain: row.get(4)?,
+        track_peak: row.get(5)?,
+        album_gain: row.get(6)?,
+        album_peak: row.get(7)?,
+        source: row.get(8)?,
+        analyzed_dt: row.get(9)?,
+    })
+}
+
+/// Get all the tempo and key results in the database.
+pub fn get_all_music_analysis() -> Vec<MUSIC_ANALYSIS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut music_analysis: Vec<MUSIC_ANALYSIS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&MUSIC_ANALYSIS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let music_analysis_iter = stmt
+                .query_map([], |row| {
+                    Ok(MUSIC_ANALYSIS_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        bpm: row.get(1).unwrap(),
+                        bpm_confidence: row.get(2).unwrap(),
+                        musical_key: row.get(3).unwrap(),
+                        camelot: row.get(4).unwrap(),
+                        key_confidence: row.get(5).unwrap(),
+                        tag_bpm: row.get(6).unwrap(),
+                        tag_key: row.get(7).unwrap(),
+                        analyzed_dt: row.get(8).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in music_analysis_iter {
+                music_analysis.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    music_analysis
+}
+
+/// Get all the gapless playback info in the database.
+pub fn get_all_gapless() -> Vec<GAPLESS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut gapless: Vec<GAPLESS_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&GAPLESS);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let gapless_iter = stmt
+                .query_map([], |row| {
+                    Ok(GAPLESS_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        encoder_delay: row.get(1).unwrap(),
+                        encoder_padding: row.get(2).unwrap(),
+                        total_samples: row.get(3).unwrap(),
+                        sample_rate: row.get(4).unwrap(),
+                        leading_silence: row.get(5).unwrap(),
+                        trailing_silence: row.get(6).unwrap(),
+                        source: row.get(7).unwrap(),
+                        analyzed_dt: row.get(8).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in gapless_iter {
+                gapless.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    gapless
+}
+
+/// Get all the artwork in the artwork store in the database.
+pub fn get_all_artwork() -> Vec<ARTWORK_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut artwork: Vec<ARTWORK_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&ARTWORK);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let artwork_iter = stmt
+                .query_map([], |row| {
+                    Ok(ARTWORK_TABLE_DATA {
+                        artwork_id: row.get(0).unwrap(),
+                        mime: row.get(1).unwrap(),
+                        width: row.get(2).unwrap(),
+                        height: row.get(3).unwrap(),
+                        filesize_bytes: row.get(4).unwrap(),
+                        palette: row.get(5).unwrap(),
+                        location: row.get(6).unwrap(),
+                        added_dt: row.get(7).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in artwork_iter {
+                artwork.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    artwork
+}
+
+/// Get all the links between songs and their artwork in the database.
+pub fn get_all_song_artwork() -> Vec<SONG_ARTWORK_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut song_artwork: Vec<SONG_ARTWORK_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&SONG_ARTWORK);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let song_artwork_iter = stmt
+                .query_map([], |row| {
+                    Ok(SONG_ARTWORK_TABLE_DATA {
+                        song_id: row.get(0).unwrap(),
+                        artwork_id: row.get(1).unwrap(),
+                        picture_type: row.get(2).unwrap(),
+                        source: row.get(3).unwrap(),
+                        position: row.get(4).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in song_artwork_iter {
+                song_artwork.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    song_artwork
+}
+
+/// Get all the links between albums and their cover in the database.
+pub fn get_all_album_artwork() -> Vec<ALBUM_ARTWORK_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut album_artwork: Vec<ALBUM_ARTWORK_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&ALBUM_ARTWORK);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let album_artwork_iter = stmt
+                .query_map([], |row| {
+                    Ok(ALBUM_ARTWORK_TABLE_DATA {
+                        album_id: row.get(0).unwrap(),
+                        artwork_id: row.get(1).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in album_artwork_iter {
+                album_artwork.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    album_artwork
+}
+
+/// Get all the batch tag edits in the database.
+pub fn get_all_tag_edit_batches() -> Vec<TAG_EDIT_BATCHES_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut tag_edit_batches: Vec<TAG_EDIT_BATCHES_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&TAG_EDIT_BATCHES);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let tag_edit_batch_iter = stmt
+                .query_map([], |row| {
+                    Ok(TAG_EDIT_BATCHES_TABLE_DATA {
+                        batch_id: row.get(0).unwrap(),
+                        description: row.get(1).unwrap(),
+                        song_count: row.get(2).unwrap(),
+                        status: row.get(3).unwrap(),
+                        created_dt: row.get(4).unwrap(),
+                        reverted_dt: row.get(5).unwrap(),
+                    })
+                })
+                .unwrap();
+            for tag_edit_batch in tag_edit_batch_iter {
+                tag_edit_batches.push(tag_edit_batch.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    tag_edit_batches
+}
+
+/// Get all the fields changed by batch tag edits in the database.
+pub fn get_all_tag_edit_journal() -> Vec<TAG_EDIT_JOURNAL_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path());
+    let mut tag_edit_journal: Vec<TAG_EDIT_JOURNAL_TABLE_DATA> = Vec::new();
+
+    // make sql query
+
+    let sql_query = generate_select_all_sql(&TAG_EDIT_JOURNAL);
+
+    match conn {
+        Ok(conn) => {
+            let mut stmt = conn.prepare(&sql_query).unwrap();
+            let tag_edit_journal_iter = stmt
+                .query_map([], |row| {
+                    Ok(TAG_EDIT_JOURNAL_TABLE_DATA {
+                        batch_id: row.get(0).unwrap(),
+                        song_id: row.get(1).unwrap(),
+                        song_path: row.get(2).unwrap(),
+                        field: row.get(3).unwrap(),
+                        old_values: row.get(4).unwrap(),
+                        new_values: row.get(5).unwrap(),
+                    })
+                })
+                .unwrap();
+            for result in tag_edit_journal_iter {
+                tag_edit_journal.push(result.unwrap());
+            }
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+    tag_edit_journal
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           RETRIEVE DATA SINGLE (TYPICALLY MORE USEFUL)
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Get a single song from the database.
+pub fn get_song_by_id(song_id: String) -> SONG_TABLE_DATA {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut song: SONG_TABLE_DATA = SONG_TABLE_DATA::default();
+
+    // make sql query
+
+    // let sql_query =  "SELECT * FROM SONGS WHERE song_id = ?1".to_string();
+    let sql_query = format!("SELECT * FROM SONGS WHERE song_id = '{}'", song_id).to_string();
+
+
+    let mut receiver = conn
+        .prepare(&sql_query)
+        .expect("Could not prepare statement");
+
+    let rows = receiver
+        .query_map([], |row| {
+            Ok(SONG_TABLE_DATA {
+                song_id: row.get(0).unwrap(),
+                main_artist: row.get(1).unwrap(),
+                filesize_bytes: row.get(2).unwrap(),
                 padding_bytes: row.get(3).unwrap(),
                 album_artwork_bit_depth: row.get(4).unwrap(),
                 album_artwork_colors: row.get(5).unwrap(),
@@ -1090,6 +2172,11 @@ pub fn get_song_by_id(song_id: String) -> SONG_TABLE_DATA {
                 track_total: row.get(25).unwrap(),
                 source: row.get(26).unwrap(),
                 filetype: row.get(27).unwrap(),
+                audio_hash: row.get(28).unwrap(),
+                bitrate_mode: row.get(29).unwrap(),
+                total_samples: row.get(30).unwrap(),
+                recording_id: row.get(31).unwrap(),
+                dt_added: row.get(32).unwrap(),
             })
         })
         .unwrap();
@@ -1101,6 +2188,17 @@ pub fn get_song_by_id(song_id: String) -> SONG_TABLE_DATA {
     song
 }
 
+/// Get the songs whose audio is exactly the same as `audio_hash` (see audio_metadata::audio_stream_hash).
+/// A file that moved or got retagged still has the same audio_hash, so this is how to find the song it used to be.
+pub fn get_songs_by_audio_hash(audio_hash: &str) -> Vec<SONG_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut stmt = conn
+        .prepare("SELECT * FROM songs WHERE audio_hash = ?1 ORDER BY song_id")
+        .expect("Could not prepare statement");
+    let rows = stmt.query_map([audio_hash], song_from_row).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
 /// Get a single play from the database by its id.
 pub fn get_play_by_id(play_id: String) -> PLAY_TABLE_DATA {
     let conn = Connection::open(get_database_file_path()).expect("Could not open database");
@@ -1117,17 +2215,7 @@ pub fn get_play_by_id(play_id: String) -> PLAY_TABLE_DATA {
         .expect("Could not prepare statement");
 
     let rows = receiver
-        .query_map([], |row| {
-            Ok(PLAY_TABLE_DATA {
-                play_id: row.get(0).unwrap(),
-                song_id: row.get(1).unwrap(),
-                song_title: row.get(2).unwrap(),
-                main_artist: row.get(3).unwrap(),
-                filesize_bytes: row.get(4).unwrap(),
-                start_dt: row.get(5).unwrap(),
-                end_dt: row.get(6).unwrap(),
-            })
-        })
+        .query_map([], play_from_row)
         .unwrap();
 
     for row in rows {
@@ -1174,7 +2262,7 @@ pub fn get_playlist_by_id(playlist_id: String) -> PLAYLIST_TABLE_DATA {
     playlist
 }
 
-/// Get all the playlistt_songs from the database by its id. (NOT SONGS IN PLAYLIST, THAT'S BELOW METHOD)
+/// Get all the playlistt_songs from the database by its id, in playlist order. (NOT SONGS IN PLAYLIST, THAT'S BELOW METHOD)
 pub fn get_playlist_songs_by_id(playlist_id: String) -> Vec<PLAYLIST_SONGS_TABLE_DATA> {
     let conn = Connection::open(get_database_file_path()).expect("Could not open database");
     let mut playlist_songs: Vec<PLAYLIST_SONGS_TABLE_DATA> = Vec::new();
@@ -1182,23 +2270,20 @@ pub fn get_playlist_songs_by_id(playlist_id: String) -> Vec<PLAYLIST_SONGS_TABLE
     // make sql query
 
     // let sql_query =  "SELECT * FROM SONGS WHERE song_id = ?1".to_string();
-    let sql_query = format!(
-        "SELECT * FROM PLAYLIST_SONGS WHERE playlist_id = '{}'",
-        playlist_id
-    )
-    .to_string();
-
+    let sql_query =
+        "SELECT * FROM PLAYLIST_SONGS WHERE playlist_id = ?1 ORDER BY position, rowid".to_string();
 
     let mut receiver = conn
         .prepare(&sql_query)
         .expect("Could not prepare statement");
 
     let rows = receiver
-        .query_map([], |row| {
+        .query_map([playlist_id], |row| {
             Ok(PLAYLIST_SONGS_TABLE_DATA {
                 playlist_id: row.get(0).unwrap(),
                 song_id: row.get(1).unwrap(),
-                added_dt: row.get(5).unwrap(),
+                added_dt: row.get(2).unwrap(),
+                position: row.get(3).unwrap(),
             })
         })
         .unwrap();
@@ -1210,8 +2295,13 @@ pub fn get_playlist_songs_by_id(playlist_id: String) -> Vec<PLAYLIST_SONGS_TABLE
     playlist_songs
 }
 
-/// Get all the songs in a playlist by the playlist_id
+/// Get all the songs in a playlist by the playlist_id, in playlist order
+/// If the playlist is a smart playlist, the rules are evaluated right now instead of reading playlist_songs
 pub fn get_songs_in_playlist(playlist_id: String) -> Vec<SONG_TABLE_DATA> {
+    if let Some(rules) = get_smart_playlist_rules(&playlist_id) {
+        return evaluate_smart_playlist(&rules);
+    }
+
     let mut songs: Vec<SONG_TABLE_DATA> = Vec::new();
 
     // what we will do is: call get_playlist_songs_by_id and search for where playlist_id = playlist_id
@@ -1344,17 +2434,56 @@ pub fn get_composers_by_song_id(song_id: String) -> Vec<COMPOSERS_TABLE_DATA> {
         .query_map([], |row| {
             Ok(COMPOSERS_TABLE_DATA {
                 song_id: row.get(0).unwrap(),
-                composer_name: row.get(2).unwrap(),
-                dt_added: row.get(3).unwrap(),
+                composer_name: row.get(2).unwrap(),
+                dt_added: row.get(3).unwrap(),
+            })
+        })
+        .unwrap();
+
+    for row in rows {
+        composers.push(row.unwrap());
+    }
+
+    composers
+}
+
+/// Get all the songpaths with a given song_id (the same song can live in more than one place)
+pub fn get_songpaths_by_song_id(song_id: String) -> Vec<SONGPATHS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut songpaths: Vec<SONGPATHS_TABLE_DATA> = Vec::new();
+
+    let sql_query = "SELECT * FROM SONGPATHS WHERE song_id = ?1".to_string();
+
+    let mut receiver = conn
+        .prepare(&sql_query)
+        .expect("Could not prepare statement");
+
+    let rows = receiver
+        .query_map([song_id], |row| {
+            Ok(SONGPATHS_TABLE_DATA {
+                song_id: row.get(0).unwrap(),
+                song_path: row.get(1).unwrap(),
             })
         })
         .unwrap();
 
     for row in rows {
-        composers.push(row.unwrap());
+        songpaths.push(row.unwrap());
     }
 
-    composers
+    songpaths
+}
+
+/// Get the song_id of the song at the given path, None if the path isn't in the songpaths table
+pub fn get_song_id_by_path(song_path: String) -> Option<String> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+
+    conn.query_row(
+        "SELECT song_id FROM SONGPATHS WHERE song_path = ?1",
+        [song_path],
+        |row| row.get(0),
+    )
+    .ok()
 }
 
 // --------------------------------------------------------------------------------------------------------------------------------------------
@@ -1382,6 +2511,12 @@ pub fn get_all_filepaths_in_directory(dirpath: String) -> Vec<String> {
 /// This is going to be used to populate the database with some data.
 /// Given a directory, it will go through all the files in the directory, and call the insert_song_information function
 pub fn populate_database(dirpath: String) {
+    populate_database_with_options(dirpath, false)
+}
+
+/// populate_database, but with `deep_check` every file also gets every frame decoded to look for damage
+/// (see file_health::deep_check_file), and the results go in the file_health table. A lot slower.
+pub fn populate_database_with_options(dirpath: String, deep_check: bool) {
     // first, we need to get all the filepaths in the directory
     let filepaths = get_all_filepaths_in_directory(dirpath);
     // let songs = get_all_songs();
@@ -1417,12 +2552,38 @@ pub fn populate_database(dirpath: String) {
         match fileExt.as_str() {
             "mp3" => {
                 let mut afile = AudioFileMP3::default();
-                afile.load_file(filepath);
+                if let Err(e) = afile.load_file(filepath.clone()) {
+                    println!("Could not read {}: {}", filepath, e);
+                    bar.inc(1);
+                    continue;
+                }
+                if deep_check {
+                    let mut health = deep_check_file(&filepath);
+                    health.song_id = afile.get_song_table_data().song_id;
+                    save_file_health(&health);
+                }
+                insert_songpath(SONGPATHS_TABLE_DATA {
+                    song_id: afile.get_song_table_data().song_id,
+                    song_path: filepath,
+                });
                 insert_song_information(afile);
             }
             "flac" => {
                 let mut afile = AudioFileFLAC::default();
-                afile.load_file(filepath);
+                if let Err(e) = afile.load_file(filepath.clone()) {
+                    println!("Could not read {}: {}", filepath, e);
+                    bar.inc(1);
+                    continue;
+                }
+                if deep_check {
+                    let mut health = deep_check_file(&filepath);
+                    health.song_id = afile.get_song_table_data().song_id;
+                    save_file_health(&health);
+                }
+                insert_songpath(SONGPATHS_TABLE_DATA {
+                    song_id: afile.get_song_table_data().song_id,
+                    song_path: filepath,
+                });
                 insert_song_information(afile);
             }
             _ => {
@@ -1434,3 +2595,1019 @@ pub fn populate_database(dirpath: String) {
     }
     bar.finish();
 }
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           QUERY BUILDER
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// get_all_songs and get_song_by_id are fine for small stuff, but the frontend wants to ask things like
+// "FLACs over 24-bit by this album artist released after 2010, sorted by disc/track, page 3"
+// so here's a little typed query builder that compiles down to parameterized sql (no more format! with quotes)
+
+/// Turns a row from `SELECT songs.* FROM songs` into a SONG_TABLE_DATA.
+/// The column order has to match the SONGS model.
+pub fn song_from_row(row: &rusqlite::Row) -> rusqlite::Result<SONG_TABLE_DATA> {
+    Ok(SONG_TABLE_DATA {
+        song_id: row.get(0)?,
+        main_artist: row.get(1)?,
+        filesize_bytes: row.get(2)?,
+        padding_bytes: row.get(3)?,
+        album_artwork_bit_depth: row.get(4)?,
+        album_artwork_colors: row.get(5)?,
+        album_artwork_height: row.get(6)?,
+        album_artwork_width: row.get(7)?,
+        bit_depth: row.get(8)?,
+        bitrate: row.get(9)?,
+        channels: row.get(10)?,
+        duration: row.get(11)?,
+        sample_rate: row.get(12)?,
+        album: row.get(13)?,
+        barcode: row.get(14)?,
+        date_created: row.get(15)?,
+        disc_number: row.get(16)?,
+        disc_total: row.get(17)?,
+        isrc: row.get(18)?,
+        itunesadvisory: row.get(19)?,
+        length: row.get(20)?,
+        publisher: row.get(21)?,
+        rating: row.get(22)?,
+        title: row.get(23)?,
+        track_number: row.get(24)?,
+        track_total: row.get(25)?,
+        source: row.get(26)?,
+        filetype: row.get(27)?,
+        audio_hash: row.get(28)?,
+        bitrate_mode: row.get(29)?,
+        total_samples: row.get(30)?,
+        recording_id: row.get(31)?,
+        dt_added: row.get(32)?,
+    })
+}
+
+/// Every column of the songs table that can be filtered or sorted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SongColumn {
+    SongId,
+    MainArtist,
+    FilesizeBytes,
+    PaddingBytes,
+    AlbumArtworkBitDepth,
+    AlbumArtworkColors,
+    AlbumArtworkHeight,
+    AlbumArtworkWidth,
+    BitDepth,
+    Bitrate,
+    Channels,
+    Duration,
+    SampleRate,
+    Album,
+    Barcode,
+    DateCreated,
+    DiscNumber,
+    DiscTotal,
+    Isrc,
+    Itunesadvisory,
+    Length,
+    Publisher,
+    Rating,
+    Title,
+    TrackNumber,
+    TrackTotal,
+    Source,
+    Filetype,
+    AudioHash,
+    BitrateMode,
+    TotalSamples,
+    RecordingId,
+    DtAdded,
+}
+
+impl SongColumn {
+    /// The name of the column in the songs table
+    pub fn name(&self) -> &'static str {
+        match self {
+            SongColumn::SongId => "song_id",
+            SongColumn::MainArtist => "main_artist",
+            SongColumn::FilesizeBytes => "filesize_bytes",
+            SongColumn::PaddingBytes => "padding_bytes",
+            SongColumn::AlbumArtworkBitDepth => "album_artwork_bit_depth",
+            SongColumn::AlbumArtworkColors => "album_artwork_colors",
+            SongColumn::AlbumArtworkHeight => "album_artwork_height",
+            SongColumn::AlbumArtworkWidth => "album_artwork_width",
+            SongColumn::BitDepth => "bit_depth",
+            SongColumn::Bitrate => "bitrate",
+            SongColumn::Channels => "channels",
+            SongColumn::Duration => "duration",
+            SongColumn::SampleRate => "sample_rate",
+            SongColumn::Album => "album",
+            SongColumn::Barcode => "barcode",
+            SongColumn::DateCreated => "date_created",
+            SongColumn::DiscNumber => "disc_number",
+            SongColumn::DiscTotal => "disc_total",
+            SongColumn::Isrc => "isrc",
+            SongColumn::Itunesadvisory => "itunesadvisory",
+            SongColumn::Length => "length",
+            SongColumn::Publisher => "publisher",
+            SongColumn::Rating => "rating",
+            SongColumn::Title => "title",
+            SongColumn::TrackNumber => "track_number",
+            SongColumn::TrackTotal => "track_total",
+            SongColumn::Source => "source",
+            SongColumn::Filetype => "filetype",
+            SongColumn::AudioHash => "audio_hash",
+            SongColumn::BitrateMode => "bitrate_mode",
+            SongColumn::TotalSamples => "total_samples",
+            SongColumn::RecordingId => "recording_id",
+            SongColumn::DtAdded => "dt_added",
+        }
+    }
+
+    /// Grabs the value of this column out of a song, used for keyset pagination
+    pub fn value_of(&self, song: &SONG_TABLE_DATA) -> Value {
+        match self {
+            SongColumn::SongId => Value::Text(song.song_id.clone()),
+            SongColumn::MainArtist => Value::Text(song.main_artist.clone()),
+            SongColumn::FilesizeBytes => Value::Integer(song.filesize_bytes),
+            SongColumn::PaddingBytes => Value::Integer(song.padding_bytes),
+            SongColumn::AlbumArtworkBitDepth => Value::Integer(song.album_artwork_bit_depth),
+            SongColumn::AlbumArtworkColors => Value::Integer(song.album_artwork_colors),
+            SongColumn::AlbumArtworkHeight => Value::Integer(song.album_artwork_height),
+            SongColumn::AlbumArtworkWidth => Value::Integer(song.album_artwork_width),
+            SongColumn::BitDepth => Value::Integer(song.bit_depth),
+            SongColumn::Bitrate => Value::Integer(song.bitrate),
+            SongColumn::Channels => Value::Integer(song.channels),
+            SongColumn::Duration => Value::Real(song.duration),
+            SongColumn::SampleRate => Value::Integer(song.sample_rate),
+            SongColumn::Album => Value::Text(song.album.clone()),
+            SongColumn::Barcode => Value::Text(song.barcode.clone()),
+            SongColumn::DateCreated => Value::Text(song.date_created.clone()),
+            SongColumn::DiscNumber => Value::Integer(song.disc_number),
+            SongColumn::DiscTotal => Value::Integer(song.disc_total),
+            SongColumn::Isrc => Value::Text(song.isrc.clone()),
+            SongColumn::Itunesadvisory => Value::Text(song.itunesadvisory.clone()),
+            SongColumn::Length => Value::Integer(song.length),
+            SongColumn::Publisher => Value::Text(song.publisher.clone()),
+            SongColumn::Rating => Value::Integer(song.rating),
+            SongColumn::Title => Value::Text(song.title.clone()),
+            SongColumn::TrackNumber => Value::Integer(song.track_number),
+            SongColumn::TrackTotal => Value::Integer(song.track_total),
+            SongColumn::Source => Value::Text(song.source.clone()),
+            SongColumn::Filetype => Value::Text(song.filetype.clone()),
+            SongColumn::AudioHash => Value::Text(song.audio_hash.clone()),
+            SongColumn::BitrateMode => Value::Text(song.bitrate_mode.clone()),
+            SongColumn::TotalSamples => Value::Integer(song.total_samples),
+            SongColumn::RecordingId => Value::Text(song.recording_id.clone()),
+            SongColumn::DtAdded => Value::Text(song.dt_added.clone()),
+        }
+    }
+}
+
+/// The link tables that hang off a song_id (song_artists, album_artists, genres, composers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongLink {
+    SongArtist,
+    AlbumArtist,
+    Genre,
+    Composer,
+}
+
+impl SongLink {
+    /// Returns (table name, name column) for the link table
+    pub fn table_and_column(&self) -> (&'static str, &'static str) {
+        match self {
+            SongLink::SongArtist => (SONG_ARTISTS.name, "artist_name"),
+            SongLink::AlbumArtist => (ALBUM_ARTISTS.name, "artist_name"),
+            SongLink::Genre => (GENRES.name, "genre_name"),
+            SongLink::Composer => (COMPOSERS.name, "composer_name"),
+        }
+    }
+}
+
+/// Comparison operators you can use in a predicate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    Like,
+    NotLike,
+    /// Equal, ignoring ASCII case
+    EqualIgnoreCase,
+}
+
+impl Comparison {
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+            Comparison::LessThan => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::GreaterThan => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Like => "LIKE",
+            Comparison::NotLike => "NOT LIKE",
+            Comparison::EqualIgnoreCase => "COLLATE NOCASE =",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+impl SortOrder {
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Ascending => "ASC",
+            SortOrder::Descending => "DESC",
+        }
+    }
+}
+
+/// A predicate tree. Leaves compare a column (or a link table name) to a value,
+/// And/Or/Not group them together.
+/// ```
+/// # use decibl_metadata::engine::analyticsdb::*;
+/// let filter = SongFilter::And(vec![
+///     SongFilter::column(SongColumn::Filetype, Comparison::Equal, "flac".to_string()),
+///     SongFilter::column(SongColumn::BitDepth, Comparison::GreaterThan, 24),
+///     SongFilter::linked(SongLink::AlbumArtist, Comparison::Equal, "brakence"),
+/// ]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum SongFilter {
+    Column(SongColumn, Comparison, Value),
+    Linked(SongLink, Comparison, String),
+    /// How many times the song was played, optionally only counting plays that started at or after the date
+    PlayCount(Comparison, i64, Option<String>),
+    /// When the song was last played. Songs that were never played don't match.
+    LastPlayed(Comparison, String),
+    /// When the song was added to the library (songs.dt_added)
+    DateAdded(Comparison, String),
+    /// Leaves out the songs that are a duplicate of another song and aren't the preferred version of their group
+    /// (see duplicates). Songs that aren't in a duplicate group always match.
+    PreferredVersions,
+    /// The detected tempo of the song (see music_analysis). Songs that weren't analyzed or had no beat don't match.
+    Bpm(Comparison, f64),
+    /// The detected key of the song is one of these, in Camelot notation (see music_analysis)
+    Camelot(Vec<String>),
+    And(Vec<SongFilter>),
+    Or(Vec<SongFilter>),
+    Not(Box<SongFilter>),
+}
+
+impl SongFilter {
+    pub fn column<V: Into<Value>>(column: SongColumn, comparison: Comparison, value: V) -> Self {
+        SongFilter::Column(column, comparison, value.into())
+    }
+
+    pub fn linked(link: SongLink, comparison: Comparison, name: &str) -> Self {
+        SongFilter::Linked(link, comparison, name.to_string())
+    }
+
+    /// Compiles the predicate into a sql fragment, pushing the values it needs into `params`
+    pub fn compile(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            SongFilter::Column(column, comparison, value) => {
+                params.push(value.clone());
+                format!("songs.{} {} ?", column.name(), comparison.to_sql())
+            }
+            SongFilter::Linked(link, comparison, name) => {
+                let (table, name_column) = link.table_and_column();
+                params.push(Value::Text(name.clone()));
+                format!(
+                    "songs.song_id IN (SELECT song_id FROM {} WHERE {} {} ?)",
+                    table,
+                    name_column,
+                    comparison.to_sql()
+                )
+            }
+            SongFilter::PlayCount(comparison, count, since) => {
+                let mut sql = String::from("(SELECT COUNT(*) FROM plays WHERE plays.song_id = songs.song_id");
+                if let Some(since) = since {
+                    sql.push_str(" AND plays.start_dt >= ?");
+                    params.push(Value::Text(since.clone()));
+                }
+                params.push(Value::Integer(*count));
+                format!("{}) {} ?", sql, comparison.to_sql())
+            }
+            SongFilter::LastPlayed(comparison, dt) => {
+                params.push(Value::Text(dt.clone()));
+                format!(
+                    "(SELECT MAX(plays.start_dt) FROM plays WHERE plays.song_id = songs.song_id) {} ?",
+                    comparison.to_sql()
+                )
+            }
+            SongFilter::DateAdded(comparison, dt) => {
+                params.push(Value::Text(dt.clone()));
+                format!("songs.dt_added {} ?", comparison.to_sql())
+            }
+            SongFilter::PreferredVersions => {
+                "songs.song_id NOT IN (SELECT song_id FROM song_versions WHERE preferred = 0)".to_string()
+            }
+            SongFilter::Bpm(comparison, bpm) => {
+                params.push(Value::Real(*bpm));
+                format!(
+                    "(SELECT bpm FROM music_analysis WHERE music_analysis.song_id = songs.song_id AND bpm >= 0) {} ?",
+                    comparison.to_sql()
+                )
+            }
+            SongFilter::Camelot(keys) => {
+                if keys.is_empty() {
+                    return "0".to_string();
+                }
+                params.extend(keys.iter().map(|key| Value::Text(key.clone())));
+                format!(
+                    "songs.song_id IN (SELECT song_id FROM music_analysis WHERE camelot IN ({}))",
+                    vec!["?"; keys.len()].join(", ")
+                )
+            }
+            SongFilter::And(filters) => compile_group(filters, " AND ", "1", params),
+            SongFilter::Or(filters) => compile_group(filters, " OR ", "0", params),
+            SongFilter::Not(filter) => format!("NOT ({})", filter.compile(params)),
+        }
+    }
+}
+
+// an empty AND is true and an empty OR is false, same as sql would think about it
+fn compile_group(filters: &[SongFilter], joiner: &str, empty: &str, params: &mut Vec<Value>) -> String {
+    if filters.is_empty() {
+        return empty.to_string();
+    }
+
+    let parts: Vec<String> = filters
+        .iter()
+        .map(|filter| format!("({})", filter.compile(params)))
+        .collect();
+
+    parts.join(joiner)
+}
+
+/// Builder for song listings. Chain the methods and hand it to `query_songs`.
+/// ```no_run
+/// # use decibl_metadata::engine::analyticsdb::*;
+/// let query = SongQuery::new()
+///     .filter(SongFilter::column(SongColumn::Filetype, Comparison::Equal, "flac".to_string()))
+///     .filter(SongFilter::column(SongColumn::DateCreated, Comparison::GreaterThan, "2010".to_string()))
+///     .order_by(SongColumn::DiscNumber, SortOrder::Ascending)
+///     .order_by(SongColumn::TrackNumber, SortOrder::Ascending)
+///     .limit(50)
+///     .offset(100);
+///
+/// let songs = query_songs(&query);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongQuery {
+    pub filters: Vec<SongFilter>,
+    pub order: Vec<(SongColumn, SortOrder)>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub after: Option<Vec<Value>>,
+}
+
+impl SongQuery {
+    pub fn new() -> Self {
+        SongQuery {
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+            after: None,
+        }
+    }
+
+    /// Adds a predicate. Calling this more than once ANDs them together.
+    pub fn filter(mut self, filter: SongFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn order_by(mut self, column: SongColumn, order: SortOrder) -> Self {
+        self.order.push((column, order));
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Keyset pagination: only return songs that sort after the given cursor.
+    /// The cursor has one value per order_by column, then the song_id.
+    pub fn after(mut self, cursor: Vec<Value>) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Keyset pagination using the last song of the previous page as the cursor
+    pub fn after_song(self, song: &SONG_TABLE_DATA) -> Self {
+        let cursor = self
+            .sort_keys()
+            .iter()
+            .map(|(column, _)| column.value_of(song))
+            .collect();
+        self.after(cursor)
+    }
+
+    /// The order_by columns with song_id tacked on the end so the order is always total
+    /// (otherwise keyset pagination can skip or repeat songs with equal values)
+    pub fn sort_keys(&self) -> Vec<(SongColumn, SortOrder)> {
+        let mut keys = self.order.clone();
+        if !keys.iter().any(|(column, _)| *column == SongColumn::SongId) {
+            keys.push((SongColumn::SongId, SortOrder::Ascending));
+        }
+        keys
+    }
+
+    /// Compiles the query into a sql string and the parameters to bind to it
+    pub fn compile(&self) -> (String, Vec<Value>) {
+        let mut params: Vec<Value> = Vec::new();
+        let mut sql = String::from("SELECT songs.* FROM songs");
+
+        let mut conditions: Vec<String> = self
+            .filters
+            .iter()
+            .map(|filter| format!("({})", filter.compile(&mut params)))
+            .collect();
+
+        let sort_keys = self.sort_keys();
+
+        if let Some(cursor) = &self.after {
+            conditions.push(compile_keyset(&sort_keys, cursor, &mut params));
+        }
+
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+
+        let order: Vec<String> = sort_keys
+            .iter()
+            .map(|(column, order)| format!("songs.{} {}", column.name(), order.to_sql()))
+            .collect();
+        sql.push_str(" ORDER BY ");
+        sql.push_str(&order.join(", "));
+
+        // sqlite won't take an OFFSET without a LIMIT, -1 means no limit
+        if self.limit.is_some() || self.offset.is_some() {
+            sql.push_str(" LIMIT ?");
+            params.push(Value::Integer(self.limit.unwrap_or(-1)));
+        }
+        if let Some(offset) = self.offset {
+            sql.push_str(" OFFSET ?");
+            params.push(Value::Integer(offset));
+        }
+
+        (sql, params)
+    }
+}
+
+impl Default for SongQuery {
+    fn default() -> Self {
+        SongQuery::new()
+    }
+}
+
+// (a > ?) OR (a = ? AND b > ?) OR (a = ? AND b = ? AND c > ?) ...
+// written out instead of a row value comparison so that each key can have its own direction
+fn compile_keyset(sort_keys: &[(SongColumn, SortOrder)], cursor: &[Value], params: &mut Vec<Value>) -> String {
+    let keys = &sort_keys[..sort_keys.len().min(cursor.len())];
+    let mut branches: Vec<String> = Vec::new();
+
+    for (i, (column, order)) in keys.iter().enumerate() {
+        let mut parts: Vec<String> = Vec::new();
+        for (j, (previous, _)) in keys[..i].iter().enumerate() {
+            parts.push(format!("songs.{} = ?", previous.name()));
+            params.push(cursor[j].clone());
+        }
+        let op = match order {
+            SortOrder::Ascending => ">",
+            SortOrder::Descending => "<",
+        };
+        parts.push(format!("songs.{} {} ?", column.name(), op));
+        params.push(cursor[i].clone());
+        branches.push(format!("({})", parts.join(" AND ")));
+    }
+
+    if branches.is_empty() {
+        return "1".to_string();
+    }
+    format!("({})", branches.join(" OR "))
+}
+
+/// Runs a SongQuery against the database
+pub fn query_songs(query: &SongQuery) -> Vec<SONG_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let (sql_query, query_params) = query.compile();
+
+    let mut stmt = conn
+        .prepare(&sql_query)
+        .expect("Could not prepare statement");
+
+    let rows = stmt
+        .query_map(params_from_iter(query_params), song_from_row)
+        .unwrap();
+
+    let mut songs: Vec<SONG_TABLE_DATA> = Vec::new();
+    for row in rows {
+        songs.push(row.unwrap());
+    }
+    songs
+}
+
+/// Counts how many songs match the filters of a SongQuery (ignores order, limit and offset)
+pub fn count_songs(query: &SongQuery) -> i64 {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+
+    let mut query_params: Vec<Value> = Vec::new();
+    let mut sql_query = String::from("SELECT COUNT(*) FROM songs");
+    let conditions: Vec<String> = query
+        .filters
+        .iter()
+        .map(|filter| format!("({})", filter.compile(&mut query_params)))
+        .collect();
+    if !conditions.is_empty() {
+        sql_query.push_str(" WHERE ");
+        sql_query.push_str(&conditions.join(" AND "));
+    }
+
+    conn.query_row(&sql_query, params_from_iter(query_params), |row| row.get(0))
+        .unwrap()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MAINTENANCE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// backups are called analytics-<timestamp>.db, the timestamp sorts the same as the time so the names sort oldest first
+const BACKUP_PREFIX: &str = "analytics-";
+const BACKUP_EXTENSION: &str = ".db";
+
+/// Copies the database to a timestamped file in `backup_dir` (get_backup_path() is the usual place) while it's
+/// still in use, then deletes all but the newest `keep` backups. Returns the path of the new backup.
+/// ```no_run
+/// # use decibl_metadata::engine::analyticsdb::*;
+/// # use decibl_metadata::engine::config::*;
+/// let backup = backup_database(get_backup_path(), 7).unwrap();
+/// ```
+pub fn backup_database(backup_dir: String, keep: usize) -> rusqlite::Result<String> {
+    std::fs::create_dir_all(&backup_dir).map_err(|e| {
+        rusqlite::Error::InvalidPath(std::path::PathBuf::from(format!("{}: {}", backup_dir, e)))
+    })?;
+
+    let timestamp = chrono::Utc::now().naive_utc().format("%Y-%m-%d_%H-%M-%S-%3f");
+    let backup_path = std::path::Path::new(&backup_dir)
+        .join(format!("{}{}{}", BACKUP_PREFIX, timestamp, BACKUP_EXTENSION))
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    // the backup api copies a page at a time, so other connections can keep reading and writing while it runs
+    let conn = Connection::open(get_database_file_path())?;
+    conn.backup(rusqlite::DatabaseName::Main, &backup_path, None)?;
+
+    prune_backups(backup_dir, keep);
+    Ok(backup_path)
+}
+
+/// Get the backups in `backup_dir`, oldest first
+pub fn get_backups(backup_dir: String) -> Vec<String> {
+    let mut backups: Vec<String> = match std::fs::read_dir(&backup_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                matches!(
+                    path.file_name().and_then(std::ffi::OsStr::to_str),
+                    Some(name) if name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+                )
+            })
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    backups.sort();
+    backups
+}
+
+/// Deletes all but the newest `keep` backups in `backup_dir` and returns the paths it deleted
+pub fn prune_backups(backup_dir: String, keep: usize) -> Vec<String> {
+    let backups = get_backups(backup_dir);
+    let old = backups.len().saturating_sub(keep);
+
+    let mut deleted: Vec<String> = Vec::new();
+    for backup in backups.into_iter().take(old) {
+        match std::fs::remove_file(&backup) {
+            Ok(_) => deleted.push(backup),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+    deleted
+}
+
+/// Rows in one table that point at a row in another table that isn't there
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanRows {
+    pub table: String,
+    pub column: String,
+    pub parent_table: String,
+    pub count: i64,
+}
+
+impl std::fmt::Display for OrphanRows {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rows in {} whose {} isn't in {}",
+            self.count, self.table, self.column, self.parent_table
+        )
+    }
+}
+
+// (table, column, parent table, parent column) for every link between tables
+// the schema has no foreign keys, so these are what foreign keys would be
+const TABLE_LINKS: [(&str, &str, &str, &str); 19] = [
+    ("playlist_songs", "song_id", "songs", "song_id"),
+    ("playlist_songs", "playlist_id", "playlists", "playlist_id"),
+    ("song_artists", "song_id", "songs", "song_id"),
+    ("album_artists", "song_id", "songs", "song_id"),
+    ("composers", "song_id", "songs", "song_id"),
+    ("genres", "song_id", "songs", "song_id"),
+    ("songpaths", "song_id", "songs", "song_id"),
+    ("smart_playlists", "playlist_id", "playlists", "playlist_id"),
+    ("song_versions", "song_id", "songs", "song_id"),
+    ("flac_verifications", "song_id", "songs", "song_id"),
+    ("file_health", "song_id", "songs", "song_id"),
+    ("loudness", "song_id", "songs", "song_id"),
+    ("music_analysis", "song_id", "songs", "song_id"),
+    ("gapless", "song_id", "songs", "song_id"),
+    ("song_artwork", "song_id", "songs", "song_id"),
+    ("song_artwork", "artwork_id", "artwork", "artwork_id"),
+    ("album_artwork", "album_id", "albums", "album_id"),
+    ("album_artwork", "artwork_id", "artwork", "artwork_id"),
+    ("tag_edit_journal", "batch_id", "tag_edit_batches", "batch_id"),
+];
+
+/// Counts the orphaned rows in every link table on `conn`. Only links with orphans are returned.
+/// Takes a connection so it can be run inside a transaction, find_orphans is the usual way in.
+pub fn count_orphans(conn: &Connection) -> Vec<OrphanRows> {
+    let mut orphans: Vec<OrphanRows> = Vec::new();
+    for (table, column, parent_table, parent_column) in TABLE_LINKS {
+        let count: i64 = conn
+            .query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM {table} WHERE {column} NOT IN (SELECT {parent_column} FROM {parent_table})"
+                ),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        if count > 0 {
+            orphans.push(OrphanRows {
+                table: table.to_string(),
+                column: column.to_string(),
+                parent_table: parent_table.to_string(),
+                count,
+            });
+        }
+    }
+    orphans
+}
+
+/// Finds rows in the link tables (song_artists, genres, playlist_songs...) whose song or playlist is gone
+pub fn find_orphans() -> Vec<OrphanRows> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    count_orphans(&conn)
+}
+
+/// Deletes everything find_orphans finds and returns how many rows went
+pub fn delete_orphans() -> usize {
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let tx = conn.transaction().expect("Could not start transaction");
+
+    let mut deleted = 0;
+    for (table, column, parent_table, parent_column) in TABLE_LINKS {
+        deleted += tx
+            .execute(
+                &format!(
+                    "DELETE FROM {table} WHERE {column} NOT IN (SELECT {parent_column} FROM {parent_table})"
+                ),
+                [],
+            )
+            .unwrap();
+    }
+    tx.commit().expect("Could not commit transaction");
+
+    // playlists that lost songs have gaps in their positions now
+    if deleted > 0 {
+        for playlist in get_all_playlists() {
+            renumber_playlist(&playlist.playlist_id);
+        }
+    }
+    deleted
+}
+
+/// What check_database found. Everything empty (and integrity == ["ok"]) means the database is healthy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegrityReport {
+    /// The lines PRAGMA integrity_check returned, just "ok" if there's nothing wrong
+    pub integrity: Vec<String>,
+    /// (table, rowid, parent table) for every row PRAGMA foreign_key_check complained about
+    pub foreign_key_problems: Vec<(String, i64, String)>,
+    pub orphans: Vec<OrphanRows>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.integrity == vec!["ok".to_string()]
+            && self.foreign_key_problems.is_empty()
+            && self.orphans.is_empty()
+    }
+}
+
+/// Runs PRAGMA integrity_check and PRAGMA foreign_key_check and looks for orphaned rows
+pub fn check_database() -> IntegrityReport {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+
+    let mut stmt = conn
+        .prepare("PRAGMA integrity_check")
+        .expect("Could not prepare statement");
+    let integrity: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|line| line.unwrap())
+        .collect();
+
+    let mut stmt = conn
+        .prepare("PRAGMA foreign_key_check")
+        .expect("Could not prepare statement");
+    let foreign_key_problems: Vec<(String, i64, String)> = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get::<_, Option<i64>>(1)?.unwrap_or(-1),
+                row.get(2)?,
+            ))
+        })
+        .unwrap()
+        .map(|problem| problem.unwrap())
+        .collect();
+
+    IntegrityReport {
+        integrity,
+        foreign_key_problems,
+        orphans: count_orphans(&conn),
+    }
+}
+
+/// Rebuilds the database file to get back the space deleted rows left behind, then refreshes the query planner's
+/// statistics. Returns the size of the file (in bytes) before and after. Needs nothing else to be writing at the time.
+pub fn vacuum_database() -> (u64, u64) {
+    let size = || {
+        std::fs::metadata(get_database_file_path())
+            .map(|metadata| metadata.len())
+            .unwrap_or(0)
+    };
+
+    let before = size();
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute_batch("VACUUM; ANALYZE;").unwrap();
+    (before, size())
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           RE-KEYING SONGS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// song_ids used to be a hash of title + filesize + album (flac) or of the whole file (mp3), so a retag gave the song
+// a new id, and two files with the same title, size and album shared one. Now the song_id is the audio_hash of the
+// file, and this moves existing databases over to it.
+// The scheme a database is on is kept in PRAGMA user_version.
+
+// tables that point at a song. When two songs turn out to be the same one these rows move over to the song that stays.
+const SONG_REFERENCES: [&str; 3] = ["plays", "playlist_songs", "songpaths"];
+// tables that describe a song. The song that stays already has its own, so these are dropped when two songs merge.
+const SONG_DETAILS: [&str; 11] = [
+    "song_artists",
+    "album_artists",
+    "composers",
+    "genres",
+    "song_versions",
+    "flac_verifications",
+    "file_health",
+    "loudness",
+    "music_analysis",
+    "gapless",
+    "song_artwork",
+];
+
+/// What rekey_all_songs did
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RekeyReport {
+    /// Songs that got a new song_id
+    pub rekeyed: usize,
+    /// Songs that turned out to be another song already in the library and were folded into it
+    pub merged: usize,
+    /// Files that were sharing a song with a different file and got a song of their own
+    pub split: usize,
+    /// song_ids that were left alone because none of their files could be found
+    pub missing: Vec<String>,
+}
+
+/// Records which song_id scheme the songs in the database are on, see get_song_id_version
+pub fn set_song_id_version(conn: &Connection, version: i64) {
+    conn.pragma_update(None, "user_version", version).unwrap();
+}
+
+/// The song_id scheme the database is on (see audio_metadata::SONG_ID_VERSION). 0 is the original scheme.
+pub fn get_song_id_version() -> i64 {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap()
+}
+
+/// Whether the database still has song_ids from an older scheme and rekey_all_songs should be run
+pub fn needs_rekey() -> bool {
+    get_song_id_version() < SONG_ID_VERSION
+}
+
+/// Reads a file the way a scan would. None if it isn't a format we can read or the file can't be read.
+fn read_song_file(filepath: &str) -> Option<SONG_TABLE_DATA> {
+    let extension = std::path::Path::new(filepath)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)?
+        .to_lowercase();
+
+    match extension.as_str() {
+        "mp3" => {
+            let mut afile = AudioFileMP3::default();
+            afile.load_file(filepath.to_string()).ok()?;
+            Some(afile.get_song_table_data())
+        }
+        "flac" => {
+            let mut afile = AudioFileFLAC::default();
+            afile.load_file(filepath.to_string()).ok()?;
+            Some(afile.get_song_table_data())
+        }
+        _ => None,
+    }
+}
+
+/// Works out the song_id a file gets when it's scanned. None if it isn't a format we can read.
+pub fn get_song_id_for_file(filepath: &str) -> Option<String> {
+    read_song_file(filepath).map(|song| song.song_id)
+}
+
+/// Changes a song's song_id from `old_id` to `new_id` everywhere it's used. If there's already a song with `new_id`
+/// the old one is folded into it: its plays, playlist entries and paths move over and the rest of it is deleted.
+/// Returns true if it was a merge.
+pub fn rekey_song(conn: &Connection, old_id: &str, new_id: &str) -> bool {
+    let exists: i64 = conn
+        .query_row("SELECT COUNT(*) FROM songs WHERE song_id = ?1", [new_id], |row| row.get(0))
+        .unwrap();
+    let merge = exists > 0;
+
+    for table in SONG_REFERENCES {
+        // OR IGNORE so a path both songs already had doesn't trip the unique index, the leftover gets deleted below
+        conn.execute(
+            &format!("UPDATE OR IGNORE {} SET song_id = ?1 WHERE song_id = ?2", table),
+            [new_id, old_id],
+        )
+        .unwrap();
+        conn.execute(&format!("DELETE FROM {} WHERE song_id = ?1", table), [old_id])
+            .unwrap();
+    }
+    for table in SONG_DETAILS {
+        if !merge {
+            conn.execute(
+                &format!("UPDATE OR IGNORE {} SET song_id = ?1 WHERE song_id = ?2", table),
+                [new_id, old_id],
+            )
+            .unwrap();
+        }
+        conn.execute(&format!("DELETE FROM {} WHERE song_id = ?1", table), [old_id])
+            .unwrap();
+    }
+
+    if merge {
+        // the merged song has been in the library since the older of the two was added
+        conn.execute(
+            "UPDATE songs SET dt_added = (SELECT COALESCE(MIN(NULLIF(dt_added, '')), '') FROM songs \
+             WHERE song_id IN (?1, ?2)) WHERE song_id = ?1",
+            [new_id, old_id],
+        )
+        .unwrap();
+        conn.execute("DELETE FROM songs WHERE song_id = ?1", [old_id]).unwrap();
+    } else {
+        conn.execute("UPDATE songs SET song_id = ?1 WHERE song_id = ?2", [new_id, old_id])
+            .unwrap();
+    }
+    merge
+}
+
+/// Moves every song over to the current song_id scheme. The files are found through songpaths and read again, so
+/// this is slow on a big library, but it only has to happen once (check needs_rekey). Songs whose files are all gone
+/// keep their old id. If a song's files don't all get the same id, the first one keeps the song (and its plays and
+/// playlist entries) and the others get a song of their own with a copy of its artists, composers and genres.
+/// Everything is rewritten in one transaction, so if it fails nothing changes.
+/// ```no_run
+/// # use decibl_metadata::engine::analyticsdb::*;
+/// if needs_rekey() {
+///     let report = rekey_all_songs();
+///     println!("{} songs got new ids, {} were merged", report.rekeyed, report.merged);
+/// }
+/// ```
+pub fn rekey_all_songs() -> RekeyReport {
+    let mut report = RekeyReport::default();
+
+    // read all the files first so the transaction isn't held open for the slow part
+    let mut files: Vec<(String, Vec<(String, SONG_TABLE_DATA)>)> = Vec::new();
+    for song in get_all_songs() {
+        let found: Vec<(String, SONG_TABLE_DATA)> = get_songpaths_by_song_id(song.song_id.clone())
+            .into_iter()
+            .filter(|songpath| std::path::Path::new(&songpath.song_path).exists())
+            .filter_map(|songpath| {
+                read_song_file(&songpath.song_path).map(|data| (songpath.song_path, data))
+            })
+            .collect();
+        if found.is_empty() {
+            report.missing.push(song.song_id);
+        } else {
+            files.push((song.song_id, found));
+        }
+    }
+
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .expect("Could not start transaction");
+    for (old_id, found) in &files {
+        let first = &found[0].1;
+        if first.song_id != *old_id {
+            if rekey_song(&tx, old_id, &first.song_id) {
+                report.merged += 1;
+            } else {
+                report.rekeyed += 1;
+            }
+        }
+        tx.execute(
+            "UPDATE songs SET recording_id = ?1 WHERE song_id = ?2",
+            [&first.recording_id, &first.song_id],
+        )
+        .unwrap();
+
+        for (song_path, data) in &found[1..] {
+            if data.song_id == first.song_id {
+                continue;
+            }
+            if insert_song_row(&tx, data).unwrap() > 0 {
+                tx.execute(
+                    "UPDATE songs SET dt_added = (SELECT dt_added FROM songs WHERE song_id = ?2) WHERE song_id = ?1",
+                    [&data.song_id, &first.song_id],
+                )
+                .unwrap();
+                for (table, name_column) in [
+                    ("song_artists", "artist_name"),
+                    ("album_artists", "artist_name"),
+                    ("composers", "composer_name"),
+                    ("genres", "genre_name"),
+                ] {
+                    tx.execute(
+                        &format!(
+                            "INSERT OR IGNORE INTO {0} ({1}, song_id, dt_added) SELECT {1}, ?1, dt_added FROM {0} WHERE song_id = ?2",
+                            table, name_column
+                        ),
+                        [&data.song_id, &first.song_id],
+                    )
+                    .unwrap();
+                }
+            }
+            tx.execute(
+                "UPDATE OR IGNORE songpaths SET song_id = ?1 WHERE song_path = ?2",
+                [&data.song_id, song_path],
+            )
+            .unwrap();
+            report.split += 1;
+        }
+    }
+    set_song_id_version(&tx, SONG_ID_VERSION);
+    tx.commit().expect("Could not commit transaction");
+
+    report
+}
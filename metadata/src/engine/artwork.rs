@@ -0,0 +1,426 @@
+// Album artwork. Every format keeps its pictures somewhere different:
+// * FLAC: PICTURE metadata blocks, or base64 METADATA_BLOCK_PICTURE vorbis comments (what Ogg taggers write)
+// * MP3: ID3 APIC frames
+// * MP4/M4A: the covr item in ilst, one data box per picture
+// On top of that a lot of libraries just have a cover.jpg or folder.png sitting next to the songs.
+//
+// Taggers get the MIME type wrong all the time (PNGs marked image/jpeg, "image/jpg", or nothing at all), so the
+// MIME type is always worked out from the image bytes themselves, and so are the dimensions.
+
+use crate::engine::audio_metadata::{
+    find_audio_payload, mp4_boxes, mp4_child, mp4_ilst, AudioContainer,
+};
+use base64::Engine;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Image files next to a song that count as its artwork, best first. Matched without case and extension.
+pub const FOLDER_IMAGE_NAMES: [(&str, PictureType); 7] = [
+    ("cover", PictureType::FrontCover),
+    ("folder", PictureType::FrontCover),
+    ("front", PictureType::FrontCover),
+    ("album", PictureType::FrontCover),
+    ("albumart", PictureType::FrontCover),
+    ("albumartsmall", PictureType::FrontCover),
+    ("back", PictureType::BackCover),
+];
+
+/// Extensions of the folder images that get picked up
+pub const FOLDER_IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           PICTURES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What a picture shows. These are the ID3 APIC types, which FLAC PICTURE blocks use too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PictureType {
+    Other,
+    FileIcon,
+    OtherFileIcon,
+    FrontCover,
+    BackCover,
+    Leaflet,
+    Media,
+    LeadArtist,
+    Artist,
+    Conductor,
+    Band,
+    Composer,
+    Lyricist,
+    RecordingLocation,
+    DuringRecording,
+    DuringPerformance,
+    ScreenCapture,
+    BrightFish,
+    Illustration,
+    BandLogo,
+    PublisherLogo,
+}
+
+impl PictureType {
+    const ALL: [PictureType; 21] = [
+        PictureType::Other,
+        PictureType::FileIcon,
+        PictureType::OtherFileIcon,
+        PictureType::FrontCover,
+        PictureType::BackCover,
+        PictureType::Leaflet,
+        PictureType::Media,
+        PictureType::LeadArtist,
+        PictureType::Artist,
+        PictureType::Conductor,
+        PictureType::Band,
+        PictureType::Composer,
+        PictureType::Lyricist,
+        PictureType::RecordingLocation,
+        PictureType::DuringRecording,
+        PictureType::DuringPerformance,
+        PictureType::ScreenCapture,
+        PictureType::BrightFish,
+        PictureType::Illustration,
+        PictureType::BandLogo,
+        PictureType::PublisherLogo,
+    ];
+
+    /// The number the type is stored as in APIC frames and PICTURE blocks
+    pub fn code(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Unknown codes are Other
+    pub fn from_code(code: u32) -> Self {
+        match PictureType::ALL.get(code as usize) {
+            Some(picture_type) => *picture_type,
+            None => PictureType::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PictureType::Other => "other",
+            PictureType::FileIcon => "file_icon",
+            PictureType::OtherFileIcon => "other_file_icon",
+            PictureType::FrontCover => "front_cover",
+            PictureType::BackCover => "back_cover",
+            PictureType::Leaflet => "leaflet",
+            PictureType::Media => "media",
+            PictureType::LeadArtist => "lead_artist",
+            PictureType::Artist => "artist",
+            PictureType::Conductor => "conductor",
+            PictureType::Band => "band",
+            PictureType::Composer => "composer",
+            PictureType::Lyricist => "lyricist",
+            PictureType::RecordingLocation => "recording_location",
+            PictureType::DuringRecording => "during_recording",
+            PictureType::DuringPerformance => "during_performance",
+            PictureType::ScreenCapture => "screen_capture",
+            PictureType::BrightFish => "bright_fish",
+            PictureType::Illustration => "illustration",
+            PictureType::BandLogo => "band_logo",
+            PictureType::PublisherLogo => "publisher_logo",
+        }
+    }
+
+    pub fn from_type(picture_type: &str) -> Option<Self> {
+        PictureType::ALL
+            .iter()
+            .find(|found| found.as_str() == picture_type)
+            .copied()
+    }
+}
+
+/// Where a picture was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtworkSource {
+    /// Inside the audio file's tags
+    Embedded,
+    /// An image file next to the audio file
+    Folder,
+}
+
+impl ArtworkSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtworkSource::Embedded => "embedded",
+            ArtworkSource::Folder => "folder",
+        }
+    }
+
+    pub fn from_source(source: &str) -> Option<Self> {
+        match source {
+            "embedded" => Some(ArtworkSource::Embedded),
+            "folder" => Some(ArtworkSource::Folder),
+            _ => None,
+        }
+    }
+}
+
+/// One picture of a song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artwork {
+    pub picture_type: PictureType,
+    /// Worked out from the image bytes, empty if they aren't an image we know
+    pub mime: String,
+    pub description: String,
+    /// 0 if the image couldn't be read
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub source: ArtworkSource,
+    /// The audio file it's embedded in, or the image file itself
+    pub path: String,
+}
+
+impl Artwork {
+    fn new(
+        picture_type: PictureType,
+        description: &str,
+        data: Vec<u8>,
+        source: ArtworkSource,
+        path: &str,
+    ) -> Self {
+        let (width, height) = image_dimensions(&data).unwrap_or((0, 0));
+        Artwork {
+            picture_type,
+            mime: sniff_image_mime(&data).unwrap_or_default(),
+            description: description.to_string(),
+            width,
+            height,
+            data,
+            source,
+            path: path.to_string(),
+        }
+    }
+}
+
+/// The MIME type of an image, from its magic bytes rather than whatever the tag claims
+/// ```
+/// # use decibl_metadata::engine::artwork::*;
+/// assert_eq!(sniff_image_mime(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]), Some("image/png".to_string()));
+/// assert_eq!(sniff_image_mime(b"not an image"), None);
+/// ```
+pub fn sniff_image_mime(data: &[u8]) -> Option<String> {
+    let format = image::guess_format(data).ok()?;
+    Some(format.to_mime_type().to_string())
+}
+
+/// (width, height) of an image, read from its header without decoding the pixels
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+// reads a big endian u32 at pos, moving pos past it
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let value = u32::from_be_bytes(bytes.get(*pos..*pos + 4)?.try_into().unwrap());
+    *pos += 4;
+    Some(value)
+}
+
+// reads a u32 length and then that many bytes
+fn read_chunk<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let length = read_u32(bytes, pos)? as usize;
+    let chunk = bytes.get(*pos..pos.checked_add(length)?)?;
+    *pos += length;
+    Some(chunk)
+}
+
+/// Parses a FLAC PICTURE block, which is also what a METADATA_BLOCK_PICTURE comment holds once base64 decoded.
+/// Returns (picture type, description, image data), None if the block is cut short.
+pub fn parse_flac_picture(block: &[u8]) -> Option<(PictureType, String, Vec<u8>)> {
+    let mut pos = 0;
+    let picture_type = PictureType::from_code(read_u32(block, &mut pos)?);
+    read_chunk(block, &mut pos)?; // mime, we sniff it ourselves
+    let description = String::from_utf8_lossy(read_chunk(block, &mut pos)?).to_string();
+    // width, height, depth and colors, also read from the image itself
+    pos += 16;
+    let data = read_chunk(block, &mut pos)?.to_vec();
+    Some((picture_type, description, data))
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           EMBEDDED ARTWORK
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+fn flac_artwork(filepath: &str) -> Vec<Artwork> {
+    let tag = match metaflac::Tag::read_from_path(filepath) {
+        Ok(tag) => tag,
+        Err(_) => return Vec::new(),
+    };
+    let mut pictures: Vec<Artwork> = tag
+        .pictures()
+        .map(|picture| {
+            Artwork::new(
+                PictureType::from_code(picture.picture_type as u32),
+                &picture.description,
+                picture.data.clone(),
+                ArtworkSource::Embedded,
+                filepath,
+            )
+        })
+        .collect();
+
+    if let Some(comments) = tag.get_vorbis("METADATA_BLOCK_PICTURE") {
+        for comment in comments {
+            let block = match base64::engine::general_purpose::STANDARD.decode(comment.trim()) {
+                Ok(block) => block,
+                Err(_) => continue,
+            };
+            if let Some((picture_type, description, data)) = parse_flac_picture(&block) {
+                pictures.push(Artwork::new(
+                    picture_type,
+                    &description,
+                    data,
+                    ArtworkSource::Embedded,
+                    filepath,
+                ));
+            }
+        }
+    }
+    pictures
+}
+
+fn id3_artwork(filepath: &str) -> Vec<Artwork> {
+    let tag = match id3::Tag::read_from_path(filepath) {
+        Ok(tag) => tag,
+        Err(_) => return Vec::new(),
+    };
+    tag.pictures()
+        .map(|picture| {
+            Artwork::new(
+                PictureType::from_code(u8::from(picture.picture_type) as u32),
+                &picture.description,
+                picture.data.clone(),
+                ArtworkSource::Embedded,
+                filepath,
+            )
+        })
+        .collect()
+}
+
+// covr has a data box per picture: 4 bytes of type (13 JPEG, 14 PNG, 27 BMP), 4 of locale, then the image.
+// MP4 has no picture types, everything in covr is a front cover.
+fn mp4_artwork(filepath: &str) -> Vec<Artwork> {
+    let bytes = match std::fs::read(filepath) {
+        Ok(bytes) => bytes,
+        Err(_) => return Vec::new(),
+    };
+    let covr = match mp4_ilst(&bytes).and_then(|ilst| mp4_child(ilst, b"covr")) {
+        Some(covr) => covr,
+        None => return Vec::new(),
+    };
+    mp4_boxes(covr)
+        .into_iter()
+        .filter(|(kind, body)| *kind == b"data" && body.len() > 8)
+        .map(|(_, body)| {
+            Artwork::new(
+                PictureType::FrontCover,
+                "",
+                body[8..].to_vec(),
+                ArtworkSource::Embedded,
+                filepath,
+            )
+        })
+        .collect()
+}
+
+/// Every picture embedded in an audio file, in the order the tags have them. Files that can't be read or have
+/// no pictures give an empty Vec. The container is sniffed from the file, not taken from the extension.
+pub fn get_embedded_artwork(filepath: &str) -> Vec<Artwork> {
+    let container = match find_audio_payload(filepath) {
+        Ok(payload) => payload.container,
+        Err(_) => return Vec::new(),
+    };
+    match container {
+        AudioContainer::Flac => flac_artwork(filepath),
+        AudioContainer::Mpeg => id3_artwork(filepath),
+        AudioContainer::Mp4 => mp4_artwork(filepath),
+        _ => Vec::new(),
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           FOLDER ARTWORK
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// where an image file ranks in FOLDER_IMAGE_NAMES, None if it isn't artwork
+fn folder_image_rank(path: &Path) -> Option<usize> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    if !FOLDER_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    FOLDER_IMAGE_NAMES
+        .iter()
+        .position(|(name, _)| *name == stem)
+}
+
+/// The artwork images in the folder of an audio file (cover.jpg, Folder.png, back.jpg...), best first
+pub fn get_folder_artwork(filepath: &str) -> Vec<Artwork> {
+    let folder = match Path::new(filepath).parent() {
+        Some(folder) => folder,
+        None => return Vec::new(),
+    };
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut images: Vec<(usize, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| folder_image_rank(&path).map(|rank| (rank, path)))
+        .collect();
+    images.sort();
+
+    images
+        .into_iter()
+        .filter_map(|(rank, path)| {
+            let data = std::fs::read(&path).ok()?;
+            Some(Artwork::new(
+                FOLDER_IMAGE_NAMES[rank].1,
+                "",
+                data,
+                ArtworkSource::Folder,
+                path.to_str()?,
+            ))
+        })
+        .collect()
+}
+
+/// Embedded pictures first, then the folder ones
+pub fn get_all_artwork(filepath: &str) -> Vec<Artwork> {
+    let mut artwork = get_embedded_artwork(filepath);
+    artwork.extend(get_folder_artwork(filepath));
+    artwork
+}
+
+/// The picture to show for a song: an embedded front cover, then a folder front cover, then whatever embedded
+/// picture there is. Pictures that aren't images we can read are skipped.
+pub fn get_front_cover(filepath: &str) -> Option<Artwork> {
+    let artwork: Vec<Artwork> = get_all_artwork(filepath)
+        .into_iter()
+        .filter(|picture| !picture.mime.is_empty())
+        .collect();
+    let front = artwork
+        .iter()
+        .position(|picture| picture.picture_type == PictureType::FrontCover);
+    let chosen = match front {
+        Some(index) => index,
+        None => artwork
+            .iter()
+            .position(|picture| picture.source == ArtworkSource::Embedded)?,
+    };
+    artwork.into_iter().nth(chosen)
+}
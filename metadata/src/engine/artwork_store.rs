@@ -0,0 +1,473 @@
+// The artwork store. Artwork is copied out of the songs into get_artwork_path(), so the UI never has to open an
+// audio file (or decode a 3000px cover) just to show a thumbnail.
+// Images are content addressed: an image is saved once under the SHA256 of its bytes however many songs have it,
+// in a folder named after the first two characters of the hash, next to its thumbnails:
+//     artwork/3F/3FA9...E1.jpg        the image as it was in the file
+//     artwork/3F/3FA9...E1_256.jpg    a thumbnail that fits in 256x256
+// Thumbnail sizes and format come from the config file (artwork_thumbnail_sizes: "64,256,600",
+// artwork_thumbnail_format: jpeg or webp). The artwork table has a row per image with its dominant colours,
+// song_artwork and album_artwork link songs and albums to them.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::artwork::{self, sniff_image_mime};
+use crate::engine::audio_metadata::sha256_digest;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use data_encoding::HEXUPPER;
+use image::{DynamicImage, ImageFormat};
+use indicatif::ProgressBar;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+/// Thumbnail sizes when the config file doesn't set artwork_thumbnail_sizes
+pub const DEFAULT_THUMBNAIL_SIZES: [u32; 3] = [64, 256, 600];
+
+/// How many colours go in an image's palette
+pub const PALETTE_SIZE: usize = 5;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           THUMBNAILS AND COLOURS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What thumbnails are saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    /// Lossless, bigger than JPEG but keeps transparency
+    Webp,
+}
+
+impl ThumbnailFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpeg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+
+    pub fn from_format(format: &str) -> Option<Self> {
+        match format {
+            "jpeg" | "jpg" => Some(ThumbnailFormat::Jpeg),
+            "webp" => Some(ThumbnailFormat::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+}
+
+/// The thumbnail sizes from the config file, smallest first. Sizes that aren't numbers are skipped.
+pub fn thumbnail_sizes() -> Vec<u32> {
+    let configured = get_config_var_or("artwork_thumbnail_sizes", "");
+    let mut sizes: Vec<u32> = configured
+        .split(',')
+        .filter_map(|size| size.trim().parse::<u32>().ok())
+        .filter(|size| *size > 0)
+        .collect();
+    if sizes.is_empty() {
+        sizes = DEFAULT_THUMBNAIL_SIZES.to_vec();
+    }
+    sizes.sort();
+    sizes.dedup();
+    sizes
+}
+
+/// The thumbnail format from the config file, JPEG if it isn't set
+pub fn thumbnail_format() -> ThumbnailFormat {
+    match ThumbnailFormat::from_format(&get_config_var_or("artwork_thumbnail_format", "jpeg")) {
+        Some(format) => format,
+        None => ThumbnailFormat::Jpeg,
+    }
+}
+
+/// Scales an image down to fit in size x size and encodes it. Images already that small are only re-encoded,
+/// never blown up.
+pub fn render_thumbnail(
+    image: &DynamicImage,
+    size: u32,
+    format: ThumbnailFormat,
+) -> Option<Vec<u8>> {
+    let thumbnail = if image.width() > size || image.height() > size {
+        image.thumbnail(size, size)
+    } else {
+        image.clone()
+    };
+    let mut bytes = Vec::new();
+    let written = match format {
+        // JPEG has no alpha channel
+        ThumbnailFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg),
+        ThumbnailFormat::Webp => DynamicImage::ImageRgba8(thumbnail.to_rgba8())
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP),
+    };
+    written.ok()?;
+    Some(bytes)
+}
+
+// colours are bucketed by the top 4 bits of each channel, and each bucket's colour is the average of its pixels
+fn image_palette(image: &DynamicImage, count: usize) -> Vec<u32> {
+    // 64px is plenty to tell which colours an image has. Smaller ones aren't scaled up, that would blend their edges.
+    let small = if image.width() > 64 || image.height() > 64 {
+        image.thumbnail(64, 64).to_rgb8()
+    } else {
+        image.to_rgb8()
+    };
+    let mut buckets: BTreeMap<u32, [u64; 4]> = BTreeMap::new();
+    for pixel in small.pixels() {
+        let [r, g, b] = pixel.0;
+        let key = ((r >> 4) as u32) << 8 | ((g >> 4) as u32) << 4 | (b >> 4) as u32;
+        let bucket = buckets.entry(key).or_insert([0; 4]);
+        bucket[0] += 1;
+        bucket[1] += r as u64;
+        bucket[2] += g as u64;
+        bucket[3] += b as u64;
+    }
+
+    let mut buckets: Vec<[u64; 4]> = buckets.into_values().collect();
+    // stable, so buckets with the same count stay in key order
+    buckets.sort_by(|a, b| b[0].cmp(&a[0]));
+    buckets
+        .into_iter()
+        .take(count)
+        .map(|[pixels, r, g, b]| {
+            ((r / pixels) as u32) << 16 | ((g / pixels) as u32) << 8 | (b / pixels) as u32
+        })
+        .collect()
+}
+
+/// The `count` most common colours of an image as 0xRRGGBB, most common first. Empty if it can't be decoded.
+pub fn color_palette(data: &[u8], count: usize) -> Vec<u32> {
+    match image::load_from_memory(data) {
+        Ok(image) => image_palette(&image, count),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// A palette the way the artwork table keeps it
+/// ```
+/// # use decibl_metadata::engine::artwork_store::*;
+/// assert_eq!(palette_to_string(&[0xFF0000, 0x00080F]), "#FF0000,#00080F");
+/// assert_eq!(palette_from_string("#FF0000,#00080F"), vec![0xFF0000, 0x00080F]);
+/// ```
+pub fn palette_to_string(palette: &[u32]) -> String {
+    palette
+        .iter()
+        .map(|color| format!("#{:06X}", color))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Reads a palette back from the artwork table. Colours that don't parse are skipped.
+pub fn palette_from_string(palette: &str) -> Vec<u32> {
+    palette
+        .split(',')
+        .filter_map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok())
+        .collect()
+}
+
+/// The dominant colour of a song's front cover (see artwork::get_front_cover) as 0xRRGGBB, which is what goes in
+/// songs.album_artwork_colors. -1 if there's no cover.
+pub fn front_cover_color(filepath: &str) -> i64 {
+    let cover = match artwork::get_front_cover(filepath) {
+        Some(cover) => cover,
+        None => return -1,
+    };
+    match color_palette(&cover.data, 1).first() {
+        Some(color) => *color as i64,
+        None => -1,
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           STORE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The id an image gets in the store, the SHA256 of its bytes
+pub fn artwork_id(data: &[u8]) -> String {
+    HEXUPPER.encode(sha256_digest(data).unwrap().as_ref())
+}
+
+fn artwork_dir(artwork_id: &str) -> PathBuf {
+    PathBuf::from(get_artwork_path()).join(&artwork_id[..2.min(artwork_id.len())])
+}
+
+fn artwork_file(artwork_id: &str, mime: &str) -> PathBuf {
+    let extension = match ImageFormat::from_mime_type(mime) {
+        Some(format) => format.extensions_str()[0],
+        None => "img",
+    };
+    artwork_dir(artwork_id).join(format!("{}.{}", artwork_id, extension))
+}
+
+fn thumbnail_file(artwork_id: &str, size: u32, format: ThumbnailFormat) -> PathBuf {
+    artwork_dir(artwork_id).join(format!("{}_{}.{}", artwork_id, size, format.extension()))
+}
+
+pub fn save_artwork(artwork: &ARTWORK_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO artwork (artwork_id, mime, width, height, filesize_bytes, palette, location, \
+         added_dt) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            artwork.artwork_id,
+            artwork.mime,
+            artwork.width,
+            artwork.height,
+            artwork.filesize_bytes,
+            artwork.palette,
+            artwork.location,
+            artwork.added_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Adds an image to the store, with a thumbnail for every configured size. Images already in the store aren't
+/// written again, but missing thumbnails (say the sizes changed) are made. None if it isn't an image we can decode.
+pub fn store_artwork(data: &[u8]) -> Option<ARTWORK_TABLE_DATA> {
+    let mime = sniff_image_mime(data)?;
+    let image = image::load_from_memory(data).ok()?;
+    let artwork_id = artwork_id(data);
+
+    let location = artwork_file(&artwork_id, &mime);
+    std::fs::create_dir_all(artwork_dir(&artwork_id)).ok()?;
+    if !location.exists() {
+        std::fs::write(&location, data).ok()?;
+    }
+    let format = thumbnail_format();
+    for size in thumbnail_sizes() {
+        let file = thumbnail_file(&artwork_id, size, format);
+        if file.exists() {
+            continue;
+        }
+        if let Some(thumbnail) = render_thumbnail(&image, size, format) {
+            let _ = std::fs::write(file, thumbnail);
+        }
+    }
+
+    if let Some(stored) = get_artwork(&artwork_id) {
+        return Some(stored);
+    }
+    let artwork = ARTWORK_TABLE_DATA {
+        artwork_id,
+        mime,
+        width: image.width() as i64,
+        height: image.height() as i64,
+        filesize_bytes: data.len() as i64,
+        palette: palette_to_string(&image_palette(&image, PALETTE_SIZE)),
+        location: location.to_str().unwrap().to_string(),
+        added_dt: now_dt(),
+    };
+    save_artwork(&artwork);
+    Some(artwork)
+}
+
+/// Stores every picture of a file (embedded and in its folder) and links them to the song, replacing the links it
+/// had. Pictures that aren't images we can decode are left out. Returns the new links.
+pub fn cache_song_artwork(song_id: &str, filepath: &str) -> Vec<SONG_ARTWORK_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute("DELETE FROM song_artwork WHERE song_id = ?1", [song_id])
+        .unwrap();
+
+    let mut links: Vec<SONG_ARTWORK_TABLE_DATA> = Vec::new();
+    for picture in artwork::get_all_artwork(filepath) {
+        let stored = match store_artwork(&picture.data) {
+            Some(stored) => stored,
+            None => continue,
+        };
+        let link = SONG_ARTWORK_TABLE_DATA {
+            song_id: song_id.to_string(),
+            artwork_id: stored.artwork_id,
+            picture_type: picture.picture_type.as_str().to_string(),
+            source: picture.source.as_str().to_string(),
+            position: links.len() as i64,
+        };
+        conn.execute(
+            "INSERT OR REPLACE INTO song_artwork (song_id, artwork_id, picture_type, source, position) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                link.song_id,
+                link.artwork_id,
+                link.picture_type,
+                link.source,
+                link.position,
+            ],
+        )
+        .unwrap();
+        links.push(link);
+    }
+    links
+}
+
+/// cache_song_artwork for a song in the library. None if the song has no path.
+pub fn cache_song(song_id: &str) -> Option<Vec<SONG_ARTWORK_TABLE_DATA>> {
+    let songpath = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?;
+    Some(cache_song_artwork(song_id, &songpath.song_path))
+}
+
+/// Makes an image of the store the cover of an album, and points albums.album_art_location at it.
+/// False if the image isn't in the store.
+pub fn link_album_artwork(album_id: &str, artwork_id: &str) -> bool {
+    let artwork = match get_artwork(artwork_id) {
+        Some(artwork) => artwork,
+        None => return false,
+    };
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO album_artwork (album_id, artwork_id) VALUES (?1, ?2)",
+        [album_id, artwork_id],
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE albums SET album_art_location = ?1 WHERE album_id = ?2",
+        [&artwork.location, album_id],
+    )
+    .unwrap();
+    true
+}
+
+/// The best cover the songs of an album have: the album's songs are the ones with its name by its artist (as main
+/// artist or album artist). Front covers come first, then embedded pictures, then track order.
+pub fn find_album_cover(album: &ALBUMS_TABLE_DATA) -> Option<String> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.query_row(
+        "SELECT song_artwork.artwork_id FROM song_artwork JOIN songs ON songs.song_id = song_artwork.song_id \
+         WHERE songs.album = ?1 AND (songs.main_artist = ?2 \
+         OR songs.song_id IN (SELECT song_id FROM album_artists WHERE artist_name = ?2)) \
+         ORDER BY song_artwork.picture_type != 'front_cover', song_artwork.source != 'embedded', \
+         songs.disc_number, songs.track_number, song_artwork.position LIMIT 1",
+        [&album.album_name, &album.artist_name],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// Stores the artwork of every song that has none linked yet (or all of them with `reanalyze`), then gives every
+/// album a cover from its songs. Returns how many songs were cached.
+pub fn cache_library_artwork(reanalyze: bool) -> usize {
+    let song_ids: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT song_id FROM songs WHERE ?1 OR song_id NOT IN (SELECT song_id FROM song_artwork) \
+                 ORDER BY song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([reanalyze], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let bar = ProgressBar::new(song_ids.len() as u64);
+    let mut cached = 0;
+    for song_id in song_ids {
+        if cache_song(&song_id).is_some() {
+            cached += 1;
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+
+    for album in get_all_albums() {
+        if let Some(artwork_id) = find_album_cover(&album) {
+            link_album_artwork(&album.album_id, &artwork_id);
+        }
+    }
+    cached
+}
+
+/// Deletes the images (and their thumbnails) that no song or album links to anymore. Returns how many went.
+pub fn prune_artwork_store() -> usize {
+    let unused: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT artwork_id FROM artwork WHERE artwork_id NOT IN (SELECT artwork_id FROM song_artwork) \
+                 AND artwork_id NOT IN (SELECT artwork_id FROM album_artwork)",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    for artwork_id in &unused {
+        if let Ok(entries) = std::fs::read_dir(artwork_dir(artwork_id)) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                if entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(artwork_id.as_str())
+                {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+        conn.execute("DELETE FROM artwork WHERE artwork_id = ?1", [artwork_id])
+            .unwrap();
+    }
+    unused.len()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           RETRIEVE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Get an image of the store, None if it isn't there
+pub fn get_artwork(artwork_id: &str) -> Option<ARTWORK_TABLE_DATA> {
+    get_all_artwork()
+        .into_iter()
+        .find(|artwork| artwork.artwork_id == artwork_id)
+}
+
+/// The pictures linked to a song, in the order they were found
+pub fn get_song_artwork(song_id: &str) -> Vec<SONG_ARTWORK_TABLE_DATA> {
+    let mut links: Vec<SONG_ARTWORK_TABLE_DATA> = get_all_song_artwork()
+        .into_iter()
+        .filter(|link| link.song_id == song_id)
+        .collect();
+    links.sort_by_key(|link| link.position);
+    links
+}
+
+/// The cover of an album, None if it doesn't have one
+pub fn get_album_artwork(album_id: &str) -> Option<ARTWORK_TABLE_DATA> {
+    let link = get_all_album_artwork()
+        .into_iter()
+        .find(|link| link.album_id == album_id)?;
+    get_artwork(&link.artwork_id)
+}
+
+/// The path of a thumbnail of an image that's at least `size` pixels (or the biggest there is), in the configured
+/// format. Thumbnails that aren't on disk are made from the stored image. None if the image isn't in the store.
+pub fn get_thumbnail(artwork_id: &str, size: u32) -> Option<String> {
+    let artwork = get_artwork(artwork_id)?;
+    let sizes = thumbnail_sizes();
+    let size = match sizes.iter().find(|configured| **configured >= size) {
+        Some(configured) => *configured,
+        None => *sizes.last()?,
+    };
+    let format = thumbnail_format();
+
+    let file = thumbnail_file(artwork_id, size, format);
+    if !file.exists() {
+        let image = image::load_from_memory(&std::fs::read(&artwork.location).ok()?).ok()?;
+        std::fs::write(&file, render_thumbnail(&image, size, format)?).ok()?;
+    }
+    Some(file.to_str()?.to_string())
+}
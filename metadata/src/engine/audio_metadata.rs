@@ -9,9 +9,13 @@ For example, if I have AudioFileFLAC and AudioFileMP3 and call get_title() on bo
 
 #![allow(non_snake_case)]
 use ring::digest::{Context, Digest, SHA256};
-use std::io::{BufReader, Read, Result, Write};
+use std::io::{BufReader, Read, Result, Seek, SeekFrom};
 use std::fs::File;
 
+use crate::engine::analyticsdb::now_dt;
+use crate::engine::artwork::get_front_cover;
+use crate::engine::artwork_store::front_cover_color;
+use crate::engine::file_health::{is_real_frame, mpeg_stream_info, verify_flac, FlacStatus};
 use crate::engine::models::*;
 
 use data_encoding::HEXUPPER;
@@ -42,7 +46,10 @@ pub trait AudioFile {
     fn get_album_artists_table_data(&self) -> Vec<ALBUM_ARTISTS_TABLE_DATA>;
     fn get_composers_table_data(&self) -> Vec<COMPOSERS_TABLE_DATA>;
     fn get_genres_table_data(&self) -> Vec<GENRES_TABLE_DATA>;
-    fn load_file(&mut self, filepath: String);
+    /// Reads everything about the file. Errors if the file can't be read.
+    fn load_file(&mut self, filepath: String) -> Result<()>;
+    /// The audio_stream_hash of the file, worked out once in load_file
+    fn get_audio_hash(&self) -> String;
 }
 
 /// Used for hashing files. SHA256 is the algorithm used.
@@ -78,6 +85,387 @@ pub fn string_to_hash(path: String) -> Result<String> {
     Ok(HEXUPPER.encode(digest.as_ref()))
 }
 
+/// The duration in seconds of `total_samples` samples per channel. Every AudioFile works its duration out with
+/// this so none of them round. -1 when either number is unknown.
+/// ```
+/// # use decibl_metadata::engine::audio_metadata::duration_from_samples;
+/// assert_eq!(duration_from_samples(66150, 44100), 1.5);
+/// assert_eq!(duration_from_samples(-1, 44100), -1.0);
+/// ```
+pub fn duration_from_samples(total_samples: i64, sample_rate: i64) -> f64 {
+    if total_samples < 0 || sample_rate <= 0 {
+        return -1.0;
+    }
+    total_samples as f64 / sample_rate as f64
+}
+
+/// Bump this whenever the way song_ids are worked out changes, so old databases know to run analyticsdb::rekey_all_songs.
+/// 0 was the hash of title + filesize + album, 1 is the audio_hash of the file.
+pub const SONG_ID_VERSION: i64 = 1;
+
+/// Which recording a file is, in order of preference: "musicbrainz:" + the MusicBrainz recording id, "isrc:" + the
+/// ISRC, or "" if it isn't tagged with either. Tag values that don't look like a real id (empty, "-1", wrong length)
+/// are skipped.
+/// Every file keeps its own songs row (song_id is the audio_hash), this is only stored so copies of the same
+/// recording in different formats can be found, see duplicates::find_duplicates.
+/// ```
+/// # use decibl_metadata::engine::audio_metadata::recording_id;
+/// assert_eq!(recording_id("", "us-sm1-20-03816"), "isrc:USSM12003816");
+/// assert_eq!(recording_id("-1", "-1"), "");
+/// ```
+pub fn recording_id(musicbrainz_recording_id: &str, isrc: &str) -> String {
+    let mbid = musicbrainz_recording_id.trim().to_lowercase();
+    if mbid.len() == 36 && mbid.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return format!("musicbrainz:{}", mbid);
+    }
+
+    match normalize_isrc(isrc) {
+        Some(isrc) => format!("isrc:{}", isrc),
+        None => String::new(),
+    }
+}
+
+/// An ISRC tag uppercased without dashes or spaces, or None if it isn't 12 letters and digits (empty, "-1", junk)
+/// ```
+/// # use decibl_metadata::engine::audio_metadata::normalize_isrc;
+/// assert_eq!(normalize_isrc("us-sm1-20-03816"), Some("USSM12003816".to_string()));
+/// assert_eq!(normalize_isrc("-1"), None);
+/// ```
+pub fn normalize_isrc(isrc: &str) -> Option<String> {
+    let isrc: String = isrc
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    if isrc.len() == 12 && isrc.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(isrc)
+    } else {
+        None
+    }
+}
+
+/// The kind of file an audio payload was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioContainer {
+    Flac,
+    /// MP3 (or MP2) frames
+    Mpeg,
+    /// MP4/M4A, the audio is in the mdat box
+    Mp4,
+    /// Didn't recognise it, the whole file counts as audio
+    Unknown,
+}
+
+/// Where the encoded audio is in a file, as (offset, length) byte ranges. Only MP4 can have more than one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioPayload {
+    pub container: AudioContainer,
+    pub ranges: Vec<(u64, u64)>,
+}
+
+fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Finds the encoded audio in a file, skipping every kind of tag we know about:
+/// * FLAC: the metadata blocks before the frames, checking a frame actually starts where they end
+/// * MP3: ID3v2 tags at the front, ID3v1 and APE tags at the back
+/// * MP4: everything but the mdat box (tags live in moov, which gets rewritten on every tag edit)
+pub fn find_audio_payload(path: &str) -> Result<AudioPayload> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let mut start = 0;
+    let mut end = length;
+    let mut header = [0u8; 10];
+
+    // ID3v2 tags, sometimes more than one. The size is "synchsafe" (7 bits a byte) and leaves out the 10 byte header
+    let mut tagged = false;
+    while start + 10 <= end {
+        read_at(&mut file, start, &mut header)?;
+        if &header[0..3] != b"ID3" {
+            break;
+        }
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, byte| (size << 7) | (*byte as u64 & 0x7f));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start += 10 + size + footer;
+        tagged = true;
+    }
+
+    if start + 8 <= end {
+        read_at(&mut file, start, &mut header[..8])?;
+    } else {
+        header = [0; 10];
+    }
+
+    // mp4 is all boxes: a 4 byte size (1 means a 64 bit size follows, 0 means "to the end") then a 4 byte type
+    if &header[4..8] == b"ftyp" {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        let mut offset = 0;
+        let mut box_header = [0u8; 16];
+        while offset + 8 <= length {
+            read_at(&mut file, offset, &mut box_header[..8])?;
+            let mut size = u32::from_be_bytes([box_header[0], box_header[1], box_header[2], box_header[3]]) as u64;
+            let mut header_size = 8;
+            if size == 1 {
+                read_at(&mut file, offset, &mut box_header)?;
+                size = u64::from_be_bytes(box_header[8..16].try_into().unwrap());
+                header_size = 16;
+            } else if size == 0 {
+                size = length - offset;
+            }
+            if size < header_size {
+                return Err(invalid_data("MP4 box is smaller than its header"));
+            }
+            if &box_header[4..8] == b"mdat" {
+                ranges.push((offset + header_size, size.min(length - offset) - header_size));
+            }
+            offset += size;
+        }
+        if ranges.is_empty() {
+            return Err(invalid_data("MP4 file has no mdat box"));
+        }
+        return Ok(AudioPayload {
+            container: AudioContainer::Mp4,
+            ranges,
+        });
+    }
+
+    let container = if &header[0..4] == b"fLaC" {
+        // each metadata block has a 4 byte header: last-block flag + type, then a 24 bit length
+        start += 4;
+        loop {
+            if start + 4 > end {
+                return Err(invalid_data("FLAC metadata runs past the end of the file"));
+            }
+            read_at(&mut file, start, &mut header[..4])?;
+            start += 4 + u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+        // every frame starts with the 14 bit sync code 11111111111110
+        if start + 2 > end {
+            return Err(invalid_data("FLAC file has no frames"));
+        }
+        read_at(&mut file, start, &mut header[..2])?;
+        if header[0] != 0xFF || header[1] & 0xFE != 0xF8 {
+            return Err(invalid_data("FLAC metadata isn't followed by a frame"));
+        }
+        AudioContainer::Flac
+    } else if header[0] == 0xFF && header[1] & 0xE0 == 0xE0 {
+        // 11 set bits is an mpeg frame sync
+        AudioContainer::Mpeg
+    } else if let Some(offset) = first_mpeg_frame(&mut file, start, end, tagged)? {
+        start += offset;
+        AudioContainer::Mpeg
+    } else {
+        return Ok(AudioPayload {
+            container: AudioContainer::Unknown,
+            ranges: vec![(0, length)],
+        });
+    };
+
+    // tags at the end can be stacked (APE then ID3v1 is common), so keep going until nothing comes off
+    let mut footer = [0u8; 32];
+    loop {
+        if end >= start + 128 {
+            read_at(&mut file, end - 128, &mut footer[..3])?;
+            if &footer[..3] == b"TAG" {
+                end -= 128;
+                continue;
+            }
+        }
+        if end >= start + 32 {
+            read_at(&mut file, end - 32, &mut footer)?;
+            if &footer[..8] == b"APETAGEX" {
+                // the size counts the items and the footer, the flags say if there's a 32 byte header too
+                let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+                let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+                let header_size = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+                end = end.saturating_sub(size + header_size).max(start);
+                continue;
+            }
+        }
+        break;
+    }
+
+    Ok(AudioPayload {
+        container,
+        ranges: vec![(start, end - start)],
+    })
+}
+
+// how far past an ID3v2 tag we look for the first frame, taggers leave padding or junk there that the tag's size
+// doesn't cover
+const MPEG_RESYNC_WINDOW: u64 = 65536;
+
+/// Where the first real MPEG frame is after `start`, as an offset from `start`. Only files that had an ID3v2 tag
+/// get searched, anything else that doesn't start with a frame isn't an MP3 we know how to read.
+fn first_mpeg_frame(file: &mut File, start: u64, end: u64, tagged: bool) -> Result<Option<u64>> {
+    if !tagged || start >= end {
+        return Ok(None);
+    }
+    let mut window = vec![0u8; (end - start).min(MPEG_RESYNC_WINDOW) as usize];
+    read_at(file, start, &mut window)?;
+    let all_of_it = start + window.len() as u64 == end;
+    Ok((0..window.len())
+        .find(|pos| is_real_frame(&window, *pos, all_of_it))
+        .map(|pos| pos as u64))
+}
+
+/// The (type, body) of every MP4 box in `bytes`, in order. Stops at the first box that doesn't fit.
+pub fn mp4_boxes(bytes: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= bytes.len() {
+        let size = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, bytes.len() - pos),
+            1 if pos + 16 <= bytes.len() => (
+                16,
+                u64::from_be_bytes(bytes[pos + 8..pos + 16].try_into().unwrap()) as usize,
+            ),
+            _ => (8, size),
+        };
+        if size < header || pos + size > bytes.len() {
+            break;
+        }
+        found.push((&bytes[pos + 4..pos + 8], &bytes[pos + header..pos + size]));
+        pos += size;
+    }
+    found
+}
+
+/// The body of the first MP4 box of type `kind` in `bytes`
+pub fn mp4_child<'a>(bytes: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    mp4_boxes(bytes)
+        .into_iter()
+        .find(|(found, _)| *found == kind)
+        .map(|(_, body)| body)
+}
+
+/// The ilst box of a whole MP4 file (moov/udta/meta/ilst), where the iTunes style tags live
+pub fn mp4_ilst(bytes: &[u8]) -> Option<&[u8]> {
+    let moov = mp4_child(bytes, b"moov")?;
+    let meta = mp4_child(mp4_child(moov, b"udta")?, b"meta")?;
+    // meta is a full box, 4 bytes of version and flags before its children
+    mp4_child(meta.get(4..)?, b"ilst")
+}
+
+/// How many bytes of padding the tags of a file leave free, so they can be edited without rewriting the file:
+/// FLAC PADDING blocks and the zeros after the last frame of ID3v2 tags. 0 for files with neither.
+pub fn tag_padding_bytes(path: &str) -> Result<u64> {
+    let mut file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let mut offset = 0;
+    let mut padding = 0;
+    let mut header = [0u8; 10];
+
+    while offset + 10 <= length {
+        read_at(&mut file, offset, &mut header)?;
+        if &header[0..3] != b"ID3" {
+            break;
+        }
+        let synchsafe = |bytes: &[u8]| bytes.iter().fold(0u64, |size, byte| (size << 7) | (*byte as u64 & 0x7f));
+        let version = header[3];
+        let size = synchsafe(&header[6..10]);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        let tag_end = (offset + 10 + size).min(length);
+        let mut tag = vec![0u8; (tag_end - offset - 10) as usize];
+        read_at(&mut file, offset + 10, &mut tag)?;
+
+        // frames are a 4 byte id (3 in v2.2) and a size, the padding starts where the next id would be a zero
+        let mut pos = 0;
+        if header[5] & 0x40 != 0 && tag.len() >= 4 {
+            pos = match version {
+                4 => synchsafe(&tag[..4]) as usize,
+                _ => 4 + u32::from_be_bytes(tag[..4].try_into().unwrap()) as usize,
+            };
+        }
+        let frame_header = if version == 2 { 6 } else { 10 };
+        while pos + frame_header <= tag.len() && tag[pos] != 0 {
+            let frame_size = match version {
+                2 => u32::from_be_bytes([0, tag[pos + 3], tag[pos + 4], tag[pos + 5]]) as u64,
+                3 => u32::from_be_bytes(tag[pos + 4..pos + 8].try_into().unwrap()) as u64,
+                _ => synchsafe(&tag[pos + 4..pos + 8]),
+            };
+            pos += frame_header + frame_size as usize;
+        }
+        padding += tag.len().saturating_sub(pos) as u64;
+        offset += 10 + size + footer;
+    }
+
+    if offset + 4 <= length {
+        read_at(&mut file, offset, &mut header[..4])?;
+        if &header[..4] == b"fLaC" {
+            // block type 1 is PADDING
+            offset += 4;
+            while offset + 4 <= length {
+                read_at(&mut file, offset, &mut header[..4])?;
+                let block_length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+                if header[0] & 0x7F == 1 {
+                    padding += block_length;
+                }
+                offset += 4 + block_length;
+                if header[0] & 0x80 != 0 {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(padding)
+}
+
+/// Hashes just the encoded audio of a file (see find_audio_payload), so editing tags, artwork or padding doesn't
+/// change it. Files we don't recognise get hashed whole. FLAC files get decoded and checked against the MD5 in
+/// STREAMINFO first (see file_health::verify_flac), and error if the frames aren't the audio the encoder wrote.
+pub fn audio_stream_hash(path: String) -> Result<String> {
+    let payload = find_audio_payload(&path)?;
+    if payload.container == AudioContainer::Flac {
+        let verification = verify_flac(&path);
+        let status = FlacStatus::from_status(&verification.status).unwrap_or(FlacStatus::Unreadable);
+        if status.is_bad() {
+            return Err(invalid_data(&format!(
+                "FLAC frames don't match STREAMINFO ({})",
+                verification.status
+            )));
+        }
+    }
+    let mut file = File::open(&path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0; 65536];
+
+    for (offset, length) in payload.ranges {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = (&mut file).take(length);
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            context.update(&buffer[..count]);
+        }
+    }
+
+    Ok(HEXUPPER.encode(context.finish().as_ref()))
+}
+
+/// The audio_hash of a file, the same value load_file stores. A file that's too broken to find the audio in (or a
+/// FLAC that fails its MD5) still needs some hash, so it gets the whole thing hashed. Errors only if the file can't be read at all.
+pub fn audio_hash_or_file_hash(path: &str) -> Result<String> {
+    match audio_stream_hash(path.to_string()) {
+        Ok(hash) => Ok(hash),
+        Err(_) => file_to_hash(path.to_string()),
+    }
+}
+
 /// Function that gets the metadata for a file using the symphonia library
 /// Returns a Symphonia object that should be used with add_symphonia_data
 pub fn get_symphonia_data(filepath: String, fileHint: String) -> Box<dyn FormatReader> {
@@ -180,48 +568,23 @@ pub fn add_symphonia_data(
     metadata
 }
 
-/// Get the album artwork from a file using the symphonia library
-/// Returns Vec<u8> of the album artwork
-pub fn get_symphonia_picture_data(filepath: String, fileHint: String) -> Vec<u8> {
-    let src = std::fs::File::open(filepath.clone()).unwrap();
-
-    let mss = MediaSourceStream::new(Box::new(src), Default::default());
-    let mut hint = Hint::new();
-    hint.with_extension("flac");
-
-    let meta_opts = MetadataOptions::default();
-    let format_opts = FormatOptions::default();
-
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &format_opts, &meta_opts)
-        .expect("failed to probe");
-
-    let format = probed.format;
-
-    let mut format = get_symphonia_data(filepath.clone(), "flac".to_string());
-    let binding = format.metadata();
-    let visualTags = binding.current().unwrap().visuals();
-
-    let mut album_art = None;
-
-    for tag in visualTags {
-        if tag.media_type == "image/jpeg" {
-            album_art = Some(tag.data.to_vec());
-        }
-        if tag.media_type == "image/png" {
-            album_art = Some(tag.data.to_vec());
-        }
+/// Get the album artwork of a file, see artwork::get_front_cover for which picture that is.
+/// Returns Vec<u8> of the album artwork, empty if there isn't any. The format is sniffed from the file, the
+/// hint is only still here for old callers.
+pub fn get_symphonia_picture_data(filepath: String, _fileHint: String) -> Vec<u8> {
+    match get_front_cover(&filepath) {
+        Some(artwork) => artwork.data,
+        None => Vec::new(),
     }
-
-    let album_art = album_art.unwrap();
-
-    album_art
 }
 
+/// Replaces the front cover of a file, see tag_editor::write_tags
 pub fn write_symphonia_picture_data(filepath: String, album_art: Vec<u8>) {
-    
-    let mut file = std::fs::File::open(filepath.clone()).unwrap();
-    file.write_all(&album_art).unwrap();
+    crate::engine::tag_editor::write_tags(
+        &filepath,
+        &[crate::engine::tag_editor::TagChange::SetCover(album_art)],
+    )
+    .expect("Could not write the album art");
 }
 /// We want to make a trait that has the following functions
 /// * get_song_table_data returns SONG_TABLE_DATA struct
@@ -340,9 +703,16 @@ impl AudioFileFLAC {
 
         bitrate_vec.push(bitrate.to_string());
 
-        let duration = streaminfo.total_samples / streaminfo.sample_rate as u64;
+        let duration = duration_from_samples(
+            streaminfo.total_samples as i64,
+            streaminfo.sample_rate as i64,
+        );
 
         duration_vec.push(duration.to_string());
+        self.raw_metadata.insert(
+            "total_samples".to_string(),
+            vec![streaminfo.total_samples.to_string()],
+        );
 
         self.raw_metadata
             .insert("sample_rate".to_string(), sample_rate_vec);
@@ -372,7 +742,7 @@ impl AudioFile for AudioFileFLAC {
             let mut composers_table_data = COMPOSERS_TABLE_DATA::default();
             composers_table_data.composer_name = composer.clone();
             composers_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            composers_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            composers_table_data.dt_added = now_dt();
             composers_table_data_vec.push(composers_table_data);
         }
         return composers_table_data_vec;
@@ -386,7 +756,7 @@ impl AudioFile for AudioFileFLAC {
             let mut genres_table_data = GENRES_TABLE_DATA::default();
             genres_table_data.genre_name = genre.clone();
             genres_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            genres_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            genres_table_data.dt_added = now_dt();
             genres_table_data_vec.push(genres_table_data);
         }
         return genres_table_data_vec;
@@ -399,7 +769,7 @@ impl AudioFile for AudioFileFLAC {
             let mut album_artists_table_data = ALBUM_ARTISTS_TABLE_DATA::default();
             album_artists_table_data.artist_name = artist.clone();
             album_artists_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            album_artists_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            album_artists_table_data.dt_added = now_dt();
             album_artists_table_data_vec.push(album_artists_table_data);
         }
         return album_artists_table_data_vec;
@@ -413,7 +783,7 @@ impl AudioFile for AudioFileFLAC {
             let mut song_artists_table_data = SONG_ARTISTS_TABLE_DATA::default();
             song_artists_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
             song_artists_table_data.artist_name = artist.clone();
-            song_artists_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            song_artists_table_data.dt_added = now_dt();
             song_artists_table_data_vec.push(song_artists_table_data);
         }
         return song_artists_table_data_vec;
@@ -432,7 +802,10 @@ impl AudioFile for AudioFileFLAC {
             Err(_) => song_table_data.filesize_bytes = -1,  
         }
         
-        song_table_data.padding_bytes = -1; // just set this to -1 for now
+        song_table_data.padding_bytes = match self.raw_metadata.get("padding_bytes") {
+            Some(padding_bytes) => padding_bytes[0].parse::<i64>().unwrap_or(-1),
+            None => -1,
+        };
         
         let temp_album_artwork_bit_depth = self.raw_metadata.get("album_artwork_bit_depth").unwrap()[0]
             .parse::<i64>();
@@ -442,7 +815,10 @@ impl AudioFile for AudioFileFLAC {
             Err(_) => song_table_data.album_artwork_bit_depth = -1,  
         }
         
-        song_table_data.album_artwork_colors = -1; // just set this to -1 for now
+        song_table_data.album_artwork_colors = match self.raw_metadata.get("album_artwork_colors") {
+            Some(colors) => colors[0].parse::<i64>().unwrap_or(-1),
+            None => -1,
+        };
         
         let temp_album_artwork_height = self.raw_metadata.get("album_artwork_height").unwrap()[0]
             .parse::<i64>();
@@ -492,6 +868,11 @@ impl AudioFile for AudioFileFLAC {
             Err(_) => song_table_data.duration = -1.0,  
         }
         
+        song_table_data.total_samples = match self.raw_metadata.get("total_samples") {
+            Some(total_samples) => total_samples[0].parse::<i64>().unwrap_or(-1),
+            None => -1,
+        };
+
         let temp_sample_rate = self.raw_metadata.get("sample_rate").unwrap()[0]
             .parse::<i64>();
         
@@ -552,11 +933,21 @@ impl AudioFile for AudioFileFLAC {
         
         song_table_data.source = self.raw_metadata.get("SOURCE").unwrap()[0].clone();
         song_table_data.filetype = "flac".to_string();
+        song_table_data.audio_hash = self.raw_metadata.get("audio_hash").unwrap()[0].clone();
+        song_table_data.recording_id = self.raw_metadata.get("recording_id").unwrap()[0].clone();
+        song_table_data.dt_added = now_dt();
         
         song_table_data
     }
 
-    fn load_file(&mut self, filepath: String) {
+    fn get_audio_hash(&self) -> String {
+        self.raw_metadata.get("audio_hash").unwrap()[0].clone()
+    }
+
+    fn load_file(&mut self, filepath: String) -> Result<()> {
+        // the libraries below panic on files they can't open, so find out first
+        let filesize = std::fs::metadata(&filepath)?.len();
+
         // add all the data from the symphonia library
         let metadata = add_symphonia_data(filepath.clone(), "flac".to_string());
         self.raw_metadata = metadata;
@@ -569,26 +960,33 @@ impl AudioFile for AudioFileFLAC {
         self.filepath = filepath;
 
         // add the filesize to the metadata
-        let file = File::open(&self.filepath).unwrap();
-        let metadata = file.metadata().unwrap();
-        let filesize = metadata.len();
-
         let mut filesize_vec: Vec<String> = Vec::new();
         filesize_vec.push(filesize.to_string());
 
         self.raw_metadata
             .insert("filesize".to_string(), filesize_vec);
+        if let Ok(padding_bytes) = tag_padding_bytes(&self.filepath) {
+            self.raw_metadata
+                .insert("padding_bytes".to_string(), vec![padding_bytes.to_string()]);
+        }
 
-        // make the song_id the hash of song title + artist + album
-        let mut song_id = String::new();
-        song_id.push_str(&self.raw_metadata.get("TITLE").unwrap()[0]);  
-        song_id.push_str(&self.raw_metadata.get("filesize").unwrap()[0]);
-        song_id.push_str(&self.raw_metadata.get("ALBUM").unwrap()[0]);
         self.raw_metadata.insert(
-            "song_id".to_string(),
-            vec![string_to_hash(song_id).unwrap()],
+            "album_artwork_colors".to_string(),
+            vec![front_cover_color(&self.filepath).to_string()],
         );
 
+        // picard writes the recording id as MUSICBRAINZ_TRACKID in vorbis comments
+        let musicbrainz_id = match self.raw_metadata.get("MUSICBRAINZ_TRACKID") {
+            Some(ids) => ids[0].clone(),
+            None => "".to_string(),
+        };
+        let audio_hash = audio_hash_or_file_hash(&self.filepath)?;
+        self.raw_metadata
+            .insert("audio_hash".to_string(), vec![audio_hash.clone()]);
+        self.raw_metadata.insert("song_id".to_string(), vec![audio_hash]);
+        let recording_id = recording_id(&musicbrainz_id, &self.raw_metadata.get("ISRC").unwrap()[0]);
+        self.raw_metadata.insert("recording_id".to_string(), vec![recording_id]);
+        Ok(())
     }
 }
 
@@ -633,7 +1031,17 @@ impl AudioFileMP3 {
     /// 10. composers
     /// 11. publisher
     pub fn add_id3_data(&mut self, filepath: String) {
-        let metadata = mp3_metadata::read_from_file(filepath).unwrap();
+        // a broken file gets the defaults from load_file instead of taking the whole scan down,
+        // file_health::deep_check_file can say what's wrong with it
+        // mp3_metadata only looks at the first frame for the bitrate, which means nothing for VBR files
+        let stream_info = mpeg_stream_info(&filepath).ok();
+        let metadata = match mp3_metadata::read_from_file(filepath) {
+            Ok(metadata) if !metadata.frames.is_empty() => metadata,
+            _ => {
+                self.raw_metadata = HashMap::new();
+                return;
+            }
+        };
         let audiotag = metadata.tag;
         let mut id3_data: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -683,7 +1091,19 @@ impl AudioFileMP3 {
 
         // add the duration
         let mut duration_vec: Vec<String> = Vec::new();
-        let duration = metadata.duration.as_secs();
+        let duration = match &stream_info {
+            Some(stream_info) => {
+                id3_data.insert(
+                    "total_samples".to_string(),
+                    vec![stream_info.total_samples.to_string()],
+                );
+                duration_from_samples(
+                    stream_info.total_samples as i64,
+                    stream_info.sample_rate as i64,
+                )
+            }
+            None => metadata.duration.as_secs_f64(),
+        };
         duration_vec.push(duration.to_string());
         id3_data.insert("duration".to_string(), duration_vec);
 
@@ -698,9 +1118,18 @@ impl AudioFileMP3 {
         let mut bitrate_vec: Vec<String> = Vec::new();
         let bitrate_u16 = metadata.frames[0].bitrate;
         // bitrate is in kbps, we want it in bps
-        let bitrate: u64 = (bitrate_u16 as u64) * 1000;
+        let bitrate: u64 = match &stream_info {
+            Some(stream_info) => stream_info.bitrate,
+            None => (bitrate_u16 as u64) * 1000,
+        };
         bitrate_vec.push(bitrate.to_string());
         id3_data.insert("bitrate".to_string(), bitrate_vec);
+        if let Some(stream_info) = &stream_info {
+            id3_data.insert(
+                "bitrate_mode".to_string(),
+                vec![stream_info.mode.as_str().to_string()],
+            );
+        }
 
         // add the channels
         let mut channels_vec: Vec<String> = Vec::new();
@@ -767,6 +1196,40 @@ impl AudioFileMP3 {
         // print id3_data
         self.raw_metadata = id3_data;
     }
+
+    /// Adds the isrc and musicbrainz_recording_id to the raw_metadata hashmap.
+    /// mp3-metadata doesn't read either so these come straight from the ID3v2 tag.
+    /// Has to be called after add_id3_data since that one replaces the whole hashmap.
+    pub fn add_identifier_data(&mut self, filepath: String) {
+        use id3::TagLike;
+
+        let tag = match id3::Tag::read_from_path(filepath) {
+            Ok(tag) => tag,
+            Err(_) => return,
+        };
+
+        if let Some(isrc) = tag.get("TSRC").and_then(|frame| frame.content().text()) {
+            self.raw_metadata
+                .insert("isrc".to_string(), vec![isrc.trim_end_matches('\0').to_string()]);
+        }
+
+        // the recording id lives in a UFID frame: the owner, a \0, then the id itself
+        for frame in tag.frames().filter(|frame| frame.id() == "UFID") {
+            let data = match frame.content().to_unknown() {
+                Ok(unknown) => unknown.data.clone(),
+                Err(_) => continue,
+            };
+            let mut parts = data.splitn(2, |byte| *byte == 0);
+            if parts.next() == Some(b"http://musicbrainz.org".as_slice()) {
+                if let Some(id) = parts.next() {
+                    self.raw_metadata.insert(
+                        "musicbrainz_recording_id".to_string(),
+                        vec![String::from_utf8_lossy(id).to_string()],
+                    );
+                }
+            }
+        }
+    }
 }
 
 impl AudioFile for AudioFileMP3 {
@@ -778,7 +1241,7 @@ impl AudioFile for AudioFileMP3 {
             let mut song_artists_table_data = SONG_ARTISTS_TABLE_DATA::default();
             song_artists_table_data.artist_name = artist.clone();
             song_artists_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            song_artists_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            song_artists_table_data.dt_added = now_dt();
             song_artists_vec.push(song_artists_table_data);
         }
 
@@ -792,7 +1255,7 @@ impl AudioFile for AudioFileMP3 {
             let mut album_artists_table_data = ALBUM_ARTISTS_TABLE_DATA::default();
             album_artists_table_data.artist_name = artist.clone();
             album_artists_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            album_artists_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            album_artists_table_data.dt_added = now_dt();
             album_artists_vec.push(album_artists_table_data);
         }
 
@@ -807,7 +1270,7 @@ impl AudioFile for AudioFileMP3 {
             let mut composers_table_data = COMPOSERS_TABLE_DATA::default();
             composers_table_data.composer_name = composer.clone();
             composers_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            composers_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            composers_table_data.dt_added = now_dt();
             // if composer_name is -1, then there is no composer
             composers_vec.push(composers_table_data);
         }
@@ -826,7 +1289,7 @@ impl AudioFile for AudioFileMP3 {
             let mut genres_table_data = GENRES_TABLE_DATA::default();
             genres_table_data.genre_name = genre.clone();
             genres_table_data.song_id = self.raw_metadata.get("song_id").unwrap()[0].clone();
-            genres_table_data.dt_added = chrono::Utc::now().naive_utc().to_string();
+            genres_table_data.dt_added = now_dt();
             genres_vec.push(genres_table_data);
         }
 
@@ -870,8 +1333,8 @@ impl AudioFile for AudioFileMP3 {
         }
 
         song_table_data.duration = self.raw_metadata.get("duration").unwrap()[0]
-            .parse::<i64>()
-            .unwrap() as f64;
+            .parse::<f64>()
+            .unwrap();
         song_table_data.sample_rate = self.raw_metadata.get("sample_rate").unwrap()[0]
             .parse::<i64>()
             .unwrap();
@@ -882,11 +1345,33 @@ impl AudioFile for AudioFileMP3 {
             .clone()
             .parse::<i64>()
             .unwrap();
+        song_table_data.padding_bytes = match self.raw_metadata.get("padding_bytes") {
+            Some(padding_bytes) => padding_bytes[0].parse::<i64>().unwrap_or(-1),
+            None => -1,
+        };
+        song_table_data.album_artwork_colors = match self.raw_metadata.get("album_artwork_colors") {
+            Some(colors) => colors[0].parse::<i64>().unwrap_or(-1),
+            None => -1,
+        };
         song_table_data.filetype = self.raw_metadata.get("filetype").unwrap()[0].clone();
+        song_table_data.isrc = self.raw_metadata.get("isrc").unwrap()[0].clone();
+        song_table_data.audio_hash = self.raw_metadata.get("audio_hash").unwrap()[0].clone();
+        song_table_data.recording_id = self.raw_metadata.get("recording_id").unwrap()[0].clone();
+        song_table_data.dt_added = now_dt();
+        song_table_data.bitrate_mode = self.raw_metadata.get("bitrate_mode").unwrap()[0].clone();
+        song_table_data.total_samples = self.raw_metadata.get("total_samples").unwrap()[0]
+            .parse::<i64>()
+            .unwrap();
         song_table_data
     }
 
-    fn load_file(&mut self, filepath: String) {
+    fn get_audio_hash(&self) -> String {
+        self.raw_metadata.get("audio_hash").unwrap()[0].clone()
+    }
+
+    fn load_file(&mut self, filepath: String) -> Result<()> {
+        // the libraries below panic on files they can't open, so find out first
+        let filesize = std::fs::metadata(&filepath)?.len();
         self.filepath = filepath.clone();
 
         let defaultMap = SONG_TABLE_DATA::default();
@@ -894,6 +1379,11 @@ impl AudioFile for AudioFileMP3 {
         // now we need to make default values for all the metadata.
 
         self.add_id3_data(filepath.clone());
+        self.add_identifier_data(filepath.clone());
+        self.raw_metadata.insert(
+            "album_artwork_colors".to_string(),
+            vec![front_cover_color(&filepath).to_string()],
+        );
 
         // now go through self.raw_metadata and add the default values for the ones that are missing, i.e. if it's None
         let keys = [
@@ -910,6 +1400,10 @@ impl AudioFile for AudioFileMP3 {
             "filesize",
             "song_id",
             "filetype",
+            "isrc",
+            "musicbrainz_recording_id",
+            "bitrate_mode",
+            "total_samples",
         ];
         for key in keys.iter() {
             if self.raw_metadata.get(key.clone()).is_none() {
@@ -953,6 +1447,18 @@ impl AudioFile for AudioFileMP3 {
                     &"filetype" => self
                         .raw_metadata
                         .insert(key.to_string(), vec!["-1".to_string()]),
+                    &"isrc" => self
+                        .raw_metadata
+                        .insert(key.to_string(), vec![defaultMap.isrc.clone()]),
+                    &"musicbrainz_recording_id" => self
+                        .raw_metadata
+                        .insert(key.to_string(), vec!["".to_string()]),
+                    &"bitrate_mode" => self
+                        .raw_metadata
+                        .insert(key.to_string(), vec![defaultMap.bitrate_mode.clone()]),
+                    &"total_samples" => self
+                        .raw_metadata
+                        .insert(key.to_string(), vec![defaultMap.total_samples.to_string()]),
                     _ => self
                         .raw_metadata
                         .insert(key.to_string(), vec!["-1".to_string()]),
@@ -961,20 +1467,26 @@ impl AudioFile for AudioFileMP3 {
         }
 
         // add the filesize to the metadata
-        let file = File::open(&self.filepath).unwrap();
-        let metadata = file.metadata().unwrap();
-        let filesize = metadata.len();
-
         let mut filesize_vec: Vec<String> = Vec::new();
         filesize_vec.push(filesize.to_string());
 
         self.raw_metadata
             .insert("filesize".to_string(), filesize_vec);
-        self.raw_metadata.insert(
-            "song_id".to_string(),
-            vec![file_to_hash(self.filepath.clone()).unwrap()],
+        if let Ok(padding_bytes) = tag_padding_bytes(&self.filepath) {
+            self.raw_metadata
+                .insert("padding_bytes".to_string(), vec![padding_bytes.to_string()]);
+        }
+        let audio_hash = audio_hash_or_file_hash(&self.filepath)?;
+        self.raw_metadata
+            .insert("audio_hash".to_string(), vec![audio_hash.clone()]);
+        self.raw_metadata.insert("song_id".to_string(), vec![audio_hash]);
+        let recording_id = recording_id(
+            &self.raw_metadata.get("musicbrainz_recording_id").unwrap()[0],
+            &self.raw_metadata.get("isrc").unwrap()[0],
         );
+        self.raw_metadata.insert("recording_id".to_string(), vec![recording_id]);
         self.raw_metadata
             .insert("filetype".to_string(), vec!["mp3".to_string()]);
+        Ok(())
     }
 }
@@ -0,0 +1,544 @@
+// Applying one tag edit to many songs at once, for library cleanup: set the album artist of a whole album, fix the
+// capitalisation of genres, renumber the tracks of an album...
+//
+// A batch goes like this:
+// * preview_batch works out, per file, which fields change from what to what. Nothing is written.
+// * apply_batch writes every file (see tag_editor::write_tags) and updates the database in one transaction. If a file
+//   can't be written, the files already written get their old tags back and the database isn't touched.
+// * every applied batch is journaled in tag_edit_batches and tag_edit_journal, with the old and new values of every
+//   changed field, so revert_batch can put them back later.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::string_to_hash;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use crate::engine::tag_editor::*;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::other(e.to_string())
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           EDITS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// How Case changes the letters of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextCase {
+    /// First letter of every word upper case, the rest lower case: "hip-HOP" becomes "Hip-Hop"
+    Title,
+    Upper,
+    Lower,
+}
+
+impl TextCase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TextCase::Title => "title",
+            TextCase::Upper => "upper",
+            TextCase::Lower => "lower",
+        }
+    }
+
+    pub fn from_case(case: &str) -> Option<Self> {
+        match case {
+            "title" => Some(TextCase::Title),
+            "upper" => Some(TextCase::Upper),
+            "lower" => Some(TextCase::Lower),
+            _ => None,
+        }
+    }
+
+    /// ```
+    /// # use decibl_metadata::engine::batch_edit::TextCase;
+    /// assert_eq!(TextCase::Title.apply("hip-HOP  and r&b"), "Hip-Hop  And R&B");
+    /// assert_eq!(TextCase::Title.apply("rock'n'roll"), "Rock'n'roll");
+    /// ```
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            TextCase::Upper => value.to_uppercase(),
+            TextCase::Lower => value.to_lowercase(),
+            TextCase::Title => {
+                let mut titled = String::with_capacity(value.len());
+                let mut word_start = true;
+                for c in value.chars() {
+                    if word_start {
+                        titled.extend(c.to_uppercase());
+                    } else {
+                        titled.extend(c.to_lowercase());
+                    }
+                    // apostrophes are inside words, everything else that isn't a letter or digit starts a new one
+                    word_start = !c.is_alphanumeric() && c != '\'';
+                }
+                titled
+            }
+        }
+    }
+}
+
+/// One edit applied to every song of a batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchEdit {
+    /// Gives the field the same values on every song
+    Set(TagField, Vec<String>),
+    Remove(TagField),
+    /// Changes the letters of every value of the field
+    Case(TagField, TextCase),
+    /// Replaces text in every value of the field
+    Replace(TagField, String, String),
+    /// Numbers the tracks 1, 2, 3... in the order the songs were given, and sets the track total to how many there are
+    RenumberTracks,
+}
+
+/// What a batch does to one field of one song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: TagField,
+    /// Empty if the field isn't set
+    pub old_values: Vec<String>,
+    /// Empty if the field gets removed
+    pub new_values: Vec<String>,
+}
+
+/// What a batch does to one song. Songs it doesn't change aren't in the preview.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongDiff {
+    pub song_id: String,
+    pub song_path: String,
+    pub fields: Vec<FieldDiff>,
+}
+
+impl SongDiff {
+    /// The tag changes that turn the old values into the new ones
+    pub fn changes(&self) -> Vec<TagChange> {
+        self.fields
+            .iter()
+            .map(|diff| match diff.new_values.is_empty() {
+                true => TagChange::Remove(diff.field),
+                false => TagChange::Set(diff.field, diff.new_values.clone()),
+            })
+            .collect()
+    }
+
+    /// The tag changes that turn the new values back into the old ones
+    pub fn undo_changes(&self) -> Vec<TagChange> {
+        self.fields
+            .iter()
+            .map(|diff| match diff.old_values.is_empty() {
+                true => TagChange::Remove(diff.field),
+                false => TagChange::Set(diff.field, diff.old_values.clone()),
+            })
+            .collect()
+    }
+}
+
+/// What a batch would do, or did
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BatchPreview {
+    /// Empty until the batch is applied
+    pub batch_id: String,
+    pub songs: Vec<SongDiff>,
+    /// Songs that couldn't be edited, as (song_id, why)
+    pub skipped: Vec<(String, String)>,
+}
+
+impl BatchPreview {
+    /// A line per changed field, grouped by file:
+    /// ```text
+    /// /music/Enemy.flac
+    ///     genre: ["alternative"] -> ["Alternative"]
+    /// ```
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for song in &self.songs {
+            text.push_str(&format!("{}\n", song.song_path));
+            for diff in &song.fields {
+                text.push_str(&format!(
+                    "    {}: {:?} -> {:?}\n",
+                    diff.field.as_str(),
+                    diff.old_values,
+                    diff.new_values
+                ));
+            }
+        }
+        for (song_id, reason) in &self.skipped {
+            text.push_str(&format!("skipped {}: {}\n", song_id, reason));
+        }
+        text
+    }
+}
+
+// the values of the fields of one song after the edits, `position` is where the song is in the batch
+fn edited_values(
+    tags: &HashMap<TagField, Vec<String>>,
+    edits: &[BatchEdit],
+    position: usize,
+    song_count: usize,
+) -> HashMap<TagField, Vec<String>> {
+    let mut values = tags.clone();
+    for edit in edits {
+        match edit {
+            BatchEdit::Set(field, new_values) => {
+                let new_values: Vec<String> = new_values
+                    .iter()
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+                    .collect();
+                values.insert(*field, new_values);
+            }
+            BatchEdit::Remove(field) => {
+                values.insert(*field, Vec::new());
+            }
+            BatchEdit::Case(field, case) => {
+                if let Some(field_values) = values.get_mut(field) {
+                    *field_values = field_values.iter().map(|value| case.apply(value)).collect();
+                }
+            }
+            BatchEdit::Replace(field, from, to) => {
+                if let Some(field_values) = values.get_mut(field) {
+                    if !from.is_empty() {
+                        *field_values = field_values
+                            .iter()
+                            .map(|value| value.replace(from.as_str(), to))
+                            .collect();
+                    }
+                }
+            }
+            BatchEdit::RenumberTracks => {
+                values.insert(TagField::TrackNumber, vec![(position + 1).to_string()]);
+                values.insert(TagField::TrackTotal, vec![song_count.to_string()]);
+            }
+        }
+    }
+    values
+}
+
+/// Works out what a batch of edits does to every song, without writing anything. The old values are read from the
+/// files themselves, so the preview shows what's really in them even if the database is behind.
+pub fn preview_batch(song_ids: &[String], edits: &[BatchEdit]) -> BatchPreview {
+    let mut preview = BatchPreview::default();
+    for (position, song_id) in song_ids.iter().enumerate() {
+        let song_path = match get_songpaths_by_song_id(song_id.clone()).into_iter().next() {
+            Some(songpath) => songpath.song_path,
+            None => {
+                preview
+                    .skipped
+                    .push((song_id.clone(), "the song has no file".to_string()));
+                continue;
+            }
+        };
+        let tags = match read_tags(&song_path) {
+            Ok(tags) => tags,
+            Err(e) => {
+                preview.skipped.push((song_id.clone(), e.to_string()));
+                continue;
+            }
+        };
+
+        let new_tags = edited_values(&tags, edits, position, song_ids.len());
+        let fields: Vec<FieldDiff> = TagField::ALL
+            .iter()
+            .filter_map(|field| {
+                let old_values = tags.get(field).cloned().unwrap_or_default();
+                let new_values = new_tags.get(field).cloned().unwrap_or_default();
+                match old_values == new_values {
+                    true => None,
+                    false => Some(FieldDiff {
+                        field: *field,
+                        old_values,
+                        new_values,
+                    }),
+                }
+            })
+            .collect();
+
+        let diff = SongDiff {
+            song_id: song_id.clone(),
+            song_path,
+            fields,
+        };
+        match validate_changes(&diff.changes()) {
+            Ok(()) if diff.fields.is_empty() => {}
+            Ok(()) => preview.songs.push(diff),
+            Err(e) => preview.skipped.push((song_id.clone(), e.to_string())),
+        }
+    }
+    preview
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           APPLYING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// writes a set of changes to each song's file. If one fails the files already written are put back the way they
+// were and the error is returned.
+fn write_all_or_nothing(songs: &[SongDiff], undo: bool) -> Result<()> {
+    for (written, song) in songs.iter().enumerate() {
+        let changes = match undo {
+            true => song.undo_changes(),
+            false => song.changes(),
+        };
+        if let Err(e) = write_tags(&song.song_path, &changes) {
+            for song in &songs[..written] {
+                let changes = match undo {
+                    true => song.changes(),
+                    false => song.undo_changes(),
+                };
+                let _ = write_tags(&song.song_path, &changes);
+            }
+            return Err(Error::new(
+                e.kind(),
+                format!("Could not write {}: {}", song.song_path, e),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// updates the database rows of each song, as (song_id, diff), to its changes
+fn update_songs(conn: &Connection, songs: &[(String, SongDiff)], undo: bool) -> Result<()> {
+    for (song_id, song) in songs {
+        let changes = match undo {
+            true => song.undo_changes(),
+            false => song.changes(),
+        };
+        update_song_tags(conn, song_id, &changes).map_err(db_error)?;
+        update_song_file(conn, song_id, &song.song_path)?;
+    }
+    Ok(())
+}
+
+/// Applies a batch of edits to songs, see preview_batch for what it does to each. With dry_run nothing is written and
+/// the preview is all that's returned.
+/// Songs that can't be edited are skipped, every other song is changed or none are: the files are written first, then
+/// the database rows and the undo journal in one transaction. If that fails the files are put back.
+/// ```no_run
+/// # use decibl_metadata::engine::batch_edit::*;
+/// # use decibl_metadata::engine::tag_editor::TagField;
+/// let album: Vec<String> = vec!["song1".to_string(), "song2".to_string()];
+/// let edits = [
+///     BatchEdit::Set(TagField::AlbumArtist, vec!["Imagine Dragons".to_string()]),
+///     BatchEdit::Case(TagField::Genre, TextCase::Title),
+///     BatchEdit::RenumberTracks,
+/// ];
+/// println!("{}", apply_batch(&album, &edits, "fix the album", true).unwrap().to_text());
+/// let applied = apply_batch(&album, &edits, "fix the album", false).unwrap();
+/// revert_batch(&applied.batch_id).unwrap();
+/// ```
+pub fn apply_batch(
+    song_ids: &[String],
+    edits: &[BatchEdit],
+    description: &str,
+    dry_run: bool,
+) -> Result<BatchPreview> {
+    let mut preview = preview_batch(song_ids, edits);
+    if dry_run || preview.songs.is_empty() {
+        return Ok(preview);
+    }
+
+    write_all_or_nothing(&preview.songs, false)?;
+
+    let created_dt = now_dt();
+    let songs: Vec<(String, SongDiff)> = preview
+        .songs
+        .iter()
+        .map(|song| (song.song_id.clone(), song.clone()))
+        .collect();
+
+    // the files are written, if the rows and the journal can't be they're put back
+    let journaled = (|| -> Result<String> {
+        let mut conn = Connection::open(get_database_file_path()).map_err(db_error)?;
+        let tx = conn.transaction().map_err(db_error)?;
+        update_songs(&tx, &songs, false)?;
+        // the same edits can be applied twice in a second, so the batch's row number goes into its id
+        let batch_number: i64 = tx
+            .query_row(
+                "SELECT COALESCE(MAX(rowid), 0) + 1 FROM tag_edit_batches",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(db_error)?;
+        let batch_id = string_to_hash(format!(
+            "{}|{}|{}|{:?}",
+            batch_number, created_dt, description, song_ids
+        ))
+        .unwrap();
+        tx.execute(
+            "INSERT INTO tag_edit_batches (batch_id, description, song_count, status, created_dt, reverted_dt) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![batch_id, description, preview.songs.len() as i64, "applied", created_dt, ""],
+        )
+        .map_err(db_error)?;
+        for song in &preview.songs {
+            for diff in &song.fields {
+                tx.execute(
+                    "INSERT OR REPLACE INTO tag_edit_journal (batch_id, song_id, song_path, field, old_values, new_values) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        batch_id,
+                        song.song_id,
+                        song.song_path,
+                        diff.field.as_str(),
+                        serde_json::to_string(&diff.old_values).unwrap(),
+                        serde_json::to_string(&diff.new_values).unwrap(),
+                    ],
+                )
+                .map_err(db_error)?;
+            }
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(batch_id)
+    })();
+    let batch_id = match journaled {
+        Ok(batch_id) => batch_id,
+        Err(e) => {
+            let _ = write_all_or_nothing(&preview.songs, true);
+            return Err(e);
+        }
+    };
+
+    preview.batch_id = batch_id;
+    Ok(preview)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           UNDO
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Every batch that was applied, newest first
+pub fn get_batches() -> Vec<TAG_EDIT_BATCHES_TABLE_DATA> {
+    let mut batches = get_all_tag_edit_batches();
+    batches.sort_by(|a, b| b.created_dt.cmp(&a.created_dt));
+    batches
+}
+
+pub fn get_batch(batch_id: &str) -> Option<TAG_EDIT_BATCHES_TABLE_DATA> {
+    get_all_tag_edit_batches()
+        .into_iter()
+        .find(|batch| batch.batch_id == batch_id)
+}
+
+/// What a batch did, one SongDiff per file, as the journal has it
+pub fn get_batch_journal(batch_id: &str) -> Vec<SongDiff> {
+    let mut songs: Vec<SongDiff> = Vec::new();
+    for entry in get_all_tag_edit_journal() {
+        if entry.batch_id != batch_id {
+            continue;
+        }
+        let field = match TagField::from_field(&entry.field) {
+            Some(field) => field,
+            None => continue,
+        };
+        let diff = FieldDiff {
+            field,
+            old_values: serde_json::from_str(&entry.old_values).unwrap_or_default(),
+            new_values: serde_json::from_str(&entry.new_values).unwrap_or_default(),
+        };
+        match songs
+            .iter_mut()
+            .find(|song| song.song_path == entry.song_path)
+        {
+            Some(song) => song.fields.push(diff),
+            None => songs.push(SongDiff {
+                song_id: entry.song_id,
+                song_path: entry.song_path,
+                fields: vec![diff],
+            }),
+        }
+    }
+    for song in songs.iter_mut() {
+        song.fields.sort_by_key(|diff| {
+            TagField::ALL
+                .iter()
+                .position(|field| *field == diff.field)
+                .unwrap()
+        });
+    }
+    songs
+}
+
+/// Puts back the values a batch changed. Fields that were changed again since the batch (their file doesn't have
+/// the values the batch wrote anymore) are left alone, as are files that are gone. Like apply_batch, every other file
+/// is reverted or none are. Returns what was reverted.
+pub fn revert_batch(batch_id: &str) -> Result<BatchPreview> {
+    let batch = get_batch(batch_id).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("There's no batch {}", batch_id),
+        )
+    })?;
+    if batch.status != "applied" {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Batch {} is {}, not applied", batch_id, batch.status),
+        ));
+    }
+
+    let mut preview = BatchPreview {
+        batch_id: batch_id.to_string(),
+        ..Default::default()
+    };
+    // (the song_id the song has now, the fields that can be reverted)
+    let mut songs: Vec<(String, SongDiff)> = Vec::new();
+    for mut song in get_batch_journal(batch_id) {
+        let tags = match read_tags(&song.song_path) {
+            Ok(tags) => tags,
+            Err(e) => {
+                preview.skipped.push((song.song_id.clone(), e.to_string()));
+                continue;
+            }
+        };
+        let changed_since: Vec<&str> = song
+            .fields
+            .iter()
+            .filter(|diff| tags.get(&diff.field).cloned().unwrap_or_default() != diff.new_values)
+            .map(|diff| diff.field.as_str())
+            .collect();
+        if !changed_since.is_empty() {
+            preview.skipped.push((
+                song.song_id.clone(),
+                format!("changed since the batch: {}", changed_since.join(", ")),
+            ));
+        }
+        song.fields
+            .retain(|diff| tags.get(&diff.field).cloned().unwrap_or_default() == diff.new_values);
+        if song.fields.is_empty() {
+            continue;
+        }
+        // the file can belong to a different song by now (a rescan after it was replaced), the path is what stays
+        let song_id = get_song_id_by_path(song.song_path.clone()).unwrap_or(song.song_id.clone());
+        songs.push((song_id, song));
+    }
+
+    let diffs: Vec<SongDiff> = songs.iter().map(|(_, song)| song.clone()).collect();
+    write_all_or_nothing(&diffs, true)?;
+
+    // the files are reverted, if the rows can't be they get the batch's values again
+    let reverted = (|| -> Result<()> {
+        let mut conn = Connection::open(get_database_file_path()).map_err(db_error)?;
+        let tx = conn.transaction().map_err(db_error)?;
+        update_songs(&tx, &songs, true)?;
+        tx.execute(
+            "UPDATE tag_edit_batches SET status = ?1, reverted_dt = ?2 WHERE batch_id = ?3",
+            params!["reverted", now_dt(), batch_id],
+        )
+        .map_err(db_error)?;
+        tx.commit().map_err(db_error)
+    })();
+    if let Err(e) = reverted {
+        let _ = write_all_or_nothing(&diffs, false);
+        return Err(e);
+    }
+
+    preview.songs = diffs;
+    Ok(preview)
+}
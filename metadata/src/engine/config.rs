@@ -21,6 +21,12 @@ pub static DATABASE_FILE_PATH: Lazy<path::PathBuf> =
     Lazy::new(|| APP_INFO.config_dir().join("analytics.db"));
 pub static ARTIST_PHOTO_PATH: Lazy<path::PathBuf> =
     Lazy::new(|| APP_INFO.config_dir().join("artists"));
+pub static BACKUP_PATH: Lazy<path::PathBuf> =
+    Lazy::new(|| APP_INFO.config_dir().join("backups"));
+pub static WAVEFORM_PATH: Lazy<path::PathBuf> =
+    Lazy::new(|| APP_INFO.config_dir().join("waveforms"));
+pub static ARTWORK_PATH: Lazy<path::PathBuf> =
+    Lazy::new(|| APP_INFO.config_dir().join("artwork"));
 
 pub static TEST_SOUNDFILES_PATH: Lazy<path::PathBuf> = Lazy::new(|| {
     path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -48,6 +54,18 @@ pub fn get_artist_photo_path() -> String {
     ARTIST_PHOTO_PATH.to_str().unwrap().to_string()
 }
 
+pub fn get_backup_path() -> String {
+    BACKUP_PATH.to_str().unwrap().to_string()
+}
+
+pub fn get_waveform_path() -> String {
+    WAVEFORM_PATH.to_str().unwrap().to_string()
+}
+
+pub fn get_artwork_path() -> String {
+    ARTWORK_PATH.to_str().unwrap().to_string()
+}
+
 pub fn get_album_photo_path(artist_name: &str, album_name: &str) -> String {
     let mut path = APP_INFO.config_dir().join("artists");
     // we want the apth to be config_dir() / "artists" / artist_name / album_name
@@ -175,6 +193,18 @@ pub fn get_config_var(key: &str) -> (String, String) {
     (key.to_string(), value.to_string())
 }
 
+/// get_config_var, but `default` when the config file or the key isn't there instead of panicking
+pub fn get_config_var_or(key: &str, default: &str) -> String {
+    let contents = std::fs::read_to_string(CONFIG_FILE_PATH.as_path()).unwrap_or_default();
+    let deserialized_map: BTreeMap<String, String> =
+        serde_yaml::from_str(&contents).unwrap_or_default();
+
+    match deserialized_map.get(key) {
+        Some(value) => value.to_string(),
+        None => default.to_string(),
+    }
+}
+
 pub fn get_config_as_str() -> String {
     let config_file_str = CONFIG_FILE_PATH.to_str().unwrap();
     let mut file = File::open(config_file_str).expect("Unable to open file");
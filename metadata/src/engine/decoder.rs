@@ -0,0 +1,139 @@
+// Decoding whole files with symphonia, for everything that needs the actual audio: loudness, waveforms, tempo and
+// key, silence for gapless playback and the health checks. They all open the file the same way, decode the default
+// track and skip frames that don't decode, so that lives here once.
+
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions, FinalizeResult};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// The default track of a file, ready to be decoded packet by packet
+pub struct AudioDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    samples: Option<SampleBuffer<f32>>,
+    /// What the container says about the track, sample rate, channels, number of samples and so on
+    pub codec_params: CodecParameters,
+    /// Packets that failed to decode so far, including a read error that ended the file early
+    pub decode_errors: i64,
+}
+
+impl AudioDecoder {
+    /// Opens a file and sets up a decoder for its default track, the extension is used as a hint for the format.
+    /// None if the file can't be opened or symphonia can't read it.
+    /// ```no_run
+    /// # use decibl_metadata::engine::decoder::*;
+    /// # use symphonia::core::codecs::DecoderOptions;
+    /// # use symphonia::core::formats::FormatOptions;
+    /// let mut decoder =
+    ///     AudioDecoder::open("/music/song.flac", FormatOptions::default(), DecoderOptions::default()).unwrap();
+    /// while let Some(samples) = decoder.next_samples() {
+    ///     println!("{} samples", samples.len());
+    /// }
+    /// ```
+    pub fn open(
+        filepath: &str,
+        format_options: FormatOptions,
+        decoder_options: DecoderOptions,
+    ) -> Option<Self> {
+        let src = std::fs::File::open(filepath).ok()?;
+        let mss = MediaSourceStream::new(Box::new(src), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = std::path::Path::new(filepath)
+            .extension()
+            .and_then(|e| e.to_str())
+        {
+            hint.with_extension(&extension.to_lowercase());
+        }
+        let format = symphonia::default::get_probe()
+            .format(&hint, mss, &format_options, &MetadataOptions::default())
+            .ok()?
+            .format;
+
+        let track = format.default_track()?;
+        let track_id = track.id;
+        let codec_params = track.codec_params.clone();
+        let decoder = symphonia::default::get_codecs()
+            .make(&codec_params, &decoder_options)
+            .ok()?;
+
+        Some(AudioDecoder {
+            format,
+            decoder,
+            track_id,
+            samples: None,
+            codec_params,
+            decode_errors: 0,
+        })
+    }
+
+    /// The sample rate of the track, None if the container doesn't say or says 0
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.codec_params.sample_rate.filter(|rate| *rate > 0)
+    }
+
+    /// The number of channels in the track, None if the container doesn't say
+    pub fn channels(&self) -> Option<usize> {
+        Some(self.codec_params.channels?.count())
+    }
+
+    // decodes the next packet of the track into the decoder, false once there's nothing left to decode
+    fn advance(&mut self) -> bool {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                // running out of file is the normal way for this to end
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return false
+                }
+                Err(_) => {
+                    self.decode_errors += 1;
+                    return false;
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Ok(_) => return true,
+                // a bad frame or two shouldn't throw the rest of the file away
+                Err(Error::DecodeError(_)) => self.decode_errors += 1,
+                Err(_) => {
+                    self.decode_errors += 1;
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// The next packet of audio as symphonia decoded it. Frames that don't decode are counted and skipped, None at the
+    /// end of the file or once decoding can't carry on.
+    pub fn next_decoded(&mut self) -> Option<AudioBufferRef<'_>> {
+        if !self.advance() {
+            return None;
+        }
+        Some(self.decoder.last_decoded())
+    }
+
+    /// Like next_decoded, but as interleaved f32 samples
+    pub fn next_samples(&mut self) -> Option<&[f32]> {
+        if !self.advance() {
+            return None;
+        }
+        let decoded = self.decoder.last_decoded();
+        let buffer = self
+            .samples
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+        Some(buffer.samples())
+    }
+
+    /// Finishes decoding, this is where the decoder checks the audio against the file's MD5 if it was asked to verify
+    pub fn finalize(&mut self) -> FinalizeResult {
+        self.decoder.finalize()
+    }
+}
@@ -0,0 +1,352 @@
+// Finding the same track in the library more than once, like a flac rip and an old mp3 of the same song.
+// Songs are grouped by one or more DuplicateKeys, every group gets ranked best quality first, and the best one
+// is marked as the preferred version in the song_versions table.
+//
+// Nothing gets deleted. Listings honor the flag with SongFilter::PreferredVersions (or the PreferredVersionsOnly
+// smart playlist rule) and playlists with get_songs_in_playlist_preferred.
+//
+// Every file is its own song (its song_id is its audio_hash), so a FLAC and an MP3 of the same recording are two
+// songs. DuplicateKey::Recording groups them when they're tagged with the same MusicBrainz recording id or ISRC.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::{normalize_isrc, string_to_hash};
+use crate::engine::config::*;
+use crate::engine::models::*;
+use crate::engine::scrobbles::normalize_name;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// How far apart (in seconds) two songs with the same title/artist/album can be and still count as the same track.
+/// Different encoders pad the start and end a little, but a radio edit is a lot shorter than the album version.
+pub const DURATION_TOLERANCE_SECONDS: f64 = 2.0;
+
+/// Filetypes that don't throw anything away. Any of these beats a lossy file no matter the bitrate.
+pub const LOSSLESS_FILETYPES: [&str; 6] = ["flac", "wav", "alac", "aiff", "ape", "wv"];
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           FINDING DUPLICATES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// What makes two songs the same track
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuplicateKey {
+    /// Same title, artist and album once normalized (see scrobbles::normalize_name),
+    /// and durations within DURATION_TOLERANCE_SECONDS
+    Metadata,
+    /// Same ISRC. Tags that aren't a real ISRC ("-1", the wrong length) never match anything.
+    Isrc,
+    /// Same recording_id, so the same MusicBrainz recording id or, for songs without one, the same ISRC.
+    /// Songs that aren't tagged with either never match anything.
+    Recording,
+    /// Byte for byte the same audio (the audio_hash column). Files like that share a song_id, so this only finds
+    /// songs from before analyticsdb::rekey_all_songs was run.
+    AudioHash,
+    /// Same acoustic fingerprint. The map is song_id -> fingerprint (chromaprint or whatever you like),
+    /// songs that aren't in it are left out. Fingerprints have to match exactly.
+    Fingerprint(HashMap<String, String>),
+}
+
+/// A set of songs that are the same track, best quality first
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub group_id: String,
+    pub songs: Vec<SONG_TABLE_DATA>,
+}
+
+impl DuplicateGroup {
+    /// The best version of the group
+    pub fn best(&self) -> &SONG_TABLE_DATA {
+        &self.songs[0]
+    }
+}
+
+/// Compares two songs by quality, Greater means `a` is the better file.
+/// Lossless beats lossy, then higher bit_depth, sample_rate and bitrate win in that order (unknowns are -1 so they lose).
+/// filesize_bytes breaks any tie that's left.
+pub fn compare_quality(a: &SONG_TABLE_DATA, b: &SONG_TABLE_DATA) -> Ordering {
+    let lossless = |song: &SONG_TABLE_DATA| {
+        LOSSLESS_FILETYPES.contains(&song.filetype.to_lowercase().as_str())
+    };
+
+    lossless(a)
+        .cmp(&lossless(b))
+        .then(a.bit_depth.cmp(&b.bit_depth))
+        .then(a.sample_rate.cmp(&b.sample_rate))
+        .then(a.bitrate.cmp(&b.bitrate))
+        .then(a.filesize_bytes.cmp(&b.filesize_bytes))
+}
+
+// plain union-find over indices into the song list
+fn find_root(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let a = find_root(parents, a);
+    let b = find_root(parents, b);
+    if a != b {
+        parents[b] = a;
+    }
+}
+
+// the scanners store "-1" for tags a file doesn't have
+fn tag_value(value: &str) -> &str {
+    if value == "-1" {
+        ""
+    } else {
+        value
+    }
+}
+
+// buckets of song indices that the key says belong together
+fn key_buckets(songs: &[SONG_TABLE_DATA], key: &DuplicateKey) -> Vec<Vec<usize>> {
+    let mut buckets: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let bucket = match key {
+            DuplicateKey::Metadata => {
+                let title = normalize_name(tag_value(&song.title));
+                if title.is_empty() {
+                    continue;
+                }
+                format!(
+                    "{}\u{1f}{}\u{1f}{}",
+                    title,
+                    normalize_name(tag_value(&song.main_artist)),
+                    normalize_name(tag_value(&song.album))
+                )
+            }
+            DuplicateKey::Isrc => match normalize_isrc(&song.isrc) {
+                Some(isrc) => isrc,
+                None => continue,
+            },
+            DuplicateKey::Recording => {
+                if song.recording_id.is_empty() {
+                    continue;
+                }
+                song.recording_id.clone()
+            }
+            DuplicateKey::AudioHash => {
+                if song.audio_hash.is_empty() {
+                    continue;
+                }
+                song.audio_hash.clone()
+            }
+            DuplicateKey::Fingerprint(fingerprints) => match fingerprints.get(&song.song_id) {
+                Some(fingerprint) if !fingerprint.is_empty() => fingerprint.clone(),
+                _ => continue,
+            },
+        };
+        buckets.entry(bucket).or_default().push(i);
+    }
+
+    if *key != DuplicateKey::Metadata {
+        return buckets.into_values().collect();
+    }
+
+    // same name isn't enough, split the bucket wherever there's a gap in duration bigger than the tolerance
+    let mut split: Vec<Vec<usize>> = Vec::new();
+    for mut bucket in buckets.into_values() {
+        bucket.sort_by(|a, b| {
+            songs[*a]
+                .duration
+                .partial_cmp(&songs[*b].duration)
+                .unwrap_or(Ordering::Equal)
+        });
+        let mut current: Vec<usize> = Vec::new();
+        for i in bucket {
+            if let Some(last) = current.last() {
+                if songs[i].duration - songs[*last].duration > DURATION_TOLERANCE_SECONDS {
+                    split.push(std::mem::take(&mut current));
+                }
+            }
+            current.push(i);
+        }
+        split.push(current);
+    }
+    split
+}
+
+/// Groups the songs in the library that are the same track. Songs that match on any of the keys end up in the
+/// same group (so with Metadata and Isrc a song can join a group through either). Only groups with more than
+/// one song are returned. The group_id comes from the song_ids in it, so the same group gets the same id every time.
+/// ```no_run
+/// # use decibl_metadata::engine::duplicates::*;
+/// let groups = find_duplicates(&[DuplicateKey::Metadata, DuplicateKey::Recording]);
+/// for group in groups {
+///     println!("{} versions of {}", group.songs.len(), group.best().title);
+/// }
+/// ```
+pub fn find_duplicates(keys: &[DuplicateKey]) -> Vec<DuplicateGroup> {
+    let songs = get_all_songs();
+    let mut parents: Vec<usize> = (0..songs.len()).collect();
+
+    for key in keys {
+        for bucket in key_buckets(&songs, key) {
+            for i in &bucket[1..] {
+                union(&mut parents, bucket[0], *i);
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<SONG_TABLE_DATA>> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        members.entry(root).or_default().push(song.clone());
+    }
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for mut group_songs in members.into_values() {
+        if group_songs.len() < 2 {
+            continue;
+        }
+        group_songs.sort_by(|a, b| compare_quality(b, a).then(a.song_id.cmp(&b.song_id)));
+
+        let mut song_ids: Vec<&str> = group_songs
+            .iter()
+            .map(|song| song.song_id.as_str())
+            .collect();
+        song_ids.sort();
+        groups.push(DuplicateGroup {
+            group_id: string_to_hash(song_ids.join(",")).unwrap(),
+            songs: group_songs,
+        });
+    }
+
+    groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+    groups
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           PREFERRED VERSIONS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Replaces every group in song_versions with `groups`. The best song of each group is preferred, unless someone
+/// picked a different one with set_preferred_version and the group hasn't changed since, then their pick stays.
+pub fn save_duplicate_groups(groups: &[DuplicateGroup]) {
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let tx = conn.transaction().expect("Could not start transaction");
+
+    // group_id -> song_id of the picks that were made by hand
+    let picked: HashMap<String, String> = {
+        let mut stmt = tx
+            .prepare("SELECT group_id, song_id FROM song_versions WHERE preferred = 1 AND quality_rank != 0")
+            .expect("Could not prepare statement");
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    tx.execute("DELETE FROM song_versions", []).unwrap();
+    for group in groups {
+        let preferred = match picked.get(&group.group_id) {
+            Some(song_id) if group.songs.iter().any(|song| song.song_id == *song_id) => {
+                song_id.as_str()
+            }
+            _ => group.best().song_id.as_str(),
+        };
+        for (rank, song) in group.songs.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO song_versions (song_id, group_id, quality_rank, preferred) VALUES (?1, ?2, ?3, ?4)",
+                params![song.song_id, group.group_id, rank as i64, (song.song_id == preferred) as i64],
+            )
+            .unwrap();
+        }
+    }
+
+    tx.commit().expect("Could not commit transaction");
+}
+
+/// find_duplicates and save_duplicate_groups in one go. Returns the groups that were saved.
+pub fn detect_duplicates(keys: &[DuplicateKey]) -> Vec<DuplicateGroup> {
+    let groups = find_duplicates(keys);
+    save_duplicate_groups(&groups);
+    groups
+}
+
+/// Get the saved groups, best quality first within each group
+pub fn get_duplicate_groups() -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let versions = {
+        let mut versions = get_all_song_versions();
+        versions.sort_by(|a, b| {
+            a.group_id
+                .cmp(&b.group_id)
+                .then(a.quality_rank.cmp(&b.quality_rank))
+        });
+        versions
+    };
+
+    for version in versions {
+        let song = get_song_by_id(version.song_id);
+        match groups.last_mut() {
+            Some(group) if group.group_id == version.group_id => group.songs.push(song),
+            _ => groups.push(DuplicateGroup {
+                group_id: version.group_id,
+                songs: vec![song],
+            }),
+        }
+    }
+    groups
+}
+
+/// Make a song the preferred version of its group. Returns false if the song isn't in a group.
+pub fn set_preferred_version(song_id: &str) -> bool {
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let tx = conn.transaction().expect("Could not start transaction");
+
+    let updated = tx
+        .execute(
+            "UPDATE song_versions SET preferred = (song_id = ?1) \
+             WHERE group_id = (SELECT group_id FROM song_versions WHERE song_id = ?1)",
+            [song_id],
+        )
+        .unwrap();
+
+    tx.commit().expect("Could not commit transaction");
+    updated > 0
+}
+
+/// Get the song_id of the version of a song that should be played. That's the song itself if it isn't a duplicate.
+pub fn get_preferred_version(song_id: &str) -> String {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.query_row(
+        "SELECT song_id FROM song_versions WHERE preferred = 1 \
+         AND group_id = (SELECT group_id FROM song_versions WHERE song_id = ?1)",
+        [song_id],
+        |row| row.get(0),
+    )
+    .unwrap_or_else(|_| song_id.to_string())
+}
+
+/// Like get_songs_in_playlist, but every song is swapped for the preferred version of it.
+/// The playlist itself isn't touched, so changing the preferred version changes what this returns.
+pub fn get_songs_in_playlist_preferred(playlist_id: String) -> Vec<SONG_TABLE_DATA> {
+    get_songs_in_playlist(playlist_id)
+        .into_iter()
+        .map(|song| {
+            let preferred = get_preferred_version(&song.song_id);
+            if preferred == song.song_id {
+                song
+            } else {
+                get_song_by_id(preferred)
+            }
+        })
+        .collect()
+}
+
+/// Forget every duplicate group
+pub fn clear_duplicate_groups() {
+    clear_table(SONG_VERSIONS.name.to_string());
+}
@@ -0,0 +1,887 @@
+// Checking that the files in the library actually play all the way through.
+// FLAC files carry an MD5 of their decoded audio in STREAMINFO, so verify_flac decodes the whole file and checks it,
+// the same thing `flac -t` does. Results go in the flac_verifications table with the time they were checked.
+//
+// Everything else (MP3 mostly) has nothing like that, so deep_check_file decodes every frame and looks for the ways
+// files usually break: frames that don't decode, MP3 frames that aren't where the one before said they'd be, a last
+// frame that got cut off, and a duration that doesn't match what the headers claim. Results go in file_health.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::{duration_from_samples, find_audio_payload, AudioContainer};
+use crate::engine::config::*;
+use crate::engine::decoder::AudioDecoder;
+use crate::engine::models::*;
+use indicatif::ProgressBar;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::io::{Read, Seek, SeekFrom};
+use symphonia::core::codecs::{DecoderOptions, VerificationCheck, CODEC_TYPE_FLAC};
+use symphonia::core::formats::FormatOptions;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           FLAC VERIFICATION
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// How a FLAC verification went. When more than one thing is wrong the worst one wins, in the order listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlacStatus {
+    /// Couldn't be opened or isn't a FLAC file at all
+    Unreadable,
+    /// Some frames failed to decode (bad CRC, lost sync)
+    CorruptFrames,
+    /// Fewer samples decoded than STREAMINFO says there are
+    Truncated,
+    /// Everything decoded but it isn't the audio the encoder wrote
+    Md5Mismatch,
+    /// Decoded fine, but the encoder left the MD5 blank so there's nothing to check it against
+    NoMd5,
+    Ok,
+}
+
+impl FlacStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlacStatus::Unreadable => "unreadable",
+            FlacStatus::CorruptFrames => "corrupt_frames",
+            FlacStatus::Truncated => "truncated",
+            FlacStatus::Md5Mismatch => "md5_mismatch",
+            FlacStatus::NoMd5 => "no_md5",
+            FlacStatus::Ok => "ok",
+        }
+    }
+
+    pub fn from_status(status: &str) -> Option<Self> {
+        match status {
+            "unreadable" => Some(FlacStatus::Unreadable),
+            "corrupt_frames" => Some(FlacStatus::CorruptFrames),
+            "truncated" => Some(FlacStatus::Truncated),
+            "md5_mismatch" => Some(FlacStatus::Md5Mismatch),
+            "no_md5" => Some(FlacStatus::NoMd5),
+            "ok" => Some(FlacStatus::Ok),
+            _ => None,
+        }
+    }
+
+    /// Whether the file is actually damaged. A missing MD5 isn't damage, just nothing to check against.
+    pub fn is_bad(&self) -> bool {
+        !matches!(self, FlacStatus::Ok | FlacStatus::NoMd5)
+    }
+}
+
+/// Decodes a whole FLAC file and checks it against STREAMINFO: the MD5 of the audio, the number of samples, and
+/// that every frame decodes. Nothing is saved, the song_id of the result is left empty (verify_song fills it in).
+/// ```no_run
+/// # use decibl_metadata::engine::file_health::*;
+/// let result = verify_flac("/music/song.flac");
+/// println!("{}", result.status);
+/// ```
+pub fn verify_flac(filepath: &str) -> FLAC_VERIFICATIONS_TABLE_DATA {
+    let mut result = FLAC_VERIFICATIONS_TABLE_DATA {
+        song_id: "".to_string(),
+        song_path: filepath.to_string(),
+        status: FlacStatus::Unreadable.as_str().to_string(),
+        expected_md5: "".to_string(),
+        md5_ok: -1,
+        decode_errors: 0,
+        expected_samples: -1,
+        decoded_samples: 0,
+        verified_dt: now_dt(),
+    };
+
+    // verifying only costs an MD5 of the audio, and the track has to be open to know if there's an MD5 to check
+    let mut decoder = match AudioDecoder::open(
+        filepath,
+        FormatOptions::default(),
+        DecoderOptions { verify: true },
+    ) {
+        Some(decoder) if decoder.codec_params.codec == CODEC_TYPE_FLAC => decoder,
+        _ => return result,
+    };
+    // the md5 is all zeros when the encoder didn't work one out
+    let expected_md5 = match decoder.codec_params.verification_check {
+        Some(VerificationCheck::Md5(md5)) if md5 != [0; 16] => Some(md5),
+        _ => None,
+    };
+    if let Some(md5) = expected_md5 {
+        result.expected_md5 = md5.iter().map(|byte| format!("{:02x}", byte)).collect();
+    }
+    if let Some(samples) = decoder.codec_params.n_frames {
+        result.expected_samples = samples as i64;
+    }
+
+    while let Some(decoded) = decoder.next_decoded() {
+        result.decoded_samples += decoded.frames() as i64;
+    }
+    result.decode_errors = decoder.decode_errors;
+
+    if expected_md5.is_some() {
+        result.md5_ok = match decoder.finalize().verify_ok {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1,
+        };
+    }
+
+    let status = if result.decode_errors > 0 {
+        FlacStatus::CorruptFrames
+    } else if result.expected_samples > 0 && result.decoded_samples < result.expected_samples {
+        FlacStatus::Truncated
+    } else if result.md5_ok == 0 {
+        FlacStatus::Md5Mismatch
+    } else if result.md5_ok == 1 {
+        FlacStatus::Ok
+    } else {
+        FlacStatus::NoMd5
+    };
+    result.status = status.as_str().to_string();
+    result
+}
+
+/// Saves a verification result, replacing the last one for the same song
+pub fn save_flac_verification(verification: &FLAC_VERIFICATIONS_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO flac_verifications (song_id, song_path, status, expected_md5, md5_ok, \
+         decode_errors, expected_samples, decoded_samples, verified_dt) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            verification.song_id,
+            verification.song_path,
+            verification.status,
+            verification.expected_md5,
+            verification.md5_ok,
+            verification.decode_errors,
+            verification.expected_samples,
+            verification.decoded_samples,
+            verification.verified_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Verifies a song's FLAC file and saves the result. None if the song has no path.
+pub fn verify_song(song_id: &str) -> Option<FLAC_VERIFICATIONS_TABLE_DATA> {
+    let songpath = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?;
+
+    let mut verification = verify_flac(&songpath.song_path);
+    verification.song_id = song_id.to_string();
+    save_flac_verification(&verification);
+    Some(verification)
+}
+
+/// Get the last verification result of a song, None if it's never been verified
+pub fn get_flac_verification(song_id: &str) -> Option<FLAC_VERIFICATIONS_TABLE_DATA> {
+    get_all_flac_verifications()
+        .into_iter()
+        .find(|verification| verification.song_id == song_id)
+}
+
+/// Verifies every FLAC in the library and returns the ones that are damaged (see FlacStatus::is_bad).
+/// Pass `older_than` (YYYY-MM-DD HH:MM:SS) to skip songs that were verified since then, so a big library can be
+/// done a bit at a time.
+/// ```no_run
+/// # use decibl_metadata::engine::file_health::*;
+/// for bad in verify_library_flacs(None) {
+///     println!("{} is {}", bad.song_path, bad.status);
+/// }
+/// ```
+pub fn verify_library_flacs(older_than: Option<&str>) -> Vec<FLAC_VERIFICATIONS_TABLE_DATA> {
+    let song_ids: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT songs.song_id FROM songs LEFT JOIN flac_verifications \
+                 ON flac_verifications.song_id = songs.song_id \
+                 WHERE songs.filetype = 'flac' AND (?1 IS NULL OR flac_verifications.verified_dt IS NULL \
+                 OR flac_verifications.verified_dt < ?1) ORDER BY songs.song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([older_than], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let bar = ProgressBar::new(song_ids.len() as u64);
+    let mut bad: Vec<FLAC_VERIFICATIONS_TABLE_DATA> = Vec::new();
+    for song_id in song_ids {
+        if let Some(verification) = verify_song(&song_id) {
+            let is_bad = match FlacStatus::from_status(&verification.status) {
+                Some(status) => status.is_bad(),
+                None => true,
+            };
+            if is_bad {
+                bad.push(verification);
+            }
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+    bad
+}
+
+/// Get the songs whose last verification found them damaged
+pub fn get_bad_flacs() -> Vec<FLAC_VERIFICATIONS_TABLE_DATA> {
+    get_all_flac_verifications()
+        .into_iter()
+        .filter(
+            |verification| match FlacStatus::from_status(&verification.status) {
+                Some(status) => status.is_bad(),
+                None => true,
+            },
+        )
+        .collect()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MPEG FRAMES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// bitrates in kbps by bitrate index, index 0 (free format) and 15 (bad) aren't allowed
+const MPEG1_LAYER1_BITRATES: [u32; 14] = [
+    32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const MPEG1_LAYER2_BITRATES: [u32; 14] = [
+    32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const MPEG1_LAYER3_BITRATES: [u32; 14] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_LAYER1_BITRATES: [u32; 14] = [
+    32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const MPEG2_LAYER23_BITRATES: [u32; 14] =
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+/// The 4 byte header in front of every MP3 (or MP2/MP1) frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpegFrameHeader {
+    /// 1 for MPEG-1, 2 for MPEG-2 and 25 for MPEG-2.5
+    pub version: u8,
+    /// 1, 2 or 3 (MP3 is layer 3)
+    pub layer: u8,
+    /// In bits per second
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    /// The whole frame in bytes, header included
+    pub frame_length: u64,
+    /// Samples per channel in the frame
+    pub samples: u64,
+}
+
+/// Parses an MPEG audio frame header, None if the bytes aren't one
+pub fn parse_mpeg_frame_header(bytes: &[u8]) -> Option<MpegFrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = match (bytes[1] >> 3) & 0b11 {
+        0 => 25,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let layer = match (bytes[1] >> 1) & 0b11 {
+        1 => 3,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    if bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    let bitrates = match (version, layer) {
+        (1, 1) => MPEG1_LAYER1_BITRATES,
+        (1, 2) => MPEG1_LAYER2_BITRATES,
+        (1, _) => MPEG1_LAYER3_BITRATES,
+        (_, 1) => MPEG2_LAYER1_BITRATES,
+        _ => MPEG2_LAYER23_BITRATES,
+    };
+    let bitrate = bitrates[bitrate_index - 1] * 1000;
+    let sample_rate = match ((bytes[2] >> 2) & 0b11, version) {
+        (3, _) => return None,
+        (index, 1) => [44100, 48000, 32000][index as usize],
+        (index, 2) => [22050, 24000, 16000][index as usize],
+        (index, _) => [11025, 12000, 8000][index as usize],
+    };
+    let padding = ((bytes[2] >> 1) & 1) as u64;
+    let channels = if bytes[3] >> 6 == 3 { 1 } else { 2 };
+
+    let (samples, frame_length) = match (layer, version) {
+        (1, _) => (
+            384,
+            (12 * bitrate as u64 / sample_rate as u64 + padding) * 4,
+        ),
+        (3, 2) | (3, 25) => (576, 72 * bitrate as u64 / sample_rate as u64 + padding),
+        _ => (1152, 144 * bitrate as u64 / sample_rate as u64 + padding),
+    };
+
+    Some(MpegFrameHeader {
+        version,
+        layer,
+        bitrate,
+        sample_rate,
+        channels,
+        frame_length,
+        samples,
+    })
+}
+
+/// The LAME extension of a Xing/Info header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LameHeader {
+    /// Like "LAME3.100"
+    pub encoder: String,
+    /// 1 is CBR, 2 ABR, 3 to 6 VBR, 8 two pass CBR and 9 two pass ABR, 0 unknown
+    pub vbr_method: u8,
+    /// Samples of silence the encoder put in front of the audio
+    pub encoder_delay: u32,
+    /// Samples of silence the encoder put after the audio to fill the last frame
+    pub encoder_padding: u32,
+}
+
+/// The Xing (VBR) or Info (CBR) header LAME and most other encoders put in a silent first frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XingHeader {
+    /// Xing rather than Info
+    pub vbr: bool,
+    /// Audio frames in the file, not counting the one the header is in
+    pub frames: Option<u64>,
+    /// Bytes of audio in the file, the header's frame included
+    pub bytes: Option<u64>,
+    pub lame: Option<LameHeader>,
+}
+
+/// Parses the Xing/Info header of a frame (the whole frame, header included). None if the frame doesn't have one.
+pub fn parse_xing_header(frame: &[u8]) -> Option<XingHeader> {
+    let header = parse_mpeg_frame_header(frame)?;
+    // the header comes right after the side information, which is sized by version and channels
+    let side_info = match (header.version, header.channels) {
+        (1, 1) => 17,
+        (1, _) => 32,
+        (_, 1) => 9,
+        _ => 17,
+    };
+    let mut pos = 4 + side_info;
+    let vbr = match frame.get(pos..pos + 4)? {
+        b"Xing" => true,
+        b"Info" => false,
+        _ => return None,
+    };
+    let read_u32 = |pos: usize| -> Option<u64> {
+        Some(u32::from_be_bytes(frame.get(pos..pos + 4)?.try_into().unwrap()) as u64)
+    };
+    let flags = read_u32(pos + 4)?;
+    pos += 8;
+
+    let mut xing = XingHeader {
+        vbr,
+        frames: None,
+        bytes: None,
+        lame: None,
+    };
+    if flags & 0x1 != 0 {
+        xing.frames = Some(read_u32(pos)?);
+        pos += 4;
+    }
+    if flags & 0x2 != 0 {
+        xing.bytes = Some(read_u32(pos)?);
+        pos += 4;
+    }
+    // seek table and vbr quality
+    if flags & 0x4 != 0 {
+        pos += 100;
+    }
+    if flags & 0x8 != 0 {
+        pos += 4;
+    }
+
+    // 9 bytes of encoder name, then a dozen more bytes of settings with the delay and padding as two 12 bit numbers
+    if let Some(lame) = frame.get(pos..pos + 24) {
+        if lame[..4].iter().all(|byte| byte.is_ascii_alphanumeric()) {
+            let delay_padding = u32::from_be_bytes([0, lame[21], lame[22], lame[23]]);
+            xing.lame = Some(LameHeader {
+                encoder: String::from_utf8_lossy(&lame[..9])
+                    .trim_end_matches(['\0', ' '])
+                    .to_string(),
+                vbr_method: lame[9] & 0x0F,
+                encoder_delay: delay_padding >> 12,
+                encoder_padding: delay_padding & 0xFFF,
+            });
+        }
+    }
+    Some(xing)
+}
+
+/// The VBRI header Fraunhofer's encoder puts in the first frame instead of a Xing header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VbriHeader {
+    pub delay: u16,
+    /// Bytes of audio in the file
+    pub bytes: u64,
+    /// Audio frames in the file
+    pub frames: u64,
+}
+
+/// Parses the VBRI header of a frame (the whole frame, header included). None if the frame doesn't have one.
+pub fn parse_vbri_header(frame: &[u8]) -> Option<VbriHeader> {
+    parse_mpeg_frame_header(frame)?;
+    // always 32 bytes after the frame header, whatever the version or channels
+    let vbri = frame.get(36..54)?;
+    if &vbri[..4] != b"VBRI" {
+        return None;
+    }
+    Some(VbriHeader {
+        delay: u16::from_be_bytes([vbri[6], vbri[7]]),
+        bytes: u32::from_be_bytes(vbri[10..14].try_into().unwrap()) as u64,
+        frames: u32::from_be_bytes(vbri[14..18].try_into().unwrap()) as u64,
+    })
+}
+
+/// What walking the frames of an MPEG file found
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MpegFrameScan {
+    /// Frames that were all there
+    pub frames: u64,
+    /// Samples per channel in those frames
+    pub samples: u64,
+    /// How many times the next frame wasn't right after the last one and had to be searched for
+    pub lost_sync: u64,
+    /// The file ends partway through a frame
+    pub truncated_final_frame: bool,
+    pub first_header: Option<MpegFrameHeader>,
+    /// Bytes in those frames
+    pub audio_bytes: u64,
+    /// Not every frame has the same bitrate
+    pub variable_bitrate: bool,
+}
+
+/// Whether there's a frame at `pos` in `audio`. A header only counts if the frame after it starts with a header too
+/// (or the audio ends there when `ends_there`), random bytes look like a header far too often.
+pub fn is_real_frame(audio: &[u8], pos: usize, ends_there: bool) -> bool {
+    match parse_mpeg_frame_header(&audio[pos..]) {
+        Some(header) => {
+            let next = pos + header.frame_length as usize;
+            (ends_there && next == audio.len())
+                || (next < audio.len() && parse_mpeg_frame_header(&audio[next..]).is_some())
+        }
+        None => false,
+    }
+}
+
+/// Walks every frame of an MPEG audio file using only the frame headers (see audio_metadata::find_audio_payload
+/// for where the frames are). Errors if the file can't be read or isn't MPEG audio.
+/// ```no_run
+/// # use decibl_metadata::engine::file_health::*;
+/// let scan = scan_mpeg_frames("/music/song.mp3").unwrap();
+/// println!("{} frames, lost sync {} times", scan.frames, scan.lost_sync);
+/// ```
+pub fn scan_mpeg_frames(filepath: &str) -> std::io::Result<MpegFrameScan> {
+    let payload = find_audio_payload(filepath)?;
+    if payload.container != AudioContainer::Mpeg {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not an MPEG audio file",
+        ));
+    }
+
+    let mut file = std::fs::File::open(filepath)?;
+    let mut audio: Vec<u8> = Vec::new();
+    for (offset, length) in payload.ranges {
+        file.seek(SeekFrom::Start(offset))?;
+        (&mut file).take(length).read_to_end(&mut audio)?;
+    }
+
+    let mut scan = MpegFrameScan::default();
+    let mut pos = 0;
+    while pos < audio.len() {
+        let header = match parse_mpeg_frame_header(&audio[pos..]) {
+            Some(header) => header,
+            None if audio.len() - pos < 4 && audio[pos] == 0xFF => {
+                // cut off in the middle of the header
+                scan.truncated_final_frame = true;
+                break;
+            }
+            None => {
+                scan.lost_sync += 1;
+                match (pos + 1..audio.len()).find(|i| is_real_frame(&audio, *i, true)) {
+                    Some(next) => {
+                        pos = next;
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+        };
+        if pos + header.frame_length as usize > audio.len() {
+            scan.truncated_final_frame = true;
+            break;
+        }
+        match &scan.first_header {
+            Some(first) if first.bitrate != header.bitrate => scan.variable_bitrate = true,
+            Some(_) => {}
+            None => scan.first_header = Some(header),
+        }
+        scan.frames += 1;
+        scan.audio_bytes += header.frame_length;
+        scan.samples += header.samples;
+        pos += header.frame_length as usize;
+    }
+    Ok(scan)
+}
+
+/// How the bitrate of an MPEG audio file is spread over its frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitrateMode {
+    /// Every frame has the same bitrate
+    Cbr,
+    Vbr,
+    /// VBR aiming for an average bitrate
+    Abr,
+}
+
+impl BitrateMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BitrateMode::Cbr => "cbr",
+            BitrateMode::Vbr => "vbr",
+            BitrateMode::Abr => "abr",
+        }
+    }
+
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "cbr" => Some(BitrateMode::Cbr),
+            "vbr" => Some(BitrateMode::Vbr),
+            "abr" => Some(BitrateMode::Abr),
+            _ => None,
+        }
+    }
+}
+
+/// The length and bitrate of a whole MPEG audio file, not just its first frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct MpegStreamInfo {
+    /// Audio frames, a Xing/VBRI header frame not included
+    pub frames: u64,
+    /// Samples per channel, the encoder delay and padding from a LAME header left out
+    pub total_samples: u64,
+    pub sample_rate: u32,
+    pub channels: u8,
+    /// total_samples / sample_rate, in seconds
+    pub duration: f64,
+    /// Average bitrate over all the frames, in bits per second
+    pub bitrate: u64,
+    pub mode: BitrateMode,
+    /// The numbers came from a Xing/Info or VBRI header rather than walking every frame
+    pub from_header: bool,
+}
+
+/// Works out the length and average bitrate of an MPEG audio file. The Xing/Info or VBRI header in the first frame
+/// is used if there is one, otherwise every frame gets walked (see scan_mpeg_frames).
+/// Errors if the file can't be read or isn't MPEG audio.
+/// ```no_run
+/// # use decibl_metadata::engine::file_health::*;
+/// let info = mpeg_stream_info("/music/song.mp3").unwrap();
+/// println!("{:.3}s at {}bps ({})", info.duration, info.bitrate, info.mode.as_str());
+/// ```
+pub fn mpeg_stream_info(filepath: &str) -> std::io::Result<MpegStreamInfo> {
+    let invalid =
+        |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let payload = find_audio_payload(filepath)?;
+    if payload.container != AudioContainer::Mpeg {
+        return Err(invalid("not an MPEG audio file"));
+    }
+
+    // the biggest possible frame is under 3000 bytes
+    let mut first = Vec::new();
+    let mut file = std::fs::File::open(filepath)?;
+    file.seek(SeekFrom::Start(payload.ranges[0].0))?;
+    file.take(3000).read_to_end(&mut first)?;
+    let header =
+        parse_mpeg_frame_header(&first).ok_or_else(|| invalid("no MPEG frame at the start"))?;
+
+    let xing = parse_xing_header(&first);
+    let vbri = parse_vbri_header(&first);
+    let (frames, bytes, delay, padding, mode, from_header) = match (&xing, &vbri) {
+        (
+            Some(
+                xing @ XingHeader {
+                    frames: Some(frames),
+                    ..
+                },
+            ),
+            _,
+        ) => {
+            let (delay, padding, vbr_method) = match &xing.lame {
+                Some(lame) => (
+                    lame.encoder_delay as u64,
+                    lame.encoder_padding as u64,
+                    lame.vbr_method,
+                ),
+                None => (0, 0, 0),
+            };
+            let mode = match (xing.vbr, vbr_method) {
+                (false, _) | (true, 1) | (true, 8) => BitrateMode::Cbr,
+                (true, 2) | (true, 9) => BitrateMode::Abr,
+                _ => BitrateMode::Vbr,
+            };
+            // the byte count covers the header frame too
+            let bytes = xing
+                .bytes
+                .unwrap_or(payload.ranges[0].1)
+                .saturating_sub(header.frame_length);
+            (*frames, bytes, delay, padding, mode, true)
+        }
+        (_, Some(vbri)) => (
+            vbri.frames,
+            vbri.bytes.saturating_sub(header.frame_length),
+            0,
+            0,
+            BitrateMode::Vbr,
+            true,
+        ),
+        _ => {
+            let scan = scan_mpeg_frames(filepath)?;
+            let (mut frames, mut bytes) = (scan.frames, scan.audio_bytes);
+            // a header frame without a frame count still isn't audio
+            if xing.is_some() {
+                frames = frames.saturating_sub(1);
+                bytes = bytes.saturating_sub(header.frame_length);
+            }
+            let mode = if scan.variable_bitrate {
+                BitrateMode::Vbr
+            } else {
+                BitrateMode::Cbr
+            };
+            (frames, bytes, 0, 0, mode, false)
+        }
+    };
+
+    let encoded_samples = frames * header.samples;
+    let total_samples = encoded_samples.saturating_sub(delay + padding);
+    let bitrate = match encoded_samples {
+        0 => 0,
+        _ => {
+            (bytes as f64 * 8.0 * header.sample_rate as f64 / encoded_samples as f64).round() as u64
+        }
+    };
+    Ok(MpegStreamInfo {
+        frames,
+        total_samples,
+        sample_rate: header.sample_rate,
+        channels: header.channels,
+        duration: duration_from_samples(total_samples as i64, header.sample_rate as i64),
+        bitrate,
+        mode,
+        from_header,
+    })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           DEEP CHECK
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// How far apart (in seconds) the duration the headers claim and the duration that decoded can be before it counts
+/// as a mismatch. Encoder delay and padding alone are a lot less than this.
+pub const DURATION_MISMATCH_SECONDS: f64 = 1.0;
+
+/// How a deep check went. When more than one thing is wrong the worst one wins, in the order listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHealthStatus {
+    /// Couldn't be opened or isn't a format symphonia knows
+    Unreadable,
+    /// Some packets failed to decode
+    CorruptFrames,
+    /// Garbage between MP3 frames that had to be skipped over
+    LostSync,
+    /// The file ends partway through its last frame
+    Truncated,
+    /// The duration the headers claim is off from what actually decoded by more than DURATION_MISMATCH_SECONDS
+    DurationMismatch,
+    Ok,
+}
+
+impl FileHealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileHealthStatus::Unreadable => "unreadable",
+            FileHealthStatus::CorruptFrames => "corrupt_frames",
+            FileHealthStatus::LostSync => "lost_sync",
+            FileHealthStatus::Truncated => "truncated",
+            FileHealthStatus::DurationMismatch => "duration_mismatch",
+            FileHealthStatus::Ok => "ok",
+        }
+    }
+
+    pub fn from_status(status: &str) -> Option<Self> {
+        match status {
+            "unreadable" => Some(FileHealthStatus::Unreadable),
+            "corrupt_frames" => Some(FileHealthStatus::CorruptFrames),
+            "lost_sync" => Some(FileHealthStatus::LostSync),
+            "truncated" => Some(FileHealthStatus::Truncated),
+            "duration_mismatch" => Some(FileHealthStatus::DurationMismatch),
+            "ok" => Some(FileHealthStatus::Ok),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes every frame of a file with symphonia and records what went wrong. MP3s also get their frames walked
+/// (scan_mpeg_frames) since symphonia quietly skips garbage and cut off frames. Works on anything symphonia can
+/// decode, for FLAC verify_flac is the better check. Nothing is saved, the song_id of the result is left empty.
+/// ```no_run
+/// # use decibl_metadata::engine::file_health::*;
+/// let health = deep_check_file("/music/song.mp3");
+/// println!("{}: {} decode errors", health.status, health.decode_errors);
+/// ```
+pub fn deep_check_file(filepath: &str) -> FILE_HEALTH_TABLE_DATA {
+    let extension = std::path::Path::new(filepath)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+    let mut health = FILE_HEALTH_TABLE_DATA {
+        song_id: "".to_string(),
+        song_path: filepath.to_string(),
+        filetype: extension.clone(),
+        status: FileHealthStatus::Unreadable.as_str().to_string(),
+        decode_errors: 0,
+        lost_sync: 0,
+        truncated_final_frame: 0,
+        declared_duration: -1.0,
+        decoded_duration: 0.0,
+        checked_dt: now_dt(),
+    };
+
+    let mut decoder = match AudioDecoder::open(
+        filepath,
+        FormatOptions::default(),
+        DecoderOptions::default(),
+    ) {
+        Some(decoder) => decoder,
+        None => return health,
+    };
+    let sample_rate = match decoder.sample_rate() {
+        Some(sample_rate) => sample_rate as f64,
+        None => return health,
+    };
+    if let Some(samples) = decoder.codec_params.n_frames {
+        health.declared_duration = samples as f64 / sample_rate;
+    }
+
+    let mut decoded_samples: u64 = 0;
+    while let Some(decoded) = decoder.next_decoded() {
+        decoded_samples += decoded.frames() as u64;
+    }
+    health.decode_errors = decoder.decode_errors;
+    health.decoded_duration = decoded_samples as f64 / sample_rate;
+
+    if let Ok(scan) = scan_mpeg_frames(filepath) {
+        health.lost_sync = scan.lost_sync as i64;
+        health.truncated_final_frame = scan.truncated_final_frame as i64;
+    }
+
+    let status = if health.decode_errors > 0 {
+        FileHealthStatus::CorruptFrames
+    } else if health.lost_sync > 0 {
+        FileHealthStatus::LostSync
+    } else if health.truncated_final_frame == 1 {
+        FileHealthStatus::Truncated
+    } else if health.declared_duration >= 0.0
+        && (health.declared_duration - health.decoded_duration).abs() > DURATION_MISMATCH_SECONDS
+    {
+        FileHealthStatus::DurationMismatch
+    } else {
+        FileHealthStatus::Ok
+    };
+    health.status = status.as_str().to_string();
+    health
+}
+
+/// Saves a deep check result, replacing the last one for the same song
+pub fn save_file_health(health: &FILE_HEALTH_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO file_health (song_id, song_path, filetype, status, decode_errors, lost_sync, \
+         truncated_final_frame, declared_duration, decoded_duration, checked_dt) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            health.song_id,
+            health.song_path,
+            health.filetype,
+            health.status,
+            health.decode_errors,
+            health.lost_sync,
+            health.truncated_final_frame,
+            health.declared_duration,
+            health.decoded_duration,
+            health.checked_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Deep checks a song's file and saves the result. None if the song has no path.
+pub fn deep_check_song(song_id: &str) -> Option<FILE_HEALTH_TABLE_DATA> {
+    let songpath = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?;
+
+    let mut health = deep_check_file(&songpath.song_path);
+    health.song_id = song_id.to_string();
+    save_file_health(&health);
+    Some(health)
+}
+
+/// Get the last deep check result of a song, None if it's never been checked
+pub fn get_file_health(song_id: &str) -> Option<FILE_HEALTH_TABLE_DATA> {
+    get_all_file_health()
+        .into_iter()
+        .find(|health| health.song_id == song_id)
+}
+
+/// Deep checks every song in the library and returns the ones that aren't ok.
+/// Pass `older_than` (YYYY-MM-DD HH:MM:SS) to skip songs that were checked since then.
+pub fn deep_check_library(older_than: Option<&str>) -> Vec<FILE_HEALTH_TABLE_DATA> {
+    let song_ids: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT songs.song_id FROM songs LEFT JOIN file_health ON file_health.song_id = songs.song_id \
+                 WHERE ?1 IS NULL OR file_health.checked_dt IS NULL OR file_health.checked_dt < ?1 \
+                 ORDER BY songs.song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([older_than], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let bar = ProgressBar::new(song_ids.len() as u64);
+    let mut unhealthy: Vec<FILE_HEALTH_TABLE_DATA> = Vec::new();
+    for song_id in song_ids {
+        if let Some(health) = deep_check_song(&song_id) {
+            if health.status != FileHealthStatus::Ok.as_str() {
+                unhealthy.push(health);
+            }
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+    unhealthy
+}
+
+/// Get the songs whose last deep check found something wrong
+pub fn get_unhealthy_files() -> Vec<FILE_HEALTH_TABLE_DATA> {
+    get_all_file_health()
+        .into_iter()
+        .filter(|health| health.status != FileHealthStatus::Ok.as_str())
+        .collect()
+}
@@ -0,0 +1,369 @@
+// Gapless playback and silence trimming. Lossy encoders add silence to both ends of a song: some at the start
+// (encoder delay) and some at the end to fill up the last frame (padding). Played as is, that's an audible gap
+// between tracks of a live album or a DJ mix. How much was added is written in the file:
+// * MP3: the LAME extension of the Xing/Info header in the first frame (see file_health::parse_xing_header)
+// * AAC/MP4: the iTunSMPB tag iTunes writes (MP3s from iTunes have it too, as a comment)
+// * FLAC: nothing is added, STREAMINFO has the exact sample count
+//
+// On top of that the song itself gets decoded to find how much silence it starts and ends with, so a player can
+// skip it if it wants to. Everything goes in the gapless table.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::{
+    find_audio_payload, mp4_boxes, mp4_child, mp4_ilst, AudioContainer,
+};
+use crate::engine::config::*;
+use crate::engine::decoder::AudioDecoder;
+use crate::engine::file_health::{parse_mpeg_frame_header, parse_xing_header};
+use crate::engine::models::*;
+use indicatif::ProgressBar;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::io::{Read, Seek, SeekFrom};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+
+/// Anything quieter than this (in dBFS) counts as silence
+pub const SILENCE_THRESHOLD_DB: f64 = -60.0;
+
+/// MP3 decoders put out this many samples of their own before the audio starts. Players that decode MP3s
+/// themselves have to skip encoder_delay + MP3_DECODER_DELAY samples, and stop that many samples later too.
+pub const MP3_DECODER_DELAY: i64 = 529;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           ENCODER DELAY AND PADDING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Where the encoder delay and padding of a song came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GaplessSource {
+    Lame,
+    ITunSmpb,
+    Flac,
+    /// Nothing in the file says, delay and padding are 0
+    None,
+}
+
+impl GaplessSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GaplessSource::Lame => "lame",
+            GaplessSource::ITunSmpb => "itunsmpb",
+            GaplessSource::Flac => "flac",
+            GaplessSource::None => "none",
+        }
+    }
+
+    pub fn from_source(source: &str) -> Option<Self> {
+        match source {
+            "lame" => Some(GaplessSource::Lame),
+            "itunsmpb" => Some(GaplessSource::ITunSmpb),
+            "flac" => Some(GaplessSource::Flac),
+            "none" => Some(GaplessSource::None),
+            _ => None,
+        }
+    }
+}
+
+/// What a file says about its encoder delay and padding, all in samples per channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GaplessInfo {
+    pub encoder_delay: i64,
+    pub encoder_padding: i64,
+    /// The song without the delay and padding, -1 if the file doesn't say
+    pub total_samples: i64,
+    /// -1 if the file doesn't say
+    pub sample_rate: i64,
+    pub source: GaplessSource,
+}
+
+/// Parses the value of an iTunSMPB tag: hex numbers separated by spaces, the second is the delay, the third the
+/// padding and the fourth the length of the song. Returns (delay, padding, total_samples).
+/// ```
+/// # use decibl_metadata::engine::gapless::*;
+/// let smpb = " 00000000 00000840 000001CA 00000000003F3AF6 00000000 00000000";
+/// assert_eq!(parse_itunsmpb(smpb), Some((2112, 458, 4143862)));
+/// ```
+pub fn parse_itunsmpb(value: &str) -> Option<(i64, i64, i64)> {
+    let fields: Vec<i64> = value
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .split_whitespace()
+        .map(|field| i64::from_str_radix(field, 16).ok())
+        .collect::<Option<Vec<i64>>>()?;
+    if fields.len() < 4 {
+        return None;
+    }
+    Some((fields[1], fields[2], fields[3]))
+}
+
+// the iTunSMPB tag of an mp4 is a freeform (----) item in ilst, with a mean, a name and a data box
+fn mp4_itunsmpb(filepath: &str) -> Option<String> {
+    let bytes = std::fs::read(filepath).ok()?;
+
+    let ilst = mp4_ilst(&bytes)?;
+    for (kind, item) in mp4_boxes(ilst) {
+        if kind != b"----" {
+            continue;
+        }
+        let name = mp4_child(item, b"name").and_then(|name| name.get(4..));
+        if name == Some(b"iTunSMPB".as_slice()) {
+            // 4 bytes of type, 4 of locale, then the text
+            let data = mp4_child(item, b"data")?.get(8..)?;
+            return Some(String::from_utf8_lossy(data).to_string());
+        }
+    }
+    None
+}
+
+/// Reads the encoder delay and padding of a file from its headers and tags, without decoding anything.
+/// Files that don't say get GaplessSource::None.
+pub fn read_gapless_info(filepath: &str) -> GaplessInfo {
+    let mut info = GaplessInfo {
+        encoder_delay: 0,
+        encoder_padding: 0,
+        total_samples: -1,
+        sample_rate: -1,
+        source: GaplessSource::None,
+    };
+    let payload = match find_audio_payload(filepath) {
+        Ok(payload) => payload,
+        Err(_) => return info,
+    };
+
+    match payload.container {
+        AudioContainer::Flac => {
+            if let Ok(tag) = metaflac::Tag::read_from_path(filepath) {
+                if let Some(streaminfo) = tag.get_streaminfo() {
+                    info.total_samples = streaminfo.total_samples as i64;
+                    info.sample_rate = streaminfo.sample_rate as i64;
+                    info.source = GaplessSource::Flac;
+                }
+            }
+        }
+        AudioContainer::Mpeg => {
+            // the biggest possible frame is under 3000 bytes
+            let mut frame = vec![0u8; 3000];
+            let read = std::fs::File::open(filepath).and_then(|mut file| {
+                file.seek(SeekFrom::Start(payload.ranges[0].0))?;
+                file.take(3000).read(&mut frame)
+            });
+            frame.truncate(read.unwrap_or(0));
+            if let Some(header) = parse_mpeg_frame_header(&frame) {
+                info.sample_rate = header.sample_rate as i64;
+                if let Some(xing) = parse_xing_header(&frame) {
+                    if let Some(lame) = xing.lame {
+                        info.encoder_delay = lame.encoder_delay as i64;
+                        info.encoder_padding = lame.encoder_padding as i64;
+                        info.source = GaplessSource::Lame;
+                        if let Some(frames) = xing.frames {
+                            info.total_samples = frames as i64 * header.samples as i64
+                                - info.encoder_delay
+                                - info.encoder_padding;
+                        }
+                        return info;
+                    }
+                }
+            }
+            // iTunes writes iTunSMPB as a comment instead
+            if let Ok(tag) = id3::Tag::read_from_path(filepath) {
+                let smpb = tag
+                    .comments()
+                    .find(|comment| comment.description == "iTunSMPB")
+                    .and_then(|comment| parse_itunsmpb(&comment.text));
+                if let Some((delay, padding, total_samples)) = smpb {
+                    info.encoder_delay = delay;
+                    info.encoder_padding = padding;
+                    info.total_samples = total_samples;
+                    info.source = GaplessSource::ITunSmpb;
+                }
+            }
+        }
+        AudioContainer::Mp4 => {
+            if let Some((delay, padding, total_samples)) =
+                mp4_itunsmpb(filepath).and_then(|smpb| parse_itunsmpb(&smpb))
+            {
+                info.encoder_delay = delay;
+                info.encoder_padding = padding;
+                info.total_samples = total_samples;
+                info.source = GaplessSource::ITunSmpb;
+            }
+        }
+        AudioContainer::Unknown => {}
+    }
+    info
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           SILENCE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Silence at the ends of a song, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Silence {
+    pub leading: f64,
+    pub trailing: f64,
+    /// Samples per channel that decoded, delay and padding already left out
+    pub decoded_samples: u64,
+    pub sample_rate: u32,
+}
+
+/// Decodes a file and measures the silence (anything under SILENCE_THRESHOLD_DB) at its start and end.
+/// Delay and padding get left out first: symphonia does that itself for MP3s, `info` is used for iTunSMPB.
+/// A song that's silent all the way through is all leading silence. None if the file can't be decoded.
+pub fn find_silence(filepath: &str, info: &GaplessInfo) -> Option<Silence> {
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    let mut decoder = AudioDecoder::open(filepath, format_options, DecoderOptions::default())?;
+    let sample_rate = decoder.sample_rate()?;
+    let channels = decoder.channels()?;
+
+    let (skip, end) = match info.source {
+        GaplessSource::ITunSmpb if info.total_samples >= 0 => (
+            info.encoder_delay.max(0) as u64,
+            (info.encoder_delay.max(0) + info.total_samples) as u64,
+        ),
+        _ => (0, u64::MAX),
+    };
+    let threshold = 10f32.powf(SILENCE_THRESHOLD_DB as f32 / 20.0);
+    let mut position: u64 = 0;
+    let mut first_loud: Option<u64> = None;
+    let mut last_loud: u64 = 0;
+    while let Some(samples) = decoder.next_samples() {
+        for frame in samples.chunks_exact(channels) {
+            if position >= skip && position < end && frame.iter().any(|s| s.abs() > threshold) {
+                let at = position - skip;
+                first_loud.get_or_insert(at);
+                last_loud = at;
+            }
+            position += 1;
+        }
+    }
+
+    let decoded_samples = position.min(end).saturating_sub(skip);
+    let seconds = |samples: u64| samples as f64 / sample_rate as f64;
+    let (leading, trailing) = match first_loud {
+        Some(first_loud) => (
+            seconds(first_loud),
+            seconds(decoded_samples.saturating_sub(last_loud + 1)),
+        ),
+        None => (seconds(decoded_samples), 0.0),
+    };
+    Some(Silence {
+        leading,
+        trailing,
+        decoded_samples,
+        sample_rate,
+    })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           LIBRARY
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads the delay and padding of a file and measures its silence. Nothing is saved, the song_id is left empty.
+/// When the headers don't say how long the song is, the decoded length is used.
+/// ```no_run
+/// # use decibl_metadata::engine::gapless::*;
+/// let gapless = analyze_gapless("/music/song.mp3");
+/// println!("skip {} samples, {}s of silence at the end", gapless.encoder_delay, gapless.trailing_silence);
+/// ```
+pub fn analyze_gapless(filepath: &str) -> GAPLESS_TABLE_DATA {
+    let info = read_gapless_info(filepath);
+    let mut gapless = GAPLESS_TABLE_DATA {
+        song_id: "".to_string(),
+        encoder_delay: info.encoder_delay,
+        encoder_padding: info.encoder_padding,
+        total_samples: info.total_samples,
+        sample_rate: info.sample_rate,
+        leading_silence: -1.0,
+        trailing_silence: -1.0,
+        source: info.source.as_str().to_string(),
+        analyzed_dt: now_dt(),
+    };
+
+    if let Some(silence) = find_silence(filepath, &info) {
+        gapless.leading_silence = silence.leading;
+        gapless.trailing_silence = silence.trailing;
+        if gapless.total_samples < 0 {
+            gapless.total_samples = silence.decoded_samples as i64;
+        }
+        if gapless.sample_rate < 0 {
+            gapless.sample_rate = silence.sample_rate as i64;
+        }
+    }
+    gapless
+}
+
+/// Saves gapless info, replacing the last one for the same song
+pub fn save_gapless(gapless: &GAPLESS_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO gapless (song_id, encoder_delay, encoder_padding, total_samples, sample_rate, \
+         leading_silence, trailing_silence, source, analyzed_dt) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            gapless.song_id,
+            gapless.encoder_delay,
+            gapless.encoder_padding,
+            gapless.total_samples,
+            gapless.sample_rate,
+            gapless.leading_silence,
+            gapless.trailing_silence,
+            gapless.source,
+            gapless.analyzed_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Analyzes a song's file and saves the result. None if the song has no path.
+pub fn analyze_song(song_id: &str) -> Option<GAPLESS_TABLE_DATA> {
+    let songpath = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?;
+
+    let mut gapless = analyze_gapless(&songpath.song_path);
+    gapless.song_id = song_id.to_string();
+    save_gapless(&gapless);
+    Some(gapless)
+}
+
+/// Analyzes every song that hasn't been analyzed yet (or all of them with `reanalyze`). Returns how many were saved.
+pub fn analyze_library_gapless(reanalyze: bool) -> usize {
+    let song_ids: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT song_id FROM songs WHERE ?1 OR song_id NOT IN (SELECT song_id FROM gapless) \
+                 ORDER BY song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([reanalyze], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let bar = ProgressBar::new(song_ids.len() as u64);
+    let mut saved = 0;
+    for song_id in song_ids {
+        if analyze_song(&song_id).is_some() {
+            saved += 1;
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+    saved
+}
+
+/// Get the gapless info of a song, None if it hasn't been analyzed
+pub fn get_gapless(song_id: &str) -> Option<GAPLESS_TABLE_DATA> {
+    get_all_gapless()
+        .into_iter()
+        .find(|gapless| gapless.song_id == song_id)
+}
@@ -0,0 +1,537 @@
+// Dumping the whole analytics database to plain files and restoring it again.
+// A dump is a folder with a manifest.json and one file per table, either newline delimited JSON (one row per line)
+// or CSV with a header. The keys/headers are the column names, so the files are easy to load into a notebook.
+//
+// Restoring reads and checks everything before writing anything, then inserts it all in one transaction,
+// so a bad dump never leaves a half restored database behind. It only restores into an empty database.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+/// Bumped whenever the layout of a dump changes, including columns being added to a table.
+/// 2: the manifest has the song_id scheme, songs have recording_id and dt_added, and the tables added since.
+pub const DUMP_VERSION: i64 = 2;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    Jsonl,
+    Csv,
+}
+
+impl DumpFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            DumpFormat::Jsonl => "jsonl",
+            DumpFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableManifest {
+    pub name: String,
+    /// Relative to the dump folder
+    pub file: String,
+    pub rows: usize,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DumpManifest {
+    pub version: i64,
+    pub format: DumpFormat,
+    pub created_dt: String,
+    /// The song_id scheme the songs were keyed with (see get_song_id_version). Dumps from before version 2 don't
+    /// say, so they count as the original scheme and get re-keyed after restoring.
+    #[serde(default)]
+    pub song_id_version: i64,
+    pub tables: Vec<TableManifest>,
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    /// Rows restored per table
+    pub rows: BTreeMap<String, usize>,
+    /// Things that look off but didn't stop the restore, like playlist songs pointing at songs that aren't there
+    pub warnings: Vec<String>,
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TABLE ROWS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A *_TABLE_DATA struct that knows which table it's a row of
+pub trait TableData: Serialize + DeserializeOwned + default {
+    fn table() -> &'static Table;
+    fn get_all() -> Vec<Self>;
+}
+
+macro_rules! table_data {
+    ($($data:ty => $table:ident, $get_all:ident;)*) => {
+        $(
+            impl TableData for $data {
+                fn table() -> &'static Table {
+                    &$table
+                }
+                fn get_all() -> Vec<Self> {
+                    $get_all()
+                }
+            }
+        )*
+    };
+}
+
+table_data! {
+    SONG_TABLE_DATA => SONGS, get_all_songs;
+    PLAY_TABLE_DATA => PLAYS, get_all_plays;
+    PLAYLIST_TABLE_DATA => PLAYLISTS, get_all_playlists;
+    PLAYLIST_SONGS_TABLE_DATA => PLAYLIST_SONGS, get_all_playlist_songs;
+    SONG_ARTISTS_TABLE_DATA => SONG_ARTISTS, get_all_song_artists;
+    ALBUM_ARTISTS_TABLE_DATA => ALBUM_ARTISTS, get_all_album_artists;
+    COMPOSERS_TABLE_DATA => COMPOSERS, get_all_composers;
+    GENRES_TABLE_DATA => GENRES, get_all_genres;
+    SONGPATHS_TABLE_DATA => SONGPATHS, get_all_songpaths;
+    ARTISTS_TABLE_DATA => ARTISTS, get_all_artists;
+    ALBUMS_TABLE_DATA => ALBUMS, get_all_albums;
+    SMART_PLAYLISTS_TABLE_DATA => SMART_PLAYLISTS, get_all_smart_playlists;
+    SONG_VERSIONS_TABLE_DATA => SONG_VERSIONS, get_all_song_versions;
+    FLAC_VERIFICATIONS_TABLE_DATA => FLAC_VERIFICATIONS, get_all_flac_verifications;
+    FILE_HEALTH_TABLE_DATA => FILE_HEALTH, get_all_file_health;
+    LOUDNESS_TABLE_DATA => LOUDNESS, get_all_loudness;
+    MUSIC_ANALYSIS_TABLE_DATA => MUSIC_ANALYSIS, get_all_music_analysis;
+    GAPLESS_TABLE_DATA => GAPLESS, get_all_gapless;
+    ARTWORK_TABLE_DATA => ARTWORK, get_all_artwork;
+    SONG_ARTWORK_TABLE_DATA => SONG_ARTWORK, get_all_song_artwork;
+    ALBUM_ARTWORK_TABLE_DATA => ALBUM_ARTWORK, get_all_album_artwork;
+    TAG_EDIT_BATCHES_TABLE_DATA => TAG_EDIT_BATCHES, get_all_tag_edit_batches;
+    TAG_EDIT_JOURNAL_TABLE_DATA => TAG_EDIT_JOURNAL, get_all_tag_edit_journal;
+}
+
+fn column_names(table: &Table) -> Vec<String> {
+    table
+        .columns
+        .iter()
+        .map(|column| column.name.to_string())
+        .collect()
+}
+
+// a row as sql values in column order
+fn row_values<T: TableData>(row: &T) -> std::io::Result<Vec<Value>> {
+    let object = match serde_json::to_value(row)? {
+        serde_json::Value::Object(object) => object,
+        _ => return Err(invalid("Rows have to serialize to objects".to_string())),
+    };
+
+    T::table()
+        .columns
+        .iter()
+        .map(|column| match object.get(column.name) {
+            Some(serde_json::Value::Null) => Ok(Value::Null),
+            Some(serde_json::Value::Bool(value)) => Ok(Value::Integer(*value as i64)),
+            Some(serde_json::Value::Number(number)) => Ok(match number.as_i64() {
+                Some(value) => Value::Integer(value),
+                None => Value::Real(number.as_f64().unwrap_or_default()),
+            }),
+            Some(serde_json::Value::String(value)) => Ok(Value::Text(value.clone())),
+            _ => Err(invalid(format!(
+                "{} has no value for column {}",
+                T::table().name,
+                column.name
+            ))),
+        })
+        .collect()
+}
+
+fn dump_table<T: TableData>(dir: &Path, format: DumpFormat) -> std::io::Result<TableManifest> {
+    let table = T::table();
+    let file = format!("{}.{}", table.name, format.extension());
+    let rows = T::get_all();
+
+    let mut writer = BufWriter::new(std::fs::File::create(dir.join(&file))?);
+    match format {
+        DumpFormat::Jsonl => {
+            for row in &rows {
+                serde_json::to_writer(&mut writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        DumpFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(&mut writer);
+            // serialize only writes the header along with the first row, an empty table still wants one
+            if rows.is_empty() {
+                csv_writer.write_record(column_names(table))?;
+            }
+            for row in &rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(TableManifest {
+        name: table.name.to_string(),
+        file,
+        rows: rows.len(),
+        columns: column_names(table),
+    })
+}
+
+// reads a table's file into typed rows (which is the validation) and hands back their values.
+// Dumps older than DUMP_VERSION can be missing columns that were added since, those get the TABLE_DATA defaults
+fn read_table<T: TableData>(
+    dir: &Path,
+    entry: &TableManifest,
+    manifest: &DumpManifest,
+) -> std::io::Result<Vec<Vec<Value>>> {
+    let mut expected = column_names(T::table());
+    let mut columns = entry.columns.clone();
+    expected.sort();
+    columns.sort();
+    let known = columns.iter().all(|column| expected.contains(column));
+    if !known || (manifest.version >= DUMP_VERSION && columns != expected) {
+        return Err(invalid(format!(
+            "{} in the dump has columns {:?} but this version of decibl expects {:?}",
+            entry.name, entry.columns, expected
+        )));
+    }
+
+    let defaults = match serde_json::to_value(T::default())? {
+        serde_json::Value::Object(object) => object,
+        _ => return Err(invalid("Rows have to serialize to objects".to_string())),
+    };
+    let missing: Vec<(&String, &serde_json::Value)> = defaults
+        .iter()
+        .filter(|(name, _)| !entry.columns.contains(name))
+        .collect();
+
+    // the manifest only ever points at files right next to it
+    if Path::new(&entry.file).components().count() != 1 {
+        return Err(invalid(format!(
+            "Bad file name in the manifest: {}",
+            entry.file
+        )));
+    }
+
+    let reader = BufReader::new(std::fs::File::open(dir.join(&entry.file))?);
+    let mut rows: Vec<T> = Vec::new();
+    match manifest.format {
+        DumpFormat::Jsonl => {
+            for (index, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row = serde_json::from_str(&line).and_then(|mut row: serde_json::Value| {
+                    if let serde_json::Value::Object(object) = &mut row {
+                        for (name, value) in &missing {
+                            object.insert(name.to_string(), (*value).clone());
+                        }
+                    }
+                    serde_json::from_value(row)
+                });
+                rows.push(
+                    row.map_err(|e| invalid(format!("{} line {}: {}", entry.file, index + 1, e)))?,
+                );
+            }
+        }
+        DumpFormat::Csv => {
+            let mut csv_reader = csv::Reader::from_reader(reader);
+            let mut headers = csv_reader.headers()?.clone();
+            let mut extra = csv::StringRecord::new();
+            for (name, value) in &missing {
+                headers.push_field(name);
+                extra.push_field(&match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    serde_json::Value::Null => String::new(),
+                    value => value.to_string(),
+                });
+            }
+            for (index, record) in csv_reader.records().enumerate() {
+                let row = record.and_then(|mut record| {
+                    record.extend(extra.iter());
+                    record.deserialize(Some(&headers))
+                });
+                // + 2 for the header and counting from 1
+                rows.push(
+                    row.map_err(|e| invalid(format!("{} line {}: {}", entry.file, index + 2, e)))?,
+                );
+            }
+        }
+    }
+
+    if rows.len() != entry.rows {
+        return Err(invalid(format!(
+            "{} has {} rows but the manifest says {}",
+            entry.file,
+            rows.len(),
+            entry.rows
+        )));
+    }
+
+    rows.iter().map(row_values).collect()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           DUMP AND RESTORE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Dumps every table in get_all_table_names() into `dirpath` (which gets created) and writes the manifest.
+/// ```no_run
+/// # use decibl_metadata::engine::library_dump::*;
+/// let manifest = dump_library("/home/me/decibl-backup".to_string(), DumpFormat::Jsonl).unwrap();
+/// println!("dumped {} tables", manifest.tables.len());
+/// ```
+pub fn dump_library(dirpath: String, format: DumpFormat) -> std::io::Result<DumpManifest> {
+    let dir = Path::new(&dirpath);
+    std::fs::create_dir_all(dir)?;
+
+    let mut tables: Vec<TableManifest> = Vec::new();
+    for name in get_all_table_names() {
+        let entry = match name.as_str() {
+            "songs" => dump_table::<SONG_TABLE_DATA>(dir, format),
+            "plays" => dump_table::<PLAY_TABLE_DATA>(dir, format),
+            "playlists" => dump_table::<PLAYLIST_TABLE_DATA>(dir, format),
+            "playlist_songs" => dump_table::<PLAYLIST_SONGS_TABLE_DATA>(dir, format),
+            "song_artists" => dump_table::<SONG_ARTISTS_TABLE_DATA>(dir, format),
+            "album_artists" => dump_table::<ALBUM_ARTISTS_TABLE_DATA>(dir, format),
+            "composers" => dump_table::<COMPOSERS_TABLE_DATA>(dir, format),
+            "genres" => dump_table::<GENRES_TABLE_DATA>(dir, format),
+            "songpaths" => dump_table::<SONGPATHS_TABLE_DATA>(dir, format),
+            "artists" => dump_table::<ARTISTS_TABLE_DATA>(dir, format),
+            "albums" => dump_table::<ALBUMS_TABLE_DATA>(dir, format),
+            "smart_playlists" => dump_table::<SMART_PLAYLISTS_TABLE_DATA>(dir, format),
+            "song_versions" => dump_table::<SONG_VERSIONS_TABLE_DATA>(dir, format),
+            "flac_verifications" => dump_table::<FLAC_VERIFICATIONS_TABLE_DATA>(dir, format),
+            "file_health" => dump_table::<FILE_HEALTH_TABLE_DATA>(dir, format),
+            "loudness" => dump_table::<LOUDNESS_TABLE_DATA>(dir, format),
+            "music_analysis" => dump_table::<MUSIC_ANALYSIS_TABLE_DATA>(dir, format),
+            "gapless" => dump_table::<GAPLESS_TABLE_DATA>(dir, format),
+            "artwork" => dump_table::<ARTWORK_TABLE_DATA>(dir, format),
+            "song_artwork" => dump_table::<SONG_ARTWORK_TABLE_DATA>(dir, format),
+            "album_artwork" => dump_table::<ALBUM_ARTWORK_TABLE_DATA>(dir, format),
+            "tag_edit_batches" => dump_table::<TAG_EDIT_BATCHES_TABLE_DATA>(dir, format),
+            "tag_edit_journal" => dump_table::<TAG_EDIT_JOURNAL_TABLE_DATA>(dir, format),
+            _ => return Err(invalid(format!("Don't know how to dump table {}", name))),
+        }?;
+        tables.push(entry);
+    }
+
+    let manifest = DumpManifest {
+        version: DUMP_VERSION,
+        format,
+        created_dt: now_dt(),
+        song_id_version: get_song_id_version(),
+        tables,
+    };
+    std::fs::write(
+        dir.join(MANIFEST_FILE_NAME),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    Ok(manifest)
+}
+
+/// Reads the manifest of a dump
+pub fn read_manifest(dirpath: &str) -> std::io::Result<DumpManifest> {
+    let contents = std::fs::read_to_string(Path::new(dirpath).join(MANIFEST_FILE_NAME))?;
+    let manifest: DumpManifest = serde_json::from_str(&contents)
+        .map_err(|e| invalid(format!("Bad {}: {}", MANIFEST_FILE_NAME, e)))?;
+
+    if manifest.version > DUMP_VERSION {
+        return Err(invalid(format!(
+            "The dump is version {} but this version of decibl only reads up to {}",
+            manifest.version, DUMP_VERSION
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Restores a dump made by dump_library into the database. The database has to be empty (tables are created if
+/// they don't exist), and every file is read and checked against the manifest and the models before anything
+/// is written. If anything fails nothing is restored.
+/// ```no_run
+/// # use decibl_metadata::engine::library_dump::*;
+/// let report = restore_library("/home/me/decibl-backup".to_string()).unwrap();
+/// for warning in report.warnings {
+///     println!("{}", warning);
+/// }
+/// ```
+pub fn restore_library(dirpath: String) -> std::io::Result<RestoreReport> {
+    let manifest = read_manifest(&dirpath)?;
+    let dir = Path::new(&dirpath);
+
+    let mut tables: Vec<(&'static Table, Vec<Vec<Value>>)> = Vec::new();
+    for entry in &manifest.tables {
+        let rows = match entry.name.as_str() {
+            "songs" => (
+                &*SONGS,
+                read_table::<SONG_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "plays" => (
+                &*PLAYS,
+                read_table::<PLAY_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "playlists" => (
+                &*PLAYLISTS,
+                read_table::<PLAYLIST_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "playlist_songs" => (
+                &*PLAYLIST_SONGS,
+                read_table::<PLAYLIST_SONGS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "song_artists" => (
+                &*SONG_ARTISTS,
+                read_table::<SONG_ARTISTS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "album_artists" => (
+                &*ALBUM_ARTISTS,
+                read_table::<ALBUM_ARTISTS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "composers" => (
+                &*COMPOSERS,
+                read_table::<COMPOSERS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "genres" => (
+                &*GENRES,
+                read_table::<GENRES_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "songpaths" => (
+                &*SONGPATHS,
+                read_table::<SONGPATHS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "artists" => (
+                &*ARTISTS,
+                read_table::<ARTISTS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "albums" => (
+                &*ALBUMS,
+                read_table::<ALBUMS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "smart_playlists" => (
+                &*SMART_PLAYLISTS,
+                read_table::<SMART_PLAYLISTS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "song_versions" => (
+                &*SONG_VERSIONS,
+                read_table::<SONG_VERSIONS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "flac_verifications" => (
+                &*FLAC_VERIFICATIONS,
+                read_table::<FLAC_VERIFICATIONS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "file_health" => (
+                &*FILE_HEALTH,
+                read_table::<FILE_HEALTH_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "loudness" => (
+                &*LOUDNESS,
+                read_table::<LOUDNESS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "music_analysis" => (
+                &*MUSIC_ANALYSIS,
+                read_table::<MUSIC_ANALYSIS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "gapless" => (
+                &*GAPLESS,
+                read_table::<GAPLESS_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "artwork" => (
+                &*ARTWORK,
+                read_table::<ARTWORK_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "song_artwork" => (
+                &*SONG_ARTWORK,
+                read_table::<SONG_ARTWORK_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "album_artwork" => (
+                &*ALBUM_ARTWORK,
+                read_table::<ALBUM_ARTWORK_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "tag_edit_batches" => (
+                &*TAG_EDIT_BATCHES,
+                read_table::<TAG_EDIT_BATCHES_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            "tag_edit_journal" => (
+                &*TAG_EDIT_JOURNAL,
+                read_table::<TAG_EDIT_JOURNAL_TABLE_DATA>(dir, entry, &manifest)?,
+            ),
+            _ => {
+                return Err(invalid(format!(
+                    "The dump has an unknown table {}",
+                    entry.name
+                )))
+            }
+        };
+        tables.push(rows);
+    }
+
+    create_all_tables();
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    for name in get_all_table_names() {
+        let count: i64 = conn
+            .query_row(&format!("SELECT COUNT(*) FROM {}", name), [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        if count > 0 {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "Can only restore into an empty database but {} has {} rows",
+                    name, count
+                ),
+            ));
+        }
+    }
+
+    let mut report = RestoreReport::default();
+    let tx = conn.transaction().expect("Could not start transaction");
+    for (table, rows) in &tables {
+        // a plain INSERT, so duplicate keys in the dump are an error instead of quietly dropped
+        let sql_query = generate_insertion_sql(table).replacen("INSERT OR IGNORE", "INSERT", 1);
+        let mut stmt = tx.prepare(&sql_query).expect("Could not prepare statement");
+        for (index, row) in rows.iter().enumerate() {
+            stmt.execute(params_from_iter(row))
+                .map_err(|e| invalid(format!("{} row {}: {}", table.name, index + 1, e)))?;
+        }
+        report.rows.insert(table.name.to_string(), rows.len());
+    }
+
+    // songs from older dumps have no date added of their own, and are on the song_id scheme they were dumped with
+    // rather than the one create_all_tables gave the empty database, so needs_rekey says to re-key them
+    if manifest.tables.iter().any(|entry| {
+        entry.name == SONGS.name && !entry.columns.iter().any(|column| column == "dt_added")
+    }) {
+        fill_songs_dt_added(&tx);
+    }
+    set_song_id_version(&tx, manifest.song_id_version);
+
+    report.warnings = count_orphans(&tx)
+        .iter()
+        .map(|orphans| orphans.to_string())
+        .collect();
+    tx.commit().expect("Could not commit transaction");
+
+    Ok(report)
+}
@@ -0,0 +1,397 @@
+// Listening statistics, all worked out from the plays table on demand.
+// Every stat takes a TimeWindow so the dashboard can ask for "this week", "this year" or "all time" with the same call.
+// A play counts towards a window if it *started* inside it.
+//
+// Times in the plays table are naive UTC, so the hour of day heatmap is in UTC too.
+
+use crate::engine::analyticsdb::format_dt;
+use crate::engine::config::*;
+use chrono::{Duration, NaiveDate};
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::Serialize;
+
+/// Plays that lasted less than this fraction of the song count as skips unless you ask for something else
+pub const DEFAULT_SKIP_FRACTION: f64 = 0.5;
+
+// how long a play lasted in seconds, broken or missing end times count as 0
+const LISTENED_SECONDS: &str =
+    "MAX(0, COALESCE((julianday(plays.end_dt) - julianday(plays.start_dt)) * 86400.0, 0))";
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TYPES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// The stretch of time a stat covers. Both ends are YYYY-MM-DD HH:MM:SS, start is inclusive and end is exclusive.
+/// Leave either end as None to leave it open.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TimeWindow {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+impl TimeWindow {
+    pub fn all_time() -> Self {
+        TimeWindow::default()
+    }
+
+    /// From `days` days ago up until now
+    pub fn last_days(days: i64) -> Self {
+        let start = chrono::Utc::now().naive_utc() - Duration::days(days);
+        TimeWindow {
+            start: Some(format_dt(start)),
+            end: None,
+        }
+    }
+
+    pub fn between(start: &str, end: &str) -> Self {
+        TimeWindow {
+            start: Some(start.to_string()),
+            end: Some(end.to_string()),
+        }
+    }
+
+    // sql to AND onto a WHERE clause, pushes its own params
+    fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        let mut sql = String::new();
+        if let Some(start) = &self.start {
+            sql.push_str(" AND plays.start_dt >= ?");
+            params.push(Value::Text(start.clone()));
+        }
+        if let Some(end) = &self.end {
+            sql.push_str(" AND plays.start_dt < ?");
+            params.push(Value::Text(end.clone()));
+        }
+        sql
+    }
+}
+
+/// One row of a top X chart. For songs `key` is the song_id and `label` the title,
+/// for artists, albums and genres they're both the name.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RankedEntry {
+    pub key: String,
+    pub label: String,
+    pub play_count: i64,
+    pub listened_seconds: f64,
+}
+
+/// The headline numbers for a window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListeningSummary {
+    pub play_count: i64,
+    pub distinct_songs: i64,
+    pub listened_seconds: f64,
+    /// Plays shorter than the skip fraction of the song's duration. Uses the recorded completion when the play has one,
+    /// otherwise how long the play lasted. Songs with no duration never count as skipped.
+    pub skip_count: i64,
+    /// skip_count / play_count, 0 if there are no plays
+    pub skip_rate: f64,
+}
+
+/// Runs of consecutive days with at least one play. Days are UTC dates.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ListeningStreaks {
+    /// The streak running up to today, or up to yesterday if nothing has been played yet today. 0 if it's broken.
+    pub current_days: i64,
+    pub longest_days: i64,
+    /// First and last day of the longest streak as YYYY-MM-DD (the most recent one if there's a tie)
+    pub longest_start: Option<String>,
+    pub longest_end: Option<String>,
+}
+
+/// Play counts bucketed by when the play started
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ListeningHeatmap {
+    /// Index 0 is midnight to 1am
+    pub by_hour: [i64; 24],
+    /// Index 0 is Sunday
+    pub by_weekday: [i64; 7],
+    /// by_weekday_hour[weekday][hour]
+    pub by_weekday_hour: [[i64; 24]; 7],
+}
+
+/// When a song was first and last played, and how often in between
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SongPlayHistory {
+    pub song_id: String,
+    pub play_count: i64,
+    pub listened_seconds: f64,
+    pub first_played: Option<String>,
+    pub last_played: Option<String>,
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TOP CHARTS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// `key` and `label` are sql expressions, `join` is whatever has to be joined onto plays to get at them
+fn top_entries(
+    key: &str,
+    label: &str,
+    join: &str,
+    window: &TimeWindow,
+    limit: i64,
+) -> Vec<RankedEntry> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut params: Vec<Value> = Vec::new();
+
+    let mut sql = format!(
+        "SELECT {key}, {label}, COUNT(*), SUM({LISTENED_SECONDS}) FROM plays {join} WHERE {key} IS NOT NULL AND {key} != ''"
+    );
+    sql.push_str(&window.to_sql(&mut params));
+    sql.push_str(&format!(
+        " GROUP BY {key} ORDER BY COUNT(*) DESC, SUM({LISTENED_SECONDS}) DESC, {key} LIMIT ?"
+    ));
+    params.push(Value::Integer(limit));
+
+    let mut stmt = conn.prepare(&sql).expect("Could not prepare statement");
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(RankedEntry {
+                key: row.get(0)?,
+                label: row.get(1)?,
+                play_count: row.get(2)?,
+                listened_seconds: row.get(3)?,
+            })
+        })
+        .unwrap();
+
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// The most played songs in the window. Falls back to the title saved on the play if the song is gone from the library.
+/// ```no_run
+/// # use decibl_metadata::engine::listening_stats::*;
+/// for entry in get_top_songs(&TimeWindow::last_days(7), 10) {
+///     println!("{} - {} plays", entry.label, entry.play_count);
+/// }
+/// ```
+pub fn get_top_songs(window: &TimeWindow, limit: i64) -> Vec<RankedEntry> {
+    top_entries(
+        "plays.song_id",
+        "COALESCE(NULLIF(MAX(songs.title), ''), MAX(plays.song_title), '')",
+        "LEFT JOIN songs ON songs.song_id = plays.song_id",
+        window,
+        limit,
+    )
+}
+
+/// The most played artists in the window, going by song_artists. A song with two artists counts for both.
+pub fn get_top_artists(window: &TimeWindow, limit: i64) -> Vec<RankedEntry> {
+    top_entries(
+        "song_artists.artist_name",
+        "song_artists.artist_name",
+        "JOIN song_artists ON song_artists.song_id = plays.song_id",
+        window,
+        limit,
+    )
+}
+
+/// The most played albums in the window
+pub fn get_top_albums(window: &TimeWindow, limit: i64) -> Vec<RankedEntry> {
+    top_entries(
+        "songs.album",
+        "songs.album",
+        "JOIN songs ON songs.song_id = plays.song_id",
+        window,
+        limit,
+    )
+}
+
+/// The most played genres in the window. A song with two genres counts for both.
+pub fn get_top_genres(window: &TimeWindow, limit: i64) -> Vec<RankedEntry> {
+    top_entries(
+        "genres.genre_name",
+        "genres.genre_name",
+        "JOIN genres ON genres.song_id = plays.song_id",
+        window,
+        limit,
+    )
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TOTALS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Play count, listening time and skips in the window. `skip_fraction` is how much of a song has to be
+/// listened to for it not to be a skip, DEFAULT_SKIP_FRACTION is a good place to start.
+pub fn get_listening_summary(window: &TimeWindow, skip_fraction: f64) -> ListeningSummary {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut params: Vec<Value> = vec![Value::Real(skip_fraction), Value::Real(skip_fraction)];
+
+    let mut sql = format!(
+        "SELECT COUNT(*), COUNT(DISTINCT plays.song_id), COALESCE(SUM({LISTENED_SECONDS}), 0), \
+         COALESCE(SUM(CASE WHEN plays.completion >= 0 THEN plays.completion < ? \
+         ELSE songs.duration > 0 AND {LISTENED_SECONDS} < songs.duration * ? END), 0) \
+         FROM plays LEFT JOIN songs ON songs.song_id = plays.song_id WHERE 1"
+    );
+    sql.push_str(&window.to_sql(&mut params));
+
+    let (play_count, distinct_songs, listened_seconds, skip_count): (i64, i64, f64, i64) = conn
+        .query_row(&sql, params_from_iter(params), |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap();
+
+    ListeningSummary {
+        play_count,
+        distinct_songs,
+        listened_seconds,
+        skip_count,
+        skip_rate: if play_count > 0 {
+            skip_count as f64 / play_count as f64
+        } else {
+            0.0
+        },
+    }
+}
+
+/// Total time spent listening in the window, in seconds
+pub fn get_total_listening_seconds(window: &TimeWindow) -> f64 {
+    get_listening_summary(window, DEFAULT_SKIP_FRACTION).listened_seconds
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           WHEN YOU LISTEN
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Play counts by hour of day and day of week
+pub fn get_listening_heatmap(window: &TimeWindow) -> ListeningHeatmap {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut params: Vec<Value> = Vec::new();
+
+    let mut sql = String::from(
+        "SELECT CAST(strftime('%w', plays.start_dt) AS INTEGER), CAST(strftime('%H', plays.start_dt) AS INTEGER), COUNT(*) \
+         FROM plays WHERE strftime('%w', plays.start_dt) IS NOT NULL",
+    );
+    sql.push_str(&window.to_sql(&mut params));
+    sql.push_str(" GROUP BY 1, 2");
+
+    let mut heatmap = ListeningHeatmap::default();
+    let mut stmt = conn.prepare(&sql).expect("Could not prepare statement");
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, usize>(0)?,
+                row.get::<_, usize>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })
+        .unwrap();
+
+    for row in rows {
+        let (weekday, hour, count) = row.unwrap();
+        heatmap.by_hour[hour] += count;
+        heatmap.by_weekday[weekday] += count;
+        heatmap.by_weekday_hour[weekday][hour] += count;
+    }
+
+    heatmap
+}
+
+/// Current and longest runs of days with at least one play
+pub fn get_listening_streaks(window: &TimeWindow) -> ListeningStreaks {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut params: Vec<Value> = Vec::new();
+
+    let mut sql = String::from(
+        "SELECT DISTINCT date(plays.start_dt) FROM plays WHERE date(plays.start_dt) IS NOT NULL",
+    );
+    sql.push_str(&window.to_sql(&mut params));
+    sql.push_str(" ORDER BY 1");
+
+    let mut stmt = conn.prepare(&sql).expect("Could not prepare statement");
+    let days: Vec<NaiveDate> = stmt
+        .query_map(params_from_iter(params), |row| row.get::<_, String>(0))
+        .unwrap()
+        .filter_map(|day| NaiveDate::parse_from_str(&day.unwrap(), "%Y-%m-%d").ok())
+        .collect();
+
+    let mut streaks = ListeningStreaks::default();
+    let mut run_start = 0;
+    for i in 0..days.len() {
+        if i > 0 && days[i] - days[i - 1] != Duration::days(1) {
+            run_start = i;
+        }
+        let length = (i - run_start + 1) as i64;
+        if length >= streaks.longest_days {
+            streaks.longest_days = length;
+            streaks.longest_start = Some(days[run_start].to_string());
+            streaks.longest_end = Some(days[i].to_string());
+        }
+    }
+
+    // the last run is still going if it reaches today, or yesterday since today isn't over yet
+    if let Some(last) = days.last() {
+        let today = chrono::Utc::now().naive_utc().date();
+        if today - *last <= Duration::days(1) {
+            streaks.current_days = (days.len() - run_start) as i64;
+        }
+    }
+
+    streaks
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           PER SONG
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+fn song_play_histories(song_id: Option<&str>, window: &TimeWindow) -> Vec<SongPlayHistory> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut params: Vec<Value> = Vec::new();
+
+    let mut sql = format!(
+        "SELECT plays.song_id, COUNT(*), SUM({LISTENED_SECONDS}), MIN(plays.start_dt), MAX(plays.start_dt) FROM plays WHERE 1"
+    );
+    if let Some(song_id) = song_id {
+        sql.push_str(" AND plays.song_id = ?");
+        params.push(Value::Text(song_id.to_string()));
+    }
+    sql.push_str(&window.to_sql(&mut params));
+    sql.push_str(" GROUP BY plays.song_id ORDER BY MAX(plays.start_dt) DESC");
+
+    let mut stmt = conn.prepare(&sql).expect("Could not prepare statement");
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(SongPlayHistory {
+                song_id: row.get(0)?,
+                play_count: row.get(1)?,
+                listened_seconds: row.get(2)?,
+                first_played: row.get(3)?,
+                last_played: row.get(4)?,
+            })
+        })
+        .unwrap();
+
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// First played, last played and play count of one song. A song that's never been played comes back with 0 plays and no dates.
+pub fn get_song_play_history(song_id: &str) -> SongPlayHistory {
+    song_play_histories(Some(song_id), &TimeWindow::all_time())
+        .pop()
+        .unwrap_or(SongPlayHistory {
+            song_id: song_id.to_string(),
+            play_count: 0,
+            listened_seconds: 0.0,
+            first_played: None,
+            last_played: None,
+        })
+}
+
+/// The play history of every song played in the window, most recently played first
+pub fn get_all_song_play_histories(window: &TimeWindow) -> Vec<SongPlayHistory> {
+    song_play_histories(None, window)
+}
@@ -0,0 +1,578 @@
+// Loudness of songs for volume normalization, measured the EBU R128 / ITU-R BS.1770 way and turned into ReplayGain 2.0
+// gains. A LoudnessMeter is fed decoded samples and works out the integrated loudness (LUFS), the loudness range (LU)
+// and the true peak. Albums are measured as one long track, so the album gain keeps the quiet songs quiet.
+//
+// Files that already have ReplayGain tags (REPLAYGAIN_TRACK_GAIN and friends) can be read instead of decoded,
+// both end up in the loudness table the same way.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::config::*;
+use crate::engine::decoder::AudioDecoder;
+use crate::engine::models::*;
+use indicatif::ProgressBar;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+
+/// The loudness ReplayGain 2.0 normalizes everything to, in LUFS
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+/// Blocks quieter than this (in LUFS) are ignored, it's silence as far as loudness goes
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks more than this many LU below the average are ignored for the integrated loudness
+const INTEGRATED_RELATIVE_GATE: f64 = -10.0;
+/// and this many for the loudness range
+const RANGE_RELATIVE_GATE: f64 = -20.0;
+
+// the meter works in 100ms steps, momentary blocks are 4 of them (400ms) and short term blocks 30 (3s)
+const STEPS_PER_MOMENTARY: usize = 4;
+const STEPS_PER_SHORT_TERM: usize = 30;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MEASURING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// a second order IIR filter, transposed direct form II
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+// the two filters that make up K-weighting (a high shelf for the head, then a high pass), worked out for any
+// sample rate instead of the 48kHz coefficients in the standard
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+// how much each channel counts, the LFE doesn't count and the surrounds count a bit more (5.1 order: L R C LFE Ls Rs)
+fn channel_weights(channels: usize) -> Vec<f64> {
+    if channels == 6 {
+        vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+    } else {
+        vec![1.0; channels]
+    }
+}
+
+// true peak is found by upsampling 4x with a windowed sinc and taking the peak of that
+const OVERSAMPLE: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+fn oversampling_filter() -> Vec<[f64; TAPS_PER_PHASE]> {
+    let length = OVERSAMPLE * TAPS_PER_PHASE;
+    let center = (length - 1) as f64 / 2.0;
+    let mut phases = vec![[0.0; TAPS_PER_PHASE]; OVERSAMPLE];
+    for n in 0..length {
+        let x = (n as f64 - center) / OVERSAMPLE as f64;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / length as f64).cos();
+        phases[n % OVERSAMPLE][n / OVERSAMPLE] = sinc * window;
+    }
+    phases
+}
+
+/// What a LoudnessMeter measured
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoudnessAnalysis {
+    /// Integrated loudness in LUFS, -70 (the absolute gate) for silence
+    pub integrated_lufs: f64,
+    /// Loudness range in LU
+    pub loudness_range: f64,
+    /// The highest peak between samples as a linear amplitude
+    pub true_peak: f64,
+    /// The highest sample as a linear amplitude
+    pub sample_peak: f64,
+    /// The mean square of every 400ms block, kept so tracks can be put together into an album (see album_loudness)
+    pub blocks: Vec<f64>,
+}
+
+impl LoudnessAnalysis {
+    /// ReplayGain 2.0 track gain in dB
+    pub fn track_gain(&self) -> f64 {
+        REPLAYGAIN_REFERENCE_LUFS - self.integrated_lufs
+    }
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+// the BS.1770 gating: drop the blocks under the absolute gate, then the ones more than `relative_gate` LU under the
+// average of what's left. Returns what's left as loudness values.
+fn gate_blocks(blocks: &[f64], relative_gate: f64) -> Vec<f64> {
+    let loud: Vec<f64> = blocks
+        .iter()
+        .copied()
+        .filter(|block| to_lufs(*block) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if loud.is_empty() {
+        return Vec::new();
+    }
+    let threshold = to_lufs(loud.iter().sum::<f64>() / loud.len() as f64) + relative_gate;
+    loud.into_iter()
+        .filter(|block| to_lufs(*block) > threshold)
+        .collect()
+}
+
+/// The integrated loudness of a set of 400ms blocks (LoudnessAnalysis::blocks), -70 if it's all silence
+pub fn integrated_loudness(blocks: &[f64]) -> f64 {
+    let gated = gate_blocks(blocks, INTEGRATED_RELATIVE_GATE);
+    if gated.is_empty() {
+        return ABSOLUTE_GATE_LUFS;
+    }
+    to_lufs(gated.iter().sum::<f64>() / gated.len() as f64)
+}
+
+/// Measures loudness. Feed it interleaved samples with add_samples, then call finish.
+/// ```
+/// # use decibl_metadata::engine::loudness::*;
+/// let mut meter = LoudnessMeter::new(48000, 2);
+/// let sine: Vec<f32> = (0..48000 * 5)
+///     .flat_map(|i| {
+///         let sample = 0.1 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin();
+///         [sample, sample]
+///     })
+///     .collect();
+/// meter.add_samples(&sine);
+/// println!("{} LUFS", meter.finish().integrated_lufs);
+/// ```
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    oversampling: Vec<[f64; TAPS_PER_PHASE]>,
+    // the last TAPS_PER_PHASE samples of every channel for the oversampling, newest first
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+    step_length: usize,
+    step_frames: usize,
+    step_sum: f64,
+    // the weighted sum of squares of every 100ms step
+    steps: Vec<f64>,
+    true_peak: f64,
+    sample_peak: f64,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        LoudnessMeter {
+            channels,
+            weights: channel_weights(channels),
+            filters: vec![k_weighting(sample_rate as f64); channels],
+            oversampling: oversampling_filter(),
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            step_length: ((sample_rate as f64 / 10.0).round() as usize).max(1),
+            step_frames: 0,
+            step_sum: 0.0,
+            steps: Vec::new(),
+            true_peak: 0.0,
+            sample_peak: 0.0,
+        }
+    }
+
+    /// Adds interleaved samples, a leftover partial frame at the end is ignored
+    pub fn add_samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64;
+                self.sample_peak = self.sample_peak.max(sample.abs());
+
+                let history = &mut self.history[channel];
+                history.copy_within(0..TAPS_PER_PHASE - 1, 1);
+                history[0] = sample;
+                for phase in &self.oversampling {
+                    let upsampled: f64 = phase.iter().zip(history.iter()).map(|(h, x)| h * x).sum();
+                    self.true_peak = self.true_peak.max(upsampled.abs());
+                }
+
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample));
+                self.step_sum += self.weights[channel] * weighted * weighted;
+            }
+
+            self.step_frames += 1;
+            if self.step_frames == self.step_length {
+                self.steps.push(self.step_sum);
+                self.step_frames = 0;
+                self.step_sum = 0.0;
+            }
+        }
+    }
+
+    // mean squares of blocks `steps` long, sliding 100ms at a time
+    fn blocks(&self, steps: usize) -> Vec<f64> {
+        self.steps
+            .windows(steps)
+            .map(|window| window.iter().sum::<f64>() / (steps * self.step_length) as f64)
+            .collect()
+    }
+
+    pub fn finish(&self) -> LoudnessAnalysis {
+        let blocks = self.blocks(STEPS_PER_MOMENTARY);
+
+        // loudness range: the spread between the 10th and 95th percentile of the gated short term loudness
+        let mut short_term = gate_blocks(&self.blocks(STEPS_PER_SHORT_TERM), RANGE_RELATIVE_GATE)
+            .into_iter()
+            .map(to_lufs)
+            .collect::<Vec<f64>>();
+        short_term.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let loudness_range = if short_term.is_empty() {
+            0.0
+        } else {
+            let percentile =
+                |p: f64| short_term[((short_term.len() - 1) as f64 * p).round() as usize];
+            percentile(0.95) - percentile(0.10)
+        };
+
+        LoudnessAnalysis {
+            integrated_lufs: integrated_loudness(&blocks),
+            loudness_range,
+            true_peak: self.true_peak.max(self.sample_peak),
+            sample_peak: self.sample_peak,
+            blocks,
+        }
+    }
+}
+
+/// Decodes a whole file with symphonia and measures it. None if it can't be decoded.
+/// ```no_run
+/// # use decibl_metadata::engine::loudness::*;
+/// let analysis = analyze_file("/music/song.flac").unwrap();
+/// println!("{} LUFS, play it at {} dB", analysis.integrated_lufs, analysis.track_gain());
+/// ```
+pub fn analyze_file(filepath: &str) -> Option<LoudnessAnalysis> {
+    let mut decoder = AudioDecoder::open(
+        filepath,
+        FormatOptions::default(),
+        DecoderOptions::default(),
+    )?;
+    let sample_rate = decoder.sample_rate()?;
+    let channels = decoder.channels()?;
+
+    let mut meter = LoudnessMeter::new(sample_rate, channels);
+    while let Some(samples) = decoder.next_samples() {
+        meter.add_samples(samples);
+    }
+    Some(meter.finish())
+}
+
+/// Measures the tracks of an album as one, returning (album gain in dB, album peak).
+/// The analyses should all be from the same album.
+pub fn album_loudness(tracks: &[LoudnessAnalysis]) -> (f64, f64) {
+    let blocks: Vec<f64> = tracks
+        .iter()
+        .flat_map(|track| track.blocks.iter().copied())
+        .collect();
+    let peak = tracks
+        .iter()
+        .map(|track| track.true_peak)
+        .fold(0.0, f64::max);
+    (
+        REPLAYGAIN_REFERENCE_LUFS - integrated_loudness(&blocks),
+        peak,
+    )
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           REPLAYGAIN TAGS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// "-6.54 dB" -> -6.54
+fn parse_replaygain_value(value: &str) -> Option<f64> {
+    let value = value.trim().trim_end_matches('\0');
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+    value.trim().parse::<f64>().ok()
+}
+
+// the REPLAYGAIN_* tags of a file, keys upper cased
+fn read_replaygain_tags(filepath: &str) -> HashMap<String, String> {
+    let mut tags: HashMap<String, String> = HashMap::new();
+    let extension = std::path::Path::new(filepath)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+
+    match extension.as_str() {
+        "flac" => {
+            if let Ok(tag) = metaflac::Tag::read_from_path(filepath) {
+                if let Some(comments) = tag.vorbis_comments() {
+                    for (key, values) in comments.comments.iter() {
+                        if key.to_uppercase().starts_with("REPLAYGAIN_") && !values.is_empty() {
+                            tags.insert(key.to_uppercase(), values[0].clone());
+                        }
+                    }
+                }
+            }
+        }
+        "mp3" => {
+            // ReplayGain in ID3 lives in TXXX frames named after the vorbis comments
+            if let Ok(tag) = id3::Tag::read_from_path(filepath) {
+                for text in tag.extended_texts() {
+                    if text.description.to_uppercase().starts_with("REPLAYGAIN_") {
+                        tags.insert(text.description.to_uppercase(), text.value.clone());
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    tags
+}
+
+/// Reads the ReplayGain tags of a file into a loudness row (song_id left empty). None if there's no track gain.
+/// The integrated loudness is worked out from the gain assuming the tags are ReplayGain 2.0 (-18 LUFS reference),
+/// the loudness range and true peak aren't in the tags so they're -1.
+pub fn loudness_from_tags(filepath: &str) -> Option<LOUDNESS_TABLE_DATA> {
+    let tags = read_replaygain_tags(filepath);
+    let value = |key: &str| {
+        tags.get(key)
+            .and_then(|value| parse_replaygain_value(value))
+    };
+
+    let track_gain = value("REPLAYGAIN_TRACK_GAIN")?;
+    let (album_gain, album_peak) = match value("REPLAYGAIN_ALBUM_GAIN") {
+        Some(album_gain) => (album_gain, value("REPLAYGAIN_ALBUM_PEAK").unwrap_or(1.0)),
+        None => (-1.0, -1.0),
+    };
+    Some(LOUDNESS_TABLE_DATA {
+        song_id: "".to_string(),
+        integrated_lufs: REPLAYGAIN_REFERENCE_LUFS - track_gain,
+        loudness_range: -1.0,
+        true_peak: -1.0,
+        track_gain,
+        track_peak: value("REPLAYGAIN_TRACK_PEAK").unwrap_or(1.0),
+        album_gain,
+        album_peak,
+        source: "tags".to_string(),
+        analyzed_dt: now_dt(),
+    })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           LIBRARY
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Saves a loudness result, replacing the last one for the same song
+pub fn save_loudness(loudness: &LOUDNESS_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO loudness (song_id, integrated_lufs, loudness_range, true_peak, track_gain, \
+         track_peak, album_gain, album_peak, source, analyzed_dt) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            loudness.song_id,
+            loudness.integrated_lufs,
+            loudness.loudness_range,
+            loudness.true_peak,
+            loudness.track_gain,
+            loudness.track_peak,
+            loudness.album_gain,
+            loudness.album_peak,
+            loudness.source,
+            loudness.analyzed_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Get the loudness of a song, None if it hasn't been measured
+pub fn get_loudness(song_id: &str) -> Option<LOUDNESS_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut stmt = conn
+        .prepare("SELECT * FROM loudness WHERE song_id = ?1")
+        .expect("Could not prepare statement");
+    let mut rows = stmt.query_map([song_id], loudness_from_row).unwrap();
+    rows.next().map(|row| row.unwrap())
+}
+
+/// Measures a set of songs that make up an album and saves them, album gain included.
+/// With `prefer_tags`, songs that have ReplayGain tags use those instead of being decoded, but only if every song
+/// in the album has them (otherwise the album gain would be from a mix of both). Returns the saved rows.
+pub fn analyze_album(song_ids: &[String], prefer_tags: bool) -> Vec<LOUDNESS_TABLE_DATA> {
+    let paths: Vec<(String, String)> = song_ids
+        .iter()
+        .filter_map(|song_id| {
+            get_songpaths_by_song_id(song_id.clone())
+                .into_iter()
+                .next()
+                .map(|songpath| (song_id.clone(), songpath.song_path))
+        })
+        .collect();
+
+    if prefer_tags {
+        let tagged: Vec<Option<LOUDNESS_TABLE_DATA>> = paths
+            .iter()
+            .map(|(_, path)| loudness_from_tags(path))
+            .collect();
+        if !tagged.is_empty() && tagged.iter().all(|loudness| loudness.is_some()) {
+            let mut saved: Vec<LOUDNESS_TABLE_DATA> = Vec::new();
+            for ((song_id, _), loudness) in paths.iter().zip(tagged) {
+                let mut loudness = loudness.unwrap();
+                loudness.song_id = song_id.clone();
+                save_loudness(&loudness);
+                saved.push(loudness);
+            }
+            return saved;
+        }
+    }
+
+    let analyzed: Vec<(String, LoudnessAnalysis)> = paths
+        .iter()
+        .filter_map(|(song_id, path)| {
+            analyze_file(path).map(|analysis| (song_id.clone(), analysis))
+        })
+        .collect();
+    let (album_gain, album_peak) = album_loudness(
+        &analyzed
+            .iter()
+            .map(|(_, analysis)| analysis.clone())
+            .collect::<Vec<LoudnessAnalysis>>(),
+    );
+
+    let mut saved: Vec<LOUDNESS_TABLE_DATA> = Vec::new();
+    for (song_id, analysis) in analyzed {
+        let loudness = LOUDNESS_TABLE_DATA {
+            song_id,
+            integrated_lufs: analysis.integrated_lufs,
+            loudness_range: analysis.loudness_range,
+            true_peak: analysis.true_peak,
+            track_gain: analysis.track_gain(),
+            track_peak: analysis.true_peak,
+            album_gain,
+            album_peak,
+            source: "analysis".to_string(),
+            analyzed_dt: now_dt(),
+        };
+        save_loudness(&loudness);
+        saved.push(loudness);
+    }
+    saved
+}
+
+/// Measures every song in the library that hasn't been measured yet (or all of them with `remeasure`), an album at a
+/// time. Albums are songs with the same album and album artist (the main artist if there isn't one), songs without
+/// an album are measured on their own. A new song changes its album's gain, so an album with any unmeasured song is
+/// measured again as a whole. See analyze_album for `prefer_tags`. Returns how many songs were saved.
+pub fn analyze_library(prefer_tags: bool, remeasure: bool) -> usize {
+    let rows: Vec<(String, String, String, bool)> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT songs.song_id, songs.album, COALESCE( \
+                 (SELECT MIN(artist_name) FROM album_artists WHERE album_artists.song_id = songs.song_id), \
+                 songs.main_artist), songs.song_id IN (SELECT song_id FROM loudness) FROM songs \
+                 ORDER BY songs.song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    // album + album artist -> (song_ids, whether any of them is unmeasured), keeping the order albums were first seen in
+    let mut albums: Vec<(Vec<String>, bool)> = Vec::new();
+    let mut album_index: HashMap<String, usize> = HashMap::new();
+    for (song_id, album, album_artist, measured) in rows {
+        // the scanners store "-1" when there's no album tag
+        let key = if album.is_empty() || album == "-1" {
+            format!("song\u{1f}{}", song_id)
+        } else {
+            format!("album\u{1f}{}\u{1f}{}", album, album_artist)
+        };
+        match album_index.get(&key) {
+            Some(i) => {
+                albums[*i].0.push(song_id);
+                albums[*i].1 |= !measured;
+            }
+            None => {
+                album_index.insert(key, albums.len());
+                albums.push((vec![song_id], !measured));
+            }
+        }
+    }
+    albums.retain(|(_, unmeasured)| remeasure || *unmeasured);
+
+    let bar = ProgressBar::new(albums.len() as u64);
+    let mut saved = 0;
+    for (song_ids, _) in albums {
+        saved += analyze_album(&song_ids, prefer_tags).len();
+        bar.inc(1);
+    }
+    bar.finish();
+    saved
+}
+
+/// The gain in dB a player should apply to a song. Uses the album gain when `album_mode` is on and there is one,
+/// the track gain otherwise, with `preamp` added on top. The gain is turned down if it would push the peak past
+/// full scale. 0 if the song hasn't been measured.
+pub fn get_playback_gain(song_id: &str, album_mode: bool, preamp: f64) -> f64 {
+    let loudness = match get_loudness(song_id) {
+        Some(loudness) => loudness,
+        None => return 0.0,
+    };
+    let (gain, peak) = if album_mode && loudness.album_peak >= 0.0 {
+        (loudness.album_gain, loudness.album_peak)
+    } else {
+        (loudness.track_gain, loudness.track_peak)
+    };
+    let gain = gain + preamp;
+    if peak > 0.0 {
+        gain.min(-20.0 * peak.log10())
+    } else {
+        gain
+    }
+}
@@ -12,3 +12,22 @@ pub mod audio_metadata;
 pub mod analyticsdb;
 pub mod models; 
 pub mod api_metadata;
+pub mod smart_playlists;
+pub mod playlist_formats;
+pub mod playlists;
+pub mod listening_stats;
+pub mod play_sessions;
+pub mod scrobbles;
+pub mod library_dump;
+pub mod duplicates;
+pub mod file_health;
+pub mod loudness;
+pub mod waveform;
+pub mod music_analysis;
+pub mod gapless;
+pub mod artwork;
+pub mod artwork_store;
+pub mod tag_editor;
+pub mod batch_edit;
+pub mod organiser;
+pub mod decoder;
\ No newline at end of file
@@ -2,6 +2,7 @@
 // This file will hold all the different tables as models
 // We'll have structs for each table and a compile method which turns the struct into an sql string
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 // This is how it's broken down:
 // 1. Make our accessory shit like Columns and Table structs which will be used to make the tables
@@ -124,6 +125,54 @@ pub fn compile_albums_table() -> String {
     compile_table(&ALBUMS)
 }
 
+pub fn compile_smart_playlists_table() -> String {
+    compile_table(&SMART_PLAYLISTS)
+}
+
+pub fn compile_song_versions_table() -> String {
+    compile_table(&SONG_VERSIONS)
+}
+
+pub fn compile_flac_verifications_table() -> String {
+    compile_table(&FLAC_VERIFICATIONS)
+}
+
+pub fn compile_file_health_table() -> String {
+    compile_table(&FILE_HEALTH)
+}
+
+pub fn compile_loudness_table() -> String {
+    compile_table(&LOUDNESS)
+}
+
+pub fn compile_music_analysis_table() -> String {
+    compile_table(&MUSIC_ANALYSIS)
+}
+
+pub fn compile_gapless_table() -> String {
+    compile_table(&GAPLESS)
+}
+
+pub fn compile_artwork_table() -> String {
+    compile_table(&ARTWORK)
+}
+
+pub fn compile_song_artwork_table() -> String {
+    compile_table(&SONG_ARTWORK)
+}
+
+pub fn compile_album_artwork_table() -> String {
+    compile_table(&ALBUM_ARTWORK)
+}
+
+pub fn compile_tag_edit_batches_table() -> String {
+    compile_table(&TAG_EDIT_BATCHES)
+}
+
+pub fn compile_tag_edit_journal_table() -> String {
+    compile_table(&TAG_EDIT_JOURNAL)
+}
+
 // ---------------------------------------------------------------------------------------------------------------------------------------------------------------------
 // ---------------------------------------------------------------------------------------------------------------------------------------------------------------------
 //                                                 INSERTION STRUCTS (HOW THE DATA SHOULD BE FORMATTED TO BE INSERTED INTO THE DATABASE)
@@ -134,8 +183,9 @@ pub trait default {
     fn default() -> Self;
 }
 
-// DERIVE DEBUG MEANS WE CAN PRINT IT
-#[derive(Debug)]
+// DERIVE DEBUG MEANS WE CAN PRINT IT, SERIALIZE/DESERIALIZE IS FOR DUMPING AND RESTORING THE LIBRARY (see library_dump)
+// the serde names have to match the column names
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SONG_TABLE_DATA {
     pub song_id: String,
     pub main_artist: String,          // yes
@@ -165,22 +215,32 @@ pub struct SONG_TABLE_DATA {
     pub track_total: i64,             // yes
     pub source: String,               // yes
     pub filetype: String,             // yes
+    pub audio_hash: String,
+    pub bitrate_mode: String,
+    pub total_samples: i64,
+    pub recording_id: String,
+    pub dt_added: String,
 
                                       // make new function
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PLAY_TABLE_DATA {
     pub play_id: String,
     pub song_id: String,
     pub song_title: String,
     pub main_artist: String,
+    #[serde(rename = "filesize")]
     pub filesize_bytes: i64,
     pub start_dt: String,
     pub end_dt: String,
+    pub position_seconds: f64,
+    pub completion: f64,
+    pub skipped: i64,
+    pub seek_count: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PLAYLIST_TABLE_DATA {
     pub playlist_id: String,
     pub playlist_name: String,
@@ -188,52 +248,53 @@ pub struct PLAYLIST_TABLE_DATA {
     pub created_dt: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PLAYLIST_SONGS_TABLE_DATA {
     pub playlist_id: String,
     pub song_id: String,
     pub added_dt: String,
+    pub position: i64, // -1 means "put it at the end"
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SONG_ARTISTS_TABLE_DATA {
     pub artist_name: String,
     pub song_id: String,
     pub dt_added: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ALBUM_ARTISTS_TABLE_DATA {
     pub artist_name: String,
     pub song_id: String,
     pub dt_added: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct COMPOSERS_TABLE_DATA {
     pub composer_name: String,
     pub song_id: String,
     pub dt_added: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GENRES_TABLE_DATA {
     pub genre_name: String,
     pub song_id: String,
     pub dt_added: String,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SONGPATHS_TABLE_DATA {
     pub song_id: String,
     pub song_path: String,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ARTISTS_TABLE_DATA {
     pub artist_name: String,
     pub artist_bio: String,
     pub artist_photo_location: String,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ALBUMS_TABLE_DATA {
     pub album_id: String,
     pub album_name: String,
@@ -243,6 +304,135 @@ pub struct ALBUMS_TABLE_DATA {
     pub album_release_date: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SMART_PLAYLISTS_TABLE_DATA {
+    pub playlist_id: String,
+    pub rules: String,
+    pub updated_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SONG_VERSIONS_TABLE_DATA {
+    pub song_id: String,
+    pub group_id: String,
+    pub quality_rank: i64,
+    pub preferred: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FLAC_VERIFICATIONS_TABLE_DATA {
+    pub song_id: String,
+    pub song_path: String,
+    pub status: String,
+    pub expected_md5: String,
+    pub md5_ok: i64,
+    pub decode_errors: i64,
+    pub expected_samples: i64,
+    pub decoded_samples: i64,
+    pub verified_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FILE_HEALTH_TABLE_DATA {
+    pub song_id: String,
+    pub song_path: String,
+    pub filetype: String,
+    pub status: String,
+    pub decode_errors: i64,
+    pub lost_sync: i64,
+    pub truncated_final_frame: i64,
+    pub declared_duration: f64,
+    pub decoded_duration: f64,
+    pub checked_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LOUDNESS_TABLE_DATA {
+    pub song_id: String,
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub true_peak: f64,
+    pub track_gain: f64,
+    pub track_peak: f64,
+    pub album_gain: f64,
+    pub album_peak: f64,
+    pub source: String,
+    pub analyzed_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MUSIC_ANALYSIS_TABLE_DATA {
+    pub song_id: String,
+    pub bpm: f64,
+    pub bpm_confidence: f64,
+    pub musical_key: String,
+    pub camelot: String,
+    pub key_confidence: f64,
+    pub tag_bpm: f64,
+    pub tag_key: String,
+    pub analyzed_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GAPLESS_TABLE_DATA {
+    pub song_id: String,
+    pub encoder_delay: i64,
+    pub encoder_padding: i64,
+    pub total_samples: i64,
+    pub sample_rate: i64,
+    pub leading_silence: f64,
+    pub trailing_silence: f64,
+    pub source: String,
+    pub analyzed_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ARTWORK_TABLE_DATA {
+    pub artwork_id: String,
+    pub mime: String,
+    pub width: i64,
+    pub height: i64,
+    pub filesize_bytes: i64,
+    pub palette: String,
+    pub location: String,
+    pub added_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SONG_ARTWORK_TABLE_DATA {
+    pub song_id: String,
+    pub artwork_id: String,
+    pub picture_type: String,
+    pub source: String,
+    pub position: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ALBUM_ARTWORK_TABLE_DATA {
+    pub album_id: String,
+    pub artwork_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TAG_EDIT_BATCHES_TABLE_DATA {
+    pub batch_id: String,
+    pub description: String,
+    pub song_count: i64,
+    pub status: String,
+    pub created_dt: String,
+    pub reverted_dt: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TAG_EDIT_JOURNAL_TABLE_DATA {
+    pub batch_id: String,
+    pub song_id: String,
+    pub song_path: String,
+    pub field: String,
+    pub old_values: String,
+    pub new_values: String,
+}
+
 impl default for SONG_TABLE_DATA {
     fn default() -> Self {
         SONG_TABLE_DATA {
@@ -274,6 +464,11 @@ impl default for SONG_TABLE_DATA {
             track_total: -1,
             source: "".to_string(),
             filetype: "".to_string(),
+            audio_hash: "".to_string(),
+            bitrate_mode: "".to_string(),
+            total_samples: -1,
+            recording_id: "".to_string(),
+            dt_added: "".to_string(),
         }
     }
 }
@@ -288,6 +483,10 @@ impl default for PLAY_TABLE_DATA {
             filesize_bytes: -1,
             start_dt: "".to_string(),
             end_dt: "".to_string(),
+            position_seconds: -1.0,
+            completion: -1.0,
+            skipped: -1,
+            seek_count: -1,
         }
     }
 }
@@ -309,6 +508,7 @@ impl default for PLAYLIST_SONGS_TABLE_DATA {
             playlist_id: "".to_string(),
             song_id: "".to_string(),
             added_dt: "".to_string(),
+            position: -1,
         }
     }
 }
@@ -385,6 +585,171 @@ impl default for ALBUMS_TABLE_DATA {
     }
 }
 
+impl default for SMART_PLAYLISTS_TABLE_DATA {
+    fn default() -> Self {
+        SMART_PLAYLISTS_TABLE_DATA {
+            playlist_id: "".to_string(),
+            rules: "".to_string(),
+            updated_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for SONG_VERSIONS_TABLE_DATA {
+    fn default() -> Self {
+        SONG_VERSIONS_TABLE_DATA {
+            song_id: "".to_string(),
+            group_id: "".to_string(),
+            quality_rank: -1,
+            preferred: -1,
+        }
+    }
+}
+
+impl default for FLAC_VERIFICATIONS_TABLE_DATA {
+    fn default() -> Self {
+        FLAC_VERIFICATIONS_TABLE_DATA {
+            song_id: "".to_string(),
+            song_path: "".to_string(),
+            status: "".to_string(),
+            expected_md5: "".to_string(),
+            md5_ok: -1,
+            decode_errors: -1,
+            expected_samples: -1,
+            decoded_samples: -1,
+            verified_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for FILE_HEALTH_TABLE_DATA {
+    fn default() -> Self {
+        FILE_HEALTH_TABLE_DATA {
+            song_id: "".to_string(),
+            song_path: "".to_string(),
+            filetype: "".to_string(),
+            status: "".to_string(),
+            decode_errors: -1,
+            lost_sync: -1,
+            truncated_final_frame: -1,
+            declared_duration: -1.0,
+            decoded_duration: -1.0,
+            checked_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for LOUDNESS_TABLE_DATA {
+    fn default() -> Self {
+        LOUDNESS_TABLE_DATA {
+            song_id: "".to_string(),
+            integrated_lufs: -1.0,
+            loudness_range: -1.0,
+            true_peak: -1.0,
+            track_gain: -1.0,
+            track_peak: -1.0,
+            album_gain: -1.0,
+            album_peak: -1.0,
+            source: "".to_string(),
+            analyzed_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for MUSIC_ANALYSIS_TABLE_DATA {
+    fn default() -> Self {
+        MUSIC_ANALYSIS_TABLE_DATA {
+            song_id: "".to_string(),
+            bpm: -1.0,
+            bpm_confidence: -1.0,
+            musical_key: "".to_string(),
+            camelot: "".to_string(),
+            key_confidence: -1.0,
+            tag_bpm: -1.0,
+            tag_key: "".to_string(),
+            analyzed_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for GAPLESS_TABLE_DATA {
+    fn default() -> Self {
+        GAPLESS_TABLE_DATA {
+            song_id: "".to_string(),
+            encoder_delay: -1,
+            encoder_padding: -1,
+            total_samples: -1,
+            sample_rate: -1,
+            leading_silence: -1.0,
+            trailing_silence: -1.0,
+            source: "".to_string(),
+            analyzed_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for ARTWORK_TABLE_DATA {
+    fn default() -> Self {
+        ARTWORK_TABLE_DATA {
+            artwork_id: "".to_string(),
+            mime: "".to_string(),
+            width: -1,
+            height: -1,
+            filesize_bytes: -1,
+            palette: "".to_string(),
+            location: "".to_string(),
+            added_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for SONG_ARTWORK_TABLE_DATA {
+    fn default() -> Self {
+        SONG_ARTWORK_TABLE_DATA {
+            song_id: "".to_string(),
+            artwork_id: "".to_string(),
+            picture_type: "".to_string(),
+            source: "".to_string(),
+            position: -1,
+        }
+    }
+}
+
+impl default for ALBUM_ARTWORK_TABLE_DATA {
+    fn default() -> Self {
+        ALBUM_ARTWORK_TABLE_DATA {
+            album_id: "".to_string(),
+            artwork_id: "".to_string(),
+        }
+    }
+}
+
+impl default for TAG_EDIT_BATCHES_TABLE_DATA {
+    fn default() -> Self {
+        TAG_EDIT_BATCHES_TABLE_DATA {
+            batch_id: "".to_string(),
+            description: "".to_string(),
+            song_count: -1,
+            status: "".to_string(),
+            created_dt: "".to_string(),
+            reverted_dt: "".to_string(),
+        }
+    }
+}
+
+impl default for TAG_EDIT_JOURNAL_TABLE_DATA {
+    fn default() -> Self {
+        TAG_EDIT_JOURNAL_TABLE_DATA {
+            batch_id: "".to_string(),
+            song_id: "".to_string(),
+            song_path: "".to_string(),
+            field: "".to_string(),
+            old_values: "".to_string(),
+            new_values: "".to_string(),
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------------------------------------------------------------------------------------------------
 // ---------------------------------------------------------------------------------------------------------------------------------------------------------------------
 //                                                                      BEGIN MODELS
@@ -397,7 +762,7 @@ impl default for ALBUMS_TABLE_DATA {
 // "filesize": -1, # in bytes
 // "padding": -1, # in bytes
 // "album_artwork_bit_depth": -1, # in bits
-// "album_artwork_colors": -1, # int
+// "album_artwork_colors": -1, # 0xRRGGBB
 // "album_artwork_height": -1, # in pixels
 // "album_artwork_width": -1, # in pixels
 // "bit_depth": -1, # in bits
@@ -471,7 +836,7 @@ pub static SONGS: Lazy<Table> = Lazy::new(|| Table {
             data_type: "INTEGER",
             primary_key: false,
             auto_increment: false,
-            notes: "The number of colors in the album artwork",
+            notes: "The dominant colour of the front cover as 0xRRGGBB, -1 if there is no cover",
             is_unique: false,
 
         },
@@ -673,6 +1038,46 @@ pub static SONGS: Lazy<Table> = Lazy::new(|| Table {
             is_unique: false,
 
         },
+        Column {
+            name: "audio_hash",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "SHA256 of just the encoded audio, tags left out (see audio_metadata::audio_stream_hash)",
+            is_unique: false,
+        },
+        Column {
+            name: "bitrate_mode",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "cbr, vbr or abr for MP3s (see file_health::mpeg_stream_info), empty when unknown",
+            is_unique: false,
+        },
+        Column {
+            name: "total_samples",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Samples per channel, the duration is worked out from this. -1 when unknown",
+            is_unique: false,
+        },
+        Column {
+            name: "recording_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "musicbrainz:<id> or isrc:<ISRC> of the recording, empty when untagged (see audio_metadata::recording_id)",
+            is_unique: false,
+        },
+        Column {
+            name: "dt_added",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the song was first added to the library, in analyticsdb::DT_FORMAT",
+            is_unique: false,
+        },
     ],
 });
 
@@ -684,6 +1089,10 @@ pub static SONGS: Lazy<Table> = Lazy::new(|| Table {
 // // start_dt TEXT NOT NULL,
 // // end_dt TEXT NOT NULL,
 // // song_id TEXT NOT NULL
+// // position_seconds REAL,
+// // completion REAL,
+// // skipped INTEGER,
+// // seek_count INTEGER
 
 pub static PLAYS: Lazy<Table> = Lazy::new(|| Table {
     name: "plays",
@@ -751,6 +1160,38 @@ pub static PLAYS: Lazy<Table> = Lazy::new(|| Table {
             is_unique: false,
 
         },
+        Column {
+            name: "position_seconds",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How far into the song playback got when the play ended, in seconds",
+            is_unique: false,
+        },
+        Column {
+            name: "completion",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "position_seconds as a fraction of the song's duration, from 0 to 1",
+            is_unique: false,
+        },
+        Column {
+            name: "skipped",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "1 if the play ended before the skip threshold, 0 if not, -1 if we don't know",
+            is_unique: false,
+        },
+        Column {
+            name: "seek_count",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How many times the listener seeked during the play",
+            is_unique: false,
+        },
     ],
 });
 
@@ -806,6 +1247,7 @@ pub static PLAYLISTS: Lazy<Table> = Lazy::new(|| Table {
 // // playlist_id INTEGER NOT NULL,
 // // song_id TEXT NOT NULL,
 // // added_dt TEXT NOT NULL
+// // position INTEGER NOT NULL (0 based, unique per playlist so the same song can be in there twice)
 
 pub static PLAYLIST_SONGS: Lazy<Table> = Lazy::new(|| Table {
     name: "playlist_songs",
@@ -835,6 +1277,14 @@ pub static PLAYLIST_SONGS: Lazy<Table> = Lazy::new(|| Table {
             notes: "The date and time the song was added to the playlist in YYYY-MM-DD HH:MM:SS",
             is_unique: false,
         },
+        Column {
+            name: "position",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The 0 based position of the song in the playlist",
+            is_unique: false,
+        },
     ],
 });
 
@@ -1096,6 +1546,832 @@ pub static ALBUMS: Lazy<Table> = Lazy::new(|| Table {
         },
     ],
 });
+// smart playlists are regular rows in the playlists table, this just holds the rules for them
+// playlist_id TEXT NOT NULL,
+// rules TEXT NOT NULL, (yaml)
+// updated_dt TEXT NOT NULL
+
+pub static SMART_PLAYLISTS: Lazy<Table> = Lazy::new(|| Table {
+    name: "smart_playlists",
+    columns: vec![
+        Column {
+            name: "playlist_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the playlist the rules belong to",
+            is_unique: true,
+        },
+        Column {
+            name: "rules",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The serialized (yaml) rule set of the smart playlist",
+            is_unique: false,
+        },
+        Column {
+            name: "updated_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The date and time the rules were last changed in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// songs that are the same recording in different files (a flac and an mp3 rip of the same track) share a group_id
+// songs that aren't duplicates of anything don't have a row
+// song_id TEXT NOT NULL,
+// group_id TEXT NOT NULL,
+// quality_rank INTEGER NOT NULL, (0 is the best)
+// preferred INTEGER NOT NULL (1 or 0)
+
+pub static SONG_VERSIONS: Lazy<Table> = Lazy::new(|| Table {
+    name: "song_versions",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song",
+            is_unique: true,
+        },
+        Column {
+            name: "group_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the duplicate group the song is in",
+            is_unique: false,
+        },
+        Column {
+            name: "quality_rank",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Where the song ranks in its group by quality, 0 is the best",
+            is_unique: false,
+        },
+        Column {
+            name: "preferred",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "1 if this is the version of the group to play and list, 0 otherwise",
+            is_unique: false,
+        },
+    ],
+});
+
+// the result of the last time a flac file was decoded and checked against its STREAMINFO MD5 (see file_health)
+// song_id TEXT NOT NULL,
+// song_path TEXT NOT NULL,
+// status TEXT NOT NULL, (ok, md5_mismatch, truncated, corrupt_frames, no_md5, unreadable)
+// expected_md5 TEXT NOT NULL,
+// md5_ok INTEGER NOT NULL, (1, 0 or -1 if it couldn't be checked)
+// decode_errors INTEGER NOT NULL,
+// expected_samples INTEGER NOT NULL,
+// decoded_samples INTEGER NOT NULL,
+// verified_dt TEXT NOT NULL
+
+pub static FLAC_VERIFICATIONS: Lazy<Table> = Lazy::new(|| Table {
+    name: "flac_verifications",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song that was verified",
+            is_unique: true,
+        },
+        Column {
+            name: "song_path",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The file that was verified",
+            is_unique: false,
+        },
+        Column {
+            name: "status",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ok, md5_mismatch, truncated, corrupt_frames, no_md5 or unreadable",
+            is_unique: false,
+        },
+        Column {
+            name: "expected_md5",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The MD5 of the decoded audio from STREAMINFO, in hex",
+            is_unique: false,
+        },
+        Column {
+            name: "md5_ok",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "1 if the decoded audio matched the MD5, 0 if not, -1 if it couldn't be checked",
+            is_unique: false,
+        },
+        Column {
+            name: "decode_errors",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How many frames failed to decode (bad CRCs, lost sync)",
+            is_unique: false,
+        },
+        Column {
+            name: "expected_samples",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The number of samples per channel STREAMINFO says there are",
+            is_unique: false,
+        },
+        Column {
+            name: "decoded_samples",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The number of samples per channel that actually decoded",
+            is_unique: false,
+        },
+        Column {
+            name: "verified_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the file was verified in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// the result of the last deep check of a file, every frame decoded to find damage (see file_health)
+// song_id TEXT NOT NULL,
+// song_path TEXT NOT NULL,
+// filetype TEXT NOT NULL,
+// status TEXT NOT NULL, (ok, duration_mismatch, truncated, lost_sync, corrupt_frames, unreadable)
+// decode_errors INTEGER NOT NULL,
+// lost_sync INTEGER NOT NULL,
+// truncated_final_frame INTEGER NOT NULL,
+// declared_duration REAL NOT NULL,
+// decoded_duration REAL NOT NULL,
+// checked_dt TEXT NOT NULL
+
+pub static FILE_HEALTH: Lazy<Table> = Lazy::new(|| Table {
+    name: "file_health",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song that was checked",
+            is_unique: true,
+        },
+        Column {
+            name: "song_path",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The file that was checked",
+            is_unique: false,
+        },
+        Column {
+            name: "filetype",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The filetype of the file, like mp3",
+            is_unique: false,
+        },
+        Column {
+            name: "status",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ok, duration_mismatch, truncated, lost_sync, corrupt_frames or unreadable",
+            is_unique: false,
+        },
+        Column {
+            name: "decode_errors",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How many packets failed to decode",
+            is_unique: false,
+        },
+        Column {
+            name: "lost_sync",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How many times the next frame wasn't where the last one said it would be (MP3 only)",
+            is_unique: false,
+        },
+        Column {
+            name: "truncated_final_frame",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "1 if the file ends partway through its last frame, 0 if not",
+            is_unique: false,
+        },
+        Column {
+            name: "declared_duration",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The duration in seconds the headers claim, -1 if they don't say",
+            is_unique: false,
+        },
+        Column {
+            name: "decoded_duration",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The duration in seconds that actually decoded",
+            is_unique: false,
+        },
+        Column {
+            name: "checked_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the file was checked in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// the loudness of a song for volume normalization, measured with EBU R128 or read from ReplayGain tags (see loudness)
+// song_id TEXT NOT NULL,
+// integrated_lufs REAL NOT NULL,
+// loudness_range REAL NOT NULL,
+// true_peak REAL NOT NULL,
+// track_gain REAL NOT NULL,
+// track_peak REAL NOT NULL,
+// album_gain REAL NOT NULL,
+// album_peak REAL NOT NULL,
+// source TEXT NOT NULL, (analysis or tags)
+// analyzed_dt TEXT NOT NULL
+
+pub static LOUDNESS: Lazy<Table> = Lazy::new(|| Table {
+    name: "loudness",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song",
+            is_unique: true,
+        },
+        Column {
+            name: "integrated_lufs",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Integrated loudness in LUFS",
+            is_unique: false,
+        },
+        Column {
+            name: "loudness_range",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Loudness range in LU, -1 if it wasn't measured",
+            is_unique: false,
+        },
+        Column {
+            name: "true_peak",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The true peak as a linear amplitude (1.0 is full scale), -1 if it wasn't measured",
+            is_unique: false,
+        },
+        Column {
+            name: "track_gain",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ReplayGain 2.0 track gain in dB",
+            is_unique: false,
+        },
+        Column {
+            name: "track_peak",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ReplayGain track peak as a linear amplitude",
+            is_unique: false,
+        },
+        Column {
+            name: "album_gain",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ReplayGain 2.0 album gain in dB, only meaningful when album_peak isn't -1",
+            is_unique: false,
+        },
+        Column {
+            name: "album_peak",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "ReplayGain album peak as a linear amplitude, -1 if there's no album gain",
+            is_unique: false,
+        },
+        Column {
+            name: "source",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "analysis if it was measured, tags if it came from ReplayGain tags in the file",
+            is_unique: false,
+        },
+        Column {
+            name: "analyzed_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the song was measured in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// the tempo and key of a song, detected from the audio, next to what the tags say (see music_analysis)
+// song_id TEXT NOT NULL,
+// bpm REAL NOT NULL,
+// bpm_confidence REAL NOT NULL,
+// musical_key TEXT NOT NULL, (like Am or F#)
+// camelot TEXT NOT NULL, (like 8A)
+// key_confidence REAL NOT NULL,
+// tag_bpm REAL NOT NULL,
+// tag_key TEXT NOT NULL,
+// analyzed_dt TEXT NOT NULL
+
+pub static MUSIC_ANALYSIS: Lazy<Table> = Lazy::new(|| Table {
+    name: "music_analysis",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song",
+            is_unique: true,
+        },
+        Column {
+            name: "bpm",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Detected tempo in beats per minute, -1 if it couldn't be detected",
+            is_unique: false,
+        },
+        Column {
+            name: "bpm_confidence",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How sure the tempo detection is, from 0 to 1",
+            is_unique: false,
+        },
+        Column {
+            name: "musical_key",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Detected key in standard notation, like Am or F#, empty if it couldn't be detected",
+            is_unique: false,
+        },
+        Column {
+            name: "camelot",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Detected key in Camelot notation, like 8A",
+            is_unique: false,
+        },
+        Column {
+            name: "key_confidence",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How sure the key detection is, from 0 to 1",
+            is_unique: false,
+        },
+        Column {
+            name: "tag_bpm",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The tempo from the TBPM/BPM tag, -1 if there isn't one",
+            is_unique: false,
+        },
+        Column {
+            name: "tag_key",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The key from the TKEY/INITIALKEY tag in standard notation (as written if it couldn't be read), empty if there isn't one",
+            is_unique: false,
+        },
+        Column {
+            name: "analyzed_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the song was analyzed in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// what a player needs for gapless playback of a song, and how much silence it starts and ends with (see gapless)
+// song_id TEXT NOT NULL,
+// encoder_delay INTEGER NOT NULL, (samples)
+// encoder_padding INTEGER NOT NULL, (samples)
+// total_samples INTEGER NOT NULL,
+// sample_rate INTEGER NOT NULL,
+// leading_silence REAL NOT NULL, (seconds)
+// trailing_silence REAL NOT NULL, (seconds)
+// source TEXT NOT NULL, (lame, itunsmpb, flac or none)
+// analyzed_dt TEXT NOT NULL
+
+pub static GAPLESS: Lazy<Table> = Lazy::new(|| Table {
+    name: "gapless",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the song",
+            is_unique: true,
+        },
+        Column {
+            name: "encoder_delay",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Samples the encoder added at the start, as written in the file",
+            is_unique: false,
+        },
+        Column {
+            name: "encoder_padding",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Samples the encoder added at the end, as written in the file",
+            is_unique: false,
+        },
+        Column {
+            name: "total_samples",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Samples per channel of the actual song, delay and padding left out. -1 if unknown",
+            is_unique: false,
+        },
+        Column {
+            name: "sample_rate",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Sample rate of the audio, -1 if unknown",
+            is_unique: false,
+        },
+        Column {
+            name: "leading_silence",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Seconds of silence at the start of the song, -1 if it couldn't be decoded",
+            is_unique: false,
+        },
+        Column {
+            name: "trailing_silence",
+            data_type: "REAL",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Seconds of silence at the end of the song, -1 if it couldn't be decoded",
+            is_unique: false,
+        },
+        Column {
+            name: "source",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Where the delay and padding came from: lame, itunsmpb, flac or none",
+            is_unique: false,
+        },
+        Column {
+            name: "analyzed_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the song was analyzed in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// every image in the artwork store (see artwork_store), saved once no matter how many songs have it
+// artwork_id TEXT NOT NULL, (SHA256 of the image bytes)
+// mime TEXT NOT NULL,
+// width INTEGER NOT NULL, (pixels)
+// height INTEGER NOT NULL, (pixels)
+// filesize_bytes INTEGER NOT NULL,
+// palette TEXT NOT NULL, (#RRGGBB,#RRGGBB,... most dominant first)
+// location TEXT NOT NULL,
+// added_dt TEXT NOT NULL
+
+pub static ARTWORK: Lazy<Table> = Lazy::new(|| Table {
+    name: "artwork",
+    columns: vec![
+        Column {
+            name: "artwork_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "SHA256 of the image bytes",
+            is_unique: true,
+        },
+        Column {
+            name: "mime",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "MIME type of the image, from its magic bytes",
+            is_unique: false,
+        },
+        Column {
+            name: "width",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Width of the image in pixels",
+            is_unique: false,
+        },
+        Column {
+            name: "height",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Height of the image in pixels",
+            is_unique: false,
+        },
+        Column {
+            name: "filesize_bytes",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Size of the image in bytes",
+            is_unique: false,
+        },
+        Column {
+            name: "palette",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The dominant colours of the image as #RRGGBB, most dominant first, separated by commas",
+            is_unique: false,
+        },
+        Column {
+            name: "location",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Where the full size image is in the artwork store",
+            is_unique: false,
+        },
+        Column {
+            name: "added_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the image was added to the store in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+    ],
+});
+
+// which images of the artwork store a song has, embedded or in its folder
+// song_id TEXT NOT NULL,
+// artwork_id TEXT NOT NULL,
+// picture_type TEXT NOT NULL, (front_cover, back_cover, artist...)
+// source TEXT NOT NULL, (embedded or folder)
+// position INTEGER NOT NULL (order the pictures were found in, 0 first)
+
+pub static SONG_ARTWORK: Lazy<Table> = Lazy::new(|| Table {
+    name: "song_artwork",
+    columns: vec![
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the song",
+            is_unique: true,
+        },
+        Column {
+            name: "artwork_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the image in the artwork table",
+            is_unique: false,
+        },
+        Column {
+            name: "picture_type",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "What the picture shows: front_cover, back_cover, artist...",
+            is_unique: false,
+        },
+        Column {
+            name: "source",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Where the picture was found: embedded or folder",
+            is_unique: false,
+        },
+        Column {
+            name: "position",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "Order the pictures of the song were found in, 0 first",
+            is_unique: true,
+        },
+    ],
+});
+
+// the cover of an album, an image of the artwork store. albums.album_art_location points at the same image
+// album_id TEXT NOT NULL,
+// artwork_id TEXT NOT NULL
+
+pub static ALBUM_ARTWORK: Lazy<Table> = Lazy::new(|| Table {
+    name: "album_artwork",
+    columns: vec![
+        Column {
+            name: "album_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the album",
+            is_unique: true,
+        },
+        Column {
+            name: "artwork_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the cover in the artwork table",
+            is_unique: false,
+        },
+    ],
+});
+
+// every batch tag edit that was applied (see batch_edit), so it can be reverted
+// batch_id TEXT NOT NULL,
+// description TEXT NOT NULL,
+// song_count INTEGER NOT NULL, (songs whose files were changed)
+// status TEXT NOT NULL, (applied or reverted)
+// created_dt TEXT NOT NULL,
+// reverted_dt TEXT NOT NULL (empty until reverted)
+
+pub static TAG_EDIT_BATCHES: Lazy<Table> = Lazy::new(|| Table {
+    name: "tag_edit_batches",
+    columns: vec![
+        Column {
+            name: "batch_id",
+            data_type: "TEXT",
+            primary_key: true,
+            auto_increment: false,
+            notes: "The ID of the batch",
+            is_unique: true,
+        },
+        Column {
+            name: "description",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "What the batch did, given by whoever applied it",
+            is_unique: false,
+        },
+        Column {
+            name: "song_count",
+            data_type: "INTEGER",
+            primary_key: false,
+            auto_increment: false,
+            notes: "How many songs had their files changed",
+            is_unique: false,
+        },
+        Column {
+            name: "status",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "applied or reverted",
+            is_unique: false,
+        },
+        Column {
+            name: "created_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the batch was applied in YYYY-MM-DD HH:MM:SS",
+            is_unique: false,
+        },
+        Column {
+            name: "reverted_dt",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "When the batch was reverted in YYYY-MM-DD HH:MM:SS, empty if it wasn't",
+            is_unique: false,
+        },
+    ],
+});
+
+// the value every field of every song had before and after a batch tag edit, to revert it
+// batch_id TEXT NOT NULL,
+// song_id TEXT NOT NULL, (before the edit)
+// song_path TEXT NOT NULL,
+// field TEXT NOT NULL, (title, artist, track_number...)
+// old_values TEXT NOT NULL, (JSON list of strings, empty if the field wasn't set)
+// new_values TEXT NOT NULL (JSON list of strings, empty if the field was removed)
+
+pub static TAG_EDIT_JOURNAL: Lazy<Table> = Lazy::new(|| Table {
+    name: "tag_edit_journal",
+    columns: vec![
+        Column {
+            name: "batch_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the batch in the tag_edit_batches table",
+            is_unique: true,
+        },
+        Column {
+            name: "song_id",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The ID of the song when the batch was applied",
+            is_unique: true,
+        },
+        Column {
+            name: "song_path",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The file that was changed",
+            is_unique: false,
+        },
+        Column {
+            name: "field",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "The field that was changed: title, artist, track_number...",
+            is_unique: true,
+        },
+        Column {
+            name: "old_values",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "JSON list of the values before the edit, empty if the field wasn't set",
+            is_unique: false,
+        },
+        Column {
+            name: "new_values",
+            data_type: "TEXT",
+            primary_key: false,
+            auto_increment: false,
+            notes: "JSON list of the values after the edit, empty if the field was removed",
+            is_unique: false,
+        },
+    ],
+});
+
 // make fn generate_insertion_sql that takes a table and returns the SQL for inserting into that table
 // for example "INSERT INTO songs (song_id, main_artist, filesize_bytes, padding_bytes, album_artwork_bit_depth, album_artwork_colors, album_artwork_height, album_artwork_width, bit_depth, bitrate, channels, duration, sample_rate, album, barcode, date_created, disc_number, disc_total, isrc, itunesadvisory, length, publisher, rating, title, track_number, track_total, source) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)";
 // static SONGS: Lazy<Table> = Lazy::new(|| Table {
@@ -0,0 +1,540 @@
+// Tempo and key detection for DJing. Songs get decoded, mixed down to mono and brought down to around 11kHz, then:
+// * tempo: an onset envelope (how suddenly the sound gets louder, 100 times a second) is autocorrelated and the
+//   strongest beat period between 60 and 200 BPM wins, with a nudge towards 120 so half and double tempos lose
+// * key: Goertzel filters on every note from C2 to B6 build a chroma (how much of each pitch class there is),
+//   which is compared against the Krumhansl-Kessler profiles of all 24 keys
+//
+// Results go in the music_analysis table next to what the TBPM/BPM and TKEY/INITIALKEY tags say, so the two
+// can be compared. Smart playlists can filter on them with SmartRule::Bpm and SmartRule::HarmonicMatch.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::config::*;
+use crate::engine::decoder::AudioDecoder;
+use crate::engine::models::*;
+use indicatif::ProgressBar;
+use rusqlite::params;
+use rusqlite::Connection;
+use std::f64::consts::PI;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+
+/// Audio is brought down to about this sample rate before it's analyzed, nothing above 5kHz matters for tempo or key
+const ANALYSIS_SAMPLE_RATE: u32 = 11025;
+
+/// The slowest and fastest tempo that can be detected
+pub const MIN_BPM: f64 = 60.0;
+pub const MAX_BPM: f64 = 200.0;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           KEYS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+const MAJOR_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&str; 12] = [
+    "Cm", "C#m", "Dm", "Ebm", "Em", "Fm", "F#m", "Gm", "G#m", "Am", "Bbm", "Bm",
+];
+
+/// A musical key, the tonic as a pitch class (0 is C, 1 is C#/Db, up to 11 for B)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MusicalKey {
+    pub tonic: u8,
+    pub minor: bool,
+}
+
+impl MusicalKey {
+    /// Standard notation, like C, F# or Bbm
+    pub fn name(&self) -> String {
+        match self.minor {
+            true => MINOR_NAMES[self.tonic as usize].to_string(),
+            false => MAJOR_NAMES[self.tonic as usize].to_string(),
+        }
+    }
+
+    /// Camelot wheel notation, 1A to 12A for minor keys and 1B to 12B for major. C major is 8B, A minor 8A.
+    pub fn camelot(&self) -> String {
+        // minor keys sit on the same number as their relative major, 3 semitones up
+        let major_tonic = match self.minor {
+            true => (self.tonic + 3) % 12,
+            false => self.tonic,
+        } as usize;
+        // every step around the wheel is a fifth (7 semitones)
+        let number = (major_tonic * 7 + 7) % 12 + 1;
+        format!("{}{}", number, if self.minor { "A" } else { "B" })
+    }
+
+    /// The keys that mix well with this one on the Camelot wheel: itself, one step either way, and its
+    /// relative major or minor. In Camelot notation.
+    pub fn compatible_keys(&self) -> Vec<String> {
+        let camelot = self.camelot();
+        let number: i64 = camelot[..camelot.len() - 1].parse().unwrap();
+        let letter = &camelot[camelot.len() - 1..];
+        let other_letter = if letter == "A" { "B" } else { "A" };
+        vec![
+            camelot.clone(),
+            format!("{}{}", (number + 10) % 12 + 1, letter),
+            format!("{}{}", number % 12 + 1, letter),
+            format!("{}{}", number, other_letter),
+        ]
+    }
+}
+
+/// Reads a key written the way tags usually write them: standard notation (Am, A minor, C#, Dbmaj, F♯m) or Camelot (8A).
+/// None for anything else, including "o" which some taggers use for off key.
+/// ```
+/// # use decibl_metadata::engine::music_analysis::*;
+/// assert_eq!(parse_key("A minor"), parse_key("8A"));
+/// assert_eq!(parse_key("Ebm").unwrap().camelot(), "2A");
+/// assert_eq!(parse_key("F♯"), parse_key("F#"));
+/// assert_eq!(parse_key("E♭").unwrap().camelot(), "5B");
+/// ```
+pub fn parse_key(key: &str) -> Option<MusicalKey> {
+    let key: String = key.chars().filter(|c| !c.is_whitespace()).collect();
+    let key = key.trim_end_matches('\0');
+
+    // camelot, 1A to 12B. The last character can be more than one byte (F♯, E♭) so split on it, not on a byte
+    let (last_index, last) = key.char_indices().last()?;
+    let number = &key[..last_index];
+    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+        let number: usize = number.parse().ok()?;
+        let minor = match last {
+            'A' | 'a' => true,
+            'B' | 'b' => false,
+            _ => return None,
+        };
+        if !(1..=12).contains(&number) {
+            return None;
+        }
+        // back around the wheel from the number to the major tonic
+        let major_tonic = ((number + 4) * 7 % 12) as u8;
+        let tonic = if minor {
+            (major_tonic + 9) % 12
+        } else {
+            major_tonic
+        };
+        return Some(MusicalKey { tonic, minor });
+    }
+
+    let mut chars = key.chars();
+    let natural: u8 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let mut rest: String = chars.collect();
+    let mut tonic = natural;
+    if rest.starts_with('#') || rest.starts_with('♯') {
+        tonic = (tonic + 1) % 12;
+        rest = rest.chars().skip(1).collect();
+    } else if rest.starts_with('b') || rest.starts_with('♭') {
+        tonic = (tonic + 11) % 12;
+        rest = rest.chars().skip(1).collect();
+    }
+    let minor = match rest.to_lowercase().as_str() {
+        "" | "maj" | "major" => false,
+        "m" | "min" | "minor" => true,
+        _ => return None,
+    };
+    Some(MusicalKey { tonic, minor })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           DETECTION
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Decodes a file, mixes it down to mono and averages it down to about ANALYSIS_SAMPLE_RATE.
+/// Returns the samples and their sample rate, None if it can't be decoded.
+pub fn decode_for_analysis(filepath: &str) -> Option<(Vec<f32>, u32)> {
+    let mut decoder = AudioDecoder::open(
+        filepath,
+        FormatOptions::default(),
+        DecoderOptions::default(),
+    )?;
+    let sample_rate = decoder.sample_rate()?;
+    let channels = decoder.channels()?;
+
+    let factor = ((sample_rate / ANALYSIS_SAMPLE_RATE) as usize).max(1);
+    let mut mono: Vec<f32> = Vec::new();
+    let (mut sum, mut count) = (0.0f32, 0);
+    while let Some(samples) = decoder.next_samples() {
+        for frame in samples.chunks_exact(channels) {
+            sum += frame.iter().sum::<f32>() / channels as f32;
+            count += 1;
+            if count == factor {
+                mono.push(sum / factor as f32);
+                sum = 0.0;
+                count = 0;
+            }
+        }
+    }
+    Some((mono, sample_rate / factor as u32))
+}
+
+/// Detects the tempo of mono samples. Returns (bpm, confidence from 0 to 1), None if there's too little audio
+/// (a few seconds) or no beat at all.
+pub fn detect_bpm(samples: &[f32], sample_rate: u32) -> Option<(f64, f64)> {
+    // onset envelope at about 100 frames a second: the log energy of the first difference (so the highs count
+    // more), and only how much it went up since the last frame
+    let hop = (sample_rate as usize / 100).max(1);
+    let frame_rate = sample_rate as f64 / hop as f64;
+    let energies: Vec<f64> = samples
+        .chunks_exact(hop)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let previous = if i == 0 {
+                chunk[0]
+            } else {
+                samples[i * hop - 1]
+            };
+            let mut energy = (chunk[0] - previous) as f64 * (chunk[0] - previous) as f64;
+            for pair in chunk.windows(2) {
+                let difference = (pair[1] - pair[0]) as f64;
+                energy += difference * difference;
+            }
+            (1.0 + 1000.0 * energy).ln()
+        })
+        .collect();
+    let mut onsets: Vec<f64> = energies
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).max(0.0))
+        .collect();
+    let mean = onsets.iter().sum::<f64>() / onsets.len().max(1) as f64;
+    onsets.iter_mut().for_each(|onset| *onset -= mean);
+
+    let min_lag = (60.0 * frame_rate / MAX_BPM).floor() as usize;
+    let max_lag = (60.0 * frame_rate / MIN_BPM).ceil() as usize;
+    // the doubled lag gets looked at too, so there has to be room for it
+    if onsets.len() < max_lag * 4 {
+        return None;
+    }
+    let energy: f64 = onsets.iter().map(|onset| onset * onset).sum();
+    if energy <= 0.0 {
+        return None;
+    }
+    let autocorrelation = |lag: usize| -> f64 {
+        onsets
+            .iter()
+            .zip(onsets[lag..].iter())
+            .map(|(a, b)| a * b)
+            .sum::<f64>()
+            / energy
+    };
+    let correlations: Vec<f64> = (0..=max_lag * 2 + 1).map(autocorrelation).collect();
+
+    // a real beat also lines up at twice its period, and tempos far from 120 need more evidence
+    let score = |lag: usize| -> f64 {
+        let bpm = 60.0 * frame_rate / lag as f64;
+        let prior = (-0.5 * (bpm / 120.0).log2().powi(2)).exp();
+        (correlations[lag] + 0.5 * correlations[lag * 2]) * prior
+    };
+    let best =
+        (min_lag.max(2)..=max_lag).max_by(|a, b| score(*a).partial_cmp(&score(*b)).unwrap())?;
+    if correlations[best] <= 0.0 {
+        return None;
+    }
+
+    // the peak is somewhere between lags, fit a parabola through the neighbours to find it
+    let (before, peak, after) = (
+        correlations[best - 1],
+        correlations[best],
+        correlations[best + 1],
+    );
+    let curvature = before - 2.0 * peak + after;
+    let offset = if curvature < 0.0 {
+        (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let bpm = 60.0 * frame_rate / (best as f64 + offset);
+    Some((bpm, correlations[best].clamp(0.0, 1.0)))
+}
+
+// Krumhansl-Kessler key profiles, how well each pitch class fits a key starting at the tonic
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+// MIDI notes of the range the chroma is built from, C2 to B6
+const LOWEST_NOTE: u32 = 36;
+const HIGHEST_NOTE: u32 = 95;
+const CHROMA_FRAME: usize = 4096;
+
+fn correlation(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let mean_a = a.iter().sum::<f64>() / 12.0;
+    let mean_b = b.iter().sum::<f64>() / 12.0;
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for i in 0..12 {
+        covariance += (a[i] - mean_a) * (b[i] - mean_b);
+        variance_a += (a[i] - mean_a).powi(2);
+        variance_b += (b[i] - mean_b).powi(2);
+    }
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return 0.0;
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// How much of each pitch class (0 is C) there is in mono samples, every frame counts the same however loud it is
+pub fn chroma(samples: &[f32], sample_rate: u32) -> [f64; 12] {
+    let window: Vec<f64> = (0..CHROMA_FRAME)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / CHROMA_FRAME as f64).cos())
+        .collect();
+    // the goertzel coefficient of every note that fits under nyquist
+    let notes: Vec<(usize, f64)> = (LOWEST_NOTE..=HIGHEST_NOTE)
+        .map(|note| (note, 440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)))
+        .filter(|(_, frequency)| *frequency < sample_rate as f64 / 2.0)
+        .map(|(note, frequency)| {
+            (
+                (note % 12) as usize,
+                2.0 * (2.0 * PI * frequency / sample_rate as f64).cos(),
+            )
+        })
+        .collect();
+
+    let mut chroma = [0.0; 12];
+    let mut windowed = vec![0.0; CHROMA_FRAME];
+    for frame in samples.chunks_exact(CHROMA_FRAME) {
+        for (i, sample) in frame.iter().enumerate() {
+            windowed[i] = *sample as f64 * window[i];
+        }
+        let mut frame_chroma = [0.0; 12];
+        for (pitch_class, coefficient) in &notes {
+            let (mut s1, mut s2) = (0.0, 0.0);
+            for sample in &windowed {
+                let s0 = sample + coefficient * s1 - s2;
+                s2 = s1;
+                s1 = s0;
+            }
+            frame_chroma[*pitch_class] +=
+                (s1 * s1 + s2 * s2 - coefficient * s1 * s2).max(0.0).sqrt();
+        }
+        let total: f64 = frame_chroma.iter().sum();
+        if total > 0.0 {
+            for i in 0..12 {
+                chroma[i] += frame_chroma[i] / total;
+            }
+        }
+    }
+    chroma
+}
+
+/// Detects the key of mono samples. Returns the key and how well the chroma fits its profile (the correlation,
+/// from 0 to 1). None if there's nothing to go on, like silence.
+pub fn detect_key(samples: &[f32], sample_rate: u32) -> Option<(MusicalKey, f64)> {
+    let chroma = chroma(samples, sample_rate);
+    if chroma.iter().all(|value| *value == 0.0) {
+        return None;
+    }
+
+    let mut best: Option<(MusicalKey, f64)> = None;
+    for tonic in 0..12u8 {
+        for minor in [false, true] {
+            let profile = if minor { MINOR_PROFILE } else { MAJOR_PROFILE };
+            let mut rotated = [0.0; 12];
+            for i in 0..12 {
+                rotated[(i + tonic as usize) % 12] = profile[i];
+            }
+            let fit = correlation(&chroma, &rotated);
+            match best {
+                Some((_, best_fit)) if best_fit >= fit => {}
+                _ => best = Some((MusicalKey { tonic, minor }, fit)),
+            }
+        }
+    }
+    best.map(|(key, fit)| (key, fit.clamp(0.0, 1.0)))
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TAGS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Reads the tempo (TBPM in ID3, BPM in vorbis comments) and key (TKEY, INITIALKEY) tags of a file.
+/// Returns (bpm, key), the bpm is -1 and the key empty when they aren't there. A key that parse_key understands
+/// comes back in standard notation, anything else as written.
+pub fn read_tempo_key_tags(filepath: &str) -> (f64, String) {
+    let extension = std::path::Path::new(filepath)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+
+    let (bpm, key): (Option<String>, Option<String>) = match extension.as_str() {
+        "flac" => match metaflac::Tag::read_from_path(filepath) {
+            Ok(tag) => (
+                tag.get_vorbis("BPM")
+                    .and_then(|mut values| values.next())
+                    .map(|value| value.to_string()),
+                tag.get_vorbis("INITIALKEY")
+                    .and_then(|mut values| values.next())
+                    .map(|value| value.to_string()),
+            ),
+            Err(_) => (None, None),
+        },
+        "mp3" => match id3::Tag::read_from_path(filepath) {
+            Ok(tag) => {
+                use id3::TagLike;
+                let text = |id: &str| {
+                    tag.get(id)
+                        .and_then(|frame| frame.content().text())
+                        .map(|text| text.to_string())
+                };
+                (text("TBPM"), text("TKEY"))
+            }
+            Err(_) => (None, None),
+        },
+        _ => (None, None),
+    };
+
+    let bpm = bpm
+        .and_then(|bpm| bpm.trim().trim_end_matches('\0').parse::<f64>().ok())
+        .unwrap_or(-1.0);
+    let key = match key {
+        Some(key) => match parse_key(&key) {
+            Some(parsed) => parsed.name(),
+            None => key.trim().trim_end_matches('\0').to_string(),
+        },
+        None => "".to_string(),
+    };
+    (bpm, key)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           LIBRARY
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Detects the tempo and key of a file and reads its tags. Nothing is saved, the song_id is left empty.
+/// When the audio can't be decoded the detected values are left at -1 and empty, the tags are still read.
+/// ```no_run
+/// # use decibl_metadata::engine::music_analysis::*;
+/// let analysis = analyze_tempo_and_key("/music/song.flac");
+/// println!("{} BPM in {} ({}), tagged {} BPM", analysis.bpm, analysis.musical_key, analysis.camelot, analysis.tag_bpm);
+/// ```
+pub fn analyze_tempo_and_key(filepath: &str) -> MUSIC_ANALYSIS_TABLE_DATA {
+    let (tag_bpm, tag_key) = read_tempo_key_tags(filepath);
+    let mut analysis = MUSIC_ANALYSIS_TABLE_DATA {
+        song_id: "".to_string(),
+        bpm: -1.0,
+        bpm_confidence: 0.0,
+        musical_key: "".to_string(),
+        camelot: "".to_string(),
+        key_confidence: 0.0,
+        tag_bpm,
+        tag_key,
+        analyzed_dt: now_dt(),
+    };
+
+    if let Some((samples, sample_rate)) = decode_for_analysis(filepath) {
+        if let Some((bpm, confidence)) = detect_bpm(&samples, sample_rate) {
+            analysis.bpm = (bpm * 100.0).round() / 100.0;
+            analysis.bpm_confidence = confidence;
+        }
+        if let Some((key, confidence)) = detect_key(&samples, sample_rate) {
+            analysis.musical_key = key.name();
+            analysis.camelot = key.camelot();
+            analysis.key_confidence = confidence;
+        }
+    }
+    analysis
+}
+
+/// Saves a tempo and key result, replacing the last one for the same song
+pub fn save_music_analysis(analysis: &MUSIC_ANALYSIS_TABLE_DATA) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "INSERT OR REPLACE INTO music_analysis (song_id, bpm, bpm_confidence, musical_key, camelot, \
+         key_confidence, tag_bpm, tag_key, analyzed_dt) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            analysis.song_id,
+            analysis.bpm,
+            analysis.bpm_confidence,
+            analysis.musical_key,
+            analysis.camelot,
+            analysis.key_confidence,
+            analysis.tag_bpm,
+            analysis.tag_key,
+            analysis.analyzed_dt,
+        ],
+    )
+    .unwrap();
+}
+
+/// Analyzes a song's file and saves the result. None if the song has no path.
+pub fn analyze_song(song_id: &str) -> Option<MUSIC_ANALYSIS_TABLE_DATA> {
+    let songpath = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?;
+
+    let mut analysis = analyze_tempo_and_key(&songpath.song_path);
+    analysis.song_id = song_id.to_string();
+    save_music_analysis(&analysis);
+    Some(analysis)
+}
+
+/// Analyzes every song that hasn't been analyzed yet (or all of them with `reanalyze`). Returns how many were saved.
+pub fn analyze_library_tempo_and_key(reanalyze: bool) -> usize {
+    let song_ids: Vec<String> = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        let mut stmt = conn
+            .prepare(
+                "SELECT song_id FROM songs WHERE ?1 OR song_id NOT IN (SELECT song_id FROM music_analysis) \
+                 ORDER BY song_id",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([reanalyze], |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    };
+
+    let bar = ProgressBar::new(song_ids.len() as u64);
+    let mut saved = 0;
+    for song_id in song_ids {
+        if analyze_song(&song_id).is_some() {
+            saved += 1;
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+    saved
+}
+
+/// Get the tempo and key of a song, None if it hasn't been analyzed
+pub fn get_music_analysis(song_id: &str) -> Option<MUSIC_ANALYSIS_TABLE_DATA> {
+    get_all_music_analysis()
+        .into_iter()
+        .find(|analysis| analysis.song_id == song_id)
+}
+
+/// Get the songs whose tags disagree with what was detected: a tagged tempo more than `bpm_tolerance` away from the
+/// detected one, or a tagged key that isn't the detected key. Songs missing either side aren't compared.
+/// A tempo tagged at half or double the detected one still counts as a disagreement, that's worth a look too.
+pub fn get_tag_disagreements(bpm_tolerance: f64) -> Vec<MUSIC_ANALYSIS_TABLE_DATA> {
+    get_all_music_analysis()
+        .into_iter()
+        .filter(|analysis| {
+            let bpm_differs = analysis.bpm >= 0.0
+                && analysis.tag_bpm >= 0.0
+                && (analysis.bpm - analysis.tag_bpm).abs() > bpm_tolerance;
+            let key_differs = match (parse_key(&analysis.tag_key), parse_key(&analysis.camelot)) {
+                (Some(tagged), Some(detected)) => tagged != detected,
+                _ => false,
+            };
+            bpm_differs || key_differs
+        })
+        .collect()
+}
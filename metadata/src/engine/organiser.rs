@@ -0,0 +1,716 @@
+// Moving and renaming audio files into a layout built from their tags, like
+// {album_artist}/{year} - {album}/{disc:02}-{track:02} {title}.{ext}
+//
+// The fields come from the songs table and the artist tables (see TemplateField). Values are made safe for any
+// filesystem (see sanitize_component), and two files that end up with the same name get " (2)", " (3)"... added.
+// organise_songs works out where every file goes first, and with dry_run that plan is all it does. Otherwise every file
+// is moved (or copied) and then songpaths, along with the other tables that keep paths, is updated in one
+// transaction. Plays, playlists and everything else point at the song_id, not the path, so no history is lost.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_TEMPLATE: &str =
+    "{album_artist}/{year} - {album}/{disc:02}-{track:02} {title}.{ext}";
+
+/// Longest a file or folder name gets, in bytes. Most filesystems allow 255, this leaves room for " (2)".
+pub const MAX_COMPONENT_BYTES: usize = 200;
+
+/// The tables other than songpaths that have a song_path column, which follows the file when it moves
+const PATH_TABLES: [&str; 3] = ["flac_verifications", "file_health", "tag_edit_journal"];
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// The template from the config file, DEFAULT_TEMPLATE if it isn't set
+pub fn organise_template() -> String {
+    let template = get_config_var_or("organise_template", "");
+    match template.trim().is_empty() {
+        true => DEFAULT_TEMPLATE.to_string(),
+        false => template.trim().to_string(),
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           TEMPLATES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A field a template can use, as {field}. Numbers can be zero padded with {field:02}.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateField {
+    Title,
+    /// The main artist
+    Artist,
+    /// Every artist of the song, separated by ", "
+    Artists,
+    /// The first album artist, the main artist if the song has none
+    AlbumArtist,
+    Album,
+    /// The first 4 characters of date_created
+    Year,
+    Date,
+    Genre,
+    Composer,
+    Track,
+    TrackTotal,
+    Disc,
+    DiscTotal,
+    Isrc,
+    Publisher,
+    /// The extension of the file, lower case
+    Ext,
+    SongId,
+}
+
+impl TemplateField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateField::Title => "title",
+            TemplateField::Artist => "artist",
+            TemplateField::Artists => "artists",
+            TemplateField::AlbumArtist => "album_artist",
+            TemplateField::Album => "album",
+            TemplateField::Year => "year",
+            TemplateField::Date => "date",
+            TemplateField::Genre => "genre",
+            TemplateField::Composer => "composer",
+            TemplateField::Track => "track",
+            TemplateField::TrackTotal => "track_total",
+            TemplateField::Disc => "disc",
+            TemplateField::DiscTotal => "disc_total",
+            TemplateField::Isrc => "isrc",
+            TemplateField::Publisher => "publisher",
+            TemplateField::Ext => "ext",
+            TemplateField::SongId => "song_id",
+        }
+    }
+
+    pub fn from_field(field: &str) -> Option<Self> {
+        match field {
+            "title" => Some(TemplateField::Title),
+            "artist" => Some(TemplateField::Artist),
+            "artists" => Some(TemplateField::Artists),
+            "album_artist" => Some(TemplateField::AlbumArtist),
+            "album" => Some(TemplateField::Album),
+            "year" => Some(TemplateField::Year),
+            "date" => Some(TemplateField::Date),
+            "genre" => Some(TemplateField::Genre),
+            "composer" => Some(TemplateField::Composer),
+            "track" => Some(TemplateField::Track),
+            "track_total" => Some(TemplateField::TrackTotal),
+            "disc" => Some(TemplateField::Disc),
+            "disc_total" => Some(TemplateField::DiscTotal),
+            "isrc" => Some(TemplateField::Isrc),
+            "publisher" => Some(TemplateField::Publisher),
+            "ext" => Some(TemplateField::Ext),
+            "song_id" => Some(TemplateField::SongId),
+            _ => None,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            TemplateField::Track
+                | TemplateField::TrackTotal
+                | TemplateField::Disc
+                | TemplateField::DiscTotal
+        )
+    }
+}
+
+/// A piece of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplatePart {
+    Text(String),
+    /// The field and how many digits to pad it to, 0 for no padding
+    Field(TemplateField, usize),
+    /// A / in the template, between folders
+    Separator,
+}
+
+/// Parses a template. Fails on unknown fields, unclosed braces, and padding on fields that aren't numbers.
+/// ```
+/// # use decibl_metadata::engine::organiser::*;
+/// let parts = parse_template("{disc}-{track:02} {title}").unwrap();
+/// assert_eq!(parts[2], TemplatePart::Field(TemplateField::Track, 2));
+/// assert!(parse_template("{title:02}").is_err());
+/// ```
+pub fn parse_template(template: &str) -> Result<Vec<TemplatePart>> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' | '\\' => {
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(TemplatePart::Separator);
+            }
+            '{' => {
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                }
+                let mut inside = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    inside.push(c);
+                }
+                if !closed {
+                    return Err(invalid(format!("Unclosed {{ in template {}", template)));
+                }
+                let (name, padding) = match inside.split_once(':') {
+                    Some((name, padding)) => (name, Some(padding)),
+                    None => (inside.as_str(), None),
+                };
+                let field = TemplateField::from_field(name.trim())
+                    .ok_or_else(|| invalid(format!("Unknown template field {{{}}}", name)))?;
+                let width = match padding {
+                    Some(padding) => match (field.is_number(), padding.parse::<usize>()) {
+                        (true, Ok(width)) => width,
+                        _ => {
+                            return Err(invalid(format!(
+                                "Can't pad {{{}}} with {:?}",
+                                name, padding
+                            )))
+                        }
+                    },
+                    None => 0,
+                };
+                parts.push(TemplatePart::Field(field, width));
+            }
+            '}' => return Err(invalid(format!("Unopened }} in template {}", template))),
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+    Ok(parts)
+}
+
+/// Makes a file or folder name safe on Windows, macOS and Linux:
+/// * characters that aren't allowed somewhere (/ \ : * ? " < > | and control characters) become _
+/// * no leading dots (hidden files), no trailing dots or spaces (Windows drops them)
+/// * names Windows keeps for devices (CON, NUL, COM1...) get a _ on the end
+/// * at most MAX_COMPONENT_BYTES bytes
+/// ```
+/// # use decibl_metadata::engine::organiser::sanitize_component;
+/// assert_eq!(sanitize_component("AC/DC: Live?"), "AC_DC_ Live_");
+/// assert_eq!(sanitize_component("..hidden. "), "_hidden");
+/// assert_eq!(sanitize_component("con.flac"), "con_.flac");
+/// assert_eq!(sanitize_component(""), "_");
+/// ```
+pub fn sanitize_component(name: &str) -> String {
+    let safe = replace_forbidden(name);
+    let trimmed = safe.trim_end_matches(['.', ' ']).trim_start_matches(' ');
+    let mut safe = match trimmed.starts_with('.') {
+        true => format!("_{}", trimmed.trim_start_matches('.')),
+        false => trimmed.to_string(),
+    };
+
+    // cut the name down, keeping a short extension
+    if safe.len() > MAX_COMPONENT_BYTES {
+        let extension = match safe.rsplit_once('.') {
+            Some((_, extension)) if extension.len() <= 8 => format!(".{}", extension),
+            _ => "".to_string(),
+        };
+        let mut end = MAX_COMPONENT_BYTES - extension.len();
+        while !safe.is_char_boundary(end) {
+            end -= 1;
+        }
+        safe = format!("{}{}", safe[..end].trim_end_matches(['.', ' ']), extension);
+    }
+
+    let (stem, extension) = match safe.split_once('.') {
+        Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+        None => (safe.clone(), "".to_string()),
+    };
+    let reserved = ["CON", "PRN", "AUX", "NUL"].contains(&stem.to_uppercase().as_str())
+        || (stem.len() == 4
+            && stem.is_ascii()
+            && ["COM", "LPT"].contains(&stem[..3].to_uppercase().as_str())
+            && stem.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        safe = format!("{}_{}", stem, extension);
+    }
+
+    match safe.is_empty() {
+        true => "_".to_string(),
+        false => safe,
+    }
+}
+
+// replaces the characters that aren't allowed in names somewhere with _
+fn replace_forbidden(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// The artists, album artists, genres and composers of every song by song_id. Loaded once per plan so organising a
+/// library doesn't read those tables again for every song.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SongNames {
+    pub artists: HashMap<String, Vec<String>>,
+    pub album_artists: HashMap<String, Vec<String>>,
+    pub genres: HashMap<String, Vec<String>>,
+    pub composers: HashMap<String, Vec<String>>,
+}
+
+impl SongNames {
+    pub fn load() -> Self {
+        let mut names = SongNames::default();
+        for artist in get_all_song_artists() {
+            names
+                .artists
+                .entry(artist.song_id)
+                .or_default()
+                .push(artist.artist_name);
+        }
+        for artist in get_all_album_artists() {
+            names
+                .album_artists
+                .entry(artist.song_id)
+                .or_default()
+                .push(artist.artist_name);
+        }
+        for genre in get_all_genres() {
+            names
+                .genres
+                .entry(genre.song_id)
+                .or_default()
+                .push(genre.genre_name);
+        }
+        for composer in get_all_composers() {
+            names
+                .composers
+                .entry(composer.song_id)
+                .or_default()
+                .push(composer.composer_name);
+        }
+        names
+    }
+}
+
+/// What a song's fields are, as a template sees them
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateValues {
+    pub song: SONG_TABLE_DATA,
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+    pub genres: Vec<String>,
+    pub composers: Vec<String>,
+    pub song_path: String,
+}
+
+impl TemplateValues {
+    /// The values of a song from the database, with its names out of `names`
+    pub fn from_song(song: SONG_TABLE_DATA, song_path: &str, names: &SongNames) -> Self {
+        let of = |names: &HashMap<String, Vec<String>>| {
+            names.get(&song.song_id).cloned().unwrap_or_default()
+        };
+        TemplateValues {
+            artists: of(&names.artists),
+            album_artists: of(&names.album_artists),
+            genres: of(&names.genres),
+            composers: of(&names.composers),
+            song,
+            song_path: song_path.to_string(),
+        }
+    }
+
+    /// The value of a field. Text fields the song doesn't have (empty, or the "-1" the scanners store) are
+    /// "Unknown Artist", "Unknown Album"... and numbers it doesn't have are 0.
+    pub fn get(&self, field: TemplateField, width: usize) -> String {
+        let first = |values: &Vec<String>| values.iter().find(|value| !is_missing(value)).cloned();
+        let text = match field {
+            TemplateField::Title => Some(self.song.title.clone()),
+            TemplateField::Artist => Some(self.song.main_artist.clone()),
+            TemplateField::Artists => {
+                let artists: Vec<String> = self
+                    .artists
+                    .iter()
+                    .filter(|artist| !is_missing(artist))
+                    .cloned()
+                    .collect();
+                match artists.is_empty() {
+                    true => Some(self.song.main_artist.clone()),
+                    false => Some(artists.join(", ")),
+                }
+            }
+            TemplateField::AlbumArtist => {
+                first(&self.album_artists).or_else(|| Some(self.song.main_artist.clone()))
+            }
+            TemplateField::Album => Some(self.song.album.clone()),
+            TemplateField::Year => Some(self.song.date_created.trim().chars().take(4).collect()),
+            TemplateField::Date => Some(self.song.date_created.clone()),
+            TemplateField::Genre => first(&self.genres),
+            TemplateField::Composer => first(&self.composers),
+            TemplateField::Isrc => Some(self.song.isrc.clone()),
+            TemplateField::Publisher => Some(self.song.publisher.clone()),
+            TemplateField::Ext => Some(
+                Path::new(&self.song_path)
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_lowercase())
+                    .unwrap_or_default(),
+            ),
+            TemplateField::SongId => Some(self.song.song_id.clone()),
+            TemplateField::Track => return pad(self.song.track_number, width),
+            TemplateField::TrackTotal => return pad(self.song.track_total, width),
+            TemplateField::Disc => return pad(self.song.disc_number, width),
+            TemplateField::DiscTotal => return pad(self.song.disc_total, width),
+        };
+        match text.map(|text| text.trim().to_string()) {
+            Some(text) if !is_missing(&text) || field == TemplateField::Ext => text,
+            _ => format!("Unknown {}", unknown_name(field)),
+        }
+    }
+}
+
+// the scanners store "-1" for tags a file doesn't have
+fn is_missing(value: &str) -> bool {
+    let value = value.trim();
+    value.is_empty() || value == "-1"
+}
+
+fn pad(number: i64, width: usize) -> String {
+    format!("{:0width$}", number.max(0), width = width)
+}
+
+fn unknown_name(field: TemplateField) -> &'static str {
+    match field {
+        TemplateField::Artist | TemplateField::Artists | TemplateField::AlbumArtist => "Artist",
+        TemplateField::Year => "Year",
+        TemplateField::Date => "Date",
+        TemplateField::Genre => "Genre",
+        TemplateField::Composer => "Composer",
+        TemplateField::Album => "Album",
+        TemplateField::Title => "Title",
+        TemplateField::Isrc => "ISRC",
+        TemplateField::Publisher => "Publisher",
+        _ => "",
+    }
+}
+
+/// Fills in a parsed template for a song, giving the path relative to the library root. Every folder and the file
+/// name are sanitized on their own, and a / in a value never makes a folder.
+pub fn render_template(parts: &[TemplatePart], values: &TemplateValues) -> PathBuf {
+    let mut path = PathBuf::new();
+    let mut component = String::new();
+    for part in parts {
+        match part {
+            TemplatePart::Text(text) => component.push_str(text),
+            TemplatePart::Field(field, width) => {
+                // a / in a value is part of the name, not a folder
+                component.push_str(&replace_forbidden(&values.get(*field, *width)))
+            }
+            TemplatePart::Separator => {
+                if !component.trim().is_empty() {
+                    path.push(sanitize_component(&component));
+                }
+                component.clear();
+            }
+        }
+    }
+    path.push(sanitize_component(&component));
+    path
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           PLANNING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Whether the files are moved, or copied with the originals left where they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrganiseMode {
+    /// The file moves and songpaths points at its new place
+    Move,
+    /// The copy is added to songpaths as another path of the song, the original stays too
+    Copy,
+}
+
+impl OrganiseMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrganiseMode::Move => "move",
+            OrganiseMode::Copy => "copy",
+        }
+    }
+
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "move" => Some(OrganiseMode::Move),
+            "copy" => Some(OrganiseMode::Copy),
+            _ => None,
+        }
+    }
+}
+
+/// Where one file goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMove {
+    pub song_id: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// What organising does, or did
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OrganisePlan {
+    pub moves: Vec<PlannedMove>,
+    /// Songs already where the template puts them, once for every file
+    pub unchanged: Vec<String>,
+    /// Songs or files that can't be organised, as (song_id, why)
+    pub skipped: Vec<(String, String)>,
+}
+
+impl OrganisePlan {
+    /// A line per file, "from -> to"
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for planned in &self.moves {
+            text.push_str(&format!("{} -> {}\n", planned.from, planned.to));
+        }
+        for (song_id, reason) in &self.skipped {
+            text.push_str(&format!("skipped {}: {}\n", song_id, reason));
+        }
+        text
+    }
+}
+
+// `path` with " (2)", " (3)"... before the extension until it's not taken. `own` is where the file is now, which
+// doesn't count as taken.
+fn free_path(path: &Path, own: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let is_free =
+        |candidate: &Path| !taken.contains(candidate) && (candidate == own || !candidate.exists());
+    if is_free(path) {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut number = 2;
+    loop {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, number, extension));
+        if is_free(&candidate) {
+            return candidate;
+        }
+        number += 1;
+    }
+}
+
+/// Works out where the template puts every song under `root`, without touching anything. A file whose place is taken,
+/// by a file already there or another song of the plan, gets " (2)", " (3)"... added to its name.
+pub fn plan_organise(song_ids: &[String], root: &str, template: &str) -> Result<OrganisePlan> {
+    let parts = parse_template(template)?;
+    let mut plan = OrganisePlan::default();
+    let mut taken: HashSet<PathBuf> = HashSet::new();
+    let names = SongNames::load();
+
+    for song_id in song_ids {
+        // files with the same audio share a song, each of them goes where the template puts it
+        let song_paths: Vec<String> = get_songpaths_by_song_id(song_id.clone())
+            .into_iter()
+            .map(|songpath| songpath.song_path)
+            .collect();
+        if song_paths.is_empty() {
+            plan.skipped
+                .push((song_id.clone(), "the song has no file".to_string()));
+            continue;
+        }
+
+        let song = get_song_by_id(song_id.clone());
+        for song_path in song_paths {
+            if !Path::new(&song_path).is_file() {
+                plan.skipped
+                    .push((song_id.clone(), format!("{} doesn't exist", song_path)));
+                continue;
+            }
+
+            let values = TemplateValues::from_song(song.clone(), &song_path, &names);
+            let target = Path::new(root).join(render_template(&parts, &values));
+            let target = free_path(&target, Path::new(&song_path), &taken);
+            taken.insert(target.clone());
+            if target == Path::new(&song_path) {
+                plan.unchanged.push(song_id.clone());
+                continue;
+            }
+            plan.moves.push(PlannedMove {
+                song_id: song_id.clone(),
+                from: song_path,
+                to: target.to_string_lossy().to_string(),
+            });
+        }
+    }
+    Ok(plan)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           ORGANISING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// moves a file without ever replacing one that's already at `to`: a hard link and then the old name is removed, or
+// where that can't be done (another filesystem) a copy into a new file
+fn move_file(from: &str, to: &str) -> Result<()> {
+    match std::fs::hard_link(from, to) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(e),
+        Err(_) => copy_file(from, to)?,
+    }
+    if let Err(e) = std::fs::remove_file(from) {
+        let _ = std::fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// copies a file into a new one, failing if there's already a file at `to`
+fn copy_file(from: &str, to: &str) -> Result<()> {
+    let mut source = File::open(from)?;
+    let mut copy = OpenOptions::new().write(true).create_new(true).open(to)?;
+    let copied = std::io::copy(&mut source, &mut copy)
+        .and_then(|_| copy.set_permissions(source.metadata()?.permissions()))
+        .and_then(|_| copy.sync_all());
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(to);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn organise_file(planned: &PlannedMove, mode: OrganiseMode) -> Result<()> {
+    if let Some(parent) = Path::new(&planned.to).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match mode {
+        OrganiseMode::Move => move_file(&planned.from, &planned.to),
+        OrganiseMode::Copy => copy_file(&planned.from, &planned.to),
+    }
+}
+
+// puts a file back where it was, or deletes its copy
+fn undo_file(planned: &PlannedMove, mode: OrganiseMode) {
+    let _ = match mode {
+        OrganiseMode::Move => move_file(&planned.to, &planned.from),
+        OrganiseMode::Copy => std::fs::remove_file(&planned.to),
+    };
+}
+
+/// Moves or copies songs into the template's layout under `root`, see plan_organise. With dry_run nothing is touched
+/// and the plan is all that's returned.
+/// Every file is organised or none are: if one can't be moved, or the paths in the database can't be changed after,
+/// the ones already moved go back. A file never replaces one that's already there. Folders a move leaves empty are
+/// removed.
+/// ```no_run
+/// # use decibl_metadata::engine::organiser::*;
+/// let songs = vec!["song1".to_string()];
+/// let plan = organise_songs(&songs, "/music", DEFAULT_TEMPLATE, OrganiseMode::Move, true).unwrap();
+/// println!("{}", plan.to_text());
+/// organise_songs(&songs, "/music", DEFAULT_TEMPLATE, OrganiseMode::Move, false).unwrap();
+/// ```
+pub fn organise_songs(
+    song_ids: &[String],
+    root: &str,
+    template: &str,
+    mode: OrganiseMode,
+    dry_run: bool,
+) -> Result<OrganisePlan> {
+    let plan = plan_organise(song_ids, root, template)?;
+    if dry_run || plan.moves.is_empty() {
+        return Ok(plan);
+    }
+
+    for (done, planned) in plan.moves.iter().enumerate() {
+        if let Err(e) = organise_file(planned, mode) {
+            for planned in plan.moves[..done].iter().rev() {
+                undo_file(planned, mode);
+            }
+            return Err(Error::new(
+                e.kind(),
+                format!("Could not {} {}: {}", mode.as_str(), planned.from, e),
+            ));
+        }
+    }
+
+    // the files are in place, if the paths can't be saved they go back
+    let saved = (|| -> rusqlite::Result<()> {
+        let mut conn = Connection::open(get_database_file_path())?;
+        let tx = conn.transaction()?;
+        for planned in &plan.moves {
+            match mode {
+                OrganiseMode::Move => {
+                    tx.execute(
+                        "UPDATE songpaths SET song_path = ?1 WHERE song_id = ?2 AND song_path = ?3",
+                        params![planned.to, planned.song_id, planned.from],
+                    )?;
+                    for table in PATH_TABLES {
+                        tx.execute(
+                            &format!("UPDATE {} SET song_path = ?1 WHERE song_path = ?2", table),
+                            params![planned.to, planned.from],
+                        )?;
+                    }
+                }
+                OrganiseMode::Copy => {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO songpaths (song_id, song_path) VALUES (?1, ?2)",
+                        params![planned.song_id, planned.to],
+                    )?;
+                }
+            }
+        }
+        tx.commit()
+    })();
+    if let Err(e) = saved {
+        for planned in plan.moves.iter().rev() {
+            undo_file(planned, mode);
+        }
+        return Err(Error::other(format!("Could not save the new paths: {}", e)));
+    }
+
+    if mode == OrganiseMode::Move {
+        for planned in &plan.moves {
+            // remove_dir only removes empty folders
+            if let Some(parent) = Path::new(&planned.from).parent() {
+                let _ = std::fs::remove_dir(parent);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Organises every song of the library under `root` with the template from the config file
+pub fn organise_library(root: &str, mode: OrganiseMode, dry_run: bool) -> Result<OrganisePlan> {
+    let song_ids: Vec<String> = get_all_songs()
+        .into_iter()
+        .map(|song| song.song_id)
+        .collect();
+    organise_songs(&song_ids, root, &organise_template(), mode, dry_run)
+}
@@ -0,0 +1,133 @@
+// Recording plays as they happen. The player calls start_play when a song starts and end_play when it stops,
+// and everything else (ids, the song's title/artist/filesize, completion, skips) gets filled in here.
+//
+// A play that has started but not ended has an empty end_dt. If the player crashes those never get ended,
+// so close_dangling_plays shuts any that are obviously dead, and start_play runs it every time.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::string_to_hash;
+use crate::engine::config::*;
+use crate::engine::listening_stats::DEFAULT_SKIP_FRACTION;
+use crate::engine::models::*;
+use rusqlite::params;
+use rusqlite::Connection;
+
+/// How long past the end of the song an unfinished play is left alone before close_dangling_plays gives up on it.
+/// Long enough to cover a good pause.
+pub const DANGLING_PLAY_GRACE_SECONDS: i64 = 6 * 60 * 60;
+
+/// Start recording a play of a song and return the new play_id. Pass it to end_play when the song stops.
+/// None if the song isn't in the library, a play has to be of something.
+/// ```no_run
+/// # use decibl_metadata::engine::play_sessions::*;
+/// let play_id = start_play("song_id").unwrap();
+/// // ... 90 seconds later the listener hits next
+/// end_play(&play_id, 90.0);
+/// ```
+pub fn start_play(song_id: &str) -> Option<String> {
+    let song = {
+        let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+        conn.query_row(
+            "SELECT * FROM songs WHERE song_id = ?1",
+            [song_id],
+            song_from_row,
+        )
+        .ok()?
+    };
+    close_dangling_plays();
+
+    let now = chrono::Utc::now().naive_utc();
+    // the full timestamp has nanoseconds in it, so starting the same song twice in a second still gets two ids
+    let play_id = string_to_hash(format!("{}{}", song_id, now)).unwrap();
+
+    insert_play(PLAY_TABLE_DATA {
+        play_id: play_id.clone(),
+        song_id: song_id.to_string(),
+        song_title: song.title,
+        main_artist: song.main_artist,
+        filesize_bytes: song.filesize_bytes,
+        start_dt: format_dt(now),
+        end_dt: "".to_string(),
+        position_seconds: 0.0,
+        completion: 0.0,
+        skipped: -1,
+        seek_count: 0,
+    });
+
+    Some(play_id)
+}
+
+/// Note that the listener jumped to `position_seconds`. Returns false if the play doesn't exist or has already ended.
+pub fn record_seek(play_id: &str, position_seconds: f64) -> bool {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "UPDATE plays SET seek_count = seek_count + 1, position_seconds = ?1 WHERE play_id = ?2 AND end_dt = ''",
+        params![position_seconds, play_id],
+    )
+    .unwrap()
+        > 0
+}
+
+/// Finish a play that was started with start_play. `position_seconds` is where in the song playback stopped,
+/// which is what completion and skipped are worked out from (a play that seeked to the end isn't a skip).
+/// Returns the finished play, or None if it doesn't exist or was already ended.
+pub fn end_play(play_id: &str, position_seconds: f64) -> Option<PLAY_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+
+    let duration: f64 = conn
+        .query_row(
+            "SELECT COALESCE((SELECT duration FROM songs WHERE songs.song_id = plays.song_id), -1) FROM plays WHERE play_id = ?1",
+            [play_id],
+            |row| row.get(0),
+        )
+        .ok()?;
+
+    // without a duration there's nothing to measure against
+    let (completion, skipped) = if duration > 0.0 {
+        let completion = (position_seconds / duration).clamp(0.0, 1.0);
+        (completion, (completion < DEFAULT_SKIP_FRACTION) as i64)
+    } else {
+        (-1.0, -1)
+    };
+
+    // the end_dt check means ending a play twice (or racing close_dangling_plays) only counts once
+    let updated = conn
+        .execute(
+            "UPDATE plays SET end_dt = ?1, position_seconds = ?2, completion = ?3, skipped = ?4 \
+             WHERE play_id = ?5 AND end_dt = ''",
+            params![now_dt(), position_seconds, completion, skipped, play_id],
+        )
+        .unwrap();
+    if updated == 0 {
+        return None;
+    }
+
+    Some(get_play_by_id(play_id.to_string()))
+}
+
+/// Get the plays that have started but not ended yet
+pub fn get_open_plays() -> Vec<PLAY_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut stmt = conn
+        .prepare("SELECT * FROM plays WHERE end_dt = '' ORDER BY start_dt")
+        .expect("Could not prepare statement");
+    let rows = stmt.query_map([], play_from_row).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// Close any unfinished play that started longer ago than the song's duration plus DANGLING_PLAY_GRACE_SECONDS.
+/// We don't know how far those got, so they end at the last position we heard about (0 unless there was a seek)
+/// and completion and skipped are left as unknown (-1). Returns how many plays were closed.
+pub fn close_dangling_plays() -> usize {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.execute(
+        "UPDATE plays SET \
+         end_dt = datetime(start_dt, '+' || MAX(COALESCE(position_seconds, 0), 0) || ' seconds'), \
+         completion = -1, skipped = -1 \
+         WHERE end_dt = '' AND julianday(start_dt) * 86400.0 \
+         + MAX(COALESCE((SELECT duration FROM songs WHERE songs.song_id = plays.song_id), 0), 0) + ?1 \
+         < julianday(?2) * 86400.0",
+        params![DANGLING_PLAY_GRACE_SECONDS, now_dt()],
+    )
+    .unwrap()
+}
@@ -0,0 +1,711 @@
+// Moving playlists in and out of decibl.
+// We support the three formats every other player seems to understand:
+// 1. M3U/M3U8 - a list of paths, with #EXTINF lines for the duration and "artist - title"
+// 2. PLS - an ini file with File1=, Title1=, Length1=...
+// 3. XSPF - xml with file:// urls
+//
+// Exporting takes the songs from get_songs_in_playlist and writes out their paths from the songpaths table.
+// Importing parses the file, tries to find every entry by its path and falls back to matching the title/artist.
+// Anything we can't find ends up in the ImportReport so the frontend can show it.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::string_to_hash;
+use crate::engine::models::*;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3U,
+    PLS,
+    XSPF,
+}
+
+impl PlaylistFormat {
+    /// Picks the format from the file extension (m3u, m3u8, pls, xspf)
+    pub fn from_path(filepath: &str) -> Option<PlaylistFormat> {
+        let extension = Path::new(filepath)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)?
+            .to_lowercase();
+
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3U),
+            "pls" => Some(PlaylistFormat::PLS),
+            "xspf" => Some(PlaylistFormat::XSPF),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a playlist file. Whatever the file didn't tell us is left empty (or -1 for the duration).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub location: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub duration: f64,
+}
+
+impl PlaylistEntry {
+    fn new(location: String) -> Self {
+        PlaylistEntry {
+            location,
+            title: String::new(),
+            artist: String::new(),
+            album: String::new(),
+            duration: -1.0,
+        }
+    }
+
+    // M3U and PLS only have one "display title", which is usually "Artist - Title"
+    fn set_display_title(&mut self, display: &str) {
+        match display.split_once(" - ") {
+            Some((artist, title)) => {
+                self.artist = artist.trim().to_string();
+                self.title = title.trim().to_string();
+            }
+            None => self.title = display.trim().to_string(),
+        }
+    }
+}
+
+/// What happened when a playlist got imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportReport {
+    pub playlist_id: String,
+    /// The song_ids that made it into the playlist, in order
+    pub song_ids: Vec<String>,
+    /// The entries we couldn't match to any song
+    pub unresolved: Vec<PlaylistEntry>,
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           HELPERS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Makes `target` relative to `base_dir`. If they don't share a root (different drives on windows) target is returned as is.
+pub fn relative_path(base_dir: &Path, target: &Path) -> PathBuf {
+    let base: Vec<Component> = base_dir.components().collect();
+    let target_components: Vec<Component> = target.components().collect();
+
+    let common = base
+        .iter()
+        .zip(target_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    if common == 0 {
+        return target.to_path_buf();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    for component in &target_components[common..] {
+        relative.push(component.as_os_str());
+    }
+    relative
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// decodes the five named entities and character references (&#233; and &#xE9;), anything else is left alone
+fn xml_unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        unescaped.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').and_then(|semi| {
+            let character = match &rest[1..semi] {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                reference => {
+                    let code = match reference.strip_prefix('#') {
+                        Some(hex) if hex.starts_with(['x', 'X']) => {
+                            u32::from_str_radix(&hex[1..], 16).ok()
+                        }
+                        Some(decimal) => decimal.parse::<u32>().ok(),
+                        None => None,
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            character.map(|character| (character, semi + 1))
+        });
+        match decoded {
+            Some((character, length)) => {
+                unescaped.push(character);
+                rest = &rest[length..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// xspf locations are urls, so spaces and friends have to be percent encoded
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+enum XmlTag<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    /// <tag/>
+    Empty(&'a str),
+}
+
+// the next tag at or after `from` as (where it starts, just past where it ends, the tag). Comments, CDATA,
+// processing instructions and doctypes aren't tags and get skipped. Quotes are followed so a '>' in an attribute
+// doesn't end the tag early
+fn next_xml_tag(xml: &str, from: usize) -> Option<(usize, usize, XmlTag<'_>)> {
+    let mut pos = from;
+    loop {
+        let start = xml[pos..].find('<')? + pos;
+        let rest = &xml[start..];
+        let skip_past = if rest.starts_with("<!--") {
+            Some("-->")
+        } else if rest.starts_with("<![CDATA[") {
+            Some("]]>")
+        } else if rest.starts_with("<?") {
+            Some("?>")
+        } else {
+            None
+        };
+        if let Some(terminator) = skip_past {
+            pos = start + rest.find(terminator)? + terminator.len();
+            continue;
+        }
+
+        let mut quote: Option<char> = None;
+        let length = rest.char_indices().find_map(|(i, c)| {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if c == '>' => return Some(i + 1),
+                None => {}
+            }
+            None
+        })?;
+        let end = start + length;
+        if rest.starts_with("<!") {
+            pos = end;
+            continue;
+        }
+
+        let inner = &rest[1..length - 1];
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let empty = !closing && inner.ends_with('/');
+        let name = inner
+            .trim_end_matches('/')
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or("");
+        let tag = if closing {
+            XmlTag::Close(name)
+        } else if empty {
+            XmlTag::Empty(name)
+        } else {
+            XmlTag::Open(name)
+        };
+        return Some((start, end, tag));
+    }
+}
+
+// "xspf:track" -> "track"
+fn xml_local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// the raw contents of every <name> element, whatever its namespace prefix and attributes. Elements inside one that
+// matched aren't looked at, so <track> finds tracks and not something nested in one. With `children_only` only the
+// elements at the top level of `xml` count, so a track's <title> isn't one from inside its <extension>
+fn xml_elements<'a>(xml: &'a str, name: &str, children_only: bool) -> Vec<&'a str> {
+    let mut found: Vec<&str> = Vec::new();
+    let mut pos = 0;
+    let mut level = 0;
+    while let Some((_, end, tag)) = next_xml_tag(xml, pos) {
+        pos = end;
+        let open = match tag {
+            XmlTag::Empty(tag) if xml_local_name(tag) == name && (level == 0 || !children_only) => {
+                found.push("");
+                continue;
+            }
+            XmlTag::Open(tag) if xml_local_name(tag) == name && (level == 0 || !children_only) => {
+                tag
+            }
+            XmlTag::Open(_) => {
+                level += 1;
+                continue;
+            }
+            XmlTag::Close(_) => {
+                level -= 1;
+                continue;
+            }
+            XmlTag::Empty(_) => continue,
+        };
+
+        let content_start = end;
+        let mut depth = 1;
+        let mut content_end = None;
+        while let Some((start, end, tag)) = next_xml_tag(xml, pos) {
+            pos = end;
+            match tag {
+                XmlTag::Open(tag) if tag == open => depth += 1,
+                XmlTag::Close(tag) if tag == open => {
+                    depth -= 1;
+                    if depth == 0 {
+                        content_end = Some(start);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        match content_end {
+            Some(content_end) => found.push(&xml[content_start..content_end]),
+            // never closed, nothing after it can be trusted either
+            None => break,
+        }
+    }
+    found
+}
+
+// the text of an element's contents: CDATA is taken as it is, everything else gets its entities decoded and any
+// markup (comments, child tags) dropped
+fn xml_text(content: &str) -> String {
+    let mut text = String::new();
+    let mut rest = content;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&xml_unescape(&rest[..open]));
+        rest = &rest[open..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            text.push_str(&cdata[..end]);
+            rest = cdata.get(end + 3..).unwrap_or("");
+        } else {
+            let terminator = if rest.starts_with("<!--") { "-->" } else { ">" };
+            rest = match rest.find(terminator) {
+                Some(end) => &rest[end + terminator.len()..],
+                None => "",
+            };
+        }
+    }
+    text.push_str(&xml_unescape(rest));
+    text.trim().to_string()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           EXPORT
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Turns a playlist into the contents of a playlist file.
+/// If relative_to is set, paths are written relative to that directory (where the playlist file will live).
+/// Songs without an entry in the songpaths table are left out since no player could open them anyway.
+pub fn playlist_to_string(
+    playlist_id: String,
+    format: PlaylistFormat,
+    relative_to: Option<&Path>,
+) -> String {
+    let playlist = get_playlist_by_id(playlist_id.clone());
+    let songs = get_songs_in_playlist(playlist_id);
+
+    // pair every song with the path we're going to write
+    let mut entries: Vec<(SONG_TABLE_DATA, String)> = Vec::new();
+    for song in songs {
+        let songpaths = get_songpaths_by_song_id(song.song_id.clone());
+        let songpath = match songpaths.first() {
+            Some(songpath) => PathBuf::from(&songpath.song_path),
+            None => continue,
+        };
+        let songpath = match relative_to {
+            Some(base_dir) => relative_path(base_dir, &songpath),
+            None => songpath,
+        };
+        entries.push((song, songpath.to_string_lossy().to_string()));
+    }
+
+    match format {
+        PlaylistFormat::M3U => {
+            let mut contents = String::from("#EXTM3U\n");
+            for (song, path) in entries {
+                contents.push_str(&format!(
+                    "#EXTINF:{},{} - {}\n{}\n",
+                    song.duration.round() as i64,
+                    song.main_artist,
+                    song.title,
+                    path
+                ));
+            }
+            contents
+        }
+        PlaylistFormat::PLS => {
+            let mut contents = String::from("[playlist]\n");
+            for (i, (song, path)) in entries.iter().enumerate() {
+                let n = i + 1;
+                contents.push_str(&format!("File{}={}\n", n, path));
+                contents.push_str(&format!(
+                    "Title{}={} - {}\n",
+                    n, song.main_artist, song.title
+                ));
+                contents.push_str(&format!("Length{}={}\n", n, song.duration.round() as i64));
+            }
+            contents.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+            contents
+        }
+        PlaylistFormat::XSPF => {
+            let mut contents = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            contents.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+            contents.push_str(&format!(
+                "  <title>{}</title>\n",
+                xml_escape(&playlist.playlist_name)
+            ));
+            contents.push_str("  <trackList>\n");
+            for (song, path) in entries {
+                let path = path.replace('\\', "/");
+                let location = if relative_to.is_some() {
+                    percent_encode(&path)
+                } else if path.starts_with('/') {
+                    format!("file://{}", percent_encode(&path))
+                } else {
+                    // windows paths like C:/Music/...
+                    format!("file:///{}", percent_encode(&path))
+                };
+
+                contents.push_str("    <track>\n");
+                contents.push_str(&format!(
+                    "      <location>{}</location>\n",
+                    xml_escape(&location)
+                ));
+                contents.push_str(&format!(
+                    "      <title>{}</title>\n",
+                    xml_escape(&song.title)
+                ));
+                contents.push_str(&format!(
+                    "      <creator>{}</creator>\n",
+                    xml_escape(&song.main_artist)
+                ));
+                contents.push_str(&format!(
+                    "      <album>{}</album>\n",
+                    xml_escape(&song.album)
+                ));
+                if song.duration >= 0.0 {
+                    // xspf durations are in milliseconds
+                    contents.push_str(&format!(
+                        "      <duration>{}</duration>\n",
+                        (song.duration * 1000.0).round() as i64
+                    ));
+                }
+                contents.push_str("    </track>\n");
+            }
+            contents.push_str("  </trackList>\n</playlist>\n");
+            contents
+        }
+    }
+}
+
+/// Writes a playlist to a file, the format is picked from the extension of `filepath`.
+/// With relative = true the song paths are written relative to the folder the playlist file is in.
+pub fn export_playlist(
+    playlist_id: String,
+    filepath: String,
+    relative: bool,
+) -> std::io::Result<()> {
+    let format = PlaylistFormat::from_path(&filepath).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unsupported playlist extension: {}", filepath),
+        )
+    })?;
+
+    let playlist_dir = Path::new(&filepath)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let relative_to = if relative {
+        Some(playlist_dir.as_path())
+    } else {
+        None
+    };
+
+    let contents = playlist_to_string(playlist_id, format, relative_to);
+    std::fs::write(filepath, contents)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           IMPORT
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Parses the contents of a playlist file into its entries. Locations are returned exactly as written in the file.
+pub fn parse_playlist(contents: &str, format: PlaylistFormat) -> Vec<PlaylistEntry> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let mut entries: Vec<PlaylistEntry> = Vec::new();
+
+    match format {
+        PlaylistFormat::M3U => {
+            // #EXTINF applies to the next path line
+            let mut pending: Option<(f64, String)> = None;
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if let Some(info) = line.strip_prefix("#EXTINF:") {
+                    let (duration, display) = info.split_once(',').unwrap_or((info, ""));
+                    pending = Some((
+                        duration.trim().parse::<f64>().unwrap_or(-1.0),
+                        display.to_string(),
+                    ));
+                    continue;
+                }
+                if line.starts_with('#') {
+                    continue;
+                }
+
+                let mut entry = PlaylistEntry::new(line.to_string());
+                if let Some((duration, display)) = pending.take() {
+                    entry.duration = duration;
+                    entry.set_display_title(&display);
+                }
+                entries.push(entry);
+            }
+        }
+        PlaylistFormat::PLS => {
+            // entries can technically come in any order, so collect them by number first
+            let mut numbered: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+            for line in contents.lines() {
+                let (key, value) = match line.trim().split_once('=') {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                let key = key.trim().to_lowercase();
+                let (field, number) =
+                    key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len()));
+                let number = match number.parse::<u32>() {
+                    Ok(number) => number,
+                    Err(_) => continue,
+                };
+
+                let entry = numbered
+                    .entry(number)
+                    .or_insert_with(|| PlaylistEntry::new(String::new()));
+                match field {
+                    "file" => entry.location = value.trim().to_string(),
+                    "title" => entry.set_display_title(value),
+                    "length" => entry.duration = value.trim().parse::<f64>().unwrap_or(-1.0),
+                    _ => {}
+                }
+            }
+            entries = numbered
+                .into_values()
+                .filter(|entry| !entry.location.is_empty())
+                .collect();
+        }
+        PlaylistFormat::XSPF => {
+            for track in xml_elements(contents, "track", false) {
+                let field = |tag: &str| {
+                    xml_elements(track, tag, true)
+                        .first()
+                        .map(|text| xml_text(text))
+                };
+                let location = match field("location") {
+                    Some(location) if !location.is_empty() => location,
+                    _ => continue,
+                };
+
+                // file:///C:/Music and file:///home/music both have to become real paths
+                let location = match location.strip_prefix("file://") {
+                    Some(path) => {
+                        let path = percent_decode(path);
+                        let is_windows_drive = path.len() > 2 && path.as_bytes()[2] == b':';
+                        if is_windows_drive {
+                            path[1..].to_string()
+                        } else {
+                            path
+                        }
+                    }
+                    None => percent_decode(&location),
+                };
+
+                let mut entry = PlaylistEntry::new(location);
+                entry.title = field("title").unwrap_or_default();
+                entry.artist = field("creator").unwrap_or_default();
+                entry.album = field("album").unwrap_or_default();
+                entry.duration = field("duration")
+                    .and_then(|ms| ms.parse::<f64>().ok())
+                    .map(|ms| ms / 1000.0)
+                    .unwrap_or(-1.0);
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries
+}
+
+/// Finds the song an entry refers to. First by path (relative paths are taken relative to playlist_dir),
+/// then by title and artist.
+pub fn resolve_playlist_entry(entry: &PlaylistEntry, playlist_dir: &Path) -> Option<String> {
+    let location = PathBuf::from(&entry.location);
+    let absolute = if location.is_absolute() {
+        location
+    } else {
+        playlist_dir.join(location)
+    };
+
+    let mut candidates: Vec<String> = vec![
+        entry.location.clone(),
+        absolute.to_string_lossy().to_string(),
+    ];
+    if let Ok(canonical) = std::fs::canonicalize(&absolute) {
+        candidates.push(canonical.to_string_lossy().to_string());
+    }
+
+    for candidate in candidates {
+        if let Some(song_id) = get_song_id_by_path(candidate) {
+            return Some(song_id);
+        }
+    }
+
+    if entry.title.is_empty() {
+        return None;
+    }
+
+    // titles and artists can contain % and _, so compare them exactly, just ignoring case
+    let mut query = SongQuery::new()
+        .filter(SongFilter::column(
+            SongColumn::Title,
+            Comparison::EqualIgnoreCase,
+            entry.title.clone(),
+        ))
+        .limit(1);
+    if !entry.artist.is_empty() {
+        query = query.filter(SongFilter::Or(vec![
+            SongFilter::column(
+                SongColumn::MainArtist,
+                Comparison::EqualIgnoreCase,
+                entry.artist.clone(),
+            ),
+            SongFilter::linked(
+                SongLink::SongArtist,
+                Comparison::EqualIgnoreCase,
+                &entry.artist,
+            ),
+        ]));
+    }
+
+    query_songs(&query)
+        .into_iter()
+        .next()
+        .map(|song| song.song_id)
+}
+
+/// Imports a playlist file into the database as a new (static) playlist named after the file.
+pub fn import_playlist(filepath: String) -> std::io::Result<ImportReport> {
+    let format = PlaylistFormat::from_path(&filepath).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unsupported playlist extension: {}", filepath),
+        )
+    })?;
+
+    let bytes = std::fs::read(&filepath)?;
+    let contents = String::from_utf8_lossy(&bytes).to_string();
+    let entries = parse_playlist(&contents, format);
+
+    let path = Path::new(&filepath);
+    let playlist_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let playlist_name = path
+        .file_stem()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("Imported playlist")
+        .to_string();
+
+    let created_dt = now_dt();
+    let playlist_id = string_to_hash(format!("{}{}", filepath, created_dt)).unwrap();
+
+    insert_playlist(PLAYLIST_TABLE_DATA {
+        playlist_id: playlist_id.clone(),
+        playlist_name,
+        playlist_desc: format!("Imported from {}", filepath),
+        created_dt: created_dt.clone(),
+    });
+
+    let mut report = ImportReport {
+        playlist_id: playlist_id.clone(),
+        song_ids: Vec::new(),
+        unresolved: Vec::new(),
+    };
+
+    for entry in entries {
+        match resolve_playlist_entry(&entry, &playlist_dir) {
+            Some(song_id) => {
+                insert_playlist_song(PLAYLIST_SONGS_TABLE_DATA {
+                    playlist_id: playlist_id.clone(),
+                    song_id: song_id.clone(),
+                    added_dt: created_dt.clone(),
+                    position: -1,
+                });
+                report.song_ids.push(song_id);
+            }
+            None => report.unresolved.push(entry),
+        }
+    }
+
+    Ok(report)
+}
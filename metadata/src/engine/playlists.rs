@@ -0,0 +1,177 @@
+use crate::engine::analyticsdb::now_dt;
+use crate::engine::config::*;
+use rusqlite::Connection;
+use rusqlite::Transaction;
+use rusqlite::TransactionBehavior;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           ORDERED PLAYLISTS
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// every row in playlist_songs has a 0 based position, and positions in a playlist always run 0..len with no gaps.
+// the same song can be in a playlist more than once, so entries are addressed by position and never by song_id.
+// every edit happens inside an IMMEDIATE transaction, so two edits to the same playlist queue up behind each other
+// instead of both reading the old order and writing conflicting positions.
+
+/// Runs `edit` on the rowids of a playlist's entries (in order) and then writes the positions back out.
+/// The edit can insert or delete rows through the transaction as long as it keeps the vec in step.
+fn edit_playlist<T>(playlist_id: &str, edit: impl FnOnce(&Transaction, &mut Vec<i64>) -> T) -> T {
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .expect("Could not start transaction");
+
+    let mut rowids: Vec<i64> = {
+        let mut stmt = tx
+            .prepare(
+                "SELECT rowid FROM playlist_songs WHERE playlist_id = ?1 ORDER BY position, rowid",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt.query_map([playlist_id], |row| row.get(0)).unwrap();
+        rows.map(|rowid| rowid.unwrap()).collect()
+    };
+
+    let result = edit(&tx, &mut rowids);
+
+    // move everything out of the way first so the unique (playlist_id, position) index doesn't trip mid update
+    tx.execute(
+        "UPDATE playlist_songs SET position = -1 - position WHERE playlist_id = ?1 AND position >= 0",
+        [playlist_id],
+    )
+    .unwrap();
+    for (position, rowid) in rowids.iter().enumerate() {
+        tx.execute(
+            "UPDATE playlist_songs SET position = ?1 WHERE rowid = ?2",
+            [position as i64, *rowid],
+        )
+        .unwrap();
+    }
+
+    tx.commit().expect("Could not commit transaction");
+    result
+}
+
+/// Closes up any gaps in a playlist's positions (say after rows were deleted behind our back), keeping the order
+pub fn renumber_playlist(playlist_id: &str) {
+    edit_playlist(playlist_id, |_, _| ())
+}
+
+/// Get the number of entries in a playlist, duplicates included.
+pub fn get_playlist_length(playlist_id: &str) -> i64 {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    conn.query_row(
+        "SELECT COUNT(*) FROM playlist_songs WHERE playlist_id = ?1",
+        [playlist_id],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+/// Insert a song at `index`, pushing everything from there onwards down by one.
+/// An index past the end appends. Returns the position the song ended up at.
+/// ```no_run
+/// # use decibl_metadata::engine::playlists::*;
+/// // put the song at the top of the playlist
+/// insert_playlist_song_at("playlist_id", "song_id", 0);
+/// ```
+pub fn insert_playlist_song_at(playlist_id: &str, song_id: &str, index: usize) -> usize {
+    let added_dt = now_dt();
+
+    edit_playlist(playlist_id, |tx, rowids| {
+        // the position gets filled in by edit_playlist, NULL doesn't clash with the unique index until then
+        tx.execute(
+            "INSERT INTO playlist_songs (playlist_id, song_id, added_dt, position) VALUES (?1, ?2, ?3, NULL)",
+            [playlist_id, song_id, &added_dt],
+        )
+        .unwrap();
+        let index = index.min(rowids.len());
+        rowids.insert(index, tx.last_insert_rowid());
+        index
+    })
+}
+
+/// Append a song to the end of a playlist. Returns its position.
+pub fn append_playlist_song(playlist_id: &str, song_id: &str) -> usize {
+    insert_playlist_song_at(playlist_id, song_id, usize::MAX)
+}
+
+/// Remove the entry at `index`. Returns the song_id that was there, or None if the index is out of range.
+/// Only that one entry goes, any other copies of the same song stay put.
+pub fn remove_playlist_song_at(playlist_id: &str, index: usize) -> Option<String> {
+    edit_playlist(playlist_id, |tx, rowids| {
+        if index >= rowids.len() {
+            return None;
+        }
+        let rowid = rowids.remove(index);
+        let song_id: String = tx
+            .query_row(
+                "SELECT song_id FROM playlist_songs WHERE rowid = ?1",
+                [rowid],
+                |row| row.get(0),
+            )
+            .unwrap();
+        tx.execute("DELETE FROM playlist_songs WHERE rowid = ?1", [rowid])
+            .unwrap();
+        Some(song_id)
+    })
+}
+
+/// Remove every copy of a song from a playlist. Returns how many entries were removed.
+pub fn remove_song_from_playlist(playlist_id: &str, song_id: &str) -> usize {
+    edit_playlist(playlist_id, |tx, rowids| {
+        let doomed: Vec<i64> = {
+            let mut stmt = tx
+                .prepare("SELECT rowid FROM playlist_songs WHERE playlist_id = ?1 AND song_id = ?2")
+                .expect("Could not prepare statement");
+            let rows = stmt
+                .query_map([playlist_id, song_id], |row| row.get(0))
+                .unwrap();
+            rows.map(|rowid| rowid.unwrap()).collect()
+        };
+        for rowid in &doomed {
+            tx.execute("DELETE FROM playlist_songs WHERE rowid = ?1", [rowid])
+                .unwrap();
+        }
+        rowids.retain(|rowid| !doomed.contains(rowid));
+        doomed.len()
+    })
+}
+
+/// Move the entry at `from` so it ends up at `to`. Returns false (and changes nothing) if either is out of range.
+/// ```no_run
+/// # use decibl_metadata::engine::playlists::*;
+/// // drag the third song up to the top
+/// move_playlist_song("playlist_id", 2, 0);
+/// ```
+pub fn move_playlist_song(playlist_id: &str, from: usize, to: usize) -> bool {
+    edit_playlist(playlist_id, |_, rowids| {
+        if from >= rowids.len() || to >= rowids.len() {
+            return false;
+        }
+        let rowid = rowids.remove(from);
+        rowids.insert(to, rowid);
+        true
+    })
+}
+
+/// Reorder a whole playlist at once. `order[i]` is the current position of the entry that should end up at position i,
+/// so `[2, 0, 1]` moves the last entry to the front. Returns false (and changes nothing) if `order` isn't a
+/// rearrangement of every current position.
+pub fn reorder_playlist(playlist_id: &str, order: &[usize]) -> bool {
+    edit_playlist(playlist_id, |_, rowids| {
+        let mut seen = vec![false; rowids.len()];
+        if order.len() != rowids.len() {
+            return false;
+        }
+        for &position in order {
+            if position >= seen.len() || seen[position] {
+                return false;
+            }
+            seen[position] = true;
+        }
+        *rowids = order.iter().map(|&position| rowids[position]).collect();
+        true
+    })
+}
@@ -0,0 +1,736 @@
+// Moving listening history in and out of decibl, without talking to any server.
+// We speak the two formats people actually have lying around:
+// 1. ListenBrainz JSON - what listenbrainz.org gives you when you export, a list of listens with a unix listened_at
+//    and a track_metadata object. We also read one listen per line and the {"payload": [...]} submission format.
+// 2. Last.fm style CSV - artist,album,title,date with no header, which is what the popular lastfm-to-csv exporters
+//    write. CSVs with a header (uts/utc_time/artist/album/track...) are read too.
+//
+// Importing matches each listen to a song in the library by artist/title (album breaks ties), first exactly after
+// normalizing the names and then fuzzily. Listens we can't match are still imported as plays with no song_id, so the
+// history isn't lost, and are listed in the report. A listen that overlaps a play we already have of the same track
+// is treated as the same listen and skipped, so importing the same dump twice (or a dump that overlaps decibl's own
+// plays) doesn't double count.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::string_to_hash;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use chrono::NaiveDateTime;
+use rusqlite::params;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How close together (in seconds) two listens of the same track with no known duration have to be to count as one.
+/// A minute, because Last.fm CSV dates only go down to the minute.
+pub const MIN_OVERLAP_SECONDS: i64 = 60;
+
+/// How similar (0 to 1) the title and the artist both have to be for a fuzzy match
+pub const FUZZY_MATCH_THRESHOLD: f64 = 0.85;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrobbleFormat {
+    ListenBrainz,
+    LastFmCsv,
+}
+
+impl ScrobbleFormat {
+    /// Picks the format from the file extension (json, jsonl, csv)
+    pub fn from_path(filepath: &str) -> Option<ScrobbleFormat> {
+        let extension = Path::new(filepath)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)?
+            .to_lowercase();
+
+        match extension.as_str() {
+            "json" | "jsonl" => Some(ScrobbleFormat::ListenBrainz),
+            "csv" => Some(ScrobbleFormat::LastFmCsv),
+            _ => None,
+        }
+    }
+}
+
+/// One listen, in the shape both formats share. Unknown text is empty and an unknown duration is -1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listen {
+    /// Unix timestamp (UTC) of when the listen started
+    pub listened_at: i64,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub duration_seconds: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct ScrobbleImportReport {
+    /// Listens that were added to the plays table, matched or not
+    pub imported: usize,
+    /// How many of the imported listens were matched to a song in the library
+    pub matched: usize,
+    /// Listens that were skipped because we already had them
+    pub duplicates: usize,
+    /// Imported listens that didn't match any song
+    pub unmatched: Vec<Listen>,
+}
+
+// the bits of the ListenBrainz format we care about, anything else in there is ignored
+#[derive(Debug, Serialize, Deserialize)]
+struct ListenBrainzListen {
+    listened_at: i64,
+    track_metadata: ListenBrainzTrack,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ListenBrainzTrack {
+    artist_name: String,
+    track_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    #[serde(default)]
+    additional_info: ListenBrainzInfo,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ListenBrainzInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    submission_client: Option<String>,
+}
+
+fn parse_dt(dt: &str) -> Option<NaiveDateTime> {
+    let dt = dt.trim();
+    if let Ok(timestamp) = dt.parse::<i64>() {
+        return NaiveDateTime::from_timestamp_opt(timestamp, 0);
+    }
+    [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.fZ",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%d %b %Y %H:%M",
+        "%d %b %Y, %H:%M",
+        "%d/%m/%Y %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(dt, format).ok())
+}
+
+fn timestamp_to_dt(timestamp: i64) -> String {
+    format_dt(NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap_or_default())
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           EXPORT
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Every finished play as a listen, oldest first. Plays we know were skipped are left out, same as a scrobbler would.
+pub fn get_listens() -> Vec<Listen> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut stmt = conn
+        .prepare(
+            "SELECT plays.start_dt, \
+             COALESCE(NULLIF(plays.main_artist, ''), songs.main_artist, ''), \
+             COALESCE(NULLIF(plays.song_title, ''), songs.title, ''), \
+             COALESCE(songs.album, ''), COALESCE(songs.duration, -1) \
+             FROM plays LEFT JOIN songs ON songs.song_id = plays.song_id \
+             WHERE plays.end_dt != '' AND COALESCE(plays.skipped, -1) != 1 ORDER BY plays.start_dt",
+        )
+        .expect("Could not prepare statement");
+
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, f64>(4)?,
+            ))
+        })
+        .unwrap();
+
+    rows.filter_map(|row| {
+        let (start_dt, artist, title, album, duration_seconds) = row.unwrap();
+        Some(Listen {
+            listened_at: parse_dt(&start_dt)?.timestamp(),
+            artist,
+            title,
+            album,
+            duration_seconds,
+        })
+    })
+    .collect()
+}
+
+/// Listens as a ListenBrainz JSON array
+pub fn listens_to_listenbrainz(listens: &[Listen]) -> String {
+    let listens: Vec<ListenBrainzListen> = listens
+        .iter()
+        .map(|listen| ListenBrainzListen {
+            listened_at: listen.listened_at,
+            track_metadata: ListenBrainzTrack {
+                artist_name: listen.artist.clone(),
+                track_name: listen.title.clone(),
+                release_name: Some(listen.album.clone()).filter(|album| !album.is_empty()),
+                additional_info: ListenBrainzInfo {
+                    duration_ms: Some((listen.duration_seconds * 1000.0).round() as i64)
+                        .filter(|duration_ms| *duration_ms > 0),
+                    duration: None,
+                    submission_client: Some("decibl".to_string()),
+                },
+            },
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&listens).unwrap()
+}
+
+/// Listens as a Last.fm style CSV: artist,album,title,date with no header and dates like "31 Jan 2021 14:03"
+pub fn listens_to_lastfm_csv(listens: &[Listen]) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for listen in listens {
+        let date = NaiveDateTime::from_timestamp_opt(listen.listened_at, 0)
+            .unwrap_or_default()
+            .format("%d %b %Y %H:%M")
+            .to_string();
+        writer
+            .write_record([&listen.artist, &listen.album, &listen.title, &date])
+            .unwrap();
+    }
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// Writes the whole listening history to a file, the format is picked from the extension of `filepath`.
+/// Returns how many listens were written.
+/// ```no_run
+/// # use decibl_metadata::engine::scrobbles::*;
+/// export_scrobbles("/home/me/listens.json".to_string()).unwrap();
+/// ```
+pub fn export_scrobbles(filepath: String) -> std::io::Result<usize> {
+    let format = scrobble_format(&filepath)?;
+    let listens = get_listens();
+
+    let contents = match format {
+        ScrobbleFormat::ListenBrainz => listens_to_listenbrainz(&listens),
+        ScrobbleFormat::LastFmCsv => listens_to_lastfm_csv(&listens),
+    };
+    std::fs::write(filepath, contents)?;
+
+    Ok(listens.len())
+}
+
+fn scrobble_format(filepath: &str) -> std::io::Result<ScrobbleFormat> {
+    ScrobbleFormat::from_path(filepath).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unsupported scrobble extension: {}", filepath),
+        )
+    })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           PARSE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+fn listenbrainz_to_listen(listen: ListenBrainzListen) -> Listen {
+    let info = listen.track_metadata.additional_info;
+    let duration_seconds = match (info.duration_ms, info.duration) {
+        (Some(duration_ms), _) => duration_ms as f64 / 1000.0,
+        (None, Some(duration)) => duration as f64,
+        (None, None) => -1.0,
+    };
+
+    Listen {
+        listened_at: listen.listened_at,
+        artist: listen.track_metadata.artist_name,
+        title: listen.track_metadata.track_name,
+        album: listen.track_metadata.release_name.unwrap_or_default(),
+        duration_seconds,
+    }
+}
+
+/// Parses a ListenBrainz export. Takes a JSON array of listens, one listen per line, or a submission payload.
+/// Anything that doesn't look like a listen is skipped.
+pub fn parse_listenbrainz(contents: &str) -> Vec<Listen> {
+    let contents = contents.trim_start_matches('\u{feff}').trim();
+
+    let values: Vec<serde_json::Value> = match serde_json::from_str::<serde_json::Value>(contents) {
+        Ok(serde_json::Value::Array(listens)) => listens,
+        Ok(serde_json::Value::Object(mut object)) => match object.remove("payload") {
+            Some(serde_json::Value::Array(listens)) => listens,
+            _ => vec![serde_json::Value::Object(object)],
+        },
+        // not one json document, so try a listen per line
+        _ => contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line.trim()).ok())
+            .collect(),
+    };
+
+    values
+        .into_iter()
+        .filter_map(|value| serde_json::from_value::<ListenBrainzListen>(value).ok())
+        .map(listenbrainz_to_listen)
+        .collect()
+}
+
+/// Parses a Last.fm style CSV. Without a header the columns are artist,album,title,date.
+/// With one, the columns are found by name (artist, album, track or title, uts or date or utc_time).
+pub fn parse_lastfm_csv(contents: &str) -> Vec<Listen> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+    let mut records = reader.records().filter_map(Result::ok).peekable();
+
+    let find = |header: &csv::StringRecord, names: &[&str]| {
+        names.iter().find_map(|name| {
+            header
+                .iter()
+                .position(|column| column.trim().eq_ignore_ascii_case(name))
+        })
+    };
+
+    // (artist, album, title, date)
+    let mut columns = (0, Some(1), 2, 3);
+    if let Some(header) = records.peek() {
+        let artist = find(header, &["artist", "artist_name"]);
+        let title = find(header, &["track", "title", "track_name", "name"]);
+        let date = find(
+            header,
+            &[
+                "uts",
+                "timestamp",
+                "listened_at",
+                "date",
+                "utc_time",
+                "time",
+            ],
+        );
+        if let (Some(artist), Some(title), Some(date)) = (artist, title, date) {
+            columns = (
+                artist,
+                find(header, &["album", "release", "album_name"]),
+                title,
+                date,
+            );
+            records.next();
+        }
+    }
+
+    let (artist, album, title, date) = columns;
+    records
+        .filter_map(|record| {
+            Some(Listen {
+                listened_at: parse_dt(record.get(date)?)?.timestamp(),
+                artist: record.get(artist)?.trim().to_string(),
+                title: record.get(title)?.trim().to_string(),
+                album: album
+                    .and_then(|album| record.get(album))
+                    .unwrap_or("")
+                    .trim()
+                    .to_string(),
+                duration_seconds: -1.0,
+            })
+        })
+        .collect()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MATCHING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Lowercases a name and throws away the noise that differs between services:
+/// anything in brackets ("(feat. X)", "[Remastered]"), a " - 2011 Remaster" style suffix, a leading "the" and punctuation.
+pub fn normalize_name(name: &str) -> String {
+    let mut stripped = String::new();
+    let mut depth = 0;
+    for c in name.to_lowercase().chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    if let Some(index) = stripped.find(" - ") {
+        let suffix = &stripped[index..];
+        if [
+            "remaster", "version", "live", "edit", "mix", "mono", "stereo",
+        ]
+        .iter()
+        .any(|word| suffix.contains(word))
+        {
+            stripped.truncate(index);
+        }
+    }
+
+    let words: Vec<String> = stripped
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+    let words = match words.first().map(String::as_str) {
+        Some("the") if words.len() > 1 => &words[1..],
+        _ => &words[..],
+    };
+    words.join(" ")
+}
+
+/// How alike two strings are, from 0 (nothing in common) to 1 (the same), by edit distance
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+        }
+        previous = current;
+    }
+
+    1.0 - previous[b.len()] as f64 / a.len().max(b.len()) as f64
+}
+
+struct Candidate {
+    song_id: String,
+    title: String,
+    artists: Vec<String>,
+    album: String,
+    duration: f64,
+}
+
+// (song_id, duration) of the song a listen matched
+type SongMatch = Option<(String, f64)>;
+
+/// The songs in the library with their names normalized, built once per import
+pub struct SongMatcher {
+    candidates: Vec<Candidate>,
+    // candidate indices keyed by (title, artist), one entry for each of a song's artists
+    exact: HashMap<(String, String), Vec<usize>>,
+    // what each (title, artist, album) matched, misses too, so a scrobble history is only scanned once per track
+    found: HashMap<(String, String, String), SongMatch>,
+}
+
+impl SongMatcher {
+    pub fn new() -> Self {
+        let mut artists: HashMap<String, Vec<String>> = HashMap::new();
+        for song_artist in get_all_song_artists() {
+            artists
+                .entry(song_artist.song_id)
+                .or_default()
+                .push(normalize_name(&song_artist.artist_name));
+        }
+
+        let candidates: Vec<Candidate> = get_all_songs()
+            .into_iter()
+            .map(|song| {
+                let mut song_artists = artists.remove(&song.song_id).unwrap_or_default();
+                song_artists.push(normalize_name(&song.main_artist));
+                Candidate {
+                    title: normalize_name(&song.title),
+                    artists: song_artists,
+                    album: normalize_name(&song.album),
+                    duration: song.duration,
+                    song_id: song.song_id,
+                }
+            })
+            .collect();
+
+        let mut exact: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for (index, candidate) in candidates.iter().enumerate() {
+            for artist in &candidate.artists {
+                let indices = exact
+                    .entry((candidate.title.clone(), artist.clone()))
+                    .or_default();
+                if !indices.contains(&index) {
+                    indices.push(index);
+                }
+            }
+        }
+
+        SongMatcher {
+            candidates,
+            exact,
+            found: HashMap::new(),
+        }
+    }
+
+    /// Finds the song a listen is of, returns its song_id and duration
+    pub fn find(&mut self, listen: &Listen) -> SongMatch {
+        let title = normalize_name(&listen.title);
+        let artist = normalize_name(&listen.artist);
+        let album = normalize_name(&listen.album);
+        if title.is_empty() {
+            return None;
+        }
+
+        let key = (title, artist, album);
+        if let Some(found) = self.found.get(&key) {
+            return found.clone();
+        }
+        let found = self.search(&key.0, &key.1, &key.2);
+        self.found.insert(key, found.clone());
+        found
+    }
+
+    fn search(&self, title: &str, artist: &str, album: &str) -> SongMatch {
+        // the same names after normalizing, preferring the right album if there's more than one
+        let exact = self
+            .exact
+            .get(&(title.to_string(), artist.to_string()))
+            .and_then(|indices| {
+                indices
+                    .iter()
+                    .map(|index| &self.candidates[*index])
+                    .max_by_key(|candidate| !album.is_empty() && candidate.album == album)
+            });
+        if let Some(candidate) = exact {
+            return Some((candidate.song_id.clone(), candidate.duration));
+        }
+
+        // otherwise the closest title and artist that are both close enough
+        let mut best: Option<(&Candidate, f64)> = None;
+        for candidate in &self.candidates {
+            let title_score = similarity(&candidate.title, title);
+            if title_score < FUZZY_MATCH_THRESHOLD {
+                continue;
+            }
+            let artist_score = candidate
+                .artists
+                .iter()
+                .map(|candidate_artist| similarity(candidate_artist, artist))
+                .fold(0.0, f64::max);
+            if artist_score < FUZZY_MATCH_THRESHOLD {
+                continue;
+            }
+            let album_bonus = if !album.is_empty() && candidate.album == album {
+                0.1
+            } else {
+                0.0
+            };
+            let score = title_score + artist_score + album_bonus;
+            match best {
+                Some((_, best_score)) if best_score >= score => {}
+                _ => best = Some((candidate, score)),
+            }
+        }
+
+        best.map(|(candidate, _)| (candidate.song_id.clone(), candidate.duration))
+    }
+}
+
+impl Default for SongMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           IMPORT
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// when each track was played (start timestamp, seconds), keyed by song_id and by normalized "artist|title"
+// so a listen can be checked against plays whether or not either of them matched a song
+struct PlayedTimes {
+    times: HashMap<String, Vec<(i64, f64)>>,
+}
+
+impl PlayedTimes {
+    fn load(conn: &Connection) -> Self {
+        let mut times: HashMap<String, Vec<(i64, f64)>> = HashMap::new();
+        let mut stmt = conn
+            .prepare(
+                "SELECT plays.song_id, plays.main_artist, plays.song_title, plays.start_dt, plays.end_dt FROM plays",
+            )
+            .expect("Could not prepare statement");
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .unwrap();
+
+        for row in rows {
+            let (song_id, artist, title, start_dt, end_dt) = row.unwrap();
+            let start = match parse_dt(&start_dt) {
+                Some(start) => start,
+                None => continue,
+            };
+            let seconds = parse_dt(&end_dt)
+                .map(|end| (end - start).num_seconds() as f64)
+                .unwrap_or(-1.0);
+            for key in PlayedTimes::keys(&song_id, &artist, &title) {
+                times
+                    .entry(key)
+                    .or_default()
+                    .push((start.timestamp(), seconds));
+            }
+        }
+
+        PlayedTimes { times }
+    }
+
+    fn keys(song_id: &str, artist: &str, title: &str) -> Vec<String> {
+        let mut keys = vec![format!(
+            "{}|{}",
+            normalize_name(artist),
+            normalize_name(title)
+        )];
+        if !song_id.is_empty() {
+            keys.push(song_id.to_string());
+        }
+        keys
+    }
+
+    fn overlaps(&self, keys: &[String], start: i64, seconds: f64) -> bool {
+        keys.iter()
+            .filter_map(|key| self.times.get(key))
+            .flatten()
+            .any(|&(other_start, other_seconds)| {
+                // whichever started first has to still be going when the other one starts
+                let (first_seconds, gap) = if other_start <= start {
+                    (other_seconds, start - other_start)
+                } else {
+                    (seconds, other_start - start)
+                };
+                (gap as f64) < first_seconds.max(MIN_OVERLAP_SECONDS as f64)
+            })
+    }
+
+    fn add(&mut self, keys: Vec<String>, start: i64, seconds: f64) {
+        for key in keys {
+            self.times.entry(key).or_default().push((start, seconds));
+        }
+    }
+}
+
+/// Adds listens to the plays table. See the top of this file for how matching and deduplicating works.
+pub fn import_listens(listens: Vec<Listen>) -> ScrobbleImportReport {
+    let mut matcher = SongMatcher::new();
+    let mut report = ScrobbleImportReport::default();
+
+    let mut conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let mut played = PlayedTimes::load(&conn);
+    let sql_query = generate_insertion_sql(&PLAYS);
+
+    // one transaction for the lot, years of history is a lot of rows
+    let tx = conn.transaction().expect("Could not start transaction");
+    for listen in listens {
+        let song = matcher.find(&listen);
+        let song_id = song
+            .as_ref()
+            .map(|(song_id, _)| song_id.clone())
+            .unwrap_or_default();
+        let seconds = match &song {
+            Some((_, duration)) if *duration > 0.0 => *duration,
+            _ => listen.duration_seconds,
+        };
+
+        let keys = PlayedTimes::keys(&song_id, &listen.artist, &listen.title);
+        if played.overlaps(&keys, listen.listened_at, seconds) {
+            report.duplicates += 1;
+            continue;
+        }
+        played.add(keys, listen.listened_at, seconds);
+
+        let start_dt = timestamp_to_dt(listen.listened_at);
+        let end_dt = timestamp_to_dt(listen.listened_at + seconds.max(0.0).round() as i64);
+        let play_id = string_to_hash(format!(
+            "{}|{}|{}",
+            listen.artist, listen.title, listen.listened_at
+        ))
+        .unwrap();
+        let filesize_bytes: i64 = if song_id.is_empty() {
+            -1
+        } else {
+            tx.query_row(
+                "SELECT filesize_bytes FROM songs WHERE song_id = ?1",
+                [&song_id],
+                |row| row.get(0),
+            )
+            .unwrap_or(-1)
+        };
+
+        // a scrobble means it was played most of the way through, but how far exactly is unknown
+        let inserted = tx
+            .execute(
+                &sql_query,
+                params![
+                    play_id,
+                    song_id,
+                    listen.title,
+                    listen.artist,
+                    filesize_bytes,
+                    start_dt,
+                    end_dt,
+                    -1.0,
+                    -1.0,
+                    0,
+                    -1
+                ],
+            )
+            .unwrap();
+        if inserted == 0 {
+            // same artist, title and start as a play we already have
+            report.duplicates += 1;
+            continue;
+        }
+
+        report.imported += 1;
+        if song.is_some() {
+            report.matched += 1;
+        } else {
+            report.unmatched.push(listen);
+        }
+    }
+    tx.commit().expect("Could not commit transaction");
+
+    report
+}
+
+/// Imports a ListenBrainz (.json/.jsonl) or Last.fm style (.csv) dump into the plays table.
+/// ```no_run
+/// # use decibl_metadata::engine::scrobbles::*;
+/// let report = import_scrobbles("/home/me/lastfm.csv".to_string()).unwrap();
+/// println!("{} new plays, {} we already had", report.imported, report.duplicates);
+/// ```
+pub fn import_scrobbles(filepath: String) -> std::io::Result<ScrobbleImportReport> {
+    let format = scrobble_format(&filepath)?;
+    let bytes = std::fs::read(&filepath)?;
+    let contents = String::from_utf8_lossy(&bytes);
+
+    let listens = match format {
+        ScrobbleFormat::ListenBrainz => parse_listenbrainz(&contents),
+        ScrobbleFormat::LastFmCsv => parse_lastfm_csv(&contents),
+    };
+
+    Ok(import_listens(listens))
+}
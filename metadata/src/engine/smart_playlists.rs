@@ -0,0 +1,349 @@
+// Smart playlists are playlists where the songs aren't picked by hand, they're picked by rules
+// like "genre is Pop and played more than 10 times". The playlist itself is still a normal row in the
+// playlists table, the rules live in the smart_playlists table as yaml.
+//
+// Membership is never stored, get_songs_in_playlist evaluates the rules every time you ask for them.
+// The rules get turned into a SongFilter from the query builder in analyticsdb, so all the sql stays parameterized.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::string_to_hash;
+use crate::engine::config::*;
+use crate::engine::models::*;
+use crate::engine::music_analysis::parse_key;
+use rusqlite::params;
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           RULES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A single rule of a smart playlist. Ranges are inclusive and either end can be left open with None.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SmartRule {
+    /// The song has this genre
+    Genre(String),
+    /// The song has this artist (song_artists, not album_artists)
+    Artist(String),
+    /// The song has this album artist
+    AlbumArtist(String),
+    Rating {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    BitDepth {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    /// In seconds
+    Duration {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    /// Play count, if within_days is set only plays from the last within_days days are counted
+    PlayCount {
+        min: Option<i64>,
+        max: Option<i64>,
+        within_days: Option<i64>,
+    },
+    /// Played at least once in the last N days
+    PlayedWithinDays(i64),
+    /// Not played in the last N days (songs that were never played count as not played)
+    NotPlayedWithinDays(i64),
+    /// Added to the library in the last N days
+    AddedWithinDays(i64),
+    /// Only the preferred version of songs that are in the library more than once
+    PreferredVersionsOnly,
+    /// The detected tempo
+    Bpm {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    /// The detected key mixes well with this one (see MusicalKey::compatible_keys). Any notation parse_key
+    /// understands, like "Am" or "8A". A key it doesn't understand matches nothing.
+    HarmonicMatch(String),
+}
+
+/// Whether every rule has to match or just one of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchMode {
+    All,
+    Any,
+}
+
+/// How the songs of a smart playlist are ordered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SmartSort {
+    /// Most plays first, if within_days is set only plays from the last within_days days are counted
+    MostPlayed {
+        within_days: Option<i64>,
+    },
+    LeastPlayed {
+        within_days: Option<i64>,
+    },
+    RecentlyPlayed,
+    RecentlyAdded,
+    Random,
+    Column(SongColumn, SortOrder),
+}
+
+/// Everything that gets stored for a smart playlist.
+/// "50 most played in the last 30 days" looks like this:
+/// ```
+/// # use decibl_metadata::engine::smart_playlists::*;
+/// let rules = SmartPlaylistRules {
+///     match_mode: MatchMode::All,
+///     rules: vec![SmartRule::PlayedWithinDays(30)],
+///     sort: Some(SmartSort::MostPlayed { within_days: Some(30) }),
+///     limit: Some(50),
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylistRules {
+    pub match_mode: MatchMode,
+    pub rules: Vec<SmartRule>,
+    pub sort: Option<SmartSort>,
+    pub limit: Option<i64>,
+}
+
+/// Date and time `days` days ago, the way the database stores timestamps
+pub fn days_ago(days: i64) -> String {
+    format_dt(chrono::Utc::now().naive_utc() - chrono::Duration::days(days))
+}
+
+// turns an inclusive min/max into filters, skipping whichever end is None
+fn range_filters<V: Into<Value> + Copy>(
+    column: SongColumn,
+    min: Option<V>,
+    max: Option<V>,
+) -> Vec<SongFilter> {
+    let mut filters: Vec<SongFilter> = Vec::new();
+    if let Some(min) = min {
+        filters.push(SongFilter::column(column, Comparison::GreaterOrEqual, min));
+    }
+    if let Some(max) = max {
+        filters.push(SongFilter::column(column, Comparison::LessOrEqual, max));
+    }
+    filters
+}
+
+impl SmartRule {
+    /// Turns the rule into a SongFilter for the query builder
+    pub fn to_filter(&self) -> SongFilter {
+        match self {
+            SmartRule::Genre(name) => SongFilter::linked(SongLink::Genre, Comparison::Equal, name),
+            SmartRule::Artist(name) => {
+                SongFilter::linked(SongLink::SongArtist, Comparison::Equal, name)
+            }
+            SmartRule::AlbumArtist(name) => {
+                SongFilter::linked(SongLink::AlbumArtist, Comparison::Equal, name)
+            }
+            SmartRule::Rating { min, max } => {
+                SongFilter::And(range_filters(SongColumn::Rating, *min, *max))
+            }
+            SmartRule::BitDepth { min, max } => {
+                SongFilter::And(range_filters(SongColumn::BitDepth, *min, *max))
+            }
+            SmartRule::Duration { min, max } => {
+                SongFilter::And(range_filters(SongColumn::Duration, *min, *max))
+            }
+            SmartRule::PlayCount {
+                min,
+                max,
+                within_days,
+            } => {
+                let since = within_days.map(days_ago);
+                let mut filters: Vec<SongFilter> = Vec::new();
+                if let Some(min) = min {
+                    filters.push(SongFilter::PlayCount(
+                        Comparison::GreaterOrEqual,
+                        *min,
+                        since.clone(),
+                    ));
+                }
+                if let Some(max) = max {
+                    filters.push(SongFilter::PlayCount(Comparison::LessOrEqual, *max, since));
+                }
+                SongFilter::And(filters)
+            }
+            SmartRule::PlayedWithinDays(days) => {
+                SongFilter::LastPlayed(Comparison::GreaterOrEqual, days_ago(*days))
+            }
+            // LastPlayed is NULL for songs that were never played, so those have to be matched separately
+            SmartRule::NotPlayedWithinDays(days) => SongFilter::Or(vec![
+                SongFilter::LastPlayed(Comparison::LessThan, days_ago(*days)),
+                SongFilter::PlayCount(Comparison::Equal, 0, None),
+            ]),
+            SmartRule::AddedWithinDays(days) => {
+                SongFilter::DateAdded(Comparison::GreaterOrEqual, days_ago(*days))
+            }
+            SmartRule::PreferredVersionsOnly => SongFilter::PreferredVersions,
+            SmartRule::Bpm { min, max } => {
+                let mut filters: Vec<SongFilter> = Vec::new();
+                if let Some(min) = min {
+                    filters.push(SongFilter::Bpm(Comparison::GreaterOrEqual, *min));
+                }
+                if let Some(max) = max {
+                    filters.push(SongFilter::Bpm(Comparison::LessOrEqual, *max));
+                }
+                SongFilter::And(filters)
+            }
+            SmartRule::HarmonicMatch(key) => SongFilter::Camelot(match parse_key(key) {
+                Some(key) => key.compatible_keys(),
+                None => Vec::new(),
+            }),
+        }
+    }
+}
+
+impl SmartPlaylistRules {
+    /// All the rules combined into one filter according to the match mode
+    pub fn to_filter(&self) -> SongFilter {
+        let filters: Vec<SongFilter> = self.rules.iter().map(|rule| rule.to_filter()).collect();
+        match self.match_mode {
+            MatchMode::All => SongFilter::And(filters),
+            // an Any playlist with no rules would match nothing, which is never what anyone wants
+            MatchMode::Any if filters.is_empty() => SongFilter::And(filters),
+            MatchMode::Any => SongFilter::Or(filters),
+        }
+    }
+
+    pub fn to_yaml(&self) -> String {
+        serde_yaml::to_string(self).unwrap()
+    }
+
+    pub fn from_yaml(yaml: &str) -> Option<Self> {
+        serde_yaml::from_str(yaml).ok()
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           STORAGE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Makes a new smart playlist (a playlists row plus its rules) and returns the playlist_id
+pub fn create_smart_playlist(name: &str, desc: &str, rules: &SmartPlaylistRules) -> String {
+    let created_dt = now_dt();
+    let playlist_id = string_to_hash(format!("{}{}", name, created_dt)).unwrap();
+
+    insert_playlist(PLAYLIST_TABLE_DATA {
+        playlist_id: playlist_id.clone(),
+        playlist_name: name.to_string(),
+        playlist_desc: desc.to_string(),
+        created_dt: created_dt.clone(),
+    });
+
+    insert_smart_playlist(SMART_PLAYLISTS_TABLE_DATA {
+        playlist_id: playlist_id.clone(),
+        rules: rules.to_yaml(),
+        updated_dt: created_dt,
+    });
+
+    playlist_id
+}
+
+/// Overwrites the rules of a playlist. If the playlist wasn't a smart playlist, now it is.
+pub fn set_smart_playlist_rules(playlist_id: &str, rules: &SmartPlaylistRules) {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let updated_dt = now_dt();
+
+    conn.execute(
+        "INSERT OR REPLACE INTO smart_playlists (playlist_id, rules, updated_dt) VALUES (?1, ?2, ?3)",
+        params![playlist_id, rules.to_yaml(), updated_dt],
+    )
+    .unwrap();
+}
+
+/// Gets the rules of a smart playlist, None if the playlist isn't a smart playlist
+pub fn get_smart_playlist_rules(playlist_id: &str) -> Option<SmartPlaylistRules> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+
+    let rules: rusqlite::Result<String> = conn.query_row(
+        "SELECT rules FROM smart_playlists WHERE playlist_id = ?1",
+        [playlist_id],
+        |row| row.get(0),
+    );
+
+    match rules {
+        Ok(rules) => SmartPlaylistRules::from_yaml(&rules),
+        Err(_) => None,
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           EVALUATION
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// the ORDER BY expression for a sort, pushing whatever parameters it needs
+fn compile_sort(sort: &SmartSort, params: &mut Vec<Value>) -> String {
+    let play_count = |within_days: &Option<i64>, params: &mut Vec<Value>| match within_days {
+        Some(days) => {
+            params.push(Value::Text(days_ago(*days)));
+            "(SELECT COUNT(*) FROM plays WHERE plays.song_id = songs.song_id AND plays.start_dt >= ?)".to_string()
+        }
+        None => "(SELECT COUNT(*) FROM plays WHERE plays.song_id = songs.song_id)".to_string(),
+    };
+
+    match sort {
+        SmartSort::MostPlayed { within_days } => format!("{} DESC", play_count(within_days, params)),
+        SmartSort::LeastPlayed { within_days } => format!("{} ASC", play_count(within_days, params)),
+        SmartSort::RecentlyPlayed => {
+            "(SELECT MAX(plays.start_dt) FROM plays WHERE plays.song_id = songs.song_id) DESC".to_string()
+        }
+        SmartSort::RecentlyAdded => {
+            "(SELECT MIN(song_artists.dt_added) FROM song_artists WHERE song_artists.song_id = songs.song_id) DESC"
+                .to_string()
+        }
+        SmartSort::Random => "RANDOM()".to_string(),
+        SmartSort::Column(column, order) => format!("songs.{} {}", column.name(), order.to_sql()),
+    }
+}
+
+/// Compiles the rules into a sql string and the parameters to bind to it
+pub fn compile_smart_playlist(rules: &SmartPlaylistRules) -> (String, Vec<Value>) {
+    let mut query_params: Vec<Value> = Vec::new();
+    let mut sql_query = String::from("SELECT songs.* FROM songs WHERE ");
+    sql_query.push_str(&rules.to_filter().compile(&mut query_params));
+
+    sql_query.push_str(" ORDER BY ");
+    if let Some(sort) = &rules.sort {
+        sql_query.push_str(&compile_sort(sort, &mut query_params));
+        sql_query.push_str(", ");
+    }
+    sql_query.push_str("songs.song_id ASC");
+
+    if let Some(limit) = rules.limit {
+        sql_query.push_str(" LIMIT ?");
+        query_params.push(Value::Integer(limit));
+    }
+
+    (sql_query, query_params)
+}
+
+/// Runs the rules against the database and returns the songs that are in the playlist right now
+pub fn evaluate_smart_playlist(rules: &SmartPlaylistRules) -> Vec<SONG_TABLE_DATA> {
+    let conn = Connection::open(get_database_file_path()).expect("Could not open database");
+    let (sql_query, query_params) = compile_smart_playlist(rules);
+
+    let mut stmt = conn
+        .prepare(&sql_query)
+        .expect("Could not prepare statement");
+
+    let rows = stmt
+        .query_map(params_from_iter(query_params), song_from_row)
+        .unwrap();
+
+    let mut songs: Vec<SONG_TABLE_DATA> = Vec::new();
+    for row in rows {
+        songs.push(row.unwrap());
+    }
+    songs
+}
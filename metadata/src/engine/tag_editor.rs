@@ -0,0 +1,1119 @@
+// Editing the tags of a song and writing them back into the file, in the file's own tag format:
+// * FLAC: vorbis comments, pictures as PICTURE blocks
+// * MP3: ID3v2.4. If the file also has an ID3v1 tag (which is what the MP3 scanner reads titles from) it's kept in step
+// * MP4/M4A: the iTunes style items in moov/udta/meta/ilst
+// The file is never written in place. The tags get written to a copy next to it, which is then renamed over the
+// original, so a crash or a full disk halfway through leaves the old file as it was.
+//
+// edit_song does the file and then the database rows (songs, song_artists, album_artists, composers, genres), so the
+// library shows what's in the file without a rescan. If the rows can't be updated the old file is put back.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::artwork::{image_dimensions, parse_flac_picture, sniff_image_mime, PictureType};
+use crate::engine::artwork_store::{cache_song_artwork, front_cover_color};
+use crate::engine::audio_metadata::{
+    find_audio_payload, mp4_boxes, mp4_child, mp4_ilst, recording_id, tag_padding_bytes,
+    AudioContainer,
+};
+use crate::engine::config::*;
+use base64::Engine;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::other(e.to_string())
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           FIELDS AND CHANGES
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// A tag that can be edited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagField {
+    Title,
+    /// Can have several values, the first is the song's main_artist
+    Artist,
+    Album,
+    AlbumArtist,
+    Composer,
+    Genre,
+    /// Free form, usually YYYY or YYYY-MM-DD
+    Date,
+    TrackNumber,
+    TrackTotal,
+    DiscNumber,
+    DiscTotal,
+    Isrc,
+    Publisher,
+    Barcode,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 14] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::Composer,
+        TagField::Genre,
+        TagField::Date,
+        TagField::TrackNumber,
+        TagField::TrackTotal,
+        TagField::DiscNumber,
+        TagField::DiscTotal,
+        TagField::Isrc,
+        TagField::Publisher,
+        TagField::Barcode,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagField::Title => "title",
+            TagField::Artist => "artist",
+            TagField::Album => "album",
+            TagField::AlbumArtist => "album_artist",
+            TagField::Composer => "composer",
+            TagField::Genre => "genre",
+            TagField::Date => "date",
+            TagField::TrackNumber => "track_number",
+            TagField::TrackTotal => "track_total",
+            TagField::DiscNumber => "disc_number",
+            TagField::DiscTotal => "disc_total",
+            TagField::Isrc => "isrc",
+            TagField::Publisher => "publisher",
+            TagField::Barcode => "barcode",
+        }
+    }
+
+    pub fn from_field(field: &str) -> Option<Self> {
+        TagField::ALL
+            .iter()
+            .find(|found| found.as_str() == field)
+            .copied()
+    }
+
+    /// Whether the field can hold several values
+    pub fn is_list(&self) -> bool {
+        matches!(
+            self,
+            TagField::Artist | TagField::AlbumArtist | TagField::Composer | TagField::Genre
+        )
+    }
+
+    /// Whether the field only takes a positive whole number
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            TagField::TrackNumber
+                | TagField::TrackTotal
+                | TagField::DiscNumber
+                | TagField::DiscTotal
+        )
+    }
+
+    /// The vorbis comment the field is kept in. These are the names the FLAC scanner reads.
+    pub fn vorbis_key(&self) -> &'static str {
+        match self {
+            TagField::Title => "TITLE",
+            TagField::Artist => "ARTIST",
+            TagField::Album => "ALBUM",
+            TagField::AlbumArtist => "ALBUMARTIST",
+            TagField::Composer => "COMPOSER",
+            TagField::Genre => "GENRE",
+            TagField::Date => "DATE",
+            TagField::TrackNumber => "TRACKNUMBER",
+            TagField::TrackTotal => "TRACKTOTAL",
+            TagField::DiscNumber => "DISCNUMBER",
+            TagField::DiscTotal => "DISCTOTAL",
+            TagField::Isrc => "ISRC",
+            TagField::Publisher => "PUBLISHER",
+            TagField::Barcode => "BARCODE",
+        }
+    }
+
+    /// The column of the songs table the field is kept in, if it has one
+    pub fn song_column(&self) -> Option<SongColumn> {
+        match self {
+            TagField::Title => Some(SongColumn::Title),
+            TagField::Artist => Some(SongColumn::MainArtist),
+            TagField::Album => Some(SongColumn::Album),
+            TagField::Date => Some(SongColumn::DateCreated),
+            TagField::TrackNumber => Some(SongColumn::TrackNumber),
+            TagField::TrackTotal => Some(SongColumn::TrackTotal),
+            TagField::DiscNumber => Some(SongColumn::DiscNumber),
+            TagField::DiscTotal => Some(SongColumn::DiscTotal),
+            TagField::Isrc => Some(SongColumn::Isrc),
+            TagField::Publisher => Some(SongColumn::Publisher),
+            TagField::Barcode => Some(SongColumn::Barcode),
+            TagField::AlbumArtist | TagField::Composer | TagField::Genre => None,
+        }
+    }
+
+    /// The (table, name column) that has a row per value of the field, if there is one
+    pub fn list_table(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            TagField::Artist => Some(("song_artists", "artist_name")),
+            TagField::AlbumArtist => Some(("album_artists", "artist_name")),
+            TagField::Composer => Some(("composers", "composer_name")),
+            TagField::Genre => Some(("genres", "genre_name")),
+            _ => None,
+        }
+    }
+}
+
+/// One edit to the tags of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagChange {
+    /// Replaces the values of a field. Fields that aren't lists only take one value.
+    Set(TagField, Vec<String>),
+    Remove(TagField),
+    /// Replaces the front cover, the image format is worked out from the bytes
+    SetCover(Vec<u8>),
+    /// Removes the front cover, other pictures stay
+    RemoveCover,
+}
+
+impl TagChange {
+    /// Set with a single value
+    pub fn set(field: TagField, value: &str) -> Self {
+        TagChange::Set(field, vec![value.to_string()])
+    }
+}
+
+/// Checks changes before anything gets written: numbers have to be numbers, only lists get several values, covers
+/// have to be images. Setting a field to nothing at all is the same as removing it, so that's fine too.
+pub fn validate_changes(changes: &[TagChange]) -> Result<()> {
+    for change in changes {
+        match change {
+            TagChange::Set(field, values) => {
+                if values.len() > 1 && !field.is_list() {
+                    return Err(invalid(format!("{} only takes one value", field.as_str())));
+                }
+                if field.is_number() {
+                    for value in values {
+                        if value.trim().parse::<u32>().is_err() {
+                            return Err(invalid(format!(
+                                "{} has to be a number, not {:?}",
+                                field.as_str(),
+                                value
+                            )));
+                        }
+                    }
+                }
+            }
+            TagChange::SetCover(data) => {
+                if sniff_image_mime(data).is_none() {
+                    return Err(invalid("The cover isn't an image".to_string()));
+                }
+            }
+            TagChange::Remove(_) | TagChange::RemoveCover => {}
+        }
+    }
+    Ok(())
+}
+
+// the values a change sets, trimmed and without empty ones. Empty for removals.
+fn change_values(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           FLAC
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// whether a METADATA_BLOCK_PICTURE comment holds a front cover
+fn is_front_cover_comment(comment: &str) -> bool {
+    match base64::engine::general_purpose::STANDARD.decode(comment.trim()) {
+        Ok(block) => matches!(
+            parse_flac_picture(&block),
+            Some((PictureType::FrontCover, _, _))
+        ),
+        Err(_) => false,
+    }
+}
+
+fn remove_flac_front_cover(tag: &mut metaflac::Tag) {
+    tag.remove_picture_type(metaflac::block::PictureType::CoverFront);
+    let kept: Vec<String> = match tag.get_vorbis("METADATA_BLOCK_PICTURE") {
+        Some(comments) => comments
+            .filter(|comment| !is_front_cover_comment(comment))
+            .map(|comment| comment.to_string())
+            .collect(),
+        None => return,
+    };
+    tag.remove_vorbis("METADATA_BLOCK_PICTURE");
+    if !kept.is_empty() {
+        tag.set_vorbis("METADATA_BLOCK_PICTURE", kept);
+    }
+}
+
+fn write_flac_tags(path: &Path, changes: &[TagChange]) -> Result<()> {
+    let mut tag = metaflac::Tag::read_from_path(path).map_err(|e| invalid(e.to_string()))?;
+    for change in changes {
+        match change {
+            TagChange::Set(field, values) => {
+                let values = change_values(values);
+                tag.remove_vorbis(field.vorbis_key());
+                if !values.is_empty() {
+                    tag.set_vorbis(field.vorbis_key(), values);
+                }
+            }
+            TagChange::Remove(field) => tag.remove_vorbis(field.vorbis_key()),
+            TagChange::SetCover(data) => {
+                remove_flac_front_cover(&mut tag);
+                let (width, height) = image_dimensions(data).unwrap_or((0, 0));
+                let mut picture = metaflac::block::Picture::new();
+                picture.picture_type = metaflac::block::PictureType::CoverFront;
+                picture.mime_type = sniff_image_mime(data).unwrap_or_default();
+                picture.width = width;
+                picture.height = height;
+                picture.data = data.clone();
+                tag.push_block(metaflac::Block::Picture(picture));
+            }
+            TagChange::RemoveCover => remove_flac_front_cover(&mut tag),
+        }
+    }
+    tag.write_to_path(path).map_err(|e| invalid(e.to_string()))
+}
+
+fn read_flac_tags(filepath: &str) -> Result<HashMap<TagField, Vec<String>>> {
+    let tag = metaflac::Tag::read_from_path(filepath).map_err(|e| invalid(e.to_string()))?;
+    let mut tags = HashMap::new();
+    for field in TagField::ALL {
+        if let Some(values) = tag.get_vorbis(field.vorbis_key()) {
+            let values: Vec<String> = values.map(|value| value.to_string()).collect();
+            if !values.is_empty() {
+                tags.insert(field, values);
+            }
+        }
+    }
+    Ok(tags)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MP3
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// the text frame a field is kept in. Track and disc numbers share TRCK and TPOS, barcode is a TXXX.
+fn id3_frame(field: TagField) -> &'static str {
+    match field {
+        TagField::Title => "TIT2",
+        TagField::Artist => "TPE1",
+        TagField::Album => "TALB",
+        TagField::AlbumArtist => "TPE2",
+        TagField::Composer => "TCOM",
+        TagField::Genre => "TCON",
+        TagField::Date => "TDRC",
+        TagField::TrackNumber | TagField::TrackTotal => "TRCK",
+        TagField::DiscNumber | TagField::DiscTotal => "TPOS",
+        TagField::Isrc => "TSRC",
+        TagField::Publisher => "TPUB",
+        TagField::Barcode => "TXXX",
+    }
+}
+
+fn set_id3_field(tag: &mut id3::Tag, field: TagField, values: Vec<String>) {
+    use id3::TagLike;
+
+    let number = values.first().and_then(|value| value.parse::<u32>().ok());
+    match (field, number) {
+        (TagField::TrackNumber, Some(number)) => tag.set_track(number),
+        (TagField::TrackNumber, None) => tag.remove_track(),
+        (TagField::TrackTotal, Some(number)) => tag.set_total_tracks(number),
+        (TagField::TrackTotal, None) => tag.remove_total_tracks(),
+        (TagField::DiscNumber, Some(number)) => tag.set_disc(number),
+        (TagField::DiscNumber, None) => tag.remove_disc(),
+        (TagField::DiscTotal, Some(number)) => tag.set_total_discs(number),
+        (TagField::DiscTotal, None) => tag.remove_total_discs(),
+        (TagField::Barcode, _) => {
+            tag.remove_extended_text(Some("BARCODE"), None);
+            if let Some(barcode) = values.first() {
+                tag.add_frame(id3::frame::ExtendedText {
+                    description: "BARCODE".to_string(),
+                    value: barcode.clone(),
+                });
+            }
+        }
+        _ => {
+            tag.remove(id3_frame(field));
+            if !values.is_empty() {
+                // ID3v2.4 keeps several values in one frame, separated by \0
+                tag.set_text_values(id3_frame(field), values);
+            }
+        }
+    }
+}
+
+// ID3v1 fields are Latin-1, zero padded
+fn id3v1_field(value: &str, length: usize) -> Vec<u8> {
+    let mut bytes: Vec<u8> = value
+        .chars()
+        .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+        .take(length)
+        .collect();
+    bytes.resize(length, 0);
+    bytes
+}
+
+// brings the fields an ID3v1 tag has in line with the changes. The genre is left alone, ID3v1 can only pick one from
+// a fixed list.
+fn sync_id3v1(path: &Path, changes: &[TagChange]) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let length = file.metadata()?.len();
+    if length < 128 {
+        return Ok(());
+    }
+    let mut tag = [0u8; 128];
+    file.seek(SeekFrom::Start(length - 128))?;
+    file.read_exact(&mut tag)?;
+    if &tag[..3] != b"TAG" {
+        return Ok(());
+    }
+
+    for change in changes {
+        let (field, values) = match change {
+            TagChange::Set(field, values) => (*field, change_values(values)),
+            TagChange::Remove(field) => (*field, Vec::new()),
+            _ => continue,
+        };
+        let value = values.first().cloned().unwrap_or_default();
+        match field {
+            TagField::Title => tag[3..33].copy_from_slice(&id3v1_field(&value, 30)),
+            TagField::Artist => tag[33..63].copy_from_slice(&id3v1_field(&value, 30)),
+            TagField::Album => tag[63..93].copy_from_slice(&id3v1_field(&value, 30)),
+            TagField::Date => tag[93..97].copy_from_slice(&id3v1_field(&value, 4)),
+            // ID3v1.1: a 0 before the last byte of the comment makes that byte the track number
+            TagField::TrackNumber => {
+                tag[125] = 0;
+                tag[126] = value.parse::<u8>().unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    file.seek(SeekFrom::Start(length - 128))?;
+    file.write_all(&tag)
+}
+
+fn write_mp3_tags(path: &Path, changes: &[TagChange]) -> Result<()> {
+    use id3::TagLike;
+
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    for change in changes {
+        match change {
+            TagChange::Set(field, values) => set_id3_field(&mut tag, *field, change_values(values)),
+            TagChange::Remove(field) => set_id3_field(&mut tag, *field, Vec::new()),
+            TagChange::SetCover(data) => {
+                tag.remove_picture_by_type(id3::frame::PictureType::CoverFront);
+                tag.add_frame(id3::frame::Picture {
+                    mime_type: sniff_image_mime(data).unwrap_or_default(),
+                    picture_type: id3::frame::PictureType::CoverFront,
+                    description: String::new(),
+                    data: data.clone(),
+                });
+            }
+            TagChange::RemoveCover => {
+                tag.remove_picture_by_type(id3::frame::PictureType::CoverFront)
+            }
+        }
+    }
+    tag.write_to_path(path, id3::Version::Id3v24)
+        .map_err(|e| invalid(e.to_string()))?;
+    sync_id3v1(path, changes)
+}
+
+fn read_mp3_tags(filepath: &str) -> Result<HashMap<TagField, Vec<String>>> {
+    use id3::TagLike;
+
+    let tag = match id3::Tag::read_from_path(filepath) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => return Ok(HashMap::new()),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    let mut tags = HashMap::new();
+    for field in TagField::ALL {
+        let values: Vec<String> = match field {
+            TagField::TrackNumber => tag.track().map(|n| n.to_string()).into_iter().collect(),
+            TagField::TrackTotal => tag
+                .total_tracks()
+                .map(|n| n.to_string())
+                .into_iter()
+                .collect(),
+            TagField::DiscNumber => tag.disc().map(|n| n.to_string()).into_iter().collect(),
+            TagField::DiscTotal => tag
+                .total_discs()
+                .map(|n| n.to_string())
+                .into_iter()
+                .collect(),
+            TagField::Barcode => tag
+                .extended_texts()
+                .filter(|text| text.description == "BARCODE")
+                .map(|text| text.value.clone())
+                .collect(),
+            _ => match tag.text_values_for_frame_id(id3_frame(field)) {
+                Some(values) => values.into_iter().map(|value| value.to_string()).collect(),
+                None => Vec::new(),
+            },
+        };
+        if !values.is_empty() {
+            tags.insert(field, values);
+        }
+    }
+    Ok(tags)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           MP4
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// the ilst item a field is kept in. Fields iTunes has no item for are freeform ----:com.apple.iTunes:NAME items.
+fn mp4_item(field: TagField) -> &'static [u8] {
+    match field {
+        TagField::Title => "\u{a9}nam".as_bytes(),
+        TagField::Artist => "\u{a9}ART".as_bytes(),
+        TagField::Album => "\u{a9}alb".as_bytes(),
+        TagField::AlbumArtist => b"aART",
+        TagField::Composer => "\u{a9}wrt".as_bytes(),
+        TagField::Genre => "\u{a9}gen".as_bytes(),
+        TagField::Date => "\u{a9}day".as_bytes(),
+        TagField::TrackNumber | TagField::TrackTotal => b"trkn",
+        TagField::DiscNumber | TagField::DiscTotal => b"disk",
+        TagField::Isrc => b"ISRC",
+        TagField::Publisher => b"LABEL",
+        TagField::Barcode => b"BARCODE",
+    }
+}
+
+fn is_freeform(field: TagField) -> bool {
+    matches!(
+        field,
+        TagField::Isrc | TagField::Publisher | TagField::Barcode
+    )
+}
+
+// the ilst item kinds are MacRoman, © is 0xA9 there
+fn mp4_kind(field: TagField) -> Vec<u8> {
+    let item = mp4_item(field);
+    if item.starts_with("\u{a9}".as_bytes()) {
+        let mut kind = vec![0xA9];
+        kind.extend_from_slice(&item[2..]);
+        kind
+    } else {
+        item.to_vec()
+    }
+}
+
+// a box too big for a 32 bit size gets a size of 1 and the real size in 64 bits after the type
+fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(body.len() + 16);
+    match u32::try_from(body.len() + 8) {
+        Ok(size) => {
+            bytes.extend_from_slice(&size.to_be_bytes());
+            bytes.extend_from_slice(kind);
+        }
+        Err(_) => {
+            bytes.extend_from_slice(&1u32.to_be_bytes());
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(&(body.len() as u64 + 16).to_be_bytes());
+        }
+    }
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+// (type, the whole box, its body)
+type Mp4Box<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+// every box in `bytes`. mp4_boxes stops at a box that doesn't fit, and rebuilding from that would drop the rest of
+// the file, so here it's an error.
+fn mp4_boxes_exact(bytes: &[u8]) -> Result<Vec<Mp4Box<'_>>> {
+    let mut boxes = Vec::new();
+    let mut start = 0;
+    for (kind, body) in mp4_boxes(bytes) {
+        let end = body.as_ptr() as usize - bytes.as_ptr() as usize + body.len();
+        boxes.push((kind, &bytes[start..end], body));
+        start = end;
+    }
+    if start != bytes.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Broken MP4 box at byte {} of {}", start, bytes.len()),
+        ));
+    }
+    Ok(boxes)
+}
+
+// a data box: 4 bytes of type (1 is UTF-8, 0 binary, 13 JPEG, 14 PNG), 4 of locale, then the value
+fn mp4_data(data_type: u32, value: &[u8]) -> Vec<u8> {
+    let mut body = data_type.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0; 4]);
+    body.extend_from_slice(value);
+    mp4_box(b"data", &body)
+}
+
+// the name of a freeform item, None for the other kinds
+fn freeform_name(item: &[u8]) -> Option<&[u8]> {
+    mp4_child(item, b"name").and_then(|name| name.get(4..))
+}
+
+fn freeform_item(name: &[u8], values: &[String]) -> Vec<u8> {
+    let mut body = mp4_box(b"mean", b"\0\0\0\0com.apple.iTunes");
+    let mut named = vec![0u8; 4];
+    named.extend_from_slice(name);
+    body.extend(mp4_box(b"name", &named));
+    for value in values {
+        body.extend(mp4_data(1, value.as_bytes()));
+    }
+    mp4_box(b"----", &body)
+}
+
+// trkn and disk hold (number, total) as 16 bit numbers after 2 bytes of padding
+fn mp4_pair(item: Option<&[u8]>) -> (u16, u16) {
+    let value = match item.and_then(|item| mp4_child(item, b"data")) {
+        Some(data) if data.len() >= 14 => &data[8..],
+        _ => return (0, 0),
+    };
+    (
+        u16::from_be_bytes([value[2], value[3]]),
+        u16::from_be_bytes([value[4], value[5]]),
+    )
+}
+
+// the ilst items after the changes, as (kind, whole box)
+fn edit_ilst_items(ilst: &[u8], changes: &[TagChange]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = mp4_boxes_exact(ilst)?
+        .into_iter()
+        .map(|(kind, _, body)| (kind.to_vec(), mp4_box(kind, body)))
+        .collect();
+    let find = |items: &Vec<(Vec<u8>, Vec<u8>)>, field: TagField| -> Option<usize> {
+        items.iter().position(|(kind, item)| {
+            if is_freeform(field) {
+                kind.as_slice() == b"----" && freeform_name(&item[8..]) == Some(mp4_item(field))
+            } else {
+                *kind == mp4_kind(field)
+            }
+        })
+    };
+
+    for change in changes {
+        let (field, values) = match change {
+            TagChange::Set(field, values) => (*field, change_values(values)),
+            TagChange::Remove(field) => (*field, Vec::new()),
+            TagChange::SetCover(data) => {
+                // covr only knows JPEG, PNG and BMP, anything else is stored as binary
+                let data_type = match sniff_image_mime(data).as_deref() {
+                    Some("image/jpeg") => 13,
+                    Some("image/png") => 14,
+                    Some("image/bmp") => 27,
+                    _ => 0,
+                };
+                items.retain(|(kind, _)| kind.as_slice() != b"covr");
+                items.push((
+                    b"covr".to_vec(),
+                    mp4_box(b"covr", &mp4_data(data_type, data)),
+                ));
+                continue;
+            }
+            TagChange::RemoveCover => {
+                items.retain(|(kind, _)| kind.as_slice() != b"covr");
+                continue;
+            }
+        };
+
+        let existing = find(&items, field);
+        let new_item = match field {
+            TagField::TrackNumber
+            | TagField::TrackTotal
+            | TagField::DiscNumber
+            | TagField::DiscTotal => {
+                let (mut number, mut total) = mp4_pair(existing.map(|index| &items[index].1[8..]));
+                let value = values
+                    .first()
+                    .and_then(|value| value.parse::<u16>().ok())
+                    .unwrap_or(0);
+                match field {
+                    TagField::TrackNumber | TagField::DiscNumber => number = value,
+                    _ => total = value,
+                }
+                if number == 0 && total == 0 {
+                    None
+                } else {
+                    let mut pair = vec![0, 0];
+                    pair.extend_from_slice(&number.to_be_bytes());
+                    pair.extend_from_slice(&total.to_be_bytes());
+                    // trkn has 2 more bytes of padding, disk doesn't
+                    if mp4_item(field) == b"trkn" {
+                        pair.extend_from_slice(&[0, 0]);
+                    }
+                    Some(mp4_box(mp4_item(field), &mp4_data(0, &pair)))
+                }
+            }
+            _ if values.is_empty() => None,
+            _ if is_freeform(field) => Some(freeform_item(mp4_item(field), &values)),
+            _ => {
+                let body: Vec<u8> = values
+                    .iter()
+                    .flat_map(|value| mp4_data(1, value.as_bytes()))
+                    .collect();
+                Some(mp4_box(&mp4_kind(field), &body))
+            }
+        };
+
+        let kind = if is_freeform(field) {
+            b"----".to_vec()
+        } else {
+            mp4_kind(field)
+        };
+        match (existing, new_item) {
+            (Some(index), Some(item)) => items[index] = (kind, item),
+            (Some(index), None) => {
+                items.remove(index);
+            }
+            (None, Some(item)) => items.push((kind, item)),
+            (None, None) => {}
+        }
+    }
+    Ok(items)
+}
+
+// replaces (or adds) the child box `kind` of a container body
+fn replace_child(body: &[u8], kind: &[u8], child: Vec<u8>) -> Result<Vec<u8>> {
+    let mut replaced = false;
+    let mut new_body = Vec::new();
+    for (found, whole, _) in mp4_boxes_exact(body)? {
+        if found == kind && !replaced {
+            new_body.extend_from_slice(&child);
+            replaced = true;
+        } else {
+            new_body.extend_from_slice(whole);
+        }
+    }
+    if !replaced {
+        new_body.extend(child);
+    }
+    Ok(new_body)
+}
+
+// adds `delta` to every chunk offset in the stco and co64 boxes of a moov body, for when the moov in front of the
+// mdat changes size
+fn shift_chunk_offsets(container: &mut [u8], delta: i64) {
+    let mut pos = 0;
+    while pos + 8 <= container.len() {
+        let size = u32::from_be_bytes(container[pos..pos + 4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, container.len() - pos),
+            1 if pos + 16 <= container.len() => (
+                16,
+                u64::from_be_bytes(container[pos + 8..pos + 16].try_into().unwrap()) as usize,
+            ),
+            _ => (8, size),
+        };
+        if size < header || pos + size > container.len() {
+            return;
+        }
+        let kind: [u8; 4] = container[pos + 4..pos + 8].try_into().unwrap();
+        let body = &mut container[pos + header..pos + size];
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(body, delta),
+            b"stco" | b"co64" if body.len() >= 8 => {
+                let entries = u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize;
+                let width = if &kind == b"stco" { 4 } else { 8 };
+                for entry in 0..entries {
+                    let at = 8 + entry * width;
+                    if at + width > body.len() {
+                        break;
+                    }
+                    if width == 4 {
+                        let offset =
+                            u32::from_be_bytes(body[at..at + 4].try_into().unwrap()) as i64;
+                        body[at..at + 4].copy_from_slice(&((offset + delta) as u32).to_be_bytes());
+                    } else {
+                        let offset =
+                            u64::from_be_bytes(body[at..at + 8].try_into().unwrap()) as i64;
+                        body[at..at + 8].copy_from_slice(&((offset + delta) as u64).to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+}
+
+fn write_mp4_tags(path: &Path, changes: &[TagChange]) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    let top = mp4_boxes_exact(&bytes)?;
+    let moov = mp4_child(&bytes, b"moov")
+        .ok_or_else(|| invalid("The file has no moov box".to_string()))?;
+    let udta = mp4_child(moov, b"udta").unwrap_or(&[]);
+    let meta = mp4_child(udta, b"meta").unwrap_or(&[0, 0, 0, 0]);
+    let ilst = mp4_ilst(&bytes).unwrap_or(&[]);
+
+    let items: Vec<u8> = edit_ilst_items(ilst, changes)?
+        .into_iter()
+        .flat_map(|(_, item)| item)
+        .collect();
+    // meta is a full box, and has to have an hdlr saying it's iTunes metadata
+    let mut meta_children = meta.get(4..).unwrap_or(&[]).to_vec();
+    if mp4_child(&meta_children, b"hdlr").is_none() {
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(b"mdirappl");
+        hdlr.extend_from_slice(&[0; 9]);
+        meta_children = [mp4_box(b"hdlr", &hdlr), meta_children].concat();
+    }
+    let meta_children = replace_child(&meta_children, b"ilst", mp4_box(b"ilst", &items))?;
+    let mut new_meta = meta[..4.min(meta.len())].to_vec();
+    new_meta.extend(meta_children);
+    let new_udta = replace_child(udta, b"meta", mp4_box(b"meta", &new_meta))?;
+    let mut new_moov = replace_child(moov, b"udta", mp4_box(b"udta", &new_udta))?;
+
+    // the audio moves if the moov is in front of it
+    let moov_index = top
+        .iter()
+        .position(|(kind, _, _)| *kind == b"moov")
+        .unwrap();
+    let mdat_index = top.iter().position(|(kind, _, _)| *kind == b"mdat");
+    if matches!(mdat_index, Some(mdat_index) if mdat_index > moov_index) {
+        let delta = mp4_box(b"moov", &new_moov).len() as i64 - top[moov_index].1.len() as i64;
+        shift_chunk_offsets(&mut new_moov, delta);
+    }
+
+    // everything but the moov is copied as it was, headers included
+    let mut file = Vec::with_capacity(bytes.len() + new_moov.len());
+    for (kind, whole, _) in top {
+        if kind == b"moov" {
+            file.extend(mp4_box(b"moov", &new_moov));
+        } else {
+            file.extend_from_slice(whole);
+        }
+    }
+    std::fs::write(path, file)
+}
+
+fn read_mp4_tags(filepath: &str) -> Result<HashMap<TagField, Vec<String>>> {
+    let bytes = std::fs::read(filepath)?;
+    let ilst = mp4_ilst(&bytes).unwrap_or(&[]);
+    let items = mp4_boxes(ilst);
+    let mut tags = HashMap::new();
+    for field in TagField::ALL {
+        let item = items.iter().find(|(kind, item)| {
+            if is_freeform(field) {
+                *kind == b"----" && freeform_name(item) == Some(mp4_item(field))
+            } else {
+                *kind == mp4_kind(field).as_slice()
+            }
+        });
+        let item = match item {
+            Some((_, item)) => *item,
+            None => continue,
+        };
+        let values: Vec<String> = match field {
+            TagField::TrackNumber | TagField::DiscNumber => {
+                vec![mp4_pair(Some(item)).0.to_string()]
+            }
+            TagField::TrackTotal | TagField::DiscTotal => vec![mp4_pair(Some(item)).1.to_string()],
+            _ => mp4_boxes(item)
+                .into_iter()
+                .filter(|(kind, data)| *kind == b"data" && data.len() >= 8)
+                .map(|(_, data)| String::from_utf8_lossy(&data[8..]).to_string())
+                .collect(),
+        };
+        let values: Vec<String> = values.into_iter().filter(|value| value != "0").collect();
+        if !values.is_empty() {
+            tags.insert(field, values);
+        }
+    }
+    Ok(tags)
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           WRITING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+// the copy that gets written, hidden next to the file so the rename stays on the same filesystem
+fn temp_path(filepath: &Path) -> PathBuf {
+    let name = filepath
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    filepath.with_file_name(format!(".{}.decibl-tmp", name))
+}
+
+/// The file as it was before an edit, kept next to it until the database has caught up. A hard link where the
+/// filesystem has them (write_atomically renames a new file over the old one, so the link keeps the old one), a copy
+/// where it doesn't.
+struct FileBackup {
+    filepath: PathBuf,
+    backup: PathBuf,
+}
+
+impl FileBackup {
+    fn new(filepath: &str) -> Result<Self> {
+        let filepath = PathBuf::from(filepath);
+        let name = filepath
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backup = filepath.with_file_name(format!(".{}.decibl-bak", name));
+        let _ = std::fs::remove_file(&backup);
+        if std::fs::hard_link(&filepath, &backup).is_err() {
+            std::fs::copy(&filepath, &backup)?;
+        }
+        Ok(FileBackup { filepath, backup })
+    }
+
+    /// Puts the old file back
+    fn restore(self) -> Result<()> {
+        std::fs::rename(&self.backup, &self.filepath)
+    }
+
+    fn discard(self) {
+        let _ = std::fs::remove_file(&self.backup);
+    }
+}
+
+// puts every file back, even when one of them can't be, and returns the first error
+fn restore_all(backups: Vec<FileBackup>) -> Result<()> {
+    backups
+        .into_iter()
+        .map(FileBackup::restore)
+        .fold(Ok(()), Result::and)
+}
+
+/// Copies a file next to itself, lets `write` change the copy, then renames the copy over the file. If anything
+/// fails the copy is deleted and the file is left as it was.
+pub fn write_atomically<F: FnOnce(&Path) -> Result<()>>(filepath: &str, write: F) -> Result<()> {
+    let filepath = Path::new(filepath);
+    let temp = temp_path(filepath);
+    std::fs::copy(filepath, &temp)?;
+
+    let written = write(&temp).and_then(|_| std::fs::File::open(&temp)?.sync_all());
+    match written.and_then(|_| std::fs::rename(&temp, filepath)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Writes changes into the tags of a file, in the file's own format (sniffed from the file, not the extension).
+/// Nothing is written if any change is invalid. The database isn't touched, see edit_song for that.
+/// ```no_run
+/// # use decibl_metadata::engine::tag_editor::*;
+/// write_tags(
+///     "song.flac",
+///     &[
+///         TagChange::set(TagField::Title, "Enemy"),
+///         TagChange::Set(TagField::Artist, vec!["Imagine Dragons".to_string(), "JID".to_string()]),
+///         TagChange::Remove(TagField::Barcode),
+///     ],
+/// )
+/// .unwrap();
+/// ```
+pub fn write_tags(filepath: &str, changes: &[TagChange]) -> Result<()> {
+    validate_changes(changes)?;
+    let container = find_audio_payload(filepath)?.container;
+    write_atomically(filepath, |path| match container {
+        AudioContainer::Flac => write_flac_tags(path, changes),
+        AudioContainer::Mpeg => write_mp3_tags(path, changes),
+        AudioContainer::Mp4 => write_mp4_tags(path, changes),
+        AudioContainer::Unknown => Err(invalid(format!(
+            "Don't know how to write tags to {}",
+            filepath
+        ))),
+    })
+}
+
+/// The editable tags a file has, read straight from its native tags. Fields it doesn't have are left out.
+pub fn read_tags(filepath: &str) -> Result<HashMap<TagField, Vec<String>>> {
+    match find_audio_payload(filepath)?.container {
+        AudioContainer::Flac => read_flac_tags(filepath),
+        AudioContainer::Mpeg => read_mp3_tags(filepath),
+        AudioContainer::Mp4 => read_mp4_tags(filepath),
+        AudioContainer::Unknown => Err(invalid(format!(
+            "Don't know how to read tags of {}",
+            filepath
+        ))),
+    }
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           DATABASE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Makes the database rows of a song match tag changes: its columns in songs and its rows in song_artists,
+/// album_artists, composers and genres. Cover changes are left to edit_song, they need the file.
+pub fn update_song_tags(
+    conn: &Connection,
+    song_id: &str,
+    changes: &[TagChange],
+) -> rusqlite::Result<()> {
+    for change in changes {
+        let (field, values) = match change {
+            TagChange::Set(field, values) => (*field, change_values(values)),
+            TagChange::Remove(field) => (*field, Vec::new()),
+            TagChange::SetCover(_) | TagChange::RemoveCover => continue,
+        };
+
+        if let Some(column) = field.song_column() {
+            let value = match (field.is_number(), values.first()) {
+                (true, Some(value)) => Value::Integer(value.parse::<i64>().unwrap_or(-1)),
+                (true, None) => Value::Integer(-1),
+                (false, Some(value)) => Value::Text(value.clone()),
+                (false, None) => Value::Text("".to_string()),
+            };
+            conn.execute(
+                &format!("UPDATE songs SET {} = ?1 WHERE song_id = ?2", column.name()),
+                params![value, song_id],
+            )?;
+        }
+
+        // a MusicBrainz recording id wins over the ISRC, see audio_metadata::recording_id
+        if field == TagField::Isrc {
+            let isrc = values.first().cloned().unwrap_or_default();
+            conn.execute(
+                "UPDATE songs SET recording_id = ?1 WHERE song_id = ?2 AND recording_id NOT LIKE 'musicbrainz:%'",
+                params![recording_id("", &isrc), song_id],
+            )?;
+        }
+
+        if let Some((table, name_column)) = field.list_table() {
+            // the new rows keep the date the old ones were added (the song's own date added is songs.dt_added)
+            let dt_added: String = conn
+                .query_row(
+                    &format!("SELECT MIN(dt_added) FROM {} WHERE song_id = ?1", table),
+                    [song_id],
+                    |row| row.get::<_, Option<String>>(0),
+                )?
+                .unwrap_or_else(now_dt);
+            conn.execute(
+                &format!("DELETE FROM {} WHERE song_id = ?1", table),
+                [song_id],
+            )?;
+            for value in &values {
+                conn.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO {} ({}, song_id, dt_added) VALUES (?1, ?2, ?3)",
+                        table, name_column
+                    ),
+                    params![value, song_id, dt_added],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Updates the file size and tag padding of a song after its file was written
+pub fn update_song_file(conn: &Connection, song_id: &str, filepath: &str) -> Result<()> {
+    let filesize = std::fs::metadata(filepath)?.len() as i64;
+    let padding = match tag_padding_bytes(filepath) {
+        Ok(padding) => padding as i64,
+        Err(_) => -1,
+    };
+    conn.execute(
+        "UPDATE songs SET filesize_bytes = ?1, padding_bytes = ?2 WHERE song_id = ?3",
+        params![filesize, padding, song_id],
+    )
+    .map_err(db_error)?;
+    Ok(())
+}
+
+/// Edits the tags of a song: writes them into its files (see write_tags) and updates its database rows to match,
+/// along with its file size, padding and, for cover changes, its cover colour and artwork links.
+/// Files with the same audio share a song, so every one of them gets the edit. The song_id is the hash of the audio,
+/// so it stays the same. If anything fails the files are put back the way they were.
+pub fn edit_song(song_id: &str, changes: &[TagChange]) -> Result<()> {
+    let filepaths: Vec<String> = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .map(|songpath| songpath.song_path)
+        .collect();
+    if filepaths.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} has no file", song_id),
+        ));
+    }
+    let mut conn = Connection::open(get_database_file_path()).map_err(db_error)?;
+
+    let mut backups = Vec::new();
+    for filepath in &filepaths {
+        let written =
+            FileBackup::new(filepath).and_then(|backup| match write_tags(filepath, changes) {
+                Ok(()) => Ok(backup),
+                Err(e) => {
+                    backup.discard();
+                    Err(e)
+                }
+            });
+        match written {
+            Ok(backup) => backups.push(backup),
+            Err(e) => {
+                restore_all(backups)?;
+                return Err(e);
+            }
+        }
+    }
+
+    let cover_changed = changes
+        .iter()
+        .any(|change| matches!(change, TagChange::SetCover(_) | TagChange::RemoveCover));
+    let updated = (|| -> Result<()> {
+        let tx = conn.transaction().map_err(db_error)?;
+        update_song_tags(&tx, song_id, changes).map_err(db_error)?;
+        update_song_file(&tx, song_id, &filepaths[0])?;
+        if cover_changed {
+            tx.execute(
+                "UPDATE songs SET album_artwork_colors = ?1 WHERE song_id = ?2",
+                params![front_cover_color(&filepaths[0]), song_id],
+            )
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)
+    })();
+    // the rows weren't updated, so the files go back to what they describe
+    if let Err(e) = updated {
+        restore_all(backups)?;
+        return Err(e);
+    }
+    for backup in backups {
+        backup.discard();
+    }
+
+    if cover_changed {
+        cache_song_artwork(song_id, &filepaths[0]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,319 @@
+// Waveforms for the seek bar. A song gets decoded once and boiled down to a min and a max sample for every point
+// on the bar, either per channel or with the channels mixed together.
+//
+// Waveforms are cached under get_waveform_path(), a folder per song_id with a file per resolution and mode.
+// Every file remembers the audio_hash it was made from, so a cache built before the audio changed gets thrown away.
+
+use crate::engine::analyticsdb::*;
+use crate::engine::audio_metadata::audio_hash_or_file_hash;
+use crate::engine::config::*;
+use crate::engine::decoder::AudioDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::Result;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+
+/// Frames get boiled down to blocks this long while decoding, then the blocks get merged down to the resolution.
+/// Small enough that even a 30 second song has more blocks than a seek bar has pixels.
+const BLOCK_FRAMES: usize = 256;
+
+// the start of every cache file, then a version in case the format ever changes
+const MAGIC: &[u8; 4] = b"DBWF";
+const FORMAT_VERSION: u8 = 1;
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           GENERATING
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+/// Whether a waveform has the channels mixed into one or kept apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WaveformMode {
+    Mixed,
+    PerChannel,
+}
+
+impl WaveformMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WaveformMode::Mixed => "mixed",
+            WaveformMode::PerChannel => "channels",
+        }
+    }
+}
+
+/// The peaks of one channel (or the mix), one min and one max for every point, from -1.0 to 1.0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveformPeaks {
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    /// The audio_hash of the file the waveform was made from
+    pub audio_hash: String,
+    pub mode: WaveformMode,
+    pub sample_rate: u32,
+    /// In seconds
+    pub duration: f64,
+    /// One for every channel, or just the one with WaveformMode::Mixed
+    pub channels: Vec<WaveformPeaks>,
+}
+
+impl Waveform {
+    /// How many points there are
+    pub fn resolution(&self) -> usize {
+        match self.channels.first() {
+            Some(peaks) => peaks.min.len(),
+            None => 0,
+        }
+    }
+}
+
+// peaks are stored as 16 bit ints, so generated waveforms get rounded the same way to match what comes out of the cache
+fn quantize(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn dequantize(sample: i16) -> f32 {
+    sample as f32 / i16::MAX as f32
+}
+
+// merges the blocks down to `resolution` points, a point gets at least one block even when there are fewer blocks
+fn downsample(blocks: &[(f32, f32)], resolution: usize) -> WaveformPeaks {
+    let mut peaks = WaveformPeaks {
+        min: vec![0.0; resolution],
+        max: vec![0.0; resolution],
+    };
+    if blocks.is_empty() {
+        return peaks;
+    }
+    for point in 0..resolution {
+        let start = point * blocks.len() / resolution;
+        let end = ((point + 1) * blocks.len() / resolution).max(start + 1);
+        let (min, max) = blocks[start..end]
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), block| {
+                (min.min(block.0), max.max(block.1))
+            });
+        peaks.min[point] = dequantize(quantize(min));
+        peaks.max[point] = dequantize(quantize(max));
+    }
+    peaks
+}
+
+/// Decodes a file and makes a waveform with `resolution` points, remembering `audio_hash` (the song's audio_hash
+/// column) as what it was made from. None if it can't be decoded.
+/// ```no_run
+/// # use decibl_metadata::engine::waveform::*;
+/// let waveform = generate_waveform("/music/song.flac", "AUDIO HASH", 800, WaveformMode::Mixed).unwrap();
+/// println!("{:?}", waveform.channels[0].max);
+/// ```
+pub fn generate_waveform(
+    filepath: &str,
+    audio_hash: &str,
+    resolution: usize,
+    mode: WaveformMode,
+) -> Option<Waveform> {
+    let mut decoder = AudioDecoder::open(
+        filepath,
+        FormatOptions::default(),
+        DecoderOptions::default(),
+    )?;
+    let sample_rate = decoder.sample_rate()?;
+    let channels = decoder.channels()?;
+
+    let outputs = match mode {
+        WaveformMode::Mixed => 1,
+        WaveformMode::PerChannel => channels,
+    };
+    // (min, max) of every block for every output
+    let mut blocks: Vec<Vec<(f32, f32)>> = vec![Vec::new(); outputs];
+    let mut current: Vec<(f32, f32)> = vec![(f32::MAX, f32::MIN); outputs];
+    let mut block_frames = 0;
+    let mut total_frames: u64 = 0;
+
+    while let Some(samples) = decoder.next_samples() {
+        for frame in samples.chunks_exact(channels) {
+            match mode {
+                WaveformMode::Mixed => {
+                    let mixed = frame.iter().sum::<f32>() / channels as f32;
+                    current[0] = (current[0].0.min(mixed), current[0].1.max(mixed));
+                }
+                WaveformMode::PerChannel => {
+                    for (channel, sample) in frame.iter().enumerate() {
+                        current[channel] = (
+                            current[channel].0.min(*sample),
+                            current[channel].1.max(*sample),
+                        );
+                    }
+                }
+            }
+            block_frames += 1;
+            total_frames += 1;
+            if block_frames == BLOCK_FRAMES {
+                for (output, block) in current.iter_mut().enumerate() {
+                    blocks[output].push(*block);
+                    *block = (f32::MAX, f32::MIN);
+                }
+                block_frames = 0;
+            }
+        }
+    }
+    if block_frames > 0 {
+        for (output, block) in current.into_iter().enumerate() {
+            blocks[output].push(block);
+        }
+    }
+
+    Some(Waveform {
+        audio_hash: audio_hash.to_string(),
+        mode,
+        sample_rate,
+        duration: total_frames as f64 / sample_rate as f64,
+        channels: blocks
+            .iter()
+            .map(|blocks| downsample(blocks, resolution))
+            .collect(),
+    })
+}
+
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+//                                                           CACHE
+// --------------------------------------------------------------------------------------------------------------------------------------------
+// --------------------------------------------------------------------------------------------------------------------------------------------
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Packs a waveform into the cache format:
+/// "DBWF", format version (u8), mode (u8, 0 mixed 1 per channel), channel count (u8), 0 (u8),
+/// resolution (u32), sample_rate (u32), duration (f64), audio_hash length (u16), the audio_hash,
+/// then for every channel every point as a min and a max (i16). Little endian all the way.
+pub fn waveform_to_bytes(waveform: &Waveform) -> Vec<u8> {
+    let resolution = waveform.resolution();
+    let mut bytes: Vec<u8> = Vec::with_capacity(64 + waveform.channels.len() * resolution * 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.push(match waveform.mode {
+        WaveformMode::Mixed => 0,
+        WaveformMode::PerChannel => 1,
+    });
+    bytes.push(waveform.channels.len() as u8);
+    bytes.push(0);
+    bytes.extend_from_slice(&(resolution as u32).to_le_bytes());
+    bytes.extend_from_slice(&waveform.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&waveform.duration.to_le_bytes());
+    bytes.extend_from_slice(&(waveform.audio_hash.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(waveform.audio_hash.as_bytes());
+    for peaks in &waveform.channels {
+        for (min, max) in peaks.min.iter().zip(peaks.max.iter()) {
+            bytes.extend_from_slice(&quantize(*min).to_le_bytes());
+            bytes.extend_from_slice(&quantize(*max).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Unpacks a waveform packed by waveform_to_bytes
+pub fn waveform_from_bytes(bytes: &[u8]) -> Result<Waveform> {
+    let take = |start: usize, length: usize| -> Result<&[u8]> {
+        bytes
+            .get(start..start + length)
+            .ok_or_else(|| invalid_data("waveform is cut off"))
+    };
+
+    if take(0, 4)? != MAGIC || take(4, 1)?[0] != FORMAT_VERSION {
+        return Err(invalid_data("not a waveform this version can read"));
+    }
+    let mode = match take(5, 1)?[0] {
+        0 => WaveformMode::Mixed,
+        1 => WaveformMode::PerChannel,
+        _ => return Err(invalid_data("unknown waveform mode")),
+    };
+    let channels = take(6, 1)?[0] as usize;
+    let resolution = u32::from_le_bytes(take(8, 4)?.try_into().unwrap()) as usize;
+    let sample_rate = u32::from_le_bytes(take(12, 4)?.try_into().unwrap());
+    let duration = f64::from_le_bytes(take(16, 8)?.try_into().unwrap());
+    let hash_length = u16::from_le_bytes(take(24, 2)?.try_into().unwrap()) as usize;
+    let audio_hash = String::from_utf8_lossy(take(26, hash_length)?).to_string();
+
+    let mut position = 26 + hash_length;
+    let mut peaks: Vec<WaveformPeaks> = Vec::with_capacity(channels);
+    for _ in 0..channels {
+        let points = take(position, resolution * 4)?;
+        position += resolution * 4;
+        let values: Vec<f32> = points
+            .chunks_exact(2)
+            .map(|value| dequantize(i16::from_le_bytes([value[0], value[1]])))
+            .collect();
+        peaks.push(WaveformPeaks {
+            min: values.iter().step_by(2).copied().collect(),
+            max: values.iter().skip(1).step_by(2).copied().collect(),
+        });
+    }
+
+    Ok(Waveform {
+        audio_hash,
+        mode,
+        sample_rate,
+        duration,
+        channels: peaks,
+    })
+}
+
+fn waveform_cache_dir(song_id: &str) -> std::path::PathBuf {
+    std::path::Path::new(&get_waveform_path()).join(song_id)
+}
+
+fn waveform_cache_file(song_id: &str, resolution: usize, mode: WaveformMode) -> std::path::PathBuf {
+    waveform_cache_dir(song_id).join(format!("{}_{}.peaks", resolution, mode.as_str()))
+}
+
+/// Get the waveform of a song, from the cache if it's there and was made from the same audio, otherwise it's
+/// generated and cached. When the audio has changed every cached waveform of the song is thrown away.
+/// None if the song has no path or can't be decoded.
+/// ```no_run
+/// # use decibl_metadata::engine::waveform::*;
+/// let waveform = get_waveform("song_id", 800, WaveformMode::PerChannel).unwrap();
+/// println!("{} channels", waveform.channels.len());
+/// ```
+pub fn get_waveform(song_id: &str, resolution: usize, mode: WaveformMode) -> Option<Waveform> {
+    let song_path = get_songpaths_by_song_id(song_id.to_string())
+        .into_iter()
+        .next()?
+        .song_path;
+    let audio_hash = {
+        let stored = get_song_by_id(song_id.to_string()).audio_hash;
+        if stored.is_empty() || stored == "-1" {
+            audio_hash_or_file_hash(&song_path).ok()?
+        } else {
+            stored
+        }
+    };
+
+    let cache_file = waveform_cache_file(song_id, resolution, mode);
+    if let Ok(bytes) = std::fs::read(&cache_file) {
+        match waveform_from_bytes(&bytes) {
+            Ok(waveform) if waveform.audio_hash == audio_hash => return Some(waveform),
+            Ok(_) => clear_waveform_cache(song_id),
+            Err(_) => {}
+        }
+    }
+
+    let waveform = generate_waveform(&song_path, &audio_hash, resolution, mode)?;
+    // the cache is only there to make things faster, a waveform that can't be cached is still a waveform
+    if std::fs::create_dir_all(waveform_cache_dir(song_id)).is_ok() {
+        let _ = std::fs::write(&cache_file, waveform_to_bytes(&waveform));
+    }
+    Some(waveform)
+}
+
+/// Throws away every cached waveform of a song
+pub fn clear_waveform_cache(song_id: &str) {
+    let _ = std::fs::remove_dir_all(waveform_cache_dir(song_id));
+}
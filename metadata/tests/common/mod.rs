@@ -0,0 +1,120 @@
+// Helpers shared by the test files. Each test file is its own crate and only uses some of them.
+#![allow(dead_code)]
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+// an empty folder in the temp dir for one test
+pub fn temp_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+// an mp4 box: 4 byte size, 4 byte type, then the body
+pub fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+// a blank image of the given size and format
+pub fn image_bytes(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image::RgbImage::new(width, height)
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .unwrap();
+    bytes
+}
+
+// crc-8 of a flac frame header, polynomial x^8 + x^2 + x + 1
+fn flac_crc8(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// crc-16 of a whole flac frame, polynomial x^16 + x^15 + x^2 + 1
+fn flac_crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// a real mono 16 bit 44.1kHz flac that decodes to `samples`, stored uncompressed (verbatim subframes) in frames of
+// up to 4096 samples. `md5` goes in STREAMINFO as is, all zeros means unknown.
+pub fn flac_bytes(samples: &[i16], md5: [u8; 16]) -> Vec<u8> {
+    let mut bytes = b"fLaC".to_vec();
+    // a last STREAMINFO block
+    bytes.extend_from_slice(&[0x80, 0, 0, 34]);
+    bytes.extend_from_slice(&4096u16.to_be_bytes());
+    bytes.extend_from_slice(&4096u16.to_be_bytes());
+    bytes.extend_from_slice(&[0; 6]);
+    // 20 bits of sample rate, 3 of channels - 1, 5 of bits per sample - 1 and 36 of total samples
+    let info: u64 = (44100 << 44) | (15 << 36) | samples.len() as u64;
+    bytes.extend_from_slice(&info.to_be_bytes());
+    bytes.extend_from_slice(&md5);
+
+    for (number, block) in samples.chunks(4096).enumerate() {
+        assert!(number < 128);
+        // sync, block size in 16 bits at the end, 44.1kHz, mono, 16 bit, frame number
+        let mut frame = vec![0xFF, 0xF8, 0x79, 0x08, number as u8];
+        frame.extend_from_slice(&(block.len() as u16 - 1).to_be_bytes());
+        frame.push(flac_crc8(&frame));
+        // a verbatim subframe
+        frame.push(0x02);
+        for sample in block {
+            frame.extend_from_slice(&sample.to_be_bytes());
+        }
+        let crc = flac_crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        bytes.extend_from_slice(&frame);
+    }
+    bytes
+}
+
+// writes a 16 bit wav file to the temp dir, returning the path. `samples` are interleaved when there's more than one
+// channel
+pub fn write_wav(name: &str, sample_rate: u32, channels: u16, samples: &[f32]) -> String {
+    let data_length = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_length.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&((sample * 32767.0) as i16).to_le_bytes());
+    }
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, bytes).unwrap();
+    path.to_str().unwrap().to_string()
+}
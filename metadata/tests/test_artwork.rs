@@ -0,0 +1,248 @@
+use base64::Engine;
+use decibl_metadata::engine::{
+    artwork::{
+        get_all_artwork, get_embedded_artwork, get_folder_artwork, get_front_cover,
+        image_dimensions, parse_flac_picture, sniff_image_mime, ArtworkSource, PictureType,
+    },
+    audio_metadata::get_symphonia_picture_data,
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+mod common;
+use common::{image_bytes, mp4_box, temp_folder};
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing artwork                                                                     */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// a FLAC PICTURE block, as METADATA_BLOCK_PICTURE comments have it
+fn flac_picture_block(picture_type: u32, mime: &str, description: &str, data: &[u8]) -> Vec<u8> {
+    let mut block = picture_type.to_be_bytes().to_vec();
+    block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime.as_bytes());
+    block.extend_from_slice(&(description.len() as u32).to_be_bytes());
+    block.extend_from_slice(description.as_bytes());
+    block.extend_from_slice(&[0; 16]);
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(data);
+    block
+}
+
+#[test]
+fn test_picture_type() {
+    assert_eq!(PictureType::from_code(3), PictureType::FrontCover);
+    assert_eq!(PictureType::from_code(8).as_str(), "artist");
+    assert_eq!(PictureType::from_code(200), PictureType::Other);
+    assert_eq!(PictureType::PublisherLogo.code(), 20);
+    assert_eq!(
+        PictureType::from_type("back_cover"),
+        Some(PictureType::BackCover)
+    );
+    assert_eq!(PictureType::from_type("cover"), None);
+    assert_eq!(
+        ArtworkSource::from_source(ArtworkSource::Folder.as_str()),
+        Some(ArtworkSource::Folder)
+    );
+}
+
+#[test]
+fn test_sniff_image() {
+    let png = image_bytes(30, 20, image::ImageFormat::Png);
+    assert_eq!(sniff_image_mime(&png), Some("image/png".to_string()));
+    assert_eq!(image_dimensions(&png), Some((30, 20)));
+
+    let jpeg = image_bytes(16, 8, image::ImageFormat::Jpeg);
+    assert_eq!(sniff_image_mime(&jpeg), Some("image/jpeg".to_string()));
+    assert_eq!(image_dimensions(&jpeg), Some((16, 8)));
+
+    assert_eq!(sniff_image_mime(&[]), None);
+    assert_eq!(image_dimensions(b"garbage"), None);
+}
+
+#[test]
+fn test_parse_flac_picture() {
+    let block = flac_picture_block(4, "image/jpeg", "the back", &[1, 2, 3]);
+    assert_eq!(
+        parse_flac_picture(&block),
+        Some((
+            PictureType::BackCover,
+            "the back".to_string(),
+            vec![1, 2, 3]
+        ))
+    );
+    // cut off in the middle of the data
+    assert_eq!(parse_flac_picture(&block[..block.len() - 1]), None);
+    assert_eq!(
+        parse_flac_picture(&[0, 0, 0, 3, 0xFF, 0xFF, 0xFF, 0xFF]),
+        None
+    );
+}
+
+#[test]
+fn test_mp3_artwork() {
+    let artwork = get_embedded_artwork("../test_soundfiles/1/cbat.mp3");
+    assert_eq!(artwork.len(), 1);
+    assert_eq!(artwork[0].picture_type, PictureType::FrontCover);
+    assert_eq!(artwork[0].mime, "image/jpeg");
+    assert_eq!(artwork[0].description, "cover");
+    assert_eq!(artwork[0].source, ArtworkSource::Embedded);
+    assert!(artwork[0].width > 0 && artwork[0].height > 0);
+
+    // more pictures, one of them a PNG that says it's a JPEG
+    use id3::TagLike;
+    let folder = temp_folder("decibl_artwork_mp3");
+    let path = folder.join("song.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    let mut tag = id3::Tag::read_from_path(&path).unwrap();
+    tag.add_frame(id3::frame::Picture {
+        mime_type: "image/jpeg".to_string(),
+        picture_type: id3::frame::PictureType::Artist,
+        description: "the band".to_string(),
+        data: image_bytes(40, 30, image::ImageFormat::Png),
+    });
+    tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+    let artwork = get_embedded_artwork(path.to_str().unwrap());
+    assert_eq!(artwork.len(), 2);
+    let artist = artwork
+        .iter()
+        .find(|picture| picture.picture_type == PictureType::Artist)
+        .unwrap();
+    assert_eq!(artist.mime, "image/png");
+    assert_eq!((artist.width, artist.height), (40, 30));
+    assert_eq!(artist.description, "the band");
+
+    let front = get_front_cover(path.to_str().unwrap()).unwrap();
+    assert_eq!(front.description, "cover");
+    assert_eq!(
+        get_symphonia_picture_data(path.to_str().unwrap().to_string(), "flac".to_string()),
+        front.data
+    );
+}
+
+#[test]
+fn test_flac_artwork() {
+    // fLaC, a last STREAMINFO block, then a frame sync
+    let mut flac = b"fLaC".to_vec();
+    flac.extend_from_slice(&[0x80, 0, 0, 34]);
+    flac.extend_from_slice(&[0; 34]);
+    flac.extend_from_slice(&[0xFF, 0xF8]);
+    let folder = temp_folder("decibl_artwork_flac");
+    let path = folder.join("song.flac");
+    std::fs::write(&path, flac).unwrap();
+    assert!(get_embedded_artwork(path.to_str().unwrap()).is_empty());
+    assert_eq!(
+        get_symphonia_picture_data(path.to_str().unwrap().to_string(), "flac".to_string()),
+        Vec::<u8>::new()
+    );
+
+    let mut tag = metaflac::Tag::read_from_path(&path).unwrap();
+    tag.add_picture(
+        "image/png",
+        metaflac::block::PictureType::CoverFront,
+        image_bytes(64, 64, image::ImageFormat::Jpeg),
+    );
+    let block = flac_picture_block(
+        7,
+        "",
+        "singer",
+        &image_bytes(10, 12, image::ImageFormat::Png),
+    );
+    tag.set_vorbis(
+        "METADATA_BLOCK_PICTURE",
+        vec![
+            base64::engine::general_purpose::STANDARD.encode(block),
+            "not base64 !".to_string(),
+        ],
+    );
+    tag.write_to_path(&path).unwrap();
+
+    let artwork = get_embedded_artwork(path.to_str().unwrap());
+    assert_eq!(artwork.len(), 2);
+    assert_eq!(artwork[0].picture_type, PictureType::FrontCover);
+    assert_eq!(artwork[0].mime, "image/jpeg");
+    assert_eq!((artwork[0].width, artwork[0].height), (64, 64));
+    assert_eq!(artwork[1].picture_type, PictureType::LeadArtist);
+    assert_eq!(artwork[1].mime, "image/png");
+    assert_eq!(artwork[1].description, "singer");
+    assert_eq!((artwork[1].width, artwork[1].height), (10, 12));
+}
+
+#[test]
+fn test_mp4_artwork() {
+    // covr with a JPEG and a PNG, the PNG's data box claiming it's a JPEG
+    let mut jpeg = vec![0, 0, 0, 13, 0, 0, 0, 0];
+    jpeg.extend(image_bytes(5, 5, image::ImageFormat::Jpeg));
+    let mut png = vec![0, 0, 0, 13, 0, 0, 0, 0];
+    png.extend(image_bytes(7, 3, image::ImageFormat::Png));
+    let mut covr = mp4_box(b"data", &jpeg);
+    covr.extend(mp4_box(b"data", &png));
+    let mut meta = vec![0, 0, 0, 0];
+    meta.extend(mp4_box(b"ilst", &mp4_box(b"covr", &covr)));
+    let mut m4a = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+    m4a.extend(mp4_box(
+        b"moov",
+        &mp4_box(b"udta", &mp4_box(b"meta", &meta)),
+    ));
+    m4a.extend(mp4_box(b"mdat", &[0; 16]));
+    let folder = temp_folder("decibl_artwork_m4a");
+    let path = folder.join("song.m4a");
+    std::fs::write(&path, m4a).unwrap();
+
+    let artwork = get_embedded_artwork(path.to_str().unwrap());
+    assert_eq!(artwork.len(), 2);
+    assert!(artwork
+        .iter()
+        .all(|picture| picture.picture_type == PictureType::FrontCover));
+    assert_eq!(artwork[0].mime, "image/jpeg");
+    assert_eq!(artwork[1].mime, "image/png");
+    assert_eq!((artwork[1].width, artwork[1].height), (7, 3));
+}
+
+#[test]
+fn test_folder_artwork() {
+    let folder = temp_folder("decibl_artwork_folder");
+    let song = folder.join("song.wav");
+    std::fs::write(&song, b"RIFF").unwrap();
+    assert!(get_folder_artwork(song.to_str().unwrap()).is_empty());
+    assert_eq!(get_front_cover(song.to_str().unwrap()), None);
+
+    std::fs::write(
+        folder.join("Back.PNG"),
+        image_bytes(4, 4, image::ImageFormat::Png),
+    )
+    .unwrap();
+    std::fs::write(
+        folder.join("folder.jpg"),
+        image_bytes(8, 8, image::ImageFormat::Jpeg),
+    )
+    .unwrap();
+    std::fs::write(
+        folder.join("cover.jpg"),
+        image_bytes(9, 9, image::ImageFormat::Jpeg),
+    )
+    .unwrap();
+    std::fs::write(folder.join("notes.jpg"), b"not artwork").unwrap();
+    std::fs::write(folder.join("cover.txt"), b"not an image").unwrap();
+
+    let artwork = get_folder_artwork(song.to_str().unwrap());
+    let names: Vec<&str> = artwork
+        .iter()
+        .map(|picture| picture.path.rsplit(['/', '\\']).next().unwrap())
+        .collect();
+    assert_eq!(names, vec!["cover.jpg", "folder.jpg", "Back.PNG"]);
+    assert_eq!(artwork[0].source, ArtworkSource::Folder);
+    assert_eq!(artwork[2].picture_type, PictureType::BackCover);
+    assert_eq!(artwork[2].mime, "image/png");
+
+    let front = get_front_cover(song.to_str().unwrap()).unwrap();
+    assert_eq!((front.width, front.height), (9, 9));
+    assert_eq!(get_all_artwork(song.to_str().unwrap()).len(), 3);
+
+    // embedded art wins over the folder
+    let mp3 = folder.join("song.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &mp3).unwrap();
+    let front = get_front_cover(mp3.to_str().unwrap()).unwrap();
+    assert_eq!(front.source, ArtworkSource::Embedded);
+    assert_eq!(get_all_artwork(mp3.to_str().unwrap()).len(), 4);
+}
@@ -0,0 +1,190 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_album, insert_song, insert_songpath},
+    artwork::sniff_image_mime,
+    artwork_store::{
+        artwork_id, cache_library_artwork, color_palette, front_cover_color, get_album_artwork,
+        get_artwork, get_song_artwork, get_thumbnail, palette_from_string, prune_artwork_store,
+        render_thumbnail, store_artwork, thumbnail_format, thumbnail_sizes, ThumbnailFormat,
+    },
+    audio_metadata::{AudioFile, AudioFileMP3},
+    models::{default, ALBUMS_TABLE_DATA, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::io::Cursor;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing artwork store                                                               */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// a png of the given size, the left `red` columns red and the rest blue
+fn red_and_blue(width: u32, height: u32, red: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(width, height, |x, _| {
+        if x < red {
+            image::Rgb([255, 0, 0])
+        } else {
+            image::Rgb([0, 0, 255])
+        }
+    });
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn test_color_palette() {
+    let palette = color_palette(&red_and_blue(40, 40, 30), 5);
+    assert_eq!(palette, vec![0xFF0000, 0x0000FF]);
+    assert_eq!(color_palette(&red_and_blue(40, 40, 10), 1), vec![0x0000FF]);
+    assert!(color_palette(b"not an image", 5).is_empty());
+    assert_eq!(
+        palette_from_string("#FF0000, 0000ff,nope"),
+        vec![0xFF0000, 0xFF]
+    );
+
+    assert_eq!(
+        front_cover_color("../test_soundfiles/does_not_exist.mp3"),
+        -1
+    );
+    let color = front_cover_color("../test_soundfiles/1/cbat.mp3");
+    assert!((0..=0xFFFFFF).contains(&color));
+
+    // scanned songs get the colour of their cover
+    let mut afile = AudioFileMP3::default();
+    afile.load_file("../test_soundfiles/1/cbat.mp3".to_string()).unwrap();
+    assert_eq!(afile.get_song_table_data().album_artwork_colors, color);
+}
+
+#[test]
+fn test_render_thumbnail() {
+    let image = image::load_from_memory(&red_and_blue(1000, 500, 500)).unwrap();
+
+    let jpeg = render_thumbnail(&image, 256, ThumbnailFormat::Jpeg).unwrap();
+    assert_eq!(sniff_image_mime(&jpeg), Some("image/jpeg".to_string()));
+    let thumbnail = image::load_from_memory(&jpeg).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+
+    let webp = render_thumbnail(&image, 64, ThumbnailFormat::Webp).unwrap();
+    assert_eq!(sniff_image_mime(&webp), Some("image/webp".to_string()));
+    let thumbnail = image::load_from_memory(&webp).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (64, 32));
+
+    // never blown up
+    let small = image::load_from_memory(&red_and_blue(10, 10, 5)).unwrap();
+    let thumbnail = render_thumbnail(&small, 600, ThumbnailFormat::Jpeg).unwrap();
+    let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (10, 10));
+
+    assert_eq!(
+        ThumbnailFormat::from_format("jpg"),
+        Some(ThumbnailFormat::Jpeg)
+    );
+    assert_eq!(ThumbnailFormat::from_format("gif"), None);
+    let sizes = thumbnail_sizes();
+    assert!(!sizes.is_empty());
+    assert!(sizes.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+#[serial]
+fn test_store_artwork() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let png = red_and_blue(800, 400, 600);
+    let stored = store_artwork(&png).unwrap();
+    assert_eq!(stored.artwork_id, artwork_id(&png));
+    assert_eq!(stored.mime, "image/png");
+    assert_eq!((stored.width, stored.height), (800, 400));
+    assert_eq!(stored.filesize_bytes, png.len() as i64);
+    assert_eq!(stored.palette, "#FF0000,#0000FF");
+    assert_eq!(std::fs::read(&stored.location).unwrap(), png);
+
+    // stored once
+    assert_eq!(store_artwork(&png).unwrap(), stored);
+    assert_eq!(analyticsdb::get_all_artwork().len(), 1);
+    assert_eq!(get_artwork(&stored.artwork_id).unwrap(), stored);
+
+    // a thumbnail at least as big as asked for, or the biggest there is
+    let sizes = thumbnail_sizes();
+    for size in &sizes {
+        let thumbnail = get_thumbnail(&stored.artwork_id, *size).unwrap();
+        assert!(thumbnail.ends_with(&format!("_{}.{}", size, thumbnail_format().extension())));
+        let thumbnail = image::open(&thumbnail).unwrap();
+        assert_eq!(thumbnail.width(), (*size).min(800));
+    }
+    let biggest = get_thumbnail(&stored.artwork_id, 100000).unwrap();
+    assert!(biggest.contains(&format!("_{}.", sizes.last().unwrap())));
+
+    assert_eq!(store_artwork(b"not an image"), None);
+    assert_eq!(get_thumbnail("NOT_STORED", 64), None);
+}
+
+#[test]
+#[serial]
+fn test_cache_library_artwork() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let folder = std::env::temp_dir().join("decibl_artwork_store");
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    let path = folder.join("cbat.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    std::fs::write(folder.join("back.png"), red_and_blue(20, 20, 0)).unwrap();
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "cbat".to_string();
+    song.album = "the album".to_string();
+    song.main_artist = "the artist".to_string();
+    insert_song(song);
+    insert_songpath(SONGPATHS_TABLE_DATA {
+        song_id: "cbat".to_string(),
+        song_path: path.to_str().unwrap().to_string(),
+    });
+    let mut album = ALBUMS_TABLE_DATA::default();
+    album.album_id = "album".to_string();
+    album.album_name = "the album".to_string();
+    album.artist_name = "the artist".to_string();
+    insert_album(album);
+
+    assert_eq!(cache_library_artwork(false), 1);
+    let links = get_song_artwork("cbat");
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].picture_type, "front_cover");
+    assert_eq!(links[0].source, "embedded");
+    assert_eq!(links[0].position, 0);
+    assert_eq!(links[1].picture_type, "back_cover");
+    assert_eq!(links[1].source, "folder");
+    assert_eq!(links[1].position, 1);
+
+    let cover = get_album_artwork("album").unwrap();
+    assert_eq!(cover.artwork_id, links[0].artwork_id);
+    assert_eq!(cover.mime, "image/jpeg");
+    assert_eq!(
+        palette_from_string(&cover.palette)[0] as i64,
+        front_cover_color(path.to_str().unwrap())
+    );
+    assert_eq!(
+        analyticsdb::get_all_albums()[0].album_art_location,
+        cover.location
+    );
+    assert_eq!(get_album_artwork("no_album"), None);
+
+    // everything has artwork now
+    assert_eq!(cache_library_artwork(false), 0);
+    assert_eq!(prune_artwork_store(), 0);
+
+    // a song without its folder image anymore leaves the image unused
+    std::fs::remove_file(folder.join("back.png")).unwrap();
+    assert_eq!(cache_library_artwork(true), 1);
+    assert_eq!(get_song_artwork("cbat").len(), 1);
+    let back = get_artwork(&links[1].artwork_id).unwrap();
+    assert_eq!(prune_artwork_store(), 1);
+    assert_eq!(get_artwork(&back.artwork_id), None);
+    assert!(!std::path::Path::new(&back.location).exists());
+    assert!(std::path::Path::new(&cover.location).exists());
+}
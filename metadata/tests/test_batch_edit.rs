@@ -0,0 +1,301 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath},
+    batch_edit::{
+        apply_batch, get_batch, get_batch_journal, get_batches, preview_batch, revert_batch,
+        BatchEdit, TextCase,
+    },
+    config::get_database_file_path,
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+    tag_editor::{read_tags, write_tags, TagChange, TagField},
+};
+mod common;
+use common::temp_folder;
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing batch edit                                                                  */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn values(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+// three tagged FLAC files of one album in the database, plus a song whose file is gone
+fn album(folder_name: &str) -> Vec<(String, String)> {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    let folder = temp_folder(folder_name);
+
+    let mut songs = Vec::new();
+    for (i, (title, genre)) in [("one", "hip HOP"), ("two", "Hip Hop"), ("three", "rock")]
+        .iter()
+        .enumerate()
+    {
+        // fLaC, a last STREAMINFO block, then a frame sync
+        let mut flac = b"fLaC".to_vec();
+        flac.extend_from_slice(&[0x80, 0, 0, 34]);
+        flac.extend_from_slice(&[0; 34]);
+        flac.extend_from_slice(&[0xFF, 0xF8, i as u8]);
+        let path = folder.join(format!("{}.flac", title));
+        std::fs::write(&path, flac).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        write_tags(
+            &path,
+            &[
+                TagChange::set(TagField::Title, title),
+                TagChange::set(TagField::Genre, genre),
+                TagChange::set(TagField::TrackNumber, "7"),
+            ],
+        )
+        .unwrap();
+
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = format!("song_{}", title);
+        song.title = title.to_string();
+        song.track_number = 7;
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: format!("song_{}", title),
+            song_path: path.clone(),
+        });
+        songs.push((format!("song_{}", title), path));
+    }
+    let mut gone = SONG_TABLE_DATA::default();
+    gone.song_id = "song_gone".to_string();
+    insert_song(gone);
+    songs.push(("song_gone".to_string(), "".to_string()));
+    songs
+}
+
+#[test]
+fn test_text_case() {
+    assert_eq!(TextCase::Title.apply("hip HOP"), "Hip Hop");
+    assert_eq!(TextCase::Upper.apply("r&b"), "R&B");
+    assert_eq!(TextCase::Lower.apply("ROCK"), "rock");
+    assert_eq!(TextCase::from_case("title"), Some(TextCase::Title));
+    assert_eq!(
+        TextCase::from_case(TextCase::Lower.as_str()),
+        Some(TextCase::Lower)
+    );
+    assert_eq!(TextCase::from_case("sentence"), None);
+}
+
+#[test]
+#[serial]
+fn test_preview_batch() {
+    let songs = album("decibl_batch_preview");
+    let song_ids: Vec<String> = songs.iter().map(|(song_id, _)| song_id.clone()).collect();
+    let before: Vec<Vec<u8>> = songs[..3]
+        .iter()
+        .map(|(_, path)| std::fs::read(path).unwrap())
+        .collect();
+
+    let edits = [
+        BatchEdit::Case(TagField::Genre, TextCase::Title),
+        BatchEdit::Replace(TagField::Title, "t".to_string(), "T".to_string()),
+        BatchEdit::RenumberTracks,
+    ];
+    let preview = preview_batch(&song_ids, &edits);
+    assert_eq!(preview.batch_id, "");
+    assert_eq!(preview.skipped.len(), 1);
+    assert_eq!(preview.skipped[0].0, "song_gone");
+    assert_eq!(preview.songs.len(), 3);
+
+    // hip HOP -> Hip Hop, one -> one, tracks 7 -> 1 and a new total of 4
+    let first = &preview.songs[0];
+    assert_eq!(first.song_id, "song_one");
+    let fields: Vec<TagField> = first.fields.iter().map(|diff| diff.field).collect();
+    assert_eq!(
+        fields,
+        vec![TagField::Genre, TagField::TrackNumber, TagField::TrackTotal]
+    );
+    assert_eq!(first.fields[0].old_values, values(&["hip HOP"]));
+    assert_eq!(first.fields[0].new_values, values(&["Hip Hop"]));
+    assert_eq!(first.fields[1].new_values, values(&["1"]));
+    assert_eq!(first.fields[2].old_values, Vec::<String>::new());
+    assert_eq!(first.fields[2].new_values, values(&["4"]));
+
+    // "Two" gets a capital, its genre is right already
+    let second = &preview.songs[1];
+    let fields: Vec<TagField> = second.fields.iter().map(|diff| diff.field).collect();
+    assert_eq!(
+        fields,
+        vec![TagField::Title, TagField::TrackNumber, TagField::TrackTotal]
+    );
+    assert_eq!(second.fields[0].new_values, values(&["Two"]));
+
+    let text = preview.to_text();
+    assert!(text.contains("genre: [\"hip HOP\"] -> [\"Hip Hop\"]"));
+    assert!(text.contains("skipped song_gone"));
+
+    // a dry run is a preview
+    assert_eq!(
+        apply_batch(&song_ids, &edits, "dry", true).unwrap(),
+        preview
+    );
+    for ((_, path), bytes) in songs[..3].iter().zip(before) {
+        assert_eq!(std::fs::read(path).unwrap(), bytes);
+    }
+    assert!(get_batches().is_empty());
+
+    // edits that would leave a bad value skip the song
+    let preview = preview_batch(
+        &song_ids[..1],
+        &[BatchEdit::Replace(
+            TagField::TrackNumber,
+            "7".to_string(),
+            "seven".to_string(),
+        )],
+    );
+    assert!(preview.songs.is_empty());
+    assert_eq!(preview.skipped.len(), 1);
+}
+
+#[test]
+#[serial]
+fn test_apply_and_revert_batch() {
+    let songs = album("decibl_batch_apply");
+    let song_ids: Vec<String> = songs[..3]
+        .iter()
+        .map(|(song_id, _)| song_id.clone())
+        .collect();
+
+    let applied = apply_batch(
+        &song_ids,
+        &[
+            BatchEdit::Set(TagField::AlbumArtist, values(&["The Band"])),
+            BatchEdit::Case(TagField::Genre, TextCase::Title),
+            BatchEdit::RenumberTracks,
+        ],
+        "clean up the album",
+        false,
+    )
+    .unwrap();
+    assert_ne!(applied.batch_id, "");
+    assert_eq!(applied.songs.len(), 3);
+
+    // the files
+    let tags = read_tags(&songs[2].1).unwrap();
+    assert_eq!(tags[&TagField::AlbumArtist], values(&["The Band"]));
+    assert_eq!(tags[&TagField::Genre], values(&["Rock"]));
+    assert_eq!(tags[&TagField::TrackNumber], values(&["3"]));
+    assert_eq!(tags[&TagField::TrackTotal], values(&["3"]));
+
+    // the database
+    let song = analyticsdb::get_song_by_id("song_three".to_string());
+    assert_eq!(song.track_number, 3);
+    assert_eq!(song.track_total, 3);
+    assert_eq!(
+        song.filesize_bytes,
+        std::fs::metadata(&songs[2].1).unwrap().len() as i64
+    );
+    assert_eq!(analyticsdb::get_all_album_artists().len(), 3);
+    let genres: Vec<String> = analyticsdb::get_all_genres()
+        .into_iter()
+        .filter(|genre| genre.song_id == "song_one")
+        .map(|genre| genre.genre_name)
+        .collect();
+    assert_eq!(genres, values(&["Hip Hop"]));
+    let genre_added = |song_id: &str| -> String {
+        analyticsdb::get_all_genres()
+            .into_iter()
+            .find(|genre| genre.song_id == song_id)
+            .unwrap()
+            .dt_added
+    };
+    let added = genre_added("song_one");
+
+    // the journal
+    let batch = get_batch(&applied.batch_id).unwrap();
+    assert_eq!(batch.description, "clean up the album");
+    assert_eq!(batch.song_count, 3);
+    assert_eq!(batch.status, "applied");
+    assert_eq!(get_batches().len(), 1);
+    let journal = get_batch_journal(&applied.batch_id);
+    assert_eq!(journal, applied.songs);
+
+    // someone fixes the last track by hand before the batch is reverted
+    write_tags(&songs[2].1, &[TagChange::set(TagField::TrackNumber, "9")]).unwrap();
+
+    let reverted = revert_batch(&applied.batch_id).unwrap();
+    assert_eq!(reverted.songs.len(), 3);
+    assert_eq!(reverted.skipped.len(), 1);
+    assert!(reverted.skipped[0].1.contains("track_number"));
+
+    let tags = read_tags(&songs[0].1).unwrap();
+    assert!(!tags.contains_key(&TagField::AlbumArtist));
+    assert!(!tags.contains_key(&TagField::TrackTotal));
+    assert_eq!(tags[&TagField::Genre], values(&["hip HOP"]));
+    assert_eq!(tags[&TagField::TrackNumber], values(&["7"]));
+    let tags = read_tags(&songs[2].1).unwrap();
+    assert_eq!(tags[&TagField::TrackNumber], values(&["9"]));
+    assert_eq!(tags[&TagField::Genre], values(&["rock"]));
+    // reverting doesn't make the songs look newly added
+    assert_eq!(genre_added("song_one"), added);
+
+    let song = analyticsdb::get_song_by_id("song_one".to_string());
+    assert_eq!(song.track_number, 7);
+    assert_eq!(song.track_total, -1);
+    assert!(analyticsdb::get_all_album_artists().is_empty());
+
+    let batch = get_batch(&applied.batch_id).unwrap();
+    assert_eq!(batch.status, "reverted");
+    assert_ne!(batch.reverted_dt, "");
+    assert!(revert_batch(&applied.batch_id).is_err());
+    assert!(revert_batch("no_batch").is_err());
+
+    // the same batch again right away is a batch of its own
+    let edits = [BatchEdit::Set(TagField::AlbumArtist, values(&["The Band"]))];
+    let first = apply_batch(&song_ids, &edits, "again", false).unwrap();
+    revert_batch(&first.batch_id).unwrap();
+    let second = apply_batch(&song_ids, &edits, "again", false).unwrap();
+    assert_ne!(first.batch_id, second.batch_id);
+    assert_eq!(get_batches().len(), 3);
+    assert_eq!(get_batch(&first.batch_id).unwrap().status, "reverted");
+}
+
+#[test]
+#[serial]
+fn test_batch_database_failure() {
+    let songs = album("decibl_batch_failure");
+    let song_ids: Vec<String> = songs[..3]
+        .iter()
+        .map(|(song_id, _)| song_id.clone())
+        .collect();
+    let before: Vec<_> = songs[..3]
+        .iter()
+        .map(|(_, path)| read_tags(path).unwrap())
+        .collect();
+    let edits = [BatchEdit::Set(TagField::AlbumArtist, values(&["The Band"]))];
+
+    // the journal can't be written, so the files go back the way they were
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_batches BEFORE INSERT ON tag_edit_batches BEGIN SELECT RAISE(ABORT, 'no'); END;",
+    )
+    .unwrap();
+    assert!(apply_batch(&song_ids, &edits, "fails", false).is_err());
+    conn.execute_batch("DROP TRIGGER no_batches;").unwrap();
+    for (i, (_, path)) in songs[..3].iter().enumerate() {
+        assert_eq!(read_tags(path).unwrap(), before[i]);
+    }
+    assert!(analyticsdb::get_all_album_artists().is_empty());
+    assert!(get_batches().is_empty());
+
+    // the batch can't be marked as reverted, so the files keep what the batch wrote
+    let applied = apply_batch(&song_ids, &edits, "works", false).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_reverts BEFORE UPDATE ON tag_edit_batches BEGIN SELECT RAISE(ABORT, 'no'); END;",
+    )
+    .unwrap();
+    assert!(revert_batch(&applied.batch_id).is_err());
+    conn.execute_batch("DROP TRIGGER no_reverts;").unwrap();
+    assert_eq!(
+        read_tags(&songs[0].1).unwrap()[&TagField::AlbumArtist],
+        values(&["The Band"])
+    );
+    assert_eq!(analyticsdb::get_all_album_artists().len(), 3);
+    assert_eq!(get_batch(&applied.batch_id).unwrap().status, "applied");
+}
@@ -1,13 +1,18 @@
 use decibl_metadata::engine::{
-    analyticsdb::{self, clear_all_tables, create_all_tables, populate_database},
-    audio_metadata::{AudioFile, AudioFileFLAC},
-    config::{get_soundfiles_path_1},
+    analyticsdb::{
+        self, clear_all_tables, create_all_tables, populate_database, Comparison, SongColumn,
+        SongFilter, SongLink, SongQuery, SortOrder,
+    },
+    audio_metadata::{audio_stream_hash, find_audio_payload, AudioFile, AudioFileFLAC},
+    config::{get_database_file_path, get_soundfiles_path_1},
     models::{
         default, ALBUM_ARTISTS_TABLE_DATA, COMPOSERS_TABLE_DATA, GENRES_TABLE_DATA,
         PLAYLIST_SONGS_TABLE_DATA, PLAYLIST_TABLE_DATA, PLAY_TABLE_DATA, SONGPATHS_TABLE_DATA,
         SONG_ARTISTS_TABLE_DATA, SONG_TABLE_DATA,
     },
 };
+mod common;
+use common::temp_folder;
 #[cfg(test)]
 // RUN cargo test --tests -- --nocapture
 use serial_test::serial;
@@ -29,7 +34,7 @@ fn test_table_creation() {
     analyticsdb::clear_all_tables();
 
     let tables = analyticsdb::get_all_table_names();
-    assert_eq!(tables.len(), 11);
+    assert_eq!(tables.len(), 23);
 }
 
 #[test]
@@ -145,6 +150,7 @@ fn test_insert_playlist_song() {
     assert_eq!(playlist_song.playlist_id, "".to_string());
     assert_eq!(playlist_song.song_id, "".to_string());
     assert_eq!(playlist_song.added_dt, "".to_string());
+    assert_eq!(playlist_song.position, 0);
 }
 
 #[test]
@@ -228,7 +234,7 @@ fn test_insert_song_information() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath.clone()).unwrap();
 
     analyticsdb::create_all_tables();
     analyticsdb::clear_all_tables();
@@ -244,15 +250,12 @@ fn test_insert_song_information() {
     let songs = analyticsdb::get_all_songs();
     let song = songs.get(0).unwrap();
 
-    assert_eq!(
-        song.song_id,
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2".to_string()
-    );
+    assert_eq!(song.song_id, audio_stream_hash(filepath).unwrap());
     assert_eq!(song.main_artist, "brakence".to_string());
     assert_eq!(song.filesize_bytes, 15297020);
-    assert_eq!(song.padding_bytes, -1);
+    assert!(song.padding_bytes >= 0);
     assert_eq!(song.album_artwork_bit_depth, 24);
-    assert_eq!(song.album_artwork_colors, -1);
+    assert!(song.album_artwork_colors >= 0);
     assert_eq!(song.album_artwork_height, 800);
     assert_eq!(song.album_artwork_width, 800);
     assert_eq!(song.bit_depth, 16);
@@ -316,6 +319,9 @@ fn test_insert_song_information() {
 #[serial]
 fn test_populate_database() {
     let filepath = get_soundfiles_path_1();
+    // song_ids are the hash of the audio
+    let a_flac_id = audio_stream_hash(format!("{}/a.flac", filepath)).unwrap_or_default();
+    let cbat_id = audio_stream_hash(format!("{}/cbat.mp3", filepath)).unwrap();
     create_all_tables();
     clear_all_tables();
     populate_database(filepath);
@@ -330,8 +336,8 @@ fn test_populate_database() {
 
     // make sure the following IDS are in the database
     let song_ids_valid = vec![
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2".to_string(),
-        "673738B05A45D5DE00AE08E007372E45DC941DCD2D386044EAAE3675E928854D".to_string(),
+        a_flac_id.clone(),
+        cbat_id.clone(),
         "A023A6089AF3FCA01F2821B58B0D643C35F3B048B4DF4F5CA1A778F2BAD00C97".to_string(),
         "CDA83C4F2C2D5AA79692AB7ABB9AB67CC6CC3CF7CA28E5912AAC7DC74E3AFE04".to_string(),
         "4A54B77CFEB6E0590F900A73CF838A504E56EED52D8BB5C63B79DFDD756114CC".to_string(),
@@ -344,7 +350,7 @@ fn test_populate_database() {
     // make sure the following bitrate is right
     let bitrate_valid = vec![1411200, 128000];
 
-    // make sure the following duration is right:
+    // make sure the following duration is right (to the second, they're exact now):
     let duration_valid = vec![161.0, 171.0, 230.0, 172.0, 120.0, 137.0];
 
     // make sure following album is right:
@@ -370,7 +376,7 @@ fn test_populate_database() {
         assert!(song_ids_valid.contains(&song.song_id));
         assert!(filesize_bytes_valid.contains(&song.filesize_bytes));
         assert!(bitrate_valid.contains(&song.bitrate));
-        assert!(duration_valid.contains(&song.duration));
+        assert!(duration_valid.contains(&song.duration.floor()));
         assert!(album_valid.contains(&song.album));
         assert!(title_valid.contains(&song.title));
     }
@@ -416,7 +422,7 @@ fn test_populate_database() {
 
     // make sure following song_ids are right:
     // 2916D1AF7C16DF259A98FEC02A984F10CD0AF370A339CE6C40670F14FB364E6E
-    // 673738B05A45D5DE00AE08E007372E45DC941DCD2D386044EAAE3675E928854D
+    // 67DE1F7A4F96954AD1E0B5A8776F4645140CA141C2042D15AD586DE8F48835C9
     // 1300CF6C7AA9AF55B7238C69CD682FF390702B91BA0B90329A2572239BD603FE
     // 0583580A97B0FEA4AB47AAC224D96FE9FC9BF71ACC53259DA294281DAC8CFE30
     // 19A9F761DD5859057EC591315FBBF557DE91C2893D63F0A4F359851EC54B0A65
@@ -424,8 +430,8 @@ fn test_populate_database() {
 
     
     let album_artist_song_ids_valid = vec![
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2".to_string(),
-        "673738B05A45D5DE00AE08E007372E45DC941DCD2D386044EAAE3675E928854D".to_string(),
+        a_flac_id.clone(),
+        cbat_id.clone(),
         "A023A6089AF3FCA01F2821B58B0D643C35F3B048B4DF4F5CA1A778F2BAD00C97".to_string(),
         "CDA83C4F2C2D5AA79692AB7ABB9AB67CC6CC3CF7CA28E5912AAC7DC74E3AFE04".to_string(),
         "4A54B77CFEB6E0590F900A73CF838A504E56EED52D8BB5C63B79DFDD756114CC".to_string(),
@@ -481,3 +487,338 @@ fn test_populate_database() {
         assert!(song_ids_valid.contains(&composer.song_id));
     }
 }
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              Testing the query builder                                                              */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// inserts a handful of songs so the query tests have something to chew on
+fn insert_query_songs() {
+    let rows = vec![
+        ("a", "flac", 24, 2012, 1, 1),
+        ("b", "flac", 16, 2015, 1, 2),
+        ("c", "flac", 24, 2008, 2, 1),
+        ("d", "mp3", -1, 2019, 1, 3),
+        ("e", "flac", 24, 2021, 2, 2),
+    ];
+
+    for (song_id, filetype, bit_depth, year, disc, track) in rows {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.filetype = filetype.to_string();
+        song.bit_depth = bit_depth;
+        song.date_created = format!("{}-01-01", year);
+        song.disc_number = disc;
+        song.track_number = track;
+        analyticsdb::insert_song(song);
+    }
+
+    for song_id in ["a", "c", "d"] {
+        let mut album_artist = ALBUM_ARTISTS_TABLE_DATA::default();
+        album_artist.song_id = song_id.to_string();
+        album_artist.artist_name = "brakence".to_string();
+        analyticsdb::insert_album_artist(album_artist);
+    }
+}
+
+fn ids(songs: &[SONG_TABLE_DATA]) -> Vec<String> {
+    songs.iter().map(|song| song.song_id.clone()).collect()
+}
+
+#[test]
+#[serial]
+fn test_query_songs_filter_and_order() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    insert_query_songs();
+
+    // FLACs at 24-bit by brakence released after 2010, sorted by disc/track
+    let query = SongQuery::new()
+        .filter(SongFilter::column(SongColumn::Filetype, Comparison::Equal, "flac".to_string()))
+        .filter(SongFilter::column(SongColumn::BitDepth, Comparison::GreaterOrEqual, 24))
+        .filter(SongFilter::Or(vec![
+            SongFilter::linked(SongLink::AlbumArtist, Comparison::Equal, "brakence"),
+            SongFilter::column(SongColumn::DateCreated, Comparison::GreaterThan, "2020".to_string()),
+        ]))
+        .order_by(SongColumn::DiscNumber, SortOrder::Descending)
+        .order_by(SongColumn::TrackNumber, SortOrder::Ascending);
+
+    let songs = analyticsdb::query_songs(&query);
+    assert_eq!(ids(&songs), vec!["c", "e", "a"]);
+    assert_eq!(analyticsdb::count_songs(&query), 3);
+
+    let not_mp3 = SongQuery::new().filter(SongFilter::Not(Box::new(SongFilter::column(
+        SongColumn::Filetype,
+        Comparison::Equal,
+        "mp3".to_string(),
+    ))));
+    assert_eq!(analyticsdb::count_songs(&not_mp3), 4);
+}
+
+#[test]
+#[serial]
+fn test_query_songs_pagination() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    insert_query_songs();
+
+    let query = SongQuery::new()
+        .order_by(SongColumn::DiscNumber, SortOrder::Ascending)
+        .order_by(SongColumn::TrackNumber, SortOrder::Ascending);
+
+    // LIMIT/OFFSET
+    let page = analyticsdb::query_songs(&query.clone().limit(2).offset(2));
+    assert_eq!(ids(&page), vec!["d", "c"]);
+
+    // keyset pagination should walk the same order without skipping anything
+    let mut seen: Vec<String> = Vec::new();
+    let mut page = analyticsdb::query_songs(&query.clone().limit(2));
+    while !page.is_empty() {
+        seen.extend(ids(&page));
+        let last = page.last().unwrap();
+        page = analyticsdb::query_songs(&query.clone().after_song(last).limit(2));
+    }
+    assert_eq!(seen, vec!["a", "b", "d", "c", "e"]);
+}
+
+#[test]
+fn test_query_compiles_to_parameters() {
+    let query = SongQuery::new()
+        .filter(SongFilter::column(SongColumn::Title, Comparison::Equal, "x' OR 1=1 --".to_string()))
+        .limit(10);
+
+    let (sql, params) = query.compile();
+    assert!(!sql.contains("OR 1=1"));
+    assert_eq!(params.len(), 2);
+}
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing maintenance                                                                 */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+#[test]
+#[serial]
+fn test_backup_and_retention() {
+    create_all_tables();
+    clear_all_tables();
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "backed up".to_string();
+    analyticsdb::insert_song(song);
+
+    let backup_dir = std::env::temp_dir().join("decibl_backups");
+    let _ = std::fs::remove_dir_all(&backup_dir);
+    let backup_dir = backup_dir.to_str().unwrap().to_string();
+
+    let mut made: Vec<String> = Vec::new();
+    for _ in 0..3 {
+        made.push(analyticsdb::backup_database(backup_dir.clone(), 2).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    // only the newest two are kept
+    assert_eq!(analyticsdb::get_backups(backup_dir.clone()), made[1..].to_vec());
+
+    let backup = rusqlite::Connection::open(&made[2]).unwrap();
+    let song_id: String = backup
+        .query_row("SELECT song_id FROM songs", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(song_id, "backed up");
+
+    assert_eq!(analyticsdb::prune_backups(backup_dir.clone(), 0).len(), 2);
+    assert!(analyticsdb::get_backups(backup_dir).is_empty());
+}
+
+#[test]
+#[serial]
+fn test_integrity_and_orphans() {
+    create_all_tables();
+    clear_all_tables();
+    assert!(analyticsdb::check_database().is_ok());
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "a".to_string();
+    analyticsdb::insert_song(song);
+    analyticsdb::insert_playlist(PLAYLIST_TABLE_DATA {
+        playlist_id: "p".to_string(),
+        playlist_name: "p".to_string(),
+        playlist_desc: "".to_string(),
+        created_dt: "".to_string(),
+    });
+    for song_id in ["gone", "a", "gone", "a"] {
+        let mut playlist_song = PLAYLIST_SONGS_TABLE_DATA::default();
+        playlist_song.playlist_id = "p".to_string();
+        playlist_song.song_id = song_id.to_string();
+        analyticsdb::insert_playlist_song(playlist_song);
+    }
+    let mut genre = GENRES_TABLE_DATA::default();
+    genre.song_id = "gone".to_string();
+    genre.genre_name = "Pop".to_string();
+    analyticsdb::insert_genre(genre);
+
+    let report = analyticsdb::check_database();
+    assert_eq!(report.integrity, vec!["ok"]);
+    assert!(!report.is_ok());
+    let orphans: Vec<(String, i64)> = report
+        .orphans
+        .iter()
+        .map(|orphans| (orphans.table.clone(), orphans.count))
+        .collect();
+    assert_eq!(
+        orphans,
+        vec![("playlist_songs".to_string(), 2), ("genres".to_string(), 1)]
+    );
+
+    assert_eq!(analyticsdb::delete_orphans(), 3);
+    assert!(analyticsdb::find_orphans().is_empty());
+    // the playlist closes up behind the deleted songs
+    let positions: Vec<i64> = analyticsdb::get_playlist_songs_by_id("p".to_string())
+        .iter()
+        .map(|playlist_song| playlist_song.position)
+        .collect();
+    assert_eq!(positions, vec![0, 1]);
+
+    let (before, after) = analyticsdb::vacuum_database();
+    assert!(before > 0 && after > 0);
+    assert!(analyticsdb::check_database().is_ok());
+}
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing re-keying                                                                   */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+#[test]
+#[serial]
+fn test_rekey_songs() {
+    create_all_tables();
+    clear_all_tables();
+    // a fresh library is already on the current scheme
+    assert!(!analyticsdb::needs_rekey());
+
+    for song_id in ["old a", "old b", "new b"] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        analyticsdb::insert_song(song);
+
+        let mut genre = GENRES_TABLE_DATA::default();
+        genre.song_id = song_id.to_string();
+        genre.genre_name = "Pop".to_string();
+        analyticsdb::insert_genre(genre);
+
+        let mut play = PLAY_TABLE_DATA::default();
+        play.play_id = format!("play {}", song_id);
+        play.song_id = song_id.to_string();
+        analyticsdb::insert_play(play);
+    }
+
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    // a plain rename takes everything with it
+    assert!(!analyticsdb::rekey_song(&conn, "old a", "new a"));
+    // "new b" already exists, so "old b" gets folded into it
+    assert!(analyticsdb::rekey_song(&conn, "old b", "new b"));
+
+    let mut song_ids: Vec<String> = analyticsdb::get_all_songs()
+        .into_iter()
+        .map(|song| song.song_id)
+        .collect();
+    song_ids.sort();
+    assert_eq!(song_ids, vec!["new a", "new b"]);
+    assert_eq!(analyticsdb::get_play_by_id("play old a".to_string()).song_id, "new a");
+    assert_eq!(analyticsdb::get_play_by_id("play old b".to_string()).song_id, "new b");
+    // the merged song's own genre rows went with it
+    assert_eq!(analyticsdb::get_all_genres().len(), 2);
+    assert!(analyticsdb::check_database().is_ok());
+
+    // no files to read, so nothing changes, but the database is marked as done
+    conn.pragma_update(None, "user_version", 0).unwrap();
+    assert!(analyticsdb::needs_rekey());
+    let report = analyticsdb::rekey_all_songs();
+    assert_eq!(report.rekeyed + report.merged, 0);
+    assert_eq!(report.missing.len(), 2);
+    assert!(!analyticsdb::needs_rekey());
+}
+
+#[test]
+#[serial]
+fn test_rekey_splits_songs() {
+    create_all_tables();
+    clear_all_tables();
+
+    // two files with different audio that the old scheme gave the same song_id
+    let folder = temp_folder("decibl_rekey_split");
+    let first = folder.join("first.mp3").to_str().unwrap().to_string();
+    let second = folder.join("second.mp3").to_str().unwrap().to_string();
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &first).unwrap();
+    let mut bytes = std::fs::read(&first).unwrap();
+    let (offset, length) = find_audio_payload(&first).unwrap().ranges[0];
+    bytes[(offset + length / 2) as usize] ^= 0xFF;
+    std::fs::write(&second, bytes).unwrap();
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "old".to_string();
+    analyticsdb::insert_song(song);
+    for path in [&first, &second] {
+        analyticsdb::insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: "old".to_string(),
+            song_path: path.clone(),
+        });
+    }
+    let mut genre = GENRES_TABLE_DATA::default();
+    genre.song_id = "old".to_string();
+    genre.genre_name = "Pop".to_string();
+    analyticsdb::insert_genre(genre);
+    let mut play = PLAY_TABLE_DATA::default();
+    play.play_id = "play old".to_string();
+    play.song_id = "old".to_string();
+    analyticsdb::insert_play(play);
+
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    conn.pragma_update(None, "user_version", 0).unwrap();
+    let report = analyticsdb::rekey_all_songs();
+    assert_eq!(report.rekeyed, 1);
+    assert_eq!(report.split, 1);
+
+    let first_id = analyticsdb::get_song_id_for_file(&first).unwrap();
+    let second_id = analyticsdb::get_song_id_for_file(&second).unwrap();
+    assert_ne!(first_id, second_id);
+    // the first file keeps the song and its plays, the second gets a song of its own with the same genres
+    assert_eq!(analyticsdb::get_play_by_id("play old".to_string()).song_id, first_id);
+    assert_eq!(
+        analyticsdb::get_songpaths_by_song_id(second_id.clone())[0].song_path,
+        second
+    );
+    assert_eq!(analyticsdb::get_song_by_id(second_id.clone()).song_id, second_id);
+    assert_eq!(analyticsdb::get_all_genres().len(), 2);
+    assert_eq!(analyticsdb::get_all_songs().len(), 2);
+    assert!(analyticsdb::check_database().is_ok());
+}
+
+#[test]
+#[serial]
+fn test_migrate_songs_dt_added() {
+    create_all_tables();
+    clear_all_tables();
+    for song_id in ["tagged", "bare"] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        analyticsdb::insert_song(song);
+    }
+    for (dt_added, artist_name) in [
+        ("2021-05-01 10:00:00.123456789", "a"),
+        ("2022-01-01 00:00:00.5", "b"),
+    ] {
+        let mut song_artist = SONG_ARTISTS_TABLE_DATA::default();
+        song_artist.song_id = "tagged".to_string();
+        song_artist.artist_name = artist_name.to_string();
+        song_artist.dt_added = dt_added.to_string();
+        analyticsdb::insert_song_artist(song_artist);
+    }
+
+    // what the table looked like before songs had their own date added
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    conn.execute("ALTER TABLE songs DROP COLUMN dt_added", []).unwrap();
+    create_all_tables();
+
+    let dt_added = |song_id: &str| analyticsdb::get_song_by_id(song_id.to_string()).dt_added;
+    assert_eq!(dt_added("tagged"), "2021-05-01 10:00:00");
+    // nothing to go on, so it's been in the library since the migration
+    assert_eq!(dt_added("bare").len(), 19);
+}
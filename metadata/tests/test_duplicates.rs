@@ -0,0 +1,207 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, query_songs, SongFilter, SongQuery},
+    audio_metadata::{AudioFile, AudioFileFLAC, AudioFileMP3},
+    duplicates::{
+        detect_duplicates, find_duplicates, get_duplicate_groups, get_preferred_version,
+        get_songs_in_playlist_preferred, set_preferred_version, DuplicateKey,
+    },
+    models::{default, PLAYLIST_SONGS_TABLE_DATA, PLAYLIST_TABLE_DATA, SONG_TABLE_DATA},
+    tag_editor::{write_tags, TagChange, TagField},
+};
+mod common;
+use common::{flac_bytes, temp_folder};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::collections::HashMap;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing duplicates                                                                  */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn insert_duplicate_songs() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    // (song_id, title, duration, filetype, bit_depth, sample_rate, bitrate, isrc)
+    let songs = vec![
+        (
+            "mp3",
+            "Punk (feat. Someone)",
+            161.0,
+            "mp3",
+            -1,
+            44100,
+            320000,
+            "-1",
+        ),
+        ("flac16", "punk", 161.5, "flac", 16, 44100, 1411200, "-1"),
+        (
+            "flac24",
+            "Punk",
+            160.2,
+            "flac",
+            24,
+            96000,
+            4608000,
+            "USSM12003816",
+        ),
+        (
+            "radio",
+            "Punk",
+            120.0,
+            "mp3",
+            -1,
+            44100,
+            320000,
+            "USSM12003816",
+        ),
+        (
+            "other",
+            "Something Else",
+            161.0,
+            "flac",
+            16,
+            44100,
+            1411200,
+            "",
+        ),
+    ];
+    for (song_id, title, duration, filetype, bit_depth, sample_rate, bitrate, isrc) in songs {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.title = title.to_string();
+        song.main_artist = "brakence".to_string();
+        song.album = "punk2".to_string();
+        song.duration = duration;
+        song.filetype = filetype.to_string();
+        song.bit_depth = bit_depth;
+        song.sample_rate = sample_rate;
+        song.bitrate = bitrate;
+        song.isrc = isrc.to_string();
+        // "other" is a retagged copy of the radio edit
+        if song_id == "other" || song_id == "radio" {
+            song.audio_hash = "SAME AUDIO".to_string();
+        }
+        analyticsdb::insert_song(song);
+    }
+}
+
+fn song_ids(songs: &[SONG_TABLE_DATA]) -> Vec<String> {
+    songs.iter().map(|song| song.song_id.clone()).collect()
+}
+
+#[test]
+#[serial]
+fn test_find_duplicates() {
+    insert_duplicate_songs();
+
+    // the radio edit is too short to be the same track by metadata
+    let groups = find_duplicates(&[DuplicateKey::Metadata]);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(song_ids(&groups[0].songs), vec!["flac24", "flac16", "mp3"]);
+
+    // but the isrc pulls it in, and it's the worst of the lot
+    let groups = find_duplicates(&[DuplicateKey::Metadata, DuplicateKey::Isrc]);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(
+        song_ids(&groups[0].songs),
+        vec!["flac24", "flac16", "mp3", "radio"]
+    );
+
+    // "-1" means there's no isrc, it doesn't make mp3 and flac16 the same track
+    let groups = find_duplicates(&[DuplicateKey::Isrc]);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(song_ids(&groups[0].songs), vec!["flac24", "radio"]);
+
+    // same group, same id
+    let again = find_duplicates(&[DuplicateKey::Isrc]);
+    assert_eq!(groups[0].group_id, again[0].group_id);
+
+    let groups = find_duplicates(&[DuplicateKey::AudioHash]);
+    assert_eq!(song_ids(&groups[0].songs), vec!["other", "radio"]);
+
+    let fingerprints: HashMap<String, String> = vec![
+        ("other".to_string(), "AQAA1".to_string()),
+        ("mp3".to_string(), "AQAA1".to_string()),
+        ("flac16".to_string(), "AQAA2".to_string()),
+    ]
+    .into_iter()
+    .collect();
+    let groups = find_duplicates(&[DuplicateKey::Fingerprint(fingerprints)]);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(song_ids(&groups[0].songs), vec!["other", "mp3"]);
+}
+
+#[test]
+#[serial]
+fn test_preferred_versions() {
+    insert_duplicate_songs();
+    detect_duplicates(&[DuplicateKey::Metadata]);
+
+    assert_eq!(get_preferred_version("mp3"), "flac24");
+    assert_eq!(get_preferred_version("other"), "other");
+
+    let songs = query_songs(&SongQuery::new().filter(SongFilter::PreferredVersions));
+    let mut ids = song_ids(&songs);
+    ids.sort();
+    assert_eq!(ids, vec!["flac24", "other", "radio"]);
+
+    let mut playlist = PLAYLIST_TABLE_DATA::default();
+    playlist.playlist_id = "p".to_string();
+    playlist.playlist_name = "p".to_string();
+    analyticsdb::insert_playlist(playlist);
+    for song_id in ["mp3", "other"] {
+        let mut playlist_song = PLAYLIST_SONGS_TABLE_DATA::default();
+        playlist_song.playlist_id = "p".to_string();
+        playlist_song.song_id = song_id.to_string();
+        analyticsdb::insert_playlist_song(playlist_song);
+    }
+    assert_eq!(
+        song_ids(&get_songs_in_playlist_preferred("p".to_string())),
+        vec!["flac24", "other"]
+    );
+
+    // a pick made by hand sticks around when the library is scanned again
+    assert!(set_preferred_version("flac16"));
+    assert!(!set_preferred_version("other"));
+    detect_duplicates(&[DuplicateKey::Metadata]);
+    assert_eq!(get_preferred_version("mp3"), "flac16");
+
+    let groups = get_duplicate_groups();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(song_ids(&groups[0].songs), vec!["flac24", "flac16", "mp3"]);
+}
+
+#[test]
+#[serial]
+fn test_tagged_copies() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    // a FLAC and an MP3 of the same recording, tagged with the same ISRC in different ways
+    let folder = temp_folder("decibl_tagged_copies");
+    let mp3 = folder.join("song.mp3").to_str().unwrap().to_string();
+    let flac = folder.join("song.flac").to_str().unwrap().to_string();
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &mp3).unwrap();
+    let samples: Vec<i16> = (0..44100).map(|i| ((i % 100) * 300) as i16).collect();
+    std::fs::write(&flac, flac_bytes(&samples, [0; 16])).unwrap();
+    write_tags(&mp3, &[TagChange::set(TagField::Isrc, "USSM12003816")]).unwrap();
+    write_tags(&flac, &[TagChange::set(TagField::Isrc, "us-sm1-20-03816")]).unwrap();
+
+    let mut mp3_file = AudioFileMP3::default();
+    mp3_file.load_file(mp3).unwrap();
+    let mp3_id = mp3_file.get_song_table_data().song_id;
+    analyticsdb::insert_song_information(mp3_file);
+    let mut flac_file = AudioFileFLAC::default();
+    flac_file.load_file(flac).unwrap();
+    let flac_id = flac_file.get_song_table_data().song_id;
+    analyticsdb::insert_song_information(flac_file);
+
+    // two songs, one group, and the lossless one is preferred
+    assert_ne!(mp3_id, flac_id);
+    let groups = detect_duplicates(&[DuplicateKey::Recording]);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(song_ids(&groups[0].songs), vec![flac_id.clone(), mp3_id.clone()]);
+    assert_eq!(get_preferred_version(&mp3_id), flac_id);
+}
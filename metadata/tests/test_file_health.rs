@@ -0,0 +1,278 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath},
+    audio_metadata::find_audio_payload,
+    file_health::{
+        deep_check_file, deep_check_library, get_bad_flacs, get_file_health, get_flac_verification,
+        get_unhealthy_files, mpeg_stream_info, parse_mpeg_frame_header, parse_vbri_header,
+        scan_mpeg_frames, verify_flac, verify_library_flacs, verify_song, BitrateMode,
+        FileHealthStatus, FlacStatus,
+    },
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing file health                                                                 */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+#[test]
+fn test_verify_flac_unreadable() {
+    let missing = verify_flac("../test_soundfiles/does_not_exist.flac");
+    assert_eq!(missing.status, FlacStatus::Unreadable.as_str());
+    assert_eq!(missing.decoded_samples, 0);
+
+    // an mp3 isn't a flac, whatever the hint says
+    let not_flac = verify_flac("../test_soundfiles/1/cbat.mp3");
+    assert_eq!(not_flac.status, FlacStatus::Unreadable.as_str());
+
+    assert!(FlacStatus::Truncated.is_bad());
+    assert!(!FlacStatus::NoMd5.is_bad());
+    assert_eq!(
+        FlacStatus::from_status(FlacStatus::Md5Mismatch.as_str()),
+        Some(FlacStatus::Md5Mismatch)
+    );
+}
+
+#[test]
+#[serial]
+fn test_verify_library_flacs() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    for (song_id, filetype) in [("broken", "flac"), ("lossy", "mp3")] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.filetype = filetype.to_string();
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path: format!("../test_soundfiles/{}.flac", song_id),
+        });
+    }
+
+    assert_eq!(verify_song("no_such_song"), None);
+    assert_eq!(get_flac_verification("broken"), None);
+
+    // only the flac gets checked
+    let bad = verify_library_flacs(None);
+    assert_eq!(bad.len(), 1);
+    assert_eq!(bad[0].song_id, "broken");
+    assert_eq!(bad[0].status, "unreadable");
+
+    let saved = get_flac_verification("broken").unwrap();
+    assert_eq!(saved.song_path, "../test_soundfiles/broken.flac");
+    assert_eq!(get_flac_verification("lossy"), None);
+    assert_eq!(get_bad_flacs(), vec![saved.clone()]);
+
+    // already verified since then, so there's nothing to do
+    assert!(verify_library_flacs(Some("2000-01-01 00:00:00")).is_empty());
+}
+
+// 200 frames (about 5 seconds) of cbat.mp3 with `breakage` done to them, decoding the whole song every time makes
+// these tests slow. The first frame of cbat.mp3 is an Info frame that says how long the whole song is, so it's
+// dropped unless `keep_info` is set. Returns the path of the copy.
+fn short_copy(name: &str, keep_info: bool, breakage: impl Fn(&mut Vec<u8>)) -> String {
+    let bytes = std::fs::read("../test_soundfiles/1/cbat.mp3").unwrap();
+    let mut start = find_audio_payload("../test_soundfiles/1/cbat.mp3")
+        .unwrap()
+        .ranges[0]
+        .0 as usize;
+    let info_end = start
+        + parse_mpeg_frame_header(&bytes[start..])
+            .unwrap()
+            .frame_length as usize;
+    if !keep_info {
+        start = info_end;
+    }
+    let mut end = info_end;
+    for _ in 0..200 {
+        end += parse_mpeg_frame_header(&bytes[end..]).unwrap().frame_length as usize;
+    }
+    let mut audio = bytes[..find_audio_payload("../test_soundfiles/1/cbat.mp3")
+        .unwrap()
+        .ranges[0]
+        .0 as usize]
+        .to_vec();
+    audio.extend_from_slice(&bytes[start..end]);
+    breakage(&mut audio);
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, audio).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_parse_mpeg_frame_header() {
+    // MPEG-1 layer 3, 128kbps, 44100Hz, no padding, stereo
+    let header = parse_mpeg_frame_header(&[0xFF, 0xFB, 0x90, 0x00]).unwrap();
+    assert_eq!(header.version, 1);
+    assert_eq!(header.layer, 3);
+    assert_eq!(header.bitrate, 128000);
+    assert_eq!(header.sample_rate, 44100);
+    assert_eq!(header.channels, 2);
+    assert_eq!(header.frame_length, 417);
+    assert_eq!(header.samples, 1152);
+
+    // padded, mono
+    let header = parse_mpeg_frame_header(&[0xFF, 0xFB, 0x92, 0xC0]).unwrap();
+    assert_eq!(header.frame_length, 418);
+    assert_eq!(header.channels, 1);
+
+    // bad bitrate, reserved sample rate, no sync
+    assert_eq!(parse_mpeg_frame_header(&[0xFF, 0xFB, 0xF0, 0x00]), None);
+    assert_eq!(parse_mpeg_frame_header(&[0xFF, 0xFB, 0x9C, 0x00]), None);
+    assert_eq!(parse_mpeg_frame_header(&[0x00, 0xFB, 0x90, 0x00]), None);
+}
+
+#[test]
+fn test_deep_check_file() {
+    let clean_path = short_copy("decibl_clean.mp3", false, |_| {});
+    let clean = deep_check_file(&clean_path);
+    assert_eq!(clean.status, FileHealthStatus::Ok.as_str());
+    assert_eq!(clean.filetype, "mp3");
+    assert!(clean.decoded_duration > 0.0);
+    let frames = scan_mpeg_frames(&clean_path).unwrap();
+    assert_eq!(frames.frames, 200);
+    assert_eq!(frames.lost_sync, 0);
+    assert!(!frames.truncated_final_frame);
+
+    let truncated = short_copy("decibl_truncated.mp3", false, |bytes| {
+        let length = bytes.len();
+        bytes.truncate(length - 300);
+    });
+    let health = deep_check_file(&truncated);
+    assert_eq!(health.truncated_final_frame, 1);
+    assert_eq!(health.status, FileHealthStatus::Truncated.as_str());
+
+    let garbage = short_copy("decibl_garbage.mp3", false, |bytes| {
+        // somewhere in the audio, the front of the file is mostly ID3 tag
+        let at = bytes.len() - 40000;
+        bytes.splice(at..at, vec![0x55; 1000]);
+    });
+    let health = deep_check_file(&garbage);
+    assert_eq!(health.lost_sync, 1);
+    assert_eq!(health.status, FileHealthStatus::LostSync.as_str());
+
+    // the Info frame still says the whole song is there
+    let cut_short = deep_check_file(&short_copy("decibl_cut_short.mp3", true, |_| {}));
+    assert_eq!(
+        cut_short.status,
+        FileHealthStatus::DurationMismatch.as_str()
+    );
+    assert!(cut_short.declared_duration > cut_short.decoded_duration + 60.0);
+
+    let missing = deep_check_file("../test_soundfiles/does_not_exist.mp3");
+    assert_eq!(missing.status, FileHealthStatus::Unreadable.as_str());
+}
+
+#[test]
+#[serial]
+fn test_deep_check_library() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let clean_path = short_copy("decibl_library_clean.mp3", false, |_| {});
+    for (song_id, song_path) in [
+        ("clean", clean_path.as_str()),
+        ("missing", "../test_soundfiles/does_not_exist.mp3"),
+    ] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.filetype = "mp3".to_string();
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path: song_path.to_string(),
+        });
+    }
+
+    let unhealthy = deep_check_library(None);
+    assert_eq!(unhealthy.len(), 1);
+    assert_eq!(unhealthy[0].song_id, "missing");
+    assert_eq!(get_file_health("clean").unwrap().status, "ok");
+    assert_eq!(get_unhealthy_files(), unhealthy);
+    assert!(deep_check_library(Some("2000-01-01 00:00:00")).is_empty());
+}
+
+// MPEG-1 layer 3 44100Hz stereo frames with nothing in them but the header, `headers` are the 4 header bytes
+fn empty_frames(headers: &[[u8; 4]]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for header in headers {
+        let length = parse_mpeg_frame_header(header).unwrap().frame_length as usize;
+        bytes.extend_from_slice(header);
+        bytes.extend(vec![0; length - 4]);
+    }
+    bytes
+}
+
+const FRAME_128K: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+const FRAME_192K: [u8; 4] = [0xFF, 0xFB, 0xB0, 0x00];
+
+#[test]
+fn test_mpeg_stream_info() {
+    // the Info header says it all
+    let cbat = mpeg_stream_info("../test_soundfiles/1/cbat.mp3").unwrap();
+    assert!(cbat.from_header);
+    assert_eq!(cbat.mode, BitrateMode::Cbr);
+    assert_eq!(cbat.frames, 6575);
+    assert_eq!(cbat.total_samples, 6575 * 1152 - 576 - 972);
+    assert!((cbat.duration - 7572852.0 / 44100.0).abs() < 1e-9);
+    assert!((cbat.bitrate as i64 - 128000).abs() < 500);
+
+    // without it every frame gets counted
+    let scanned = mpeg_stream_info(&short_copy("decibl_no_info.mp3", false, |_| {})).unwrap();
+    assert!(!scanned.from_header);
+    assert_eq!(scanned.frames, 200);
+    assert_eq!(scanned.mode, BitrateMode::Cbr);
+    assert!((scanned.bitrate as i64 - 128000).abs() < 500);
+
+    // half the frames at 128kbps and half at 192kbps
+    let path = std::env::temp_dir().join("decibl_vbr.mp3");
+    std::fs::write(
+        &path,
+        empty_frames(&[FRAME_128K, FRAME_192K, FRAME_128K, FRAME_192K]),
+    )
+    .unwrap();
+    let vbr = mpeg_stream_info(path.to_str().unwrap()).unwrap();
+    assert_eq!(vbr.mode, BitrateMode::Vbr);
+    assert_eq!(vbr.frames, 4);
+    assert!((vbr.bitrate as i64 - 160000).abs() < 500);
+    assert!((vbr.duration - 4.0 * 1152.0 / 44100.0).abs() < 1e-9);
+
+    // a Xing header from an ABR encode, the frame count is what counts even though only 2 frames follow
+    let mut xing = empty_frames(&[FRAME_128K]);
+    xing[36..44].copy_from_slice(b"Xing\0\0\0\x03");
+    xing[44..48].copy_from_slice(&1000u32.to_be_bytes());
+    xing[48..52].copy_from_slice(&(1000 * 522u32 + 417).to_be_bytes());
+    xing[52..61].copy_from_slice(b"LAME3.100");
+    xing[61] = 0x02;
+    xing[73..76].copy_from_slice(&[0x24, 0x03, 0xCC]);
+    xing.extend(empty_frames(&[FRAME_128K, FRAME_192K]));
+    std::fs::write(&path, xing).unwrap();
+    let abr = mpeg_stream_info(path.to_str().unwrap()).unwrap();
+    assert!(abr.from_header);
+    assert_eq!(abr.mode, BitrateMode::Abr);
+    assert_eq!(abr.frames, 1000);
+    assert_eq!(abr.total_samples, 1000 * 1152 - 576 - 972);
+    assert!((abr.bitrate as i64 - 160000).abs() < 500);
+
+    // Fraunhofer's VBRI header
+    let mut vbri = empty_frames(&[FRAME_128K, FRAME_128K]);
+    vbri[36..40].copy_from_slice(b"VBRI");
+    vbri[46..50].copy_from_slice(&(500 * 522u32 + 417).to_be_bytes());
+    vbri[50..54].copy_from_slice(&500u32.to_be_bytes());
+    std::fs::write(&path, vbri).unwrap();
+    assert_eq!(
+        parse_vbri_header(&std::fs::read(&path).unwrap())
+            .unwrap()
+            .frames,
+        500
+    );
+    let vbri = mpeg_stream_info(path.to_str().unwrap()).unwrap();
+    assert_eq!(vbri.mode, BitrateMode::Vbr);
+    assert_eq!(vbri.frames, 500);
+    assert!((vbri.duration - 500.0 * 1152.0 / 44100.0).abs() < 1e-9);
+
+    assert!(mpeg_stream_info("../test_soundfiles/does_not_exist.mp3").is_err());
+}
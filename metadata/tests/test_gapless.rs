@@ -0,0 +1,171 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath},
+    audio_metadata::{find_audio_payload, tag_padding_bytes},
+    file_health::parse_xing_header,
+    gapless::{
+        analyze_gapless, analyze_library_gapless, get_gapless, parse_itunsmpb, read_gapless_info,
+        GaplessSource,
+    },
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+};
+mod common;
+use common::{mp4_box, write_wav};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::f32::consts::PI;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing gapless                                                                     */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// a second of silence, two seconds of 440Hz, half a second of silence
+fn tone_between_silence() -> Vec<f32> {
+    let mut samples = vec![0.0; 44100];
+    samples.extend((0..88200).map(|i| 0.5 * (2.0 * PI * 440.0 * i as f32 / 44100.0).sin()));
+    samples.extend(vec![0.0; 22050]);
+    samples
+}
+
+#[test]
+fn test_parse_xing_header() {
+    let bytes = std::fs::read("../test_soundfiles/1/cbat.mp3").unwrap();
+    let start = find_audio_payload("../test_soundfiles/1/cbat.mp3")
+        .unwrap()
+        .ranges[0]
+        .0 as usize;
+    let xing = parse_xing_header(&bytes[start..]).unwrap();
+    assert!(!xing.vbr);
+    assert_eq!(xing.frames, Some(6575));
+    let lame = xing.lame.unwrap();
+    assert_eq!(lame.encoder, "LAME3.99r");
+    assert_eq!(lame.encoder_delay, 576);
+    assert_eq!(lame.encoder_padding, 972);
+
+    // the frame after it is audio
+    let second = start + 417;
+    assert_eq!(parse_xing_header(&bytes[second..]), None);
+}
+
+#[test]
+fn test_read_gapless_info() {
+    let info = read_gapless_info("../test_soundfiles/1/cbat.mp3");
+    assert_eq!(info.source, GaplessSource::Lame);
+    assert_eq!(info.encoder_delay, 576);
+    assert_eq!(info.encoder_padding, 972);
+    assert_eq!(info.total_samples, 6575 * 1152 - 576 - 972);
+    assert_eq!(info.sample_rate, 44100);
+
+    assert_eq!(parse_itunsmpb("not hex at all"), None);
+    assert_eq!(parse_itunsmpb(" 00000000 00000840"), None);
+
+    // just enough of an m4a for the tag to be found
+    let smpb = b" 00000000 00000840 000001CA 00000000003F3AF6 00000000 00000000";
+    let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+    data.extend_from_slice(smpb);
+    let mut item = mp4_box(b"mean", b"\0\0\0\0com.apple.iTunes");
+    item.extend(mp4_box(b"name", b"\0\0\0\0iTunSMPB"));
+    item.extend(mp4_box(b"data", &data));
+    let mut meta = vec![0, 0, 0, 0];
+    meta.extend(mp4_box(b"ilst", &mp4_box(b"----", &item)));
+    let mut m4a = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+    m4a.extend(mp4_box(
+        b"moov",
+        &mp4_box(b"udta", &mp4_box(b"meta", &meta)),
+    ));
+    m4a.extend(mp4_box(b"mdat", &[0; 16]));
+    let path = std::env::temp_dir().join("decibl_itunsmpb.m4a");
+    std::fs::write(&path, m4a).unwrap();
+    let info = read_gapless_info(path.to_str().unwrap());
+    assert_eq!(info.source, GaplessSource::ITunSmpb);
+    assert_eq!(info.encoder_delay, 2112);
+    assert_eq!(info.encoder_padding, 458);
+    assert_eq!(info.total_samples, 4143862);
+
+    let missing = read_gapless_info("../test_soundfiles/does_not_exist.mp3");
+    assert_eq!(missing.source, GaplessSource::None);
+    assert_eq!(missing.total_samples, -1);
+}
+
+#[test]
+fn test_analyze_gapless() {
+    let tone = analyze_gapless(&write_wav(
+        "decibl_gapless_tone.wav",
+        44100,
+        1,
+        &tone_between_silence(),
+    ));
+    assert_eq!(tone.source, "none");
+    assert_eq!(tone.total_samples, 154350);
+    assert_eq!(tone.sample_rate, 44100);
+    assert!((tone.leading_silence - 1.0).abs() < 0.001);
+    assert!((tone.trailing_silence - 0.5).abs() < 0.001);
+
+    let silence = analyze_gapless(&write_wav(
+        "decibl_gapless_silence.wav",
+        44100,
+        1,
+        &[0.0; 44100],
+    ));
+    assert_eq!(silence.leading_silence, 1.0);
+    assert_eq!(silence.trailing_silence, 0.0);
+
+    // symphonia leaves out the delay and padding itself
+    let cbat = analyze_gapless("../test_soundfiles/1/cbat.mp3");
+    assert_eq!(cbat.source, "lame");
+    assert_eq!(cbat.total_samples, 6575 * 1152 - 576 - 972);
+    assert!(cbat.leading_silence >= 0.0 && cbat.leading_silence < 1.0);
+
+    let missing = analyze_gapless("../test_soundfiles/does_not_exist.wav");
+    assert_eq!(missing.leading_silence, -1.0);
+}
+
+#[test]
+fn test_tag_padding_bytes() {
+    assert_eq!(
+        tag_padding_bytes("../test_soundfiles/1/cbat.mp3").unwrap(),
+        4096
+    );
+
+    // fLaC, a STREAMINFO block, then a last PADDING block of 100 bytes
+    let mut flac = b"fLaC".to_vec();
+    flac.extend_from_slice(&[0x00, 0, 0, 34]);
+    flac.extend_from_slice(&[0; 34]);
+    flac.extend_from_slice(&[0x81, 0, 0, 100]);
+    flac.extend_from_slice(&[0; 100]);
+    flac.extend_from_slice(&[0xFF, 0xF8]);
+    let path = std::env::temp_dir().join("decibl_padded.flac");
+    std::fs::write(&path, flac).unwrap();
+    assert_eq!(tag_padding_bytes(path.to_str().unwrap()).unwrap(), 100);
+
+    let wav = write_wav("decibl_unpadded.wav", 44100, 1, &[0.0; 10]);
+    assert_eq!(tag_padding_bytes(&wav).unwrap(), 0);
+}
+
+#[test]
+#[serial]
+fn test_analyze_library_gapless() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "tone".to_string();
+    song.filetype = "wav".to_string();
+    insert_song(song);
+    insert_songpath(SONGPATHS_TABLE_DATA {
+        song_id: "tone".to_string(),
+        song_path: write_wav(
+            "decibl_library_gapless.wav",
+            44100,
+            1,
+            &tone_between_silence(),
+        ),
+    });
+
+    assert_eq!(analyze_library_gapless(false), 1);
+    assert_eq!(analyze_library_gapless(false), 0);
+    let gapless = get_gapless("tone").unwrap();
+    assert!((gapless.leading_silence - 1.0).abs() < 0.001);
+    assert_eq!(get_gapless("not_analyzed"), None);
+    assert_eq!(analyze_library_gapless(true), 1);
+}
@@ -0,0 +1,304 @@
+use decibl_metadata::engine::{
+    analyticsdb,
+    audio_metadata::SONG_ID_VERSION,
+    library_dump::{dump_library, read_manifest, restore_library, DumpFormat, DUMP_VERSION},
+    models::{
+        default, GENRES_TABLE_DATA, PLAYLIST_SONGS_TABLE_DATA, PLAYLIST_TABLE_DATA,
+        PLAY_TABLE_DATA, SONG_TABLE_DATA,
+    },
+    smart_playlists::{create_smart_playlist, MatchMode, SmartPlaylistRules, SmartRule},
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing library dumps                                                               */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn dump_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("decibl_library_dump").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn insert_library() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "a".to_string();
+    song.title = "Alpha, \"quoted\"\nwith a newline".to_string();
+    song.duration = 201.5;
+    song.bit_depth = 24;
+    analyticsdb::insert_song(song);
+
+    let mut genre = GENRES_TABLE_DATA::default();
+    genre.genre_name = "Pop".to_string();
+    genre.song_id = "a".to_string();
+    analyticsdb::insert_genre(genre);
+
+    let mut play = PLAY_TABLE_DATA::default();
+    play.play_id = "1".to_string();
+    play.song_id = "a".to_string();
+    play.filesize_bytes = 1234;
+    play.start_dt = "2023-01-01 00:00:00".to_string();
+    play.completion = 0.75;
+    analyticsdb::insert_play(play);
+
+    analyticsdb::insert_playlist(PLAYLIST_TABLE_DATA {
+        playlist_id: "p".to_string(),
+        playlist_name: "Road trip".to_string(),
+        playlist_desc: "".to_string(),
+        created_dt: "2023-01-01 00:00:00".to_string(),
+    });
+    for _ in 0..2 {
+        analyticsdb::insert_playlist_song(PLAYLIST_SONGS_TABLE_DATA {
+            playlist_id: "p".to_string(),
+            song_id: "a".to_string(),
+            added_dt: "2023-01-01 00:00:00".to_string(),
+            position: -1,
+        });
+    }
+
+    create_smart_playlist(
+        "Pop",
+        "",
+        &SmartPlaylistRules {
+            match_mode: MatchMode::All,
+            rules: vec![SmartRule::Genre("Pop".to_string())],
+            sort: None,
+            limit: None,
+        },
+    );
+}
+
+fn snapshot() -> String {
+    format!(
+        "{:?}{:?}{:?}{:?}{:?}{:?}{:?}",
+        analyticsdb::get_all_songs(),
+        analyticsdb::get_all_genres(),
+        analyticsdb::get_all_plays(),
+        analyticsdb::get_all_playlists(),
+        analyticsdb::get_all_playlist_songs(),
+        analyticsdb::get_all_smart_playlists(),
+        analyticsdb::get_all_song_artists(),
+    )
+}
+
+#[test]
+#[serial]
+fn test_dump_and_restore_round_trip() {
+    for format in [DumpFormat::Jsonl, DumpFormat::Csv] {
+        insert_library();
+        let before = snapshot();
+
+        let dir = dump_dir(&format!("{:?}", format));
+        let dirpath = dir.to_str().unwrap().to_string();
+        let manifest = dump_library(dirpath.clone(), format).unwrap();
+        assert_eq!(manifest.version, DUMP_VERSION);
+        assert_eq!(manifest.song_id_version, SONG_ID_VERSION);
+        assert_eq!(
+            manifest.tables.len(),
+            analyticsdb::get_all_table_names().len()
+        );
+        assert_eq!(read_manifest(&dirpath).unwrap(), manifest);
+
+        let plays = manifest
+            .tables
+            .iter()
+            .find(|table| table.name == "plays")
+            .unwrap();
+        assert_eq!(plays.rows, 1);
+        assert!(plays.columns.contains(&"filesize".to_string()));
+
+        // it won't restore on top of an existing library
+        let error = restore_library(dirpath.clone()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+        analyticsdb::clear_all_tables();
+        let report = restore_library(dirpath).unwrap();
+        assert_eq!(report.rows["playlist_songs"], 2);
+        assert!(report.warnings.is_empty());
+        assert_eq!(snapshot(), before);
+        assert!(!analyticsdb::needs_rekey());
+    }
+}
+
+// turns a dump into what a version 1 dump looked like, without the song_id scheme in the manifest and without
+// the song columns added since
+fn make_version_1_dump(dir: &Path, format: DumpFormat) {
+    let old_columns = ["recording_id", "dt_added"];
+
+    let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+    let mut manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    manifest["version"] = 1.into();
+    manifest.as_object_mut().unwrap().remove("song_id_version");
+    for table in manifest["tables"].as_array_mut().unwrap() {
+        if table["name"] == "songs" {
+            table["columns"]
+                .as_array_mut()
+                .unwrap()
+                .retain(|column| !old_columns.contains(&column.as_str().unwrap()));
+        }
+    }
+    std::fs::write(dir.join("manifest.json"), manifest.to_string()).unwrap();
+
+    match format {
+        DumpFormat::Jsonl => {
+            let songs = std::fs::read_to_string(dir.join("songs.jsonl")).unwrap();
+            let mut lines = String::new();
+            for line in songs.lines() {
+                let mut song: serde_json::Value = serde_json::from_str(line).unwrap();
+                for column in old_columns {
+                    song.as_object_mut().unwrap().remove(column);
+                }
+                lines.push_str(&format!("{}\n", song));
+            }
+            std::fs::write(dir.join("songs.jsonl"), lines).unwrap();
+
+            std::fs::write(
+                dir.join("genres.jsonl"),
+                "{\"genre_name\":\"Pop\",\"song_id\":\"a\",\"dt_added\":\"2021-05-01 10:00:00.123\"}\n",
+            )
+            .unwrap();
+        }
+        DumpFormat::Csv => {
+            let mut reader = csv::Reader::from_path(dir.join("songs.csv")).unwrap();
+            let headers = reader.headers().unwrap().clone();
+            let keep: Vec<usize> = (0..headers.len())
+                .filter(|index| !old_columns.contains(&&headers[*index]))
+                .collect();
+            let mut writer = csv::Writer::from_path(dir.join("songs.tmp")).unwrap();
+            writer
+                .write_record(keep.iter().map(|index| &headers[*index]))
+                .unwrap();
+            for record in reader.records() {
+                let record = record.unwrap();
+                writer
+                    .write_record(keep.iter().map(|index| &record[*index]))
+                    .unwrap();
+            }
+            writer.flush().unwrap();
+            std::fs::rename(dir.join("songs.tmp"), dir.join("songs.csv")).unwrap();
+
+            std::fs::write(
+                dir.join("genres.csv"),
+                "genre_name,song_id,dt_added\nPop,a,2021-05-01 10:00:00.123\n",
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_restore_version_1_dump() {
+    for format in [DumpFormat::Jsonl, DumpFormat::Csv] {
+        insert_library();
+        let dir = dump_dir(&format!("version_1_{:?}", format));
+        let dirpath = dir.to_str().unwrap().to_string();
+        dump_library(dirpath.clone(), format).unwrap();
+        make_version_1_dump(&dir, format);
+        analyticsdb::clear_all_tables();
+
+        let report = restore_library(dirpath.clone()).unwrap();
+        assert_eq!(report.rows["songs"], 1);
+
+        // the missing columns get the defaults, apart from the date added which comes from the genre
+        let songs = analyticsdb::get_all_songs();
+        assert_eq!(songs[0].title, "Alpha, \"quoted\"\nwith a newline");
+        assert_eq!(songs[0].recording_id, "");
+        assert_eq!(songs[0].dt_added, "2021-05-01 10:00:00");
+
+        // the songs are keyed however they were when they were dumped, not the way an empty database starts out
+        assert_eq!(analyticsdb::get_song_id_version(), 0);
+        assert!(analyticsdb::needs_rekey());
+
+        // a current dump has to have every column
+        analyticsdb::clear_all_tables();
+        let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        std::fs::write(
+            dir.join("manifest.json"),
+            manifest.replace("\"version\":1", &format!("\"version\":{}", DUMP_VERSION)),
+        )
+        .unwrap();
+        let error = restore_library(dirpath).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("songs in the dump has columns"));
+    }
+}
+
+#[test]
+#[serial]
+fn test_restore_validates_before_writing() {
+    insert_library();
+    let dir = dump_dir("broken");
+    let dirpath = dir.to_str().unwrap().to_string();
+    dump_library(dirpath.clone(), DumpFormat::Jsonl).unwrap();
+    analyticsdb::clear_all_tables();
+
+    // a row that doesn't fit the model
+    let songs = std::fs::read_to_string(dir.join("songs.jsonl")).unwrap();
+    std::fs::write(dir.join("songs.jsonl"), songs.replace("201.5", "\"long\"")).unwrap();
+    let error = restore_library(dirpath.clone()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("songs.jsonl line 1"));
+    std::fs::write(dir.join("songs.jsonl"), &songs).unwrap();
+
+    // a missing row
+    let genres = std::fs::read_to_string(dir.join("genres.jsonl")).unwrap();
+    std::fs::write(dir.join("genres.jsonl"), "").unwrap();
+    assert!(restore_library(dirpath.clone())
+        .unwrap_err()
+        .to_string()
+        .contains("manifest says 1"));
+    std::fs::write(dir.join("genres.jsonl"), &genres).unwrap();
+
+    // a dump from a newer decibl
+    let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+    std::fs::write(
+        dir.join("manifest.json"),
+        manifest.replace(
+            &format!("\"version\": {}", DUMP_VERSION),
+            &format!("\"version\": {}", DUMP_VERSION + 1),
+        ),
+    )
+    .unwrap();
+    assert!(restore_library(dirpath.clone()).is_err());
+    std::fs::write(dir.join("manifest.json"), &manifest).unwrap();
+
+    // none of that got anywhere near the database
+    assert!(analyticsdb::get_all_songs().is_empty());
+    assert!(analyticsdb::get_all_genres().is_empty());
+
+    // duplicate keys fail the whole restore and roll back
+    std::fs::write(dir.join("songs.jsonl"), format!("{}{}", songs, songs)).unwrap();
+    std::fs::write(
+        dir.join("manifest.json"),
+        manifest.replacen("\"rows\": 1", "\"rows\": 2", 1),
+    )
+    .unwrap();
+    assert!(restore_library(dirpath.clone()).is_err());
+    assert!(analyticsdb::get_all_songs().is_empty());
+    assert!(analyticsdb::get_all_plays().is_empty());
+
+    // and with everything put back it restores fine, minus the song, which gets flagged
+    std::fs::write(dir.join("manifest.json"), &manifest).unwrap();
+    std::fs::write(dir.join("songs.jsonl"), "").unwrap();
+    std::fs::write(
+        dir.join("manifest.json"),
+        manifest.replacen("\"rows\": 1", "\"rows\": 0", 1),
+    )
+    .unwrap();
+    let report = restore_library(dirpath).unwrap();
+    assert_eq!(
+        report.warnings,
+        vec![
+            "2 rows in playlist_songs whose song_id isn't in songs",
+            "1 rows in genres whose song_id isn't in songs"
+        ]
+    );
+}
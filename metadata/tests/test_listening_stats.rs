@@ -0,0 +1,215 @@
+use decibl_metadata::engine::{
+    analyticsdb,
+    listening_stats::{
+        get_all_song_play_histories, get_listening_heatmap, get_listening_streaks,
+        get_listening_summary, get_song_play_history, get_top_albums, get_top_artists,
+        get_top_genres, get_top_songs, get_total_listening_seconds, TimeWindow,
+        DEFAULT_SKIP_FRACTION,
+    },
+    models::{
+        default, GENRES_TABLE_DATA, PLAY_TABLE_DATA, SONG_ARTISTS_TABLE_DATA, SONG_TABLE_DATA,
+    },
+    smart_playlists::days_ago,
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing listening stats                                                             */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn insert_play(play_id: &str, song_id: &str, song_title: &str, start_dt: &str, end_dt: &str) {
+    let mut play = PLAY_TABLE_DATA::default();
+    play.play_id = play_id.to_string();
+    play.song_id = song_id.to_string();
+    play.song_title = song_title.to_string();
+    play.start_dt = start_dt.to_string();
+    play.end_dt = end_dt.to_string();
+    analyticsdb::insert_play(play);
+}
+
+// "a" by Ann, "b" by Bob and Ann, both on album X. "c" got played but isn't in the library any more.
+fn insert_listening_history() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let songs = vec![
+        ("a", "Alpha", 200.0, vec!["Ann"], "Pop"),
+        ("b", "Beta", 100.0, vec!["Bob", "Ann"], "Rock"),
+    ];
+    for (song_id, title, duration, artists, genre) in songs {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.title = title.to_string();
+        song.album = "X".to_string();
+        song.duration = duration;
+        analyticsdb::insert_song(song);
+
+        for artist in artists {
+            let mut song_artist = SONG_ARTISTS_TABLE_DATA::default();
+            song_artist.artist_name = artist.to_string();
+            song_artist.song_id = song_id.to_string();
+            analyticsdb::insert_song_artist(song_artist);
+        }
+
+        let mut genre_data = GENRES_TABLE_DATA::default();
+        genre_data.genre_name = genre.to_string();
+        genre_data.song_id = song_id.to_string();
+        analyticsdb::insert_genre(genre_data);
+    }
+
+    // monday, listened all the way through
+    insert_play(
+        "1",
+        "a",
+        "Alpha",
+        "2023-01-02 10:00:00",
+        "2023-01-02 10:03:20",
+    );
+    // monday night, skipped after 30 seconds
+    insert_play(
+        "2",
+        "a",
+        "Alpha",
+        "2023-01-02 22:00:00",
+        "2023-01-02 22:00:30",
+    );
+    // tuesday
+    insert_play(
+        "3",
+        "b",
+        "Beta",
+        "2023-01-03 10:00:00",
+        "2023-01-03 10:01:40",
+    );
+    insert_play(
+        "4",
+        "c",
+        "Gone",
+        "2023-01-03 11:00:00",
+        "2023-01-03 11:01:00",
+    );
+    // wednesday in february, never got an end time
+    insert_play("5", "a", "Alpha", "2023-02-01 09:00:00", "");
+}
+
+fn january() -> TimeWindow {
+    TimeWindow::between("2023-01-01 00:00:00", "2023-02-01 00:00:00")
+}
+
+#[test]
+#[serial]
+fn test_top_charts() {
+    insert_listening_history();
+
+    let songs = get_top_songs(&january(), 10);
+    let labels: Vec<&str> = songs.iter().map(|entry| entry.label.as_str()).collect();
+    assert_eq!(labels, vec!["Alpha", "Beta", "Gone"]);
+    assert_eq!(songs[0].key, "a");
+    assert_eq!(songs[0].play_count, 2);
+    assert_eq!(songs[0].listened_seconds.round(), 230.0);
+    assert_eq!(get_top_songs(&january(), 1).len(), 1);
+
+    let artists: Vec<(String, i64)> = get_top_artists(&january(), 10)
+        .into_iter()
+        .map(|entry| (entry.label, entry.play_count))
+        .collect();
+    assert_eq!(
+        artists,
+        vec![("Ann".to_string(), 3), ("Bob".to_string(), 1)]
+    );
+
+    let albums = get_top_albums(&TimeWindow::all_time(), 10);
+    assert_eq!(albums.len(), 1);
+    assert_eq!(albums[0].label, "X");
+    assert_eq!(albums[0].play_count, 4);
+
+    let genres: Vec<(String, i64)> = get_top_genres(&january(), 10)
+        .into_iter()
+        .map(|entry| (entry.label, entry.play_count))
+        .collect();
+    assert_eq!(
+        genres,
+        vec![("Pop".to_string(), 2), ("Rock".to_string(), 1)]
+    );
+}
+
+#[test]
+#[serial]
+fn test_summary_and_skips() {
+    insert_listening_history();
+
+    let summary = get_listening_summary(&january(), DEFAULT_SKIP_FRACTION);
+    assert_eq!(summary.play_count, 4);
+    assert_eq!(summary.distinct_songs, 3);
+    assert_eq!(summary.listened_seconds.round(), 390.0);
+    assert_eq!(summary.skip_count, 1);
+    assert_eq!(summary.skip_rate, 0.25);
+
+    // the play with no end time counts as a skip too
+    let summary = get_listening_summary(&TimeWindow::all_time(), DEFAULT_SKIP_FRACTION);
+    assert_eq!(summary.play_count, 5);
+    assert_eq!(summary.skip_count, 2);
+
+    assert_eq!(get_total_listening_seconds(&january()).round(), 390.0);
+
+    let empty = get_listening_summary(
+        &TimeWindow::between("2020-01-01 00:00:00", "2020-02-01 00:00:00"),
+        DEFAULT_SKIP_FRACTION,
+    );
+    assert_eq!(empty.play_count, 0);
+    assert_eq!(empty.skip_rate, 0.0);
+}
+
+#[test]
+#[serial]
+fn test_heatmap_and_history() {
+    insert_listening_history();
+
+    let heatmap = get_listening_heatmap(&TimeWindow::all_time());
+    assert_eq!(heatmap.by_hour[10], 2);
+    assert_eq!(heatmap.by_hour[22], 1);
+    assert_eq!(heatmap.by_hour.iter().sum::<i64>(), 5);
+    assert_eq!(heatmap.by_weekday, [0, 2, 2, 1, 0, 0, 0]);
+    assert_eq!(heatmap.by_weekday_hour[1][22], 1);
+
+    let history = get_song_play_history("a");
+    assert_eq!(history.play_count, 3);
+    assert_eq!(
+        history.first_played,
+        Some("2023-01-02 10:00:00".to_string())
+    );
+    assert_eq!(history.last_played, Some("2023-02-01 09:00:00".to_string()));
+
+    let never = get_song_play_history("never played");
+    assert_eq!(never.play_count, 0);
+    assert_eq!(never.last_played, None);
+
+    let histories: Vec<String> = get_all_song_play_histories(&january())
+        .into_iter()
+        .map(|history| history.song_id)
+        .collect();
+    assert_eq!(histories, vec!["c", "b", "a"]);
+
+    let streaks = get_listening_streaks(&TimeWindow::all_time());
+    assert_eq!(streaks.longest_days, 2);
+    assert_eq!(streaks.longest_start, Some("2023-01-02".to_string()));
+    assert_eq!(streaks.longest_end, Some("2023-01-03".to_string()));
+    assert_eq!(streaks.current_days, 0);
+}
+
+#[test]
+#[serial]
+fn test_current_streak() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    for (play_id, days) in [("1", 0), ("2", 1), ("3", 1), ("4", 2), ("5", 5), ("6", 6)] {
+        insert_play(play_id, "a", "Alpha", &days_ago(days), &days_ago(days));
+    }
+
+    let streaks = get_listening_streaks(&TimeWindow::all_time());
+    assert_eq!(streaks.current_days, 3);
+    assert_eq!(streaks.longest_days, 3);
+}
@@ -0,0 +1,175 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath},
+    loudness::{
+        analyze_file, analyze_library, get_loudness, get_playback_gain, loudness_from_tags,
+        LoudnessMeter,
+    },
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+};
+
+mod common;
+use common::write_wav;
+
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::f32::consts::PI;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing loudness                                                                    */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// a stereo sine, `amplitude` is the peak
+fn sine(sample_rate: u32, seconds: f32, frequency: f32, amplitude: f32, phase: f32) -> Vec<f32> {
+    (0..(sample_rate as f32 * seconds) as usize)
+        .flat_map(|i| {
+            let sample =
+                amplitude * (2.0 * PI * frequency * i as f32 / sample_rate as f32 + phase).sin();
+            [sample, sample]
+        })
+        .collect()
+}
+
+fn dbfs(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[test]
+fn test_loudness_meter() {
+    // EBU Tech 3341: a 1kHz sine at -23 dBFS in both channels is -23 LUFS
+    let mut meter = LoudnessMeter::new(48000, 2);
+    meter.add_samples(&sine(48000, 20.0, 1000.0, dbfs(-23.0), 0.0));
+    let analysis = meter.finish();
+    assert!((analysis.integrated_lufs - -23.0).abs() < 0.1);
+    assert!((analysis.track_gain() - 5.0).abs() < 0.1);
+    assert!(analysis.loudness_range < 0.1);
+
+    // EBU Tech 3342: 20s at -20 then 20s at -30 has a loudness range of 10
+    let mut meter = LoudnessMeter::new(48000, 2);
+    meter.add_samples(&sine(48000, 20.0, 1000.0, dbfs(-20.0), 0.0));
+    meter.add_samples(&sine(48000, 20.0, 1000.0, dbfs(-30.0), 0.0));
+    assert!((meter.finish().loudness_range - 10.0).abs() < 1.0);
+
+    // a quarter of the sample rate shifted by 45 degrees never has a sample on the peak
+    let mut meter = LoudnessMeter::new(48000, 2);
+    meter.add_samples(&sine(48000, 1.0, 12000.0, 0.5, PI / 4.0));
+    let analysis = meter.finish();
+    assert!((analysis.sample_peak - 0.354).abs() < 0.01);
+    assert!((analysis.true_peak - 0.5).abs() < 0.02);
+
+    // silence is as quiet as it gets
+    let mut meter = LoudnessMeter::new(44100, 2);
+    meter.add_samples(&vec![0.0; 44100 * 2]);
+    assert_eq!(meter.finish().integrated_lufs, -70.0);
+}
+
+#[test]
+#[serial]
+fn test_analyze_library() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let loud = write_wav(
+        "decibl_loud.wav",
+        44100,
+        2,
+        &sine(44100, 10.0, 1000.0, dbfs(-12.0), 0.0),
+    );
+    let quiet = write_wav(
+        "decibl_quiet.wav",
+        44100,
+        2,
+        &sine(44100, 10.0, 1000.0, dbfs(-24.0), 0.0),
+    );
+    assert!((analyze_file(&loud).unwrap().integrated_lufs - -12.0).abs() < 0.2);
+    assert_eq!(analyze_file("../test_soundfiles/does_not_exist.wav"), None);
+
+    for (song_id, path) in [("loud", &loud), ("quiet", &quiet)] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.album = "Dynamics".to_string();
+        song.main_artist = "Sine".to_string();
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path: path.to_string(),
+        });
+    }
+
+    assert_eq!(analyze_library(false, false), 2);
+    // nothing left to measure
+    assert_eq!(analyze_library(false, false), 0);
+
+    let loud = get_loudness("loud").unwrap();
+    let quiet = get_loudness("quiet").unwrap();
+    assert_eq!(loud.source, "analysis");
+    assert!((loud.track_gain - -6.0).abs() < 0.2);
+    assert!((quiet.track_gain - 6.0).abs() < 0.2);
+    // one album gain for both, so the quiet song stays quieter
+    assert_eq!(loud.album_gain, quiet.album_gain);
+    assert!(loud.album_gain < 0.0);
+    assert_eq!(loud.album_peak, loud.true_peak);
+
+    assert!((get_playback_gain("quiet", false, 0.0) - quiet.track_gain).abs() < 1e-9);
+    assert_eq!(get_playback_gain("quiet", true, 0.0), quiet.album_gain);
+    // +20 would clip the loud song, so it's turned down to just under full scale
+    let limited = get_playback_gain("loud", false, 20.0);
+    assert!((limited - -20.0 * loud.track_peak.log10()).abs() < 1e-9);
+    assert_eq!(get_playback_gain("not_measured", false, 0.0), 0.0);
+
+    // a new song on the album changes the album gain, so the whole album is measured again
+    let louder = write_wav(
+        "decibl_louder.wav",
+        44100,
+        2,
+        &sine(44100, 10.0, 1000.0, dbfs(-6.0), 0.0),
+    );
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "louder".to_string();
+    song.album = "Dynamics".to_string();
+    song.main_artist = "Sine".to_string();
+    insert_song(song);
+    insert_songpath(SONGPATHS_TABLE_DATA {
+        song_id: "louder".to_string(),
+        song_path: louder,
+    });
+    assert_eq!(analyze_library(false, false), 3);
+    let remeasured = get_loudness("quiet").unwrap();
+    assert!(remeasured.album_gain < loud.album_gain);
+    assert_eq!(
+        remeasured.album_gain,
+        get_loudness("louder").unwrap().album_gain
+    );
+}
+
+#[test]
+fn test_loudness_from_tags() {
+    use id3::TagLike;
+
+    let path = std::env::temp_dir().join("decibl_replaygain.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    let path = path.to_str().unwrap().to_string();
+    assert_eq!(loudness_from_tags(&path), None);
+
+    let mut tag = id3::Tag::read_from_path(&path).unwrap();
+    for (description, value) in [
+        ("REPLAYGAIN_TRACK_GAIN", "-6.50 dB"),
+        ("REPLAYGAIN_TRACK_PEAK", "0.988525"),
+        ("replaygain_album_gain", "-7.25 dB"),
+    ] {
+        tag.add_frame(id3::frame::ExtendedText {
+            description: description.to_string(),
+            value: value.to_string(),
+        });
+    }
+    tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+
+    let loudness = loudness_from_tags(&path).unwrap();
+    assert_eq!(loudness.source, "tags");
+    assert_eq!(loudness.track_gain, -6.5);
+    assert_eq!(loudness.integrated_lufs, -11.5);
+    assert_eq!(loudness.track_peak, 0.988525);
+    assert_eq!(loudness.album_gain, -7.25);
+    assert_eq!(loudness.album_peak, 1.0);
+    assert_eq!(loudness.true_peak, -1.0);
+}
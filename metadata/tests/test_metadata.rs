@@ -1,10 +1,15 @@
 use decibl_metadata::engine::{
     audio_metadata::{
-        add_symphonia_data, file_to_hash, string_to_hash, AudioFileFLAC, AudioFile,
+        add_symphonia_data, audio_hash_or_file_hash, audio_stream_hash, file_to_hash,
+        find_audio_payload, recording_id, string_to_hash, AudioContainer, AudioFileFLAC,
+        AudioFileMP3, AudioFile,
     },
     config::get_soundfiles_path_1,
 };
 
+mod common;
+use common::flac_bytes;
+
 
 #[cfg(test)]
 // RUN cargo test --tests -- --nocapture
@@ -45,6 +50,151 @@ fn test_string_to_hash() {
 /*                                                              testing flac files                                                              */
 /* ------------------------------------------------------------------------------------------------------------------------------------------------ */
 
+fn write_temp(name: &str, data: &[u8]) -> String {
+    let dir = std::env::temp_dir().join("decibl_stream_hash");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name).to_str().unwrap().to_string();
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn test_audio_stream_hash() {
+    let mp3_audio = vec![0xFFu8, 0xFB, 0x90, 0x64, 1, 2, 3, 4, 5, 6, 7, 8];
+    let bare = write_temp("bare.mp3", &mp3_audio);
+
+    // ID3v2 (synchsafe size 5) at the front, then APE and ID3v1 at the back
+    let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x05TIT2!".to_vec();
+    tagged.extend_from_slice(&mp3_audio);
+    tagged.extend_from_slice(b"item");
+    tagged.extend_from_slice(b"APETAGEX");
+    tagged.extend_from_slice(&2000u32.to_le_bytes());
+    tagged.extend_from_slice(&36u32.to_le_bytes()); // 4 bytes of items + the 32 byte footer
+    tagged.extend_from_slice(&1u32.to_le_bytes());
+    tagged.extend_from_slice(&0u32.to_le_bytes());
+    tagged.extend_from_slice(&[0; 8]);
+    let mut id3v1 = b"TAG".to_vec();
+    id3v1.resize(128, b' ');
+    tagged.extend_from_slice(&id3v1);
+    let tagged = write_temp("tagged.mp3", &tagged);
+
+    let payload = find_audio_payload(&tagged).unwrap();
+    assert_eq!(payload.container, AudioContainer::Mpeg);
+    assert_eq!(payload.ranges, vec![(15, mp3_audio.len() as u64)]);
+    assert_eq!(
+        audio_stream_hash(tagged.clone()).unwrap(),
+        audio_stream_hash(bare.clone()).unwrap()
+    );
+    // file_to_hash still sees the tags
+    assert_ne!(file_to_hash(tagged).unwrap(), file_to_hash(bare).unwrap());
+
+    // flac: the metadata blocks are skipped, a last-block flag ends them
+    let samples: Vec<i16> = (0..5000).map(|i| (i % 300) as i16).collect();
+    let flac = flac_bytes(&samples, [0; 16]);
+    // the STREAMINFO block without its last-block flag, then the frames
+    let (streaminfo, flac_audio) = (&flac[8..42], &flac[42..]);
+    let mut flac_a = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac_a.extend_from_slice(streaminfo);
+    flac_a.extend_from_slice(&[0x01, 0, 0, 3, 0, 0, 0, 0x81, 0, 0, 1, 0]);
+    flac_a.extend_from_slice(flac_audio);
+    let mut flac_b = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac_b.extend_from_slice(streaminfo);
+    flac_b.extend_from_slice(&[0x81, 0, 0, 2, 0, 0]);
+    flac_b.extend_from_slice(flac_audio);
+    let flac_a = write_temp("a.flac", &flac_a);
+    let flac_b = write_temp("b.flac", &flac_b);
+    assert_eq!(find_audio_payload(&flac_a).unwrap().container, AudioContainer::Flac);
+    assert_eq!(
+        audio_stream_hash(flac_a).unwrap(),
+        audio_stream_hash(flac_b).unwrap()
+    );
+
+    // frames that don't match the STREAMINFO MD5 aren't hashed as audio, the whole file is
+    let mismatch = write_temp("mismatch.flac", &flac_bytes(&samples, [1; 16]));
+    assert!(audio_stream_hash(mismatch.clone()).is_err());
+    assert_eq!(
+        audio_hash_or_file_hash(&mismatch).unwrap(),
+        file_to_hash(mismatch).unwrap()
+    );
+
+    // metadata that doesn't end on a frame is an error, not a silently wrong hash
+    let mut broken = b"fLaC".to_vec();
+    broken.extend_from_slice(&[0x84, 0, 0, 2, b'z', b'z', 1, 2, 3]);
+    assert!(find_audio_payload(&write_temp("broken.flac", &broken)).is_err());
+
+    // mp4: only the mdat box counts
+    let mp4 = |tag: &[u8]| {
+        let mut data = Vec::new();
+        data.extend_from_slice(&16u32.to_be_bytes());
+        data.extend_from_slice(b"ftypM4A \0\0\0\0");
+        data.extend_from_slice(&(8 + tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(b"moov");
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&12u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[7, 7, 7, 7]);
+        data
+    };
+    let short_tag = write_temp("short.m4a", &mp4(b"title"));
+    let long_tag = write_temp("long.m4a", &mp4(b"a much longer title"));
+    let payload = find_audio_payload(&short_tag).unwrap();
+    assert_eq!(payload.container, AudioContainer::Mp4);
+    assert_eq!(payload.ranges, vec![(37, 4)]);
+    assert_eq!(
+        audio_stream_hash(short_tag).unwrap(),
+        audio_stream_hash(long_tag).unwrap()
+    );
+}
+
+#[test]
+fn test_audio_stream_hash_junk_after_id3() {
+    let original = std::fs::read("../test_soundfiles/1/cbat.mp3").unwrap();
+    let tag_size = original[6..10]
+        .iter()
+        .fold(0usize, |size, byte| (size << 7) | (*byte as usize & 0x7f));
+
+    // zeros and junk the tag's size doesn't cover, the frames start after them
+    let mut junk = original[..10 + tag_size].to_vec();
+    junk.extend_from_slice(&[0; 700]);
+    junk.extend_from_slice(b"junk\xFF\x00junk");
+    junk.extend_from_slice(&original[10 + tag_size..]);
+    let original = write_temp("original.mp3", &original);
+    let junk = write_temp("junk.mp3", &junk);
+
+    let payload = find_audio_payload(&junk).unwrap();
+    assert_eq!(payload.container, AudioContainer::Mpeg);
+    assert_eq!(
+        payload.ranges[0].0,
+        find_audio_payload(&original).unwrap().ranges[0].0 + 710
+    );
+    assert_eq!(
+        audio_stream_hash(junk).unwrap(),
+        audio_stream_hash(original).unwrap()
+    );
+}
+
+#[test]
+fn test_load_file_missing() {
+    let mut afile = AudioFileMP3::default();
+    assert!(afile.load_file("../test_soundfiles/1/missing.mp3".to_string()).is_err());
+    let mut afile = AudioFileFLAC::default();
+    assert!(afile.load_file("../test_soundfiles/1/missing.flac".to_string()).is_err());
+}
+
+#[test]
+fn test_recording_id() {
+    let mbid = "4E0D8649-1F89-44F3-91AF-4C0DBEE81F28";
+    assert_eq!(
+        recording_id(mbid, "USSM12003816"),
+        format!("musicbrainz:{}", mbid.to_lowercase())
+    );
+    assert_eq!(recording_id("", "us-sm1-20-03816"), "isrc:USSM12003816");
+    // junk in the MusicBrainz tag falls through to the ISRC
+    assert_eq!(recording_id("garbage", " ussm12003816 "), "isrc:USSM12003816");
+    // nothing usable in the tags
+    assert_eq!(recording_id("-1", "-1"), "");
+}
+
 #[test]
 fn test_symphonia_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
@@ -93,7 +243,8 @@ fn test_metaflac_data() {
     // print afile.raw_metadata 
     // {"duration": ["161"], "channels": ["2"], "sample_rate": ["44100"], "bit_depth": ["16"], "bitrate": ["1411200"]}
 
-    assert_eq!(afile.raw_metadata.get("duration").unwrap(), &vec!["161"]);
+    let duration = afile.raw_metadata.get("duration").unwrap()[0].parse::<f64>().unwrap();
+    assert_eq!(duration.floor(), 161.0);
     assert_eq!(afile.raw_metadata.get("channels").unwrap(), &vec!["2"]);
     assert_eq!(afile.raw_metadata.get("sample_rate").unwrap(), &vec!["44100"]);
     assert_eq!(afile.raw_metadata.get("bit_depth").unwrap(), &vec!["16"]);
@@ -107,16 +258,14 @@ fn test_flac_get_composers_table_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath.clone()).unwrap();
 
     let data = afile.get_composers_table_data();
 // [COMPOSERS_TABLE_DATA { composer_name: "Majent", song_id: "2916D1AF7C16DF259A98FEC02A984F10CD0AF370A339CE6C40670F14FB364E6E", dt_added: "2022-12-31 10:04:06.264219900" }, COMPOSERS_TABLE_DATA { composer_name: "Randy Findell", song_id: "2916D1AF7C16DF259A98FEC02A984F10CD0AF370A339CE6C40670F14FB364E6E", dt_added: "2022-12-31 10:04:06.264245700" }]
 
     let valid_composers = vec!["Majent", "Randy Findell"];
-    let valid_ids = vec![
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2",
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2",
-    ];
+    let song_id = audio_stream_hash(filepath).unwrap();
+    let valid_ids = [song_id.clone(), song_id];
 
     for (i, composer) in data.iter().enumerate() {
         assert_eq!(composer.composer_name, valid_composers[i]);
@@ -130,7 +279,7 @@ fn test_flac_get_genres_table_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath).unwrap();
 
     let data = afile.get_genres_table_data();
     
@@ -143,7 +292,7 @@ fn test_flac_get_album_artists_table_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath).unwrap();
 
     let data = afile.get_album_artists_table_data();
     
@@ -156,7 +305,7 @@ fn test_flac_get_song_artists_table_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath).unwrap();
 
     let data = afile.get_song_artists_table_data();
     
@@ -169,25 +318,25 @@ fn test_get_song_table_data() {
     let filepath = format!("{}/a.flac", get_soundfiles_path_1());
 
     let mut afile = AudioFileFLAC::default();
-    afile.load_file(filepath);
+    afile.load_file(filepath.clone()).unwrap();
 
     let song = afile.get_song_table_data();
 
-    assert_eq!(
-        song.song_id,
-        "551229E1295C4B719B1B2AC853677B9E7537AC39ED715E3436FD1370D621E7E2".to_string()
-    );
+    // the song_id is the hash of the audio, so retagging the file doesn't change it
+    assert_eq!(song.song_id, audio_stream_hash(filepath).unwrap());
+    assert_eq!(song.recording_id, "isrc:USSM12003816");
     assert_eq!(song.main_artist, "brakence".to_string());
     assert_eq!(song.filesize_bytes, 15297020);
-    assert_eq!(song.padding_bytes, -1);
+    assert!(song.padding_bytes >= 0);
     assert_eq!(song.album_artwork_bit_depth, 24);
-    assert_eq!(song.album_artwork_colors, -1);
+    assert!(song.album_artwork_colors >= 0);
     assert_eq!(song.album_artwork_height, 800);
     assert_eq!(song.album_artwork_width, 800);
     assert_eq!(song.bit_depth, 16);
     assert_eq!(song.bitrate, 1411200);
     assert_eq!(song.channels, 2);
-    assert_eq!(song.duration, 161.0);
+    assert_eq!(song.duration, song.total_samples as f64 / 44100.0);
+    assert_eq!(song.duration.floor(), 161.0);
     assert_eq!(song.sample_rate, 44100);
     assert_eq!(song.album, "punk2".to_string());
     assert_eq!(song.barcode, "886448554691".to_string());
@@ -212,3 +361,39 @@ fn test_get_song_table_data() {
 /*                                                              testing mp3 files                                                              */
 /* ------------------------------------------------------------------------------------------------------------------------------------------------ */
 
+#[test]
+fn test_mp3_broken_file() {
+    // used to panic in mp3_metadata::read_from_file(...).unwrap()
+    let path = std::env::temp_dir().join("decibl_broken.mp3");
+    std::fs::write(&path, vec![0u8; 4096]).unwrap();
+
+    let mut afile = AudioFileMP3::default();
+    afile.load_file(path.to_str().unwrap().to_string()).unwrap();
+    let song = afile.get_song_table_data();
+    assert_eq!(song.duration, -1.0);
+    assert_eq!(song.total_samples, -1);
+    assert_eq!(song.filetype, "mp3".to_string());
+    assert_eq!(song.filesize_bytes, 4096);
+    assert_eq!(song.padding_bytes, 0);
+}
+
+#[test]
+fn test_mp3_padding_bytes() {
+    // the ID3v2 tag of cbat.mp3 leaves 4096 bytes free
+    let mut afile = AudioFileMP3::default();
+    afile.load_file("../test_soundfiles/1/cbat.mp3".to_string()).unwrap();
+    assert_eq!(afile.get_song_table_data().padding_bytes, 4096);
+}
+
+#[test]
+fn test_mp3_duration_and_bitrate() {
+    // from the Info header, not rounded to whole seconds
+    let mut afile = AudioFileMP3::default();
+    afile.load_file("../test_soundfiles/1/cbat.mp3".to_string()).unwrap();
+    let song = afile.get_song_table_data();
+    assert_eq!(song.total_samples, 7572852);
+    assert_eq!(song.duration, 7572852.0 / 44100.0);
+    assert_eq!(song.bitrate_mode, "cbr".to_string());
+    assert!((song.bitrate - 128000).abs() < 500);
+    assert_eq!(song.sample_rate, 44100);
+}
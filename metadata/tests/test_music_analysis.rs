@@ -0,0 +1,232 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath, query_songs, SongQuery},
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+    music_analysis::{
+        analyze_library_tempo_and_key, analyze_tempo_and_key, get_music_analysis,
+        get_tag_disagreements, parse_key, read_tempo_key_tags, save_music_analysis, MusicalKey,
+    },
+    smart_playlists::SmartRule,
+};
+
+mod common;
+use common::write_wav;
+
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::f32::consts::PI;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing music analysis                                                              */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// 20 seconds of short 1.5kHz clicks at `bpm`
+fn click_track(bpm: f32) -> Vec<f32> {
+    let sample_rate = 44100.0;
+    let beat = sample_rate * 60.0 / bpm;
+    (0..(sample_rate * 20.0) as usize)
+        .map(|i| {
+            let since_beat = (i as f32 % beat) / sample_rate;
+            0.8 * (-since_beat * 200.0).exp() * (2.0 * PI * 1500.0 * since_beat).sin()
+        })
+        .collect()
+}
+
+// a second of each chord, as midi notes, played three times over
+fn chords(progression: &[[u32; 3]]) -> Vec<f32> {
+    let sample_rate = 44100.0;
+    let mut samples: Vec<f32> = Vec::new();
+    for _ in 0..3 {
+        for chord in progression {
+            for i in 0..sample_rate as usize {
+                let t = i as f32 / sample_rate;
+                samples.push(
+                    chord
+                        .iter()
+                        .map(|note| {
+                            let frequency = 440.0 * 2f32.powf((*note as f32 - 69.0) / 12.0);
+                            0.25 * (2.0 * PI * frequency * t).sin()
+                        })
+                        .sum(),
+                );
+            }
+        }
+    }
+    samples
+}
+
+// Am Dm E Am
+fn a_minor_cadence() -> Vec<f32> {
+    chords(&[[57, 60, 64], [62, 65, 69], [64, 68, 71], [57, 60, 64]])
+}
+
+#[test]
+fn test_parse_key() {
+    let a_minor = MusicalKey {
+        tonic: 9,
+        minor: true,
+    };
+    for key in ["Am", "A minor", "a min", "8A", "08a"] {
+        assert_eq!(parse_key(key), Some(a_minor));
+    }
+    assert_eq!(parse_key("C#").unwrap(), parse_key("Db").unwrap());
+    assert_eq!(parse_key("F♯m").unwrap().name(), "F#m");
+    assert_eq!(parse_key("Bb major").unwrap().name(), "Bb");
+    assert_eq!(parse_key("o"), None);
+    assert_eq!(parse_key("13A"), None);
+    assert_eq!(parse_key("H"), None);
+
+    // the whole wheel goes both ways
+    for number in 1..=12 {
+        for letter in ["A", "B"] {
+            let camelot = format!("{}{}", number, letter);
+            assert_eq!(parse_key(&camelot).unwrap().camelot(), camelot);
+        }
+    }
+
+    assert_eq!(a_minor.camelot(), "8A");
+    assert_eq!(parse_key("C").unwrap().camelot(), "8B");
+    assert_eq!(parse_key("F#").unwrap().camelot(), "2B");
+    assert_eq!(parse_key("Ebm").unwrap().camelot(), "2A");
+    assert_eq!(parse_key("B").unwrap().camelot(), "1B");
+    assert_eq!(a_minor.compatible_keys(), vec!["8A", "7A", "9A", "8B"]);
+    assert_eq!(
+        parse_key("12B").unwrap().compatible_keys(),
+        vec!["12B", "11B", "1B", "12A"]
+    );
+}
+
+#[test]
+fn test_detect_tempo_and_key() {
+    let clicks = analyze_tempo_and_key(&write_wav(
+        "decibl_clicks.wav",
+        44100,
+        1,
+        &click_track(128.0),
+    ));
+    assert!((clicks.bpm - 128.0).abs() < 1.0, "{}", clicks.bpm);
+    assert!(clicks.bpm_confidence > 0.5);
+
+    let slow = analyze_tempo_and_key(&write_wav(
+        "decibl_slow_clicks.wav",
+        44100,
+        1,
+        &click_track(87.0),
+    ));
+    assert!((slow.bpm - 87.0).abs() < 1.0, "{}", slow.bpm);
+
+    let a_minor = analyze_tempo_and_key(&write_wav(
+        "decibl_a_minor.wav",
+        44100,
+        1,
+        &a_minor_cadence(),
+    ));
+    assert_eq!(a_minor.musical_key, "Am");
+    assert_eq!(a_minor.camelot, "8A");
+    assert!(a_minor.key_confidence > 0.5);
+
+    // C F G C
+    let c_major = chords(&[[60, 64, 67], [65, 69, 72], [67, 71, 74], [60, 64, 67]]);
+    let c_major = analyze_tempo_and_key(&write_wav("decibl_c_major.wav", 44100, 1, &c_major));
+    assert_eq!(c_major.musical_key, "C");
+    assert_eq!(c_major.camelot, "8B");
+
+    // nothing to go on
+    let silence = analyze_tempo_and_key(&write_wav("decibl_silence.wav", 44100, 1, &[0.0; 441000]));
+    assert_eq!(silence.bpm, -1.0);
+    assert_eq!(silence.musical_key, "");
+    let missing = analyze_tempo_and_key("../test_soundfiles/does_not_exist.flac");
+    assert_eq!(missing.bpm, -1.0);
+    assert_eq!(missing.tag_bpm, -1.0);
+}
+
+#[test]
+fn test_read_tempo_key_tags() {
+    use id3::TagLike;
+
+    let path = std::env::temp_dir().join("decibl_tempo_key.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    let path = path.to_str().unwrap().to_string();
+    let mut tag = id3::Tag::read_from_path(&path).unwrap();
+    tag.remove("TBPM");
+    tag.remove("TKEY");
+    tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+    assert_eq!(read_tempo_key_tags(&path), (-1.0, "".to_string()));
+
+    tag.set_text("TBPM", "128");
+    tag.set_text("TKEY", "A minor");
+    tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+    assert_eq!(read_tempo_key_tags(&path), (128.0, "Am".to_string()));
+
+    // kept as written when it isn't a key
+    tag.set_text("TKEY", "o");
+    tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+    assert_eq!(read_tempo_key_tags(&path).1, "o");
+}
+
+#[test]
+#[serial]
+fn test_analyze_library_tempo_and_key() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    for (song_id, song_path) in [
+        (
+            "clicks",
+            write_wav("decibl_library_clicks.wav", 44100, 1, &click_track(128.0)),
+        ),
+        (
+            "a_minor",
+            write_wav("decibl_library_a_minor.wav", 44100, 1, &a_minor_cadence()),
+        ),
+    ] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.filetype = "wav".to_string();
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path,
+        });
+    }
+
+    assert_eq!(analyze_library_tempo_and_key(false), 2);
+    assert_eq!(get_music_analysis("a_minor").unwrap().camelot, "8A");
+    assert!(get_music_analysis("not_analyzed").is_none());
+    // already done
+    assert_eq!(analyze_library_tempo_and_key(false), 0);
+
+    let ids = |rule: SmartRule| -> Vec<String> {
+        query_songs(&SongQuery::new().filter(rule.to_filter()))
+            .into_iter()
+            .map(|song| song.song_id)
+            .collect()
+    };
+    assert_eq!(
+        ids(SmartRule::Bpm {
+            min: Some(125.0),
+            max: Some(131.0)
+        }),
+        vec!["clicks"]
+    );
+    // E minor is 9A, one step from A minor
+    assert_eq!(
+        ids(SmartRule::HarmonicMatch("Em".to_string())),
+        vec!["a_minor"]
+    );
+    assert!(ids(SmartRule::HarmonicMatch("C#".to_string())).is_empty());
+    assert!(ids(SmartRule::HarmonicMatch("not a key".to_string())).is_empty());
+
+    // tagged a tempo off by more than the tolerance and the relative major
+    let mut tagged = get_music_analysis("a_minor").unwrap();
+    tagged.tag_key = "C".to_string();
+    save_music_analysis(&tagged);
+    let mut tagged = get_music_analysis("clicks").unwrap();
+    tagged.tag_bpm = tagged.bpm + 0.4;
+    save_music_analysis(&tagged);
+    let disagreements: Vec<String> = get_tag_disagreements(0.5)
+        .into_iter()
+        .map(|analysis| analysis.song_id)
+        .collect();
+    assert_eq!(disagreements, vec!["a_minor"]);
+}
@@ -0,0 +1,321 @@
+use decibl_metadata::engine::{
+    analyticsdb::{
+        self, insert_album_artist, insert_file_health, insert_play, insert_song, insert_songpath,
+    },
+    config::get_database_file_path,
+    models::{
+        default, ALBUM_ARTISTS_TABLE_DATA, FILE_HEALTH_TABLE_DATA, PLAY_TABLE_DATA,
+        SONGPATHS_TABLE_DATA, SONG_TABLE_DATA,
+    },
+    organiser::{
+        organise_songs, parse_template, plan_organise, render_template, sanitize_component,
+        OrganiseMode, TemplateField, TemplatePart, TemplateValues, DEFAULT_TEMPLATE,
+    },
+};
+mod common;
+use common::temp_folder;
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::path::{Path, PathBuf};
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing organiser                                                                   */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn song(song_id: &str, title: &str, album: &str, track_number: i64) -> SONG_TABLE_DATA {
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = song_id.to_string();
+    song.title = title.to_string();
+    song.main_artist = "The Artist".to_string();
+    song.album = album.to_string();
+    song.date_created = "2021-10-27".to_string();
+    song.track_number = track_number;
+    song.disc_number = 1;
+    song
+}
+
+fn values(song: SONG_TABLE_DATA, song_path: &str) -> TemplateValues {
+    TemplateValues {
+        song,
+        artists: Vec::new(),
+        album_artists: Vec::new(),
+        genres: Vec::new(),
+        composers: Vec::new(),
+        song_path: song_path.to_string(),
+    }
+}
+
+#[test]
+fn test_parse_template() {
+    let parts = parse_template(DEFAULT_TEMPLATE).unwrap();
+    assert_eq!(parts[0], TemplatePart::Field(TemplateField::AlbumArtist, 0));
+    assert_eq!(parts[1], TemplatePart::Separator);
+    assert_eq!(parts[3], TemplatePart::Text(" - ".to_string()));
+    assert_eq!(parts[6], TemplatePart::Field(TemplateField::Disc, 2));
+    assert_eq!(
+        parts.last(),
+        Some(&TemplatePart::Field(TemplateField::Ext, 0))
+    );
+
+    assert!(parse_template("{title").is_err());
+    assert!(parse_template("title}").is_err());
+    assert!(parse_template("{mood}").is_err());
+    assert!(parse_template("{track:two}").is_err());
+    assert_eq!(
+        TemplateField::from_field(TemplateField::AlbumArtist.as_str()),
+        Some(TemplateField::AlbumArtist)
+    );
+}
+
+#[test]
+fn test_sanitize_component() {
+    assert_eq!(sanitize_component("What? <Now>"), "What_ _Now_");
+    assert_eq!(sanitize_component("a\tb"), "a_b");
+    assert_eq!(sanitize_component("  The End...  "), "The End");
+    assert_eq!(sanitize_component(".."), "_");
+    assert_eq!(sanitize_component("NUL"), "NUL_");
+    assert_eq!(sanitize_component("lpt1.mp3"), "lpt1_.mp3");
+    assert_eq!(sanitize_component("COMPUTER.mp3"), "COMPUTER.mp3");
+    assert_eq!(sanitize_component("Beyoncé"), "Beyoncé");
+
+    // long names are cut on a character, keeping the extension
+    let long = sanitize_component(&format!("{}.flac", "é".repeat(150)));
+    assert!(long.len() <= 200);
+    assert!(long.ends_with("é.flac"));
+}
+
+#[test]
+fn test_render_template() {
+    let parts = parse_template(DEFAULT_TEMPLATE).unwrap();
+    let mut song_values = values(song("id", "Enemy", "Enemy / Arcane", 3), "/old/Song.FLAC");
+    assert_eq!(
+        render_template(&parts, &song_values),
+        Path::new("The Artist")
+            .join("2021 - Enemy _ Arcane")
+            .join("01-03 Enemy.flac")
+    );
+
+    song_values.album_artists = vec!["".to_string(), "AC/DC".to_string()];
+    song_values.song.album = "".to_string();
+    song_values.song.date_created = "".to_string();
+    song_values.song.disc_number = -1;
+    assert_eq!(
+        render_template(&parts, &song_values),
+        Path::new("AC_DC")
+            .join("Unknown Year - Unknown Album")
+            .join("00-03 Enemy.flac")
+    );
+
+    // an untagged FLAC has "-1" for every tag it doesn't have
+    let mut untagged = song("id", "-1", "-1", -1);
+    untagged.main_artist = "-1".to_string();
+    untagged.date_created = "-1".to_string();
+    untagged.disc_number = -1;
+    let mut untagged = values(untagged, "/old/untagged.flac");
+    untagged.album_artists = vec!["-1".to_string()];
+    assert_eq!(
+        render_template(&parts, &untagged),
+        Path::new("Unknown Artist")
+            .join("Unknown Year - Unknown Album")
+            .join("00-00 Unknown Title.flac")
+    );
+
+    // an empty folder is left out
+    let parts = parse_template("{publisher}/{track} {artists}.{ext}").unwrap();
+    song_values.song.publisher = "   ".to_string();
+    song_values.artists = vec!["One".to_string(), "Two".to_string()];
+    assert_eq!(
+        render_template(&parts, &song_values),
+        Path::new("Unknown Publisher").join("3 One, Two.flac")
+    );
+    let parts = parse_template("/{title}.{ext}").unwrap();
+    assert_eq!(
+        render_template(&parts, &song_values),
+        PathBuf::from("Enemy.flac")
+    );
+}
+
+#[test]
+#[serial]
+fn test_organise_songs() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    let source = temp_folder("decibl_organiser_source");
+    let root = temp_folder("decibl_organiser_library");
+    let template = "{album_artist}/{album}/{track:02} {title}.{ext}";
+
+    // two songs that end up with the same name, one already in place and one whose file is gone
+    let mut song_ids = Vec::new();
+    for (song_id, title, path) in [
+        ("a", "Same", source.join("a").join("a.mp3")),
+        ("b", "Same", source.join("b.mp3")),
+        (
+            "c",
+            "Placed",
+            root.join("Band").join("Album").join("01 Placed.mp3"),
+        ),
+        ("d", "Gone", source.join("d.mp3")),
+    ] {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        if song_id != "d" {
+            std::fs::write(&path, song_id.as_bytes()).unwrap();
+        }
+        insert_song(song(song_id, title, "Album", 1));
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path: path.to_str().unwrap().to_string(),
+        });
+        insert_album_artist(ALBUM_ARTISTS_TABLE_DATA {
+            artist_name: "Band".to_string(),
+            song_id: song_id.to_string(),
+            dt_added: "".to_string(),
+        });
+        song_ids.push(song_id.to_string());
+    }
+    let mut play = PLAY_TABLE_DATA::default();
+    play.play_id = "play".to_string();
+    play.song_id = "a".to_string();
+    insert_play(play);
+    let mut health = FILE_HEALTH_TABLE_DATA::default();
+    health.song_id = "a".to_string();
+    health.song_path = source.join("a").join("a.mp3").to_str().unwrap().to_string();
+    insert_file_health(health);
+
+    let root_str = root.to_str().unwrap();
+    let album = root.join("Band").join("Album");
+    let plan = plan_organise(&song_ids, root_str, template).unwrap();
+    assert_eq!(plan.moves.len(), 2);
+    assert_eq!(
+        plan.moves[0].to,
+        album.join("01 Same.mp3").to_str().unwrap()
+    );
+    assert_eq!(
+        plan.moves[1].to,
+        album.join("01 Same (2).mp3").to_str().unwrap()
+    );
+    assert_eq!(plan.unchanged, vec!["c".to_string()]);
+    assert_eq!(plan.skipped.len(), 1);
+    assert_eq!(plan.skipped[0].0, "d");
+    assert!(plan.to_text().contains(" -> "));
+
+    // a dry run only plans
+    let dry = organise_songs(&song_ids, root_str, template, OrganiseMode::Move, true).unwrap();
+    assert_eq!(dry, plan);
+    assert!(source.join("b.mp3").exists());
+    assert!(!album.join("01 Same.mp3").exists());
+
+    let moved = organise_songs(&song_ids, root_str, template, OrganiseMode::Move, false).unwrap();
+    assert_eq!(moved, plan);
+    assert_eq!(std::fs::read(album.join("01 Same.mp3")).unwrap(), b"a");
+    assert_eq!(std::fs::read(album.join("01 Same (2).mp3")).unwrap(), b"b");
+    assert!(!source.join("b.mp3").exists());
+    // the folder the move emptied is gone
+    assert!(!source.join("a").exists());
+
+    let paths = analyticsdb::get_songpaths_by_song_id("a".to_string());
+    assert_eq!(paths.len(), 1);
+    assert_eq!(
+        paths[0].song_path,
+        album.join("01 Same.mp3").to_str().unwrap()
+    );
+    assert_eq!(
+        analyticsdb::get_all_file_health()[0].song_path,
+        paths[0].song_path
+    );
+    assert_eq!(analyticsdb::get_all_plays()[0].song_id, "a");
+
+    // organised already
+    let again = plan_organise(&song_ids, root_str, template).unwrap();
+    assert!(again.moves.is_empty());
+    assert_eq!(again.unchanged.len(), 3);
+
+    // copies are another path of the song
+    let copies = temp_folder("decibl_organiser_copies");
+    let copied = organise_songs(
+        &song_ids[..1],
+        copies.to_str().unwrap(),
+        "{title}.{ext}",
+        OrganiseMode::Copy,
+        false,
+    )
+    .unwrap();
+    assert_eq!(copied.moves.len(), 1);
+    assert!(album.join("01 Same.mp3").exists());
+    assert_eq!(std::fs::read(copies.join("Same.mp3")).unwrap(), b"a");
+    let mut paths: Vec<String> = analyticsdb::get_songpaths_by_song_id("a".to_string())
+        .into_iter()
+        .map(|songpath| songpath.song_path)
+        .collect();
+    paths.sort();
+    let mut expected = vec![
+        album.join("01 Same.mp3").to_str().unwrap().to_string(),
+        copies.join("Same.mp3").to_str().unwrap().to_string(),
+    ];
+    expected.sort();
+    assert_eq!(paths, expected);
+
+    assert!(organise_songs(&song_ids, root_str, "{nope}", OrganiseMode::Move, true).is_err());
+}
+
+#[test]
+#[serial]
+fn test_organise_every_file() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    let source = temp_folder("decibl_organiser_every_file");
+    let root = temp_folder("decibl_organiser_every_file_library");
+    let root_str = root.to_str().unwrap();
+    let template = "{title}.{ext}";
+
+    // a song with two files of the same audio
+    insert_song(song("a", "Song", "Album", 1));
+    let mut paths = Vec::new();
+    for name in ["one.mp3", "two.mp3"] {
+        let path = source.join(name).to_str().unwrap().to_string();
+        std::fs::write(&path, name.as_bytes()).unwrap();
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: "a".to_string(),
+            song_path: path.clone(),
+        });
+        paths.push(path);
+    }
+    let song_ids = vec!["a".to_string()];
+    let plan = plan_organise(&song_ids, root_str, template).unwrap();
+    let mut targets: Vec<String> = plan
+        .moves
+        .iter()
+        .map(|planned| planned.to.clone())
+        .collect();
+    targets.sort();
+    assert_eq!(
+        targets,
+        vec![
+            root.join("Song (2).mp3").to_str().unwrap().to_string(),
+            root.join("Song.mp3").to_str().unwrap().to_string(),
+        ]
+    );
+
+    // the paths can't be saved, so both files go back
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_moves BEFORE UPDATE ON songpaths BEGIN SELECT RAISE(ABORT, 'no'); END;",
+    )
+    .unwrap();
+    assert!(organise_songs(&song_ids, root_str, template, OrganiseMode::Move, false).is_err());
+    conn.execute_batch("DROP TRIGGER no_moves;").unwrap();
+    for path in &paths {
+        assert!(Path::new(path).exists());
+    }
+    assert!(!root.join("Song.mp3").exists());
+    assert!(!root.join("Song (2).mp3").exists());
+
+    organise_songs(&song_ids, root_str, template, OrganiseMode::Move, false).unwrap();
+    let mut moved: Vec<String> = analyticsdb::get_songpaths_by_song_id("a".to_string())
+        .into_iter()
+        .map(|songpath| std::fs::read_to_string(songpath.song_path).unwrap())
+        .collect();
+    moved.sort();
+    assert_eq!(moved, vec!["one.mp3", "two.mp3"]);
+    assert!(!source.join("one.mp3").exists());
+}
@@ -0,0 +1,114 @@
+use decibl_metadata::engine::{
+    analyticsdb,
+    listening_stats::{get_listening_summary, TimeWindow, DEFAULT_SKIP_FRACTION},
+    models::{default, PLAY_TABLE_DATA, SONG_TABLE_DATA},
+    play_sessions::{close_dangling_plays, end_play, get_open_plays, record_seek, start_play},
+    smart_playlists::days_ago,
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing play sessions                                                               */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn setup() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "a".to_string();
+    song.title = "Alpha".to_string();
+    song.main_artist = "Ann".to_string();
+    song.filesize_bytes = 1234;
+    song.duration = 200.0;
+    analyticsdb::insert_song(song);
+}
+
+#[test]
+#[serial]
+fn test_start_and_end_play() {
+    setup();
+
+    let play_id = start_play("a").unwrap();
+    let play = analyticsdb::get_play_by_id(play_id.clone());
+    assert_eq!(play.song_title, "Alpha");
+    assert_eq!(play.main_artist, "Ann");
+    assert_eq!(play.filesize_bytes, 1234);
+    assert_eq!(play.end_dt, "");
+    assert_eq!(get_open_plays().len(), 1);
+
+    // the same song again straight away is a different play
+    let second_id = start_play("a").unwrap();
+    assert_ne!(play_id, second_id);
+
+    assert!(record_seek(&play_id, 150.0));
+    let play = end_play(&play_id, 180.0).unwrap();
+    assert_ne!(play.end_dt, "");
+    assert_eq!(play.position_seconds, 180.0);
+    assert_eq!(play.completion, 0.9);
+    assert_eq!(play.skipped, 0);
+    assert_eq!(play.seek_count, 1);
+
+    // can't end or seek in it twice
+    assert!(end_play(&play_id, 10.0).is_none());
+    assert!(!record_seek(&play_id, 10.0));
+    assert!(end_play("no such play", 10.0).is_none());
+
+    let skipped = end_play(&second_id, 20.0).unwrap();
+    assert_eq!(skipped.completion, 0.1);
+    assert_eq!(skipped.skipped, 1);
+    assert!(get_open_plays().is_empty());
+
+    // the stats go by the recorded completion, not the (tiny) wall clock time
+    let summary = get_listening_summary(&TimeWindow::all_time(), DEFAULT_SKIP_FRACTION);
+    assert_eq!(summary.play_count, 2);
+    assert_eq!(summary.skip_count, 1);
+}
+
+#[test]
+#[serial]
+fn test_unknown_song_and_dangling_plays() {
+    setup();
+
+    // a play has to be of a song in the library
+    assert_eq!(start_play("not in the library"), None);
+    assert!(get_open_plays().is_empty());
+
+    // no duration, so there's no telling if it was skipped
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "no duration".to_string();
+    analyticsdb::insert_song(song);
+    let play_id = start_play("no duration").unwrap();
+    let play = end_play(&play_id, 30.0).unwrap();
+    assert_eq!(play.completion, -1.0);
+    assert_eq!(play.skipped, -1);
+
+    // a play from a player that crashed yesterday
+    let mut crashed = PLAY_TABLE_DATA::default();
+    crashed.play_id = "crashed".to_string();
+    crashed.song_id = "a".to_string();
+    crashed.start_dt = "2023-01-01 10:00:00".to_string();
+    crashed.position_seconds = 42.0;
+    crashed.seek_count = 0;
+    analyticsdb::insert_play(crashed);
+
+    // and one that's only just started
+    let mut playing = PLAY_TABLE_DATA::default();
+    playing.play_id = "playing".to_string();
+    playing.song_id = "a".to_string();
+    playing.start_dt = days_ago(0);
+    analyticsdb::insert_play(playing);
+
+    assert_eq!(close_dangling_plays(), 1);
+    let crashed = analyticsdb::get_play_by_id("crashed".to_string());
+    assert_eq!(crashed.end_dt, "2023-01-01 10:00:42");
+    assert_eq!(crashed.skipped, -1);
+
+    let open: Vec<String> = get_open_plays()
+        .into_iter()
+        .map(|play| play.play_id)
+        .collect();
+    assert_eq!(open, vec!["playing"]);
+}
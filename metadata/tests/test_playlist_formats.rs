@@ -0,0 +1,177 @@
+use decibl_metadata::engine::{
+    analyticsdb,
+    models::{
+        default, PLAYLIST_SONGS_TABLE_DATA, PLAYLIST_TABLE_DATA, SONGPATHS_TABLE_DATA,
+        SONG_TABLE_DATA,
+    },
+    playlist_formats::{
+        export_playlist, import_playlist, parse_playlist, playlist_to_string, relative_path,
+        PlaylistFormat,
+    },
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+use std::path::{Path, PathBuf};
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing playlist formats                                                              */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn playlist_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join("decibl_playlist_formats");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// two songs living in <tmp>/decibl_playlist_formats/music, in a playlist called "road trip"
+fn insert_playlist_songs() -> String {
+    let music_dir = playlist_dir().join("music");
+    let songs = vec![
+        (
+            "a",
+            "brakence",
+            "punk2",
+            161.0,
+            "brakence 2.0 freestyle.flac",
+        ),
+        ("b", "Hudson Mohawke", "Cbat", 171.0, "cbat & friends.mp3"),
+    ];
+
+    for (song_id, artist, title, duration, filename) in songs {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.main_artist = artist.to_string();
+        song.title = title.to_string();
+        song.duration = duration;
+        analyticsdb::insert_song(song);
+
+        analyticsdb::insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: song_id.to_string(),
+            song_path: music_dir.join(filename).to_str().unwrap().to_string(),
+        });
+
+        analyticsdb::insert_playlist_song(PLAYLIST_SONGS_TABLE_DATA {
+            playlist_id: "road trip".to_string(),
+            song_id: song_id.to_string(),
+            added_dt: "2023-01-01 00:00:00".to_string(),
+            position: -1,
+        });
+    }
+
+    let mut playlist = PLAYLIST_TABLE_DATA::default();
+    playlist.playlist_id = "road trip".to_string();
+    playlist.playlist_name = "Road Trip".to_string();
+    analyticsdb::insert_playlist(playlist);
+
+    "road trip".to_string()
+}
+
+#[test]
+fn test_relative_path() {
+    let relative = relative_path(
+        Path::new("/music/playlists"),
+        Path::new("/music/albums/a.flac"),
+    );
+    assert_eq!(relative, PathBuf::from("../albums/a.flac"));
+}
+
+#[test]
+#[serial]
+fn test_export_m3u8() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    let playlist_id = insert_playlist_songs();
+
+    let contents = playlist_to_string(playlist_id, PlaylistFormat::M3U, Some(&playlist_dir()));
+    let expected = "#EXTM3U\n#EXTINF:161,brakence - punk2\nmusic/brakence 2.0 freestyle.flac\n#EXTINF:171,Hudson Mohawke - Cbat\nmusic/cbat & friends.mp3\n";
+    assert_eq!(contents, expected);
+
+    let entries = parse_playlist(&contents, PlaylistFormat::M3U);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].artist, "Hudson Mohawke");
+    assert_eq!(entries[1].title, "Cbat");
+    assert_eq!(entries[1].duration, 171.0);
+}
+
+#[test]
+#[serial]
+fn test_round_trip_every_format() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    let playlist_id = insert_playlist_songs();
+
+    for (filename, relative) in [
+        ("trip.m3u8", true),
+        ("trip.pls", false),
+        ("trip.xspf", false),
+        ("trip2.xspf", true),
+    ] {
+        let filepath = playlist_dir().join(filename).to_str().unwrap().to_string();
+        export_playlist(playlist_id.clone(), filepath.clone(), relative).unwrap();
+
+        let report = import_playlist(filepath).unwrap();
+        assert_eq!(report.song_ids, vec!["a", "b"], "{}", filename);
+        assert!(report.unresolved.is_empty(), "{}", filename);
+
+        let songs = analyticsdb::get_songs_in_playlist(report.playlist_id);
+        assert_eq!(songs.len(), 2);
+    }
+}
+
+#[test]
+#[serial]
+fn test_import_falls_back_to_title_and_reports_unresolved() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    insert_playlist_songs();
+
+    let contents = "[playlist]\nFile1=/somewhere/else/cbat.mp3\nTitle1=hudson mohawke - cbat\nLength1=171\nFile2=/nope.mp3\nTitle2=Nobody - Nothing\nFile3=/also/nope.mp3\nTitle3=Hudson Mohawke - Cb_t\nNumberOfEntries=3\nVersion=2\n";
+    let filepath = playlist_dir().join("moved.pls");
+    std::fs::write(&filepath, contents).unwrap();
+
+    let report = import_playlist(filepath.to_str().unwrap().to_string()).unwrap();
+    assert_eq!(report.song_ids, vec!["b"]);
+    // _ is not a wildcard when matching titles
+    assert_eq!(report.unresolved.len(), 2);
+    assert_eq!(report.unresolved[0].location, "/nope.mp3");
+    assert_eq!(report.unresolved[1].location, "/also/nope.mp3");
+
+    let playlist = analyticsdb::get_playlist_by_id(report.playlist_id);
+    assert_eq!(playlist.playlist_name, "moved");
+}
+
+#[test]
+fn test_parse_xspf_markup() {
+    // a namespace prefix, attributes (one with a '>' in it), a comment, CDATA and character references
+    let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- made by some other player -->
+<x:playlist version="1" xmlns:x="http://xspf.org/ns/0/">
+  <x:title>Not a track</x:title>
+  <x:trackList>
+    <x:track id="1" note="a > b">
+      <x:location>file:///music/Beyonc%C3%A9/halo.mp3</x:location>
+      <x:title>Halo &amp; Caf&#233; &#x2014; <![CDATA[<live> & loud]]></x:title>
+      <x:creator><!-- nobody --> Beyonc&#xE9; </x:creator>
+      <x:duration>261000</x:duration>
+    </x:track>
+    <track>
+      <location><![CDATA[/music/a & b.flac]]></location>
+      <extension application="x"><title>not this one</title></extension>
+      <title>A &amp; B</title>
+    </track>
+    <track/>
+    <track><title>no location</title></track>
+  </x:trackList>
+</x:playlist>"#;
+
+    let entries = parse_playlist(contents, PlaylistFormat::XSPF);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].location, "/music/Beyoncé/halo.mp3");
+    assert_eq!(entries[0].title, "Halo & Café — <live> & loud");
+    assert_eq!(entries[0].artist, "Beyoncé");
+    assert_eq!(entries[0].duration, 261.0);
+    assert_eq!(entries[1].location, "/music/a & b.flac");
+    assert_eq!(entries[1].title, "A & B");
+    assert_eq!(entries[1].duration, -1.0);
+}
@@ -0,0 +1,175 @@
+use decibl_metadata::engine::{
+    analyticsdb, config,
+    models::{default, PLAYLIST_SONGS_TABLE_DATA, SONG_TABLE_DATA},
+    playlists::{
+        append_playlist_song, get_playlist_length, insert_playlist_song_at, move_playlist_song,
+        remove_playlist_song_at, remove_song_from_playlist, reorder_playlist,
+    },
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing ordered playlists                                                           */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn setup() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    for song_id in ["a", "b", "c", "d"] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        analyticsdb::insert_song(song);
+    }
+}
+
+fn order(playlist_id: &str) -> Vec<String> {
+    analyticsdb::get_playlist_songs_by_id(playlist_id.to_string())
+        .into_iter()
+        .map(|playlist_song| playlist_song.song_id)
+        .collect()
+}
+
+fn positions(playlist_id: &str) -> Vec<i64> {
+    analyticsdb::get_playlist_songs_by_id(playlist_id.to_string())
+        .into_iter()
+        .map(|playlist_song| playlist_song.position)
+        .collect()
+}
+
+#[test]
+#[serial]
+fn test_insert_and_remove_with_duplicates() {
+    setup();
+    append_playlist_song("p", "a");
+    append_playlist_song("p", "b");
+    append_playlist_song("p", "a");
+    assert_eq!(insert_playlist_song_at("p", "c", 1), 1);
+    assert_eq!(insert_playlist_song_at("p", "d", 99), 4);
+
+    assert_eq!(order("p"), vec!["a", "c", "b", "a", "d"]);
+    assert_eq!(positions("p"), vec![0, 1, 2, 3, 4]);
+
+    // only the entry at 3 goes, the other "a" stays
+    assert_eq!(remove_playlist_song_at("p", 3), Some("a".to_string()));
+    assert_eq!(remove_playlist_song_at("p", 10), None);
+    assert_eq!(order("p"), vec!["a", "c", "b", "d"]);
+    assert_eq!(positions("p"), vec![0, 1, 2, 3]);
+
+    append_playlist_song("p", "c");
+    assert_eq!(remove_song_from_playlist("p", "c"), 2);
+    assert_eq!(order("p"), vec!["a", "b", "d"]);
+    assert_eq!(positions("p"), vec![0, 1, 2]);
+    assert_eq!(get_playlist_length("p"), 3);
+
+    let titles: Vec<String> = analyticsdb::get_songs_in_playlist("p".to_string())
+        .into_iter()
+        .map(|song| song.song_id)
+        .collect();
+    assert_eq!(titles, vec!["a", "b", "d"]);
+}
+
+#[test]
+#[serial]
+fn test_insert_playlist_song_at_taken_position() {
+    setup();
+    let insert = |song_id: &str, position: i64| {
+        analyticsdb::insert_playlist_song(PLAYLIST_SONGS_TABLE_DATA {
+            playlist_id: "p".to_string(),
+            song_id: song_id.to_string(),
+            added_dt: "".to_string(),
+            position,
+        })
+    };
+    insert("a", -1);
+    insert("b", -1);
+    // 0 is taken, so everything from there moves down instead of the insert getting dropped
+    insert("c", 0);
+    insert("d", 2);
+    // past the end appends rather than leaving a gap
+    insert("a", 40);
+
+    assert_eq!(order("p"), vec!["c", "a", "d", "b", "a"]);
+    assert_eq!(positions("p"), vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+#[serial]
+fn test_move_and_reorder() {
+    setup();
+    for song_id in ["a", "b", "c", "d"] {
+        append_playlist_song("p", song_id);
+    }
+    // a different playlist shouldn't be touched
+    append_playlist_song("q", "a");
+
+    assert!(move_playlist_song("p", 3, 0));
+    assert_eq!(order("p"), vec!["d", "a", "b", "c"]);
+    assert!(move_playlist_song("p", 0, 2));
+    assert_eq!(order("p"), vec!["a", "b", "d", "c"]);
+    assert!(!move_playlist_song("p", 0, 4));
+
+    assert!(reorder_playlist("p", &[3, 2, 1, 0]));
+    assert_eq!(order("p"), vec!["c", "d", "b", "a"]);
+    assert_eq!(positions("p"), vec![0, 1, 2, 3]);
+
+    // not a rearrangement, so nothing changes
+    assert!(!reorder_playlist("p", &[0, 0, 1, 2]));
+    assert!(!reorder_playlist("p", &[0, 1, 2]));
+    assert_eq!(order("p"), vec!["c", "d", "b", "a"]);
+
+    assert_eq!(order("q"), vec!["a"]);
+    assert_eq!(positions("q"), vec![0]);
+}
+
+#[test]
+#[serial]
+fn test_concurrent_edits_keep_positions() {
+    setup();
+    let handles: Vec<_> = ["a", "b", "c", "d"]
+        .into_iter()
+        .map(|song_id| {
+            std::thread::spawn(move || {
+                for i in 0..5 {
+                    if i % 2 == 0 {
+                        append_playlist_song("p", song_id);
+                    } else {
+                        insert_playlist_song_at("p", song_id, 0);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(get_playlist_length("p"), 20);
+    assert_eq!(positions("p"), (0..20).collect::<Vec<i64>>());
+}
+
+#[test]
+#[serial]
+fn test_migrate_unordered_playlist_songs() {
+    setup();
+
+    // what the table looked like before it had positions
+    let conn = rusqlite::Connection::open(config::get_database_file_path()).unwrap();
+    conn.execute_batch(
+        "DROP TABLE playlist_songs;
+         CREATE TABLE playlist_songs (playlist_id TEXT, song_id TEXT, added_dt TEXT);
+         INSERT INTO playlist_songs VALUES ('p', 'b', ''), ('q', 'a', ''), ('p', 'a', ''), ('p', 'b', '');",
+    )
+    .unwrap();
+
+    analyticsdb::create_all_tables();
+
+    assert_eq!(order("p"), vec!["b", "a", "b"]);
+    assert_eq!(positions("p"), vec![0, 1, 2]);
+    assert_eq!(positions("q"), vec![0]);
+
+    // and the new api works on the migrated table
+    assert!(move_playlist_song("p", 2, 0));
+    assert_eq!(order("p"), vec!["b", "b", "a"]);
+}
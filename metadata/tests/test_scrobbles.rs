@@ -0,0 +1,221 @@
+use decibl_metadata::engine::{
+    analyticsdb,
+    models::{default, PLAY_TABLE_DATA, SONG_ARTISTS_TABLE_DATA, SONG_TABLE_DATA},
+    scrobbles::{
+        export_scrobbles, get_listens, import_listens, import_scrobbles, listens_to_lastfm_csv,
+        listens_to_listenbrainz, normalize_name, parse_lastfm_csv, parse_listenbrainz, similarity,
+        Listen,
+    },
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing scrobbles                                                                   */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// 2023-01-01 00:00:00 UTC
+const T: i64 = 1672531200;
+
+fn listen(listened_at: i64, artist: &str, title: &str, album: &str) -> Listen {
+    Listen {
+        listened_at,
+        artist: artist.to_string(),
+        title: title.to_string(),
+        album: album.to_string(),
+        duration_seconds: -1.0,
+    }
+}
+
+fn insert_scrobble_songs() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let songs = vec![
+        ("a", "Alpha", "The Ann Band", "X", 200.0),
+        (
+            "b",
+            "Wonderful Tonight (Remastered)",
+            "Bob",
+            "Slowhand",
+            220.0,
+        ),
+    ];
+    for (song_id, title, artist, album, duration) in songs {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.title = title.to_string();
+        song.main_artist = artist.to_string();
+        song.album = album.to_string();
+        song.duration = duration;
+        analyticsdb::insert_song(song);
+
+        let mut song_artist = SONG_ARTISTS_TABLE_DATA::default();
+        song_artist.artist_name = artist.to_string();
+        song_artist.song_id = song_id.to_string();
+        analyticsdb::insert_song_artist(song_artist);
+    }
+}
+
+#[test]
+fn test_normalize_and_similarity() {
+    assert_eq!(normalize_name("The Beatles"), "beatles");
+    assert_eq!(normalize_name("Help! (feat. Someone) [Remastered]"), "help");
+    assert_eq!(normalize_name("Layla - 2011 Remaster"), "layla");
+    assert_eq!(normalize_name("Hey - You"), "hey you");
+    assert_eq!(similarity("abc", "abc"), 1.0);
+    assert_eq!(similarity("abcd", "abce"), 0.75);
+    assert_eq!(similarity("", "abc"), 0.0);
+}
+
+#[test]
+fn test_parse_formats() {
+    let array = r#"[{"listened_at": 1672531200, "track_metadata": {"artist_name": "Bob", "track_name": "Song",
+        "release_name": "Album", "additional_info": {"duration_ms": 200000, "something_else": 1}}},
+        {"not": "a listen"}]"#;
+    assert_eq!(
+        parse_listenbrainz(array),
+        vec![Listen {
+            duration_seconds: 200.0,
+            ..listen(T, "Bob", "Song", "Album")
+        }]
+    );
+
+    let lines = "{\"listened_at\": 1672531200, \"track_metadata\": {\"artist_name\": \"Bob\", \"track_name\": \"Song\"}}\n\
+                 {\"listened_at\": 1672531260, \"track_metadata\": {\"artist_name\": \"Ann\", \"track_name\": \"Other\"}}\n";
+    assert_eq!(parse_listenbrainz(lines).len(), 2);
+
+    let payload = r#"{"listen_type": "import", "payload": [{"listened_at": 1672531200,
+        "track_metadata": {"artist_name": "Bob", "track_name": "Song", "additional_info": {"duration": 90}}}]}"#;
+    assert_eq!(parse_listenbrainz(payload)[0].duration_seconds, 90.0);
+
+    let headerless = "Bob,Album,Song,01 Jan 2023 00:00\n\"Ann, Jr\",,\"Other\",01 Jan 2023 00:05\n";
+    let listens = parse_lastfm_csv(headerless);
+    assert_eq!(listens[0], listen(T, "Bob", "Song", "Album"));
+    assert_eq!(listens[1], listen(T + 300, "Ann, Jr", "Other", ""));
+
+    let with_header = "uts,utc_time,artist,artist_mbid,album,album_mbid,track,track_mbid\n\
+                       1672531200,\"01 Jan 2023, 00:00\",Bob,,Album,,Song,\n";
+    assert_eq!(
+        parse_lastfm_csv(with_header),
+        vec![listen(T, "Bob", "Song", "Album")]
+    );
+}
+
+#[test]
+#[serial]
+fn test_import_matches_and_deduplicates() {
+    insert_scrobble_songs();
+
+    // decibl already has a play of "a" an hour in
+    let mut play = PLAY_TABLE_DATA::default();
+    play.play_id = "existing".to_string();
+    play.song_id = "a".to_string();
+    play.song_title = "Alpha".to_string();
+    play.main_artist = "The Ann Band".to_string();
+    play.start_dt = "2023-01-01 01:00:00".to_string();
+    play.end_dt = "2023-01-01 01:03:20".to_string();
+    analyticsdb::insert_play(play);
+
+    let listens = vec![
+        // "The" gets dropped
+        listen(T, "Ann Band", "Alpha", "X"),
+        // the remaster suffix and the bracketed bit both go
+        listen(T + 1000, "Bob", "Wonderful Tonight - 2011 Remaster", ""),
+        // typo, fuzzy match
+        listen(T + 2000, "Bob", "Wonderfull Tonight", ""),
+        listen(T + 3000, "Nobody", "Nothing", ""),
+        // the same listen as the first one, from another service with a slightly different time
+        listen(T + 10, "ann band", "ALPHA", ""),
+        // overlaps the play decibl already had
+        listen(T + 3605, "The Ann Band", "Alpha", ""),
+        // a real second listen
+        listen(T + 7200, "The Ann Band", "Alpha", ""),
+    ];
+
+    let report = import_listens(listens.clone());
+    assert_eq!(report.imported, 5);
+    assert_eq!(report.matched, 4);
+    assert_eq!(report.duplicates, 2);
+    assert_eq!(
+        report.unmatched,
+        vec![listen(T + 3000, "Nobody", "Nothing", "")]
+    );
+
+    let plays = analyticsdb::get_all_plays();
+    assert_eq!(plays.len(), 6);
+    let song_ids: Vec<&str> = plays
+        .iter()
+        .filter(|play| play.play_id != "existing")
+        .map(|play| play.song_id.as_str())
+        .collect();
+    assert_eq!(
+        song_ids.iter().filter(|song_id| **song_id == "a").count(),
+        2
+    );
+    assert_eq!(
+        song_ids.iter().filter(|song_id| **song_id == "b").count(),
+        2
+    );
+    assert_eq!(
+        song_ids.iter().filter(|song_id| song_id.is_empty()).count(),
+        1
+    );
+
+    // the matched song's duration gives the end time
+    let first = plays
+        .iter()
+        .find(|play| play.start_dt == "2023-01-01 00:00:00")
+        .unwrap();
+    assert_eq!(first.end_dt, "2023-01-01 00:03:20");
+
+    // importing the same thing again does nothing
+    let again = import_listens(listens);
+    assert_eq!(again.imported, 0);
+    assert_eq!(again.duplicates, 7);
+}
+
+#[test]
+#[serial]
+fn test_export_round_trip() {
+    insert_scrobble_songs();
+    import_listens(vec![
+        listen(T, "The Ann Band", "Alpha", ""),
+        listen(T + 1000, "Nobody", "Nothing", ""),
+    ]);
+
+    // a skipped play isn't a scrobble
+    let mut skipped = PLAY_TABLE_DATA::default();
+    skipped.play_id = "skipped".to_string();
+    skipped.song_id = "a".to_string();
+    skipped.start_dt = "2023-01-02 00:00:00".to_string();
+    skipped.end_dt = "2023-01-02 00:00:10".to_string();
+    skipped.skipped = 1;
+    analyticsdb::insert_play(skipped);
+
+    let listens = get_listens();
+    assert_eq!(listens.len(), 2);
+    assert_eq!(listens[0].album, "X");
+    assert_eq!(listens[0].duration_seconds, 200.0);
+
+    assert_eq!(
+        parse_listenbrainz(&listens_to_listenbrainz(&listens)),
+        listens
+    );
+    let from_csv = parse_lastfm_csv(&listens_to_lastfm_csv(&listens));
+    // csv dates only go down to the minute
+    assert_eq!(from_csv[1], listen(T + 960, "Nobody", "Nothing", ""));
+
+    let dir = std::env::temp_dir().join("decibl_scrobbles");
+    std::fs::create_dir_all(&dir).unwrap();
+    let json_path = dir.join("listens.json").to_str().unwrap().to_string();
+    let csv_path = dir.join("scrobbles.csv").to_str().unwrap().to_string();
+    assert_eq!(export_scrobbles(json_path.clone()).unwrap(), 2);
+    assert_eq!(export_scrobbles(csv_path.clone()).unwrap(), 2);
+    assert!(export_scrobbles(dir.join("listens.txt").to_str().unwrap().to_string()).is_err());
+
+    // everything in the exports is already in the database
+    assert_eq!(import_scrobbles(json_path).unwrap().duplicates, 2);
+    assert_eq!(import_scrobbles(csv_path).unwrap().imported, 0);
+}
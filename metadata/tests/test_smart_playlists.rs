@@ -0,0 +1,179 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, SongColumn, SortOrder},
+    models::{default, GENRES_TABLE_DATA, PLAY_TABLE_DATA, SONG_TABLE_DATA},
+    smart_playlists::{
+        create_smart_playlist, days_ago, evaluate_smart_playlist, get_smart_playlist_rules,
+        set_smart_playlist_rules, MatchMode, SmartPlaylistRules, SmartRule, SmartSort,
+    },
+};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing smart playlists                                                              */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// three pop songs and a rock song, "a" gets played a lot recently, "b" a long time ago
+fn insert_smart_songs() {
+    let songs = vec![
+        ("a", "Pop", 24, 200.0),
+        ("b", "Pop", 16, 100.0),
+        ("c", "Pop", 24, 300.0),
+        ("d", "Rock", 24, 250.0),
+    ];
+
+    for (song_id, genre, bit_depth, duration) in songs {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.bit_depth = bit_depth;
+        song.duration = duration;
+        analyticsdb::insert_song(song);
+
+        let mut genre_data = GENRES_TABLE_DATA::default();
+        genre_data.song_id = song_id.to_string();
+        genre_data.genre_name = genre.to_string();
+        analyticsdb::insert_genre(genre_data);
+    }
+
+    let plays = vec![
+        ("1", "a", 1),
+        ("2", "a", 2),
+        ("3", "a", 3),
+        ("4", "c", 5),
+        ("5", "b", 90),
+    ];
+    for (play_id, song_id, days) in plays {
+        let mut play = PLAY_TABLE_DATA::default();
+        play.play_id = play_id.to_string();
+        play.song_id = song_id.to_string();
+        play.start_dt = days_ago(days);
+        play.end_dt = days_ago(days);
+        analyticsdb::insert_play(play);
+    }
+}
+
+fn ids(songs: &[SONG_TABLE_DATA]) -> Vec<String> {
+    songs.iter().map(|song| song.song_id.clone()).collect()
+}
+
+#[test]
+#[serial]
+fn test_smart_playlist_membership() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    insert_smart_songs();
+
+    let rules = SmartPlaylistRules {
+        match_mode: MatchMode::All,
+        rules: vec![
+            SmartRule::Genre("Pop".to_string()),
+            SmartRule::BitDepth {
+                min: Some(24),
+                max: None,
+            },
+            SmartRule::Duration {
+                min: None,
+                max: Some(250.0),
+            },
+        ],
+        sort: Some(SmartSort::Column(
+            SongColumn::Duration,
+            SortOrder::Descending,
+        )),
+        limit: None,
+    };
+
+    let playlist_id = create_smart_playlist("pop", "24 bit pop", &rules);
+    let songs = analyticsdb::get_songs_in_playlist(playlist_id.clone());
+    assert_eq!(ids(&songs), vec!["a"]);
+
+    // membership follows the library, not a snapshot
+    let mut song = SONG_TABLE_DATA::default();
+    song.song_id = "e".to_string();
+    song.bit_depth = 24;
+    song.duration = 230.0;
+    analyticsdb::insert_song(song);
+    let mut genre_data = GENRES_TABLE_DATA::default();
+    genre_data.song_id = "e".to_string();
+    genre_data.genre_name = "Pop".to_string();
+    analyticsdb::insert_genre(genre_data);
+
+    let songs = analyticsdb::get_songs_in_playlist(playlist_id);
+    assert_eq!(ids(&songs), vec!["e", "a"]);
+}
+
+#[test]
+#[serial]
+fn test_smart_playlist_added_within_days() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    // the date added is the song's own, "new" has no artists or genres at all
+    for (song_id, days) in [("new", 2), ("old", 40)] {
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = song_id.to_string();
+        song.dt_added = days_ago(days);
+        analyticsdb::insert_song(song);
+    }
+
+    let rules = SmartPlaylistRules {
+        match_mode: MatchMode::All,
+        rules: vec![SmartRule::AddedWithinDays(30)],
+        sort: None,
+        limit: None,
+    };
+    assert_eq!(ids(&evaluate_smart_playlist(&rules)), vec!["new"]);
+}
+
+#[test]
+#[serial]
+fn test_smart_playlist_most_played_recently() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+    insert_smart_songs();
+
+    // "2 most played in the last 30 days"
+    let rules = SmartPlaylistRules {
+        match_mode: MatchMode::All,
+        rules: vec![SmartRule::PlayedWithinDays(30)],
+        sort: Some(SmartSort::MostPlayed {
+            within_days: Some(30),
+        }),
+        limit: Some(2),
+    };
+    let playlist_id = create_smart_playlist("recent", "", &rules);
+    assert_eq!(
+        ids(&analyticsdb::get_songs_in_playlist(playlist_id.clone())),
+        vec!["a", "c"]
+    );
+
+    // any of: not played in a month, or played at least 3 times
+    let rules = SmartPlaylistRules {
+        match_mode: MatchMode::Any,
+        rules: vec![
+            SmartRule::NotPlayedWithinDays(30),
+            SmartRule::PlayCount {
+                min: Some(3),
+                max: None,
+                within_days: None,
+            },
+        ],
+        sort: None,
+        limit: None,
+    };
+    set_smart_playlist_rules(&playlist_id, &rules);
+    assert_eq!(get_smart_playlist_rules(&playlist_id), Some(rules));
+    assert_eq!(
+        ids(&analyticsdb::get_songs_in_playlist(playlist_id)),
+        vec!["a", "b", "d"]
+    );
+}
+
+#[test]
+#[serial]
+fn test_static_playlist_is_not_smart() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    assert_eq!(get_smart_playlist_rules("not a playlist"), None);
+}
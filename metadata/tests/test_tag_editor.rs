@@ -0,0 +1,416 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_song_artist, insert_songpath},
+    artwork::{get_embedded_artwork, get_front_cover, PictureType},
+    audio_metadata::{
+        find_audio_payload, get_symphonia_picture_data, write_symphonia_picture_data, AudioFile,
+        AudioFileMP3,
+    },
+    config::get_database_file_path,
+    models::{SONGPATHS_TABLE_DATA, SONG_ARTISTS_TABLE_DATA, SONG_TABLE_DATA},
+    tag_editor::{edit_song, read_tags, validate_changes, write_tags, TagChange, TagField},
+};
+mod common;
+use common::{image_bytes, mp4_box, temp_folder};
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing tag editor                                                                  */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+fn values(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+// the audio of a file, which tag edits must never touch
+fn audio_of(path: &str) -> Vec<u8> {
+    let bytes = std::fs::read(path).unwrap();
+    find_audio_payload(path)
+        .unwrap()
+        .ranges
+        .iter()
+        .flat_map(|(offset, length)| bytes[*offset as usize..(*offset + *length) as usize].to_vec())
+        .collect()
+}
+
+#[test]
+fn test_tag_field() {
+    for field in TagField::ALL {
+        assert_eq!(TagField::from_field(field.as_str()), Some(field));
+    }
+    assert_eq!(TagField::from_field("mood"), None);
+    assert!(TagField::Genre.is_list());
+    assert!(!TagField::Title.is_list());
+    assert!(TagField::DiscTotal.is_number());
+
+    assert!(validate_changes(&[
+        TagChange::Set(TagField::Artist, values(&["one", "two"])),
+        TagChange::set(TagField::TrackNumber, " 4 "),
+        TagChange::Set(TagField::Title, Vec::new()),
+        TagChange::RemoveCover,
+    ])
+    .is_ok());
+    assert!(validate_changes(&[TagChange::Set(TagField::Title, values(&["one", "two"]))]).is_err());
+    assert!(validate_changes(&[TagChange::set(TagField::DiscNumber, "one")]).is_err());
+    assert!(validate_changes(&[TagChange::SetCover(b"not an image".to_vec())]).is_err());
+}
+
+#[test]
+fn test_write_mp3_tags() {
+    let folder = temp_folder("decibl_tag_editor_mp3");
+    let path = folder.join("cbat.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    let path = path.to_str().unwrap();
+    let audio = audio_of(path);
+
+    let png = image_bytes(12, 10, image::ImageFormat::Png);
+    write_tags(
+        path,
+        &[
+            TagChange::set(TagField::Title, "Crab Rave"),
+            TagChange::Set(TagField::Artist, values(&["Noisestorm", "Someone Else"])),
+            TagChange::set(TagField::TrackNumber, "3"),
+            TagChange::set(TagField::TrackTotal, "12"),
+            TagChange::set(TagField::Barcode, "0123456789"),
+            TagChange::SetCover(png.clone()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(audio_of(path), audio);
+    assert!(!folder.join(".cbat.mp3.decibl-tmp").exists());
+
+    let tags = read_tags(path).unwrap();
+    assert_eq!(tags[&TagField::Title], values(&["Crab Rave"]));
+    assert_eq!(
+        tags[&TagField::Artist],
+        values(&["Noisestorm", "Someone Else"])
+    );
+    assert_eq!(tags[&TagField::TrackNumber], values(&["3"]));
+    assert_eq!(tags[&TagField::TrackTotal], values(&["12"]));
+    assert_eq!(tags[&TagField::Barcode], values(&["0123456789"]));
+
+    // the scanner reads the title from ID3v1, which has to say the same
+    let mut afile = AudioFileMP3::default();
+    afile.load_file(path.to_string()).unwrap();
+    assert_eq!(afile.get_song_table_data().title, "Crab Rave");
+    assert_eq!(afile.get_song_table_data().main_artist, "Noisestorm");
+
+    let cover = get_front_cover(path).unwrap();
+    assert_eq!(cover.data, png);
+    assert_eq!(cover.mime, "image/png");
+    assert_eq!(get_embedded_artwork(path).len(), 1);
+
+    write_tags(
+        path,
+        &[TagChange::Remove(TagField::Barcode), TagChange::RemoveCover],
+    )
+    .unwrap();
+    let tags = read_tags(path).unwrap();
+    assert!(!tags.contains_key(&TagField::Barcode));
+    assert_eq!(tags[&TagField::Title], values(&["Crab Rave"]));
+    assert!(get_embedded_artwork(path).is_empty());
+
+    // nothing gets written if a change is wrong
+    let before = std::fs::read(path).unwrap();
+    assert!(write_tags(
+        path,
+        &[
+            TagChange::set(TagField::Title, "Not Written"),
+            TagChange::set(TagField::TrackNumber, "x")
+        ]
+    )
+    .is_err());
+    assert_eq!(std::fs::read(path).unwrap(), before);
+}
+
+#[test]
+fn test_write_flac_tags() {
+    // fLaC, a last STREAMINFO block, then a frame sync
+    let mut flac = b"fLaC".to_vec();
+    flac.extend_from_slice(&[0x80, 0, 0, 34]);
+    flac.extend_from_slice(&[0; 34]);
+    flac.extend_from_slice(&[0xFF, 0xF8, 1, 2, 3]);
+    let folder = temp_folder("decibl_tag_editor_flac");
+    let path = folder.join("song.flac");
+    std::fs::write(&path, flac).unwrap();
+    let path = path.to_str().unwrap();
+    assert!(read_tags(path).unwrap().is_empty());
+
+    let jpeg = image_bytes(20, 20, image::ImageFormat::Jpeg);
+    write_tags(
+        path,
+        &[
+            TagChange::set(TagField::Album, "Enemy"),
+            TagChange::Set(TagField::Genre, values(&["Alternative", "Rock"])),
+            TagChange::set(TagField::Isrc, "USUM72120989"),
+            TagChange::SetCover(jpeg.clone()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(audio_of(path), vec![0xFF, 0xF8, 1, 2, 3]);
+
+    let tags = read_tags(path).unwrap();
+    assert_eq!(tags[&TagField::Album], values(&["Enemy"]));
+    assert_eq!(tags[&TagField::Genre], values(&["Alternative", "Rock"]));
+    assert_eq!(tags[&TagField::Isrc], values(&["USUM72120989"]));
+    let artwork = get_embedded_artwork(path);
+    assert_eq!(artwork.len(), 1);
+    assert_eq!(artwork[0].picture_type, PictureType::FrontCover);
+    assert_eq!((artwork[0].width, artwork[0].height), (20, 20));
+
+    // replacing the cover leaves one cover
+    let png = image_bytes(8, 8, image::ImageFormat::Png);
+    write_symphonia_picture_data(path.to_string(), png.clone());
+    assert_eq!(
+        get_symphonia_picture_data(path.to_string(), "flac".to_string()),
+        png
+    );
+    assert_eq!(get_embedded_artwork(path).len(), 1);
+
+    write_tags(
+        path,
+        &[
+            TagChange::Remove(TagField::Genre),
+            TagChange::Set(TagField::Album, Vec::new()),
+        ],
+    )
+    .unwrap();
+    let tags = read_tags(path).unwrap();
+    assert!(!tags.contains_key(&TagField::Genre));
+    assert!(!tags.contains_key(&TagField::Album));
+    assert_eq!(tags.len(), 1);
+}
+
+#[test]
+fn test_write_mp4_tags() {
+    // a moov in front of the mdat, with a chunk offset pointing at the audio and no tags yet
+    let audio = vec![7u8; 32];
+    let ftyp = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+    let stco_body = |offset: u32| {
+        let mut body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        body.extend_from_slice(&offset.to_be_bytes());
+        body
+    };
+    let trak = |offset: u32| {
+        mp4_box(
+            b"trak",
+            &mp4_box(
+                b"mdia",
+                &mp4_box(
+                    b"minf",
+                    &mp4_box(b"stbl", &mp4_box(b"stco", &stco_body(offset))),
+                ),
+            ),
+        )
+    };
+    let moov_length = mp4_box(b"moov", &trak(0)).len();
+    let offset = (ftyp.len() + moov_length + 8) as u32;
+    let mut m4a = ftyp.clone();
+    m4a.extend(mp4_box(b"moov", &trak(offset)));
+    m4a.extend(mp4_box(b"mdat", &audio));
+    let folder = temp_folder("decibl_tag_editor_m4a");
+    let path = folder.join("song.m4a");
+    std::fs::write(&path, m4a).unwrap();
+    let path = path.to_str().unwrap();
+
+    let jpeg = image_bytes(6, 6, image::ImageFormat::Jpeg);
+    write_tags(
+        path,
+        &[
+            TagChange::set(TagField::Title, "Song"),
+            TagChange::Set(TagField::Composer, values(&["One", "Two"])),
+            TagChange::set(TagField::DiscNumber, "2"),
+            TagChange::set(TagField::DiscTotal, "3"),
+            TagChange::set(TagField::Publisher, "Label"),
+            TagChange::SetCover(jpeg.clone()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(audio_of(path), audio);
+
+    // the chunk offset still points at the audio
+    let bytes = std::fs::read(path).unwrap();
+    let stco = bytes
+        .windows(4)
+        .position(|window| window == b"stco")
+        .unwrap();
+    let chunk = u32::from_be_bytes(bytes[stco + 12..stco + 16].try_into().unwrap()) as usize;
+    assert_eq!(&bytes[chunk..chunk + audio.len()], audio.as_slice());
+
+    let tags = read_tags(path).unwrap();
+    assert_eq!(tags[&TagField::Title], values(&["Song"]));
+    assert_eq!(tags[&TagField::Composer], values(&["One", "Two"]));
+    assert_eq!(tags[&TagField::DiscNumber], values(&["2"]));
+    assert_eq!(tags[&TagField::DiscTotal], values(&["3"]));
+    assert_eq!(tags[&TagField::Publisher], values(&["Label"]));
+    assert_eq!(get_front_cover(path).unwrap().data, jpeg);
+
+    // removing the total keeps the number
+    write_tags(
+        path,
+        &[
+            TagChange::Remove(TagField::DiscTotal),
+            TagChange::Remove(TagField::Publisher),
+            TagChange::RemoveCover,
+        ],
+    )
+    .unwrap();
+    let tags = read_tags(path).unwrap();
+    assert_eq!(tags[&TagField::DiscNumber], values(&["2"]));
+    assert!(!tags.contains_key(&TagField::DiscTotal));
+    assert!(!tags.contains_key(&TagField::Publisher));
+    assert!(get_embedded_artwork(path).is_empty());
+    assert_eq!(audio_of(path), audio);
+}
+
+#[test]
+fn test_write_mp4_tags_box_headers() {
+    // an mdat with a 64 bit size behind the moov
+    let audio = vec![7u8; 32];
+    let ftyp = mp4_box(b"ftyp", b"M4A \0\0\0\0");
+    let moov = |offset: u32| {
+        let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco.extend_from_slice(&offset.to_be_bytes());
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco));
+        mp4_box(
+            b"moov",
+            &mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl))),
+        )
+    };
+    let mut mdat = 1u32.to_be_bytes().to_vec();
+    mdat.extend_from_slice(b"mdat");
+    mdat.extend_from_slice(&(audio.len() as u64 + 16).to_be_bytes());
+    mdat.extend_from_slice(&audio);
+    let mut m4a = ftyp.clone();
+    m4a.extend(moov((ftyp.len() + moov(0).len() + 16) as u32));
+    m4a.extend(&mdat);
+    let folder = temp_folder("decibl_tag_editor_m4a_headers");
+    let path = folder.join("song.m4a");
+    std::fs::write(&path, m4a).unwrap();
+    let path = path.to_str().unwrap();
+
+    write_tags(path, &[TagChange::set(TagField::Title, "Song")]).unwrap();
+    let bytes = std::fs::read(path).unwrap();
+    // the mdat kept its header, and the chunk offset still points at the audio
+    assert!(bytes.ends_with(&mdat));
+    let stco = bytes
+        .windows(4)
+        .position(|window| window == b"stco")
+        .unwrap();
+    let chunk = u32::from_be_bytes(bytes[stco + 12..stco + 16].try_into().unwrap()) as usize;
+    assert_eq!(&bytes[chunk..chunk + audio.len()], audio.as_slice());
+    assert_eq!(read_tags(path).unwrap()[&TagField::Title], values(&["Song"]));
+
+    // a box that says it's longer than what's left of the file would get dropped, so nothing is written
+    let mut truncated = bytes.clone();
+    truncated.extend_from_slice(&100u32.to_be_bytes());
+    truncated.extend_from_slice(b"free");
+    truncated.extend_from_slice(&[0; 10]);
+    std::fs::write(path, &truncated).unwrap();
+    assert!(write_tags(path, &[TagChange::set(TagField::Title, "Other")]).is_err());
+    assert_eq!(std::fs::read(path).unwrap(), truncated);
+}
+
+#[test]
+#[serial]
+fn test_edit_song() {
+    analyticsdb::create_all_tables();
+    analyticsdb::clear_all_tables();
+
+    let folder = temp_folder("decibl_tag_editor_db");
+    let path = folder.join("cbat.mp3");
+    std::fs::copy("../test_soundfiles/1/cbat.mp3", &path).unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let mut afile = AudioFileMP3::default();
+    afile.load_file(path.clone()).unwrap();
+    let song = afile.get_song_table_data();
+    let song_id = song.song_id.clone();
+    insert_song(song);
+    insert_songpath(SONGPATHS_TABLE_DATA {
+        song_id: song_id.clone(),
+        song_path: path.clone(),
+    });
+    insert_song_artist(SONG_ARTISTS_TABLE_DATA {
+        artist_name: "Original".to_string(),
+        song_id: song_id.clone(),
+        dt_added: "2020-01-01 00:00:00".to_string(),
+    });
+
+    let png = image_bytes(16, 16, image::ImageFormat::Png);
+    edit_song(
+        &song_id,
+        &[
+            TagChange::set(TagField::Title, "Edited"),
+            TagChange::Set(TagField::Artist, values(&["First", "Second"])),
+            TagChange::Set(TagField::Genre, values(&["Electronic"])),
+            TagChange::set(TagField::TrackNumber, "9"),
+            TagChange::SetCover(png),
+        ],
+    )
+    .unwrap();
+
+    let song: SONG_TABLE_DATA = analyticsdb::get_song_by_id(song_id.clone());
+    assert_eq!(song.title, "Edited");
+    assert_eq!(song.main_artist, "First");
+    assert_eq!(song.track_number, 9);
+    assert_eq!(
+        song.filesize_bytes,
+        std::fs::metadata(&path).unwrap().len() as i64
+    );
+    // the new cover is black
+    assert_eq!(song.album_artwork_colors, 0);
+    let mut artists: Vec<String> = analyticsdb::get_all_song_artists()
+        .into_iter()
+        .map(|artist| artist.artist_name)
+        .collect();
+    artists.sort();
+    assert_eq!(artists, values(&["First", "Second"]));
+    // the song still counts as added when it was first added
+    assert!(analyticsdb::get_all_song_artists()
+        .iter()
+        .all(|artist| artist.dt_added == "2020-01-01 00:00:00"));
+    // and the copy of the old file is gone
+    assert!(!folder.join(".cbat.mp3.decibl-bak").exists());
+    let genres = analyticsdb::get_all_genres();
+    assert_eq!(genres.len(), 1);
+    assert_eq!(genres[0].genre_name, "Electronic");
+
+    // a new ISRC changes which recording the song is, but it keeps its song_id
+    edit_song(&song_id, &[TagChange::set(TagField::Isrc, "GBAYE0601498")]).unwrap();
+    let song = analyticsdb::get_song_by_id(song_id.clone());
+    assert_eq!(song.isrc, "GBAYE0601498");
+    assert_eq!(song.recording_id, "isrc:GBAYE0601498");
+    assert_eq!(
+        analyticsdb::get_songpaths_by_song_id(song_id.clone())[0].song_path,
+        path
+    );
+
+    assert!(edit_song("no_song", &[TagChange::set(TagField::Title, "x")]).is_err());
+
+    // a copy with the same audio is the same song, so it gets the edit too
+    let copy = folder.join("copy.mp3").to_str().unwrap().to_string();
+    std::fs::copy(&path, &copy).unwrap();
+    insert_songpath(SONGPATHS_TABLE_DATA {
+        song_id: song_id.clone(),
+        song_path: copy.clone(),
+    });
+    edit_song(&song_id, &[TagChange::set(TagField::Title, "Both")]).unwrap();
+    assert_eq!(read_tags(&path).unwrap()[&TagField::Title], values(&["Both"]));
+    assert_eq!(read_tags(&copy).unwrap()[&TagField::Title], values(&["Both"]));
+
+    // when the rows can't be updated both files go back to how they were
+    let conn = rusqlite::Connection::open(get_database_file_path()).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER no_updates BEFORE UPDATE ON songs BEGIN SELECT RAISE(ABORT, 'no'); END;",
+    )
+    .unwrap();
+    let before = std::fs::read(&path).unwrap();
+    assert!(edit_song(&song_id, &[TagChange::set(TagField::Title, "Lost")]).is_err());
+    conn.execute_batch("DROP TRIGGER no_updates;").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), before);
+    assert_eq!(read_tags(&copy).unwrap()[&TagField::Title], values(&["Both"]));
+    assert!(!folder.join(".copy.mp3.decibl-bak").exists());
+    assert_eq!(analyticsdb::get_song_by_id(song_id.clone()).title, "Both");
+}
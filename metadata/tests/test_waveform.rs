@@ -0,0 +1,126 @@
+use decibl_metadata::engine::{
+    analyticsdb::{self, insert_song, insert_songpath},
+    config::get_waveform_path,
+    models::{default, SONGPATHS_TABLE_DATA, SONG_TABLE_DATA},
+    waveform::{
+        clear_waveform_cache, generate_waveform, get_waveform, waveform_from_bytes,
+        waveform_to_bytes, WaveformMode,
+    },
+};
+
+mod common;
+use common::write_wav;
+
+#[cfg(test)]
+// RUN cargo test --tests -- --nocapture
+use serial_test::serial;
+
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+/*                                                              testing waveforms                                                                   */
+/* ------------------------------------------------------------------------------------------------------------------------------------------------ */
+
+// writes 2 seconds of 16 bit stereo wav at 8kHz: a sine at half volume on the left, the first second silent on the right
+// and the second second full volume
+fn write_test_wav(name: &str) -> String {
+    let sample_rate: u32 = 8000;
+    let mut samples: Vec<f32> = Vec::new();
+    for i in 0..sample_rate * 2 {
+        let sine = (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin();
+        let left = 0.5 * sine;
+        let right = if i < sample_rate { 0.0 } else { sine };
+        samples.extend_from_slice(&[left, right]);
+    }
+    write_wav(name, sample_rate, 2, &samples)
+}
+
+#[test]
+fn test_generate_waveform() {
+    let path = write_test_wav("decibl_waveform.wav");
+
+    let waveform = generate_waveform(&path, "HASH", 10, WaveformMode::PerChannel).unwrap();
+    assert_eq!(waveform.audio_hash, "HASH");
+    assert_eq!(waveform.channels.len(), 2);
+    assert_eq!(waveform.resolution(), 10);
+    assert_eq!(waveform.sample_rate, 8000);
+    assert_eq!(waveform.duration, 2.0);
+    for point in 0..10 {
+        assert!((waveform.channels[0].max[point] - 0.5).abs() < 0.01);
+        assert!((waveform.channels[0].min[point] + 0.5).abs() < 0.01);
+    }
+    assert_eq!(waveform.channels[1].max[0], 0.0);
+    assert!(waveform.channels[1].max[9] > 0.99);
+
+    let mixed = generate_waveform(&path, "HASH", 10, WaveformMode::Mixed).unwrap();
+    assert_eq!(mixed.channels.len(), 1);
+    assert!((mixed.channels[0].max[0] - 0.25).abs() < 0.01);
+    assert!((mixed.channels[0].max[9] - 0.75).abs() < 0.01);
+
+    // more points than there is audio for still fills every point
+    let detailed = generate_waveform(&path, "HASH", 1000, WaveformMode::Mixed).unwrap();
+    assert_eq!(detailed.resolution(), 1000);
+    assert!(detailed.channels[0].max.iter().all(|max| *max > 0.0));
+
+    assert_eq!(
+        waveform_from_bytes(&waveform_to_bytes(&waveform)).unwrap(),
+        waveform
+    );
+    assert!(waveform_from_bytes(&waveform_to_bytes(&waveform)[..40]).is_err());
+    assert!(waveform_from_bytes(b"not a waveform").is_err());
+    assert_eq!(
+        generate_waveform(
+            "../test_soundfiles/does_not_exist.wav",
+            "HASH",
+            10,
+            WaveformMode::Mixed
+        ),
+        None
+    );
+}
+
+#[test]
+#[serial]
+fn test_waveform_cache() {
+    let path = write_test_wav("decibl_waveform_cache.wav");
+    let add_song = |audio_hash: &str| {
+        analyticsdb::create_all_tables();
+        analyticsdb::clear_all_tables();
+        let mut song = SONG_TABLE_DATA::default();
+        song.song_id = "waveform".to_string();
+        song.audio_hash = audio_hash.to_string();
+        insert_song(song);
+        insert_songpath(SONGPATHS_TABLE_DATA {
+            song_id: "waveform".to_string(),
+            song_path: path.clone(),
+        });
+    };
+    let cache_file = std::path::Path::new(&get_waveform_path())
+        .join("waveform")
+        .join("100_mixed.peaks");
+    clear_waveform_cache("waveform");
+    add_song("FIRST");
+
+    let waveform = get_waveform("waveform", 100, WaveformMode::Mixed).unwrap();
+    assert_eq!(waveform.audio_hash, "FIRST");
+    assert!(cache_file.exists());
+
+    // the cached one is what comes back, even with the file gone
+    std::fs::rename(&path, format!("{}.moved", path)).unwrap();
+    assert_eq!(
+        get_waveform("waveform", 100, WaveformMode::Mixed).unwrap(),
+        waveform
+    );
+    std::fs::rename(format!("{}.moved", path), &path).unwrap();
+
+    // new audio, so the cache is stale
+    add_song("SECOND");
+    assert_eq!(
+        get_waveform("waveform", 100, WaveformMode::Mixed)
+            .unwrap()
+            .audio_hash,
+        "SECOND"
+    );
+    assert_eq!(get_waveform("not_a_song", 100, WaveformMode::Mixed), None);
+
+    clear_waveform_cache("waveform");
+    assert!(!cache_file.exists());
+}